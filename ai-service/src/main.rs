@@ -1,19 +1,41 @@
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{Context, Result, bail};
 use axum::{
     Router,
-    extract::State,
+    extract::{DefaultBodyLimit, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use clap::Parser;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, fmt};
 
+const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+/// Upstream limit for `images/edits` source images.
+const MAX_EDIT_IMAGE_BYTES: usize = 50 * 1024 * 1024;
+
+const STYLE_PREFIX: &str = "Чёрно-белое изображение. 
+Только чёрные линии (#000000). 
+Фон — чистый сплошной белый цвет (#FFFFFF), ровная плоская заливка.
+Без градиентов, без теней, без виньетки, без текстуры, без освещения, без серых оттенков.
+Высокий контраст, жёсткие края.
+
+Black and white vector illustration.
+Background: pure solid white (#FFFFFF), flat fill.
+No gradients, no shadows, no vignette, no texture, no lighting, no gray background.
+Hard edges, high contrast.";
+
 #[derive(Debug, Parser)]
 #[command(name = "ai-service")]
 #[command(about = "AI image generation service for sticker bot")]
@@ -22,18 +44,28 @@ struct Args {
     listen: String,
     #[arg(long)]
     openai_api_key: Option<String>,
+    #[arg(long)]
+    openai_base_url: Option<String>,
     #[arg(long, default_value = "gpt-image-1-mini")]
     model: String,
     #[arg(long)]
     api_token: Option<String>,
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    #[arg(long, default_value_t = 7 * 24 * 3600)]
+    cache_ttl: u64,
 }
 
 #[derive(Clone)]
 struct AppState {
     http: Client,
     openai_api_key: String,
+    /// API root such as `https://api.openai.com/v1`, without a trailing slash.
+    openai_base_url: String,
     model: String,
     api_token: Option<String>,
+    cache_dir: Option<PathBuf>,
+    cache_ttl: Duration,
 }
 
 #[derive(Debug, Deserialize)]
@@ -42,6 +74,18 @@ struct GenerateRequest {
     size: Option<String>,
     quality: Option<String>,
     n: Option<u8>,
+    /// Skip the cache lookup; the fresh result still replaces the cached one.
+    no_cache: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EditRequest {
+    /// Source image as PNG, JPEG or WebP.
+    image_base64: String,
+    /// What to change, e.g. "сделай из этого раскраску".
+    prompt: String,
+    size: Option<String>,
+    quality: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -52,6 +96,14 @@ struct GenerateResponse {
     size: String,
     quality: String,
     usage: Option<GenerationUsage>,
+    cached: bool,
+}
+
+/// What is stored per prompt in `--cache-dir`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedImage {
+    image_base64: String,
+    revised_prompt: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -121,6 +173,19 @@ async fn main() -> Result<()> {
         Some(v) => v,
         None => bail!("openai api key is missing: pass --openai-api-key or set OPENAI_API_KEY"),
     };
+    let openai_base_url = args
+        .openai_base_url
+        .or_else(|| std::env::var("OPENAI_BASE_URL").ok())
+        // docker-compose passes unset variables through as empty strings.
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_OPENAI_BASE_URL.to_string());
+    let parsed = reqwest::Url::parse(&openai_base_url)
+        .with_context(|| format!("invalid OpenAI base URL {openai_base_url:?}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        bail!("OpenAI base URL must be http(s), got {openai_base_url:?}");
+    }
+    let openai_base_url = openai_base_url.trim_end_matches('/').to_string();
+    info!(openai_base_url = %openai_base_url, "using OpenAI API");
     let addr: SocketAddr = args.listen.parse().context("invalid --listen address")?;
 
     let state = Arc::new(AppState {
@@ -129,13 +194,23 @@ async fn main() -> Result<()> {
             .build()
             .context("failed to build http client")?,
         openai_api_key,
+        openai_base_url,
         model: args.model,
         api_token: args.api_token,
+        cache_dir: args.cache_dir,
+        cache_ttl: Duration::from_secs(args.cache_ttl),
     });
 
     let app = Router::new()
         .route("/health", get(health))
         .route("/api/v1/generate", post(generate))
+        .route(
+            "/api/v1/edit",
+            // Base64 inflates the source by 4/3, plus room for the other fields.
+            post(edit).layer(DefaultBodyLimit::max(
+                MAX_EDIT_IMAGE_BYTES / 3 * 4 + 64 * 1024,
+            )),
+        )
         .with_state(state);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
@@ -179,20 +254,31 @@ async fn generate(
     let final_prompt = format!("{} User request: {}", style_prefix, req.prompt.trim());
     */
 
-    let style_prefix = "Чёрно-белое изображение. 
-Только чёрные линии (#000000). 
-Фон — чистый сплошной белый цвет (#FFFFFF), ровная плоская заливка.
-Без градиентов, без теней, без виньетки, без текстуры, без освещения, без серых оттенков.
-Высокий контраст, жёсткие края.
-
-Black and white vector illustration.
-Background: pure solid white (#FFFFFF), flat fill.
-No gradients, no shadows, no vignette, no texture, no lighting, no gray background.
-Hard edges, high contrast.";
-
-
     //let style_prefix = "Чёрно-белое изображение, чёткие чёрные линии, фон только белый. Без закрашивания, без теней, высокий контраст";
-    let final_prompt = format!("Стиль изображения: {}. Содержимое изображения: {}", style_prefix, req.prompt.trim()); 
+    let final_prompt = format!("Стиль изображения: {}. Содержимое изображения: {}", STYLE_PREFIX, req.prompt.trim()); 
+    let cache_path = state.cache_dir.as_ref().map(|dir| {
+        dir.join(format!(
+            "{}.json",
+            cache_key(&state.model, &size, &quality, &final_prompt)
+        ))
+    });
+    if let Some(path) = &cache_path
+        && !req.no_cache.unwrap_or(false)
+        && let Some(hit) = read_cache(path, state.cache_ttl).await
+    {
+        info!(model = %state.model, size = %size, "image served from cache");
+        let out = GenerateResponse {
+            image_base64: hit.image_base64,
+            revised_prompt: hit.revised_prompt,
+            model: state.model.clone(),
+            size,
+            quality,
+            usage: None,
+            cached: true,
+        };
+        return (StatusCode::OK, axum::Json(out)).into_response();
+    }
+
     let oa_req = OpenAiImageRequest {
         model: state.model.clone(),
         prompt: final_prompt,
@@ -211,7 +297,17 @@ Hard edges, high contrast.";
                 size,
                 quality,
                 usage,
+                cached: false,
             };
+            if let Some(path) = &cache_path {
+                let entry = CachedImage {
+                    image_base64: out.image_base64.clone(),
+                    revised_prompt: out.revised_prompt.clone(),
+                };
+                if let Err(err) = write_cache(path, &entry).await {
+                    warn!(path = %path.display(), error = %err, "failed to write image cache");
+                }
+            }
             (StatusCode::OK, axum::Json(out)).into_response()
         }
         Err(err) => {
@@ -224,19 +320,134 @@ Hard edges, high contrast.";
     }
 }
 
+async fn edit(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(req): axum::Json<EditRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers) {
+        return resp;
+    }
+
+    if req.prompt.trim().is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "prompt is empty");
+    }
+
+    let image = match BASE64.decode(req.image_base64.trim()) {
+        Ok(v) => v,
+        Err(_) => {
+            return error_response(StatusCode::BAD_REQUEST, "image_base64 is not valid base64");
+        }
+    };
+    if image.len() > MAX_EDIT_IMAGE_BYTES {
+        return error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!(
+                "source image is {} bytes, max is {MAX_EDIT_IMAGE_BYTES}",
+                image.len()
+            ),
+        );
+    }
+    let Some(format) = SourceFormat::detect(&image) else {
+        return error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "source image must be PNG, JPEG or WebP",
+        );
+    };
+
+    let size = req.size.unwrap_or_else(|| "1024x1024".to_string());
+    if !is_allowed_size(&size) {
+        return error_response(StatusCode::BAD_REQUEST, "unsupported size");
+    }
+
+    let quality = req.quality.unwrap_or_else(|| "low".to_string());
+    if !matches!(quality.as_str(), "low" | "medium" | "high") {
+        return error_response(StatusCode::BAD_REQUEST, "quality must be low|medium|high");
+    }
+
+    let final_prompt = format!(
+        "Стиль изображения: {}. Что изменить в исходном изображении: {}",
+        STYLE_PREFIX,
+        req.prompt.trim()
+    );
+
+    match edit_openai_image(
+        &state,
+        image,
+        format,
+        final_prompt,
+        size.clone(),
+        quality.clone(),
+    )
+    .await
+    {
+        Ok((image_base64, revised_prompt, usage)) => {
+            info!(model = %state.model, size = %size, "image edited");
+            let out = GenerateResponse {
+                image_base64,
+                revised_prompt,
+                model: state.model.clone(),
+                size,
+                quality,
+                usage,
+                cached: false,
+            };
+            (StatusCode::OK, axum::Json(out)).into_response()
+        }
+        Err(err) => {
+            error!(error = %err, "image edit failed");
+            error_response(StatusCode::BAD_GATEWAY, &format!("edit failed: {err}"))
+        }
+    }
+}
+
 async fn generate_openai_image(
     state: &AppState,
     req: OpenAiImageRequest,
 ) -> Result<(String, Option<String>, Option<GenerationUsage>)> {
     let resp = state
         .http
-        .post("https://api.openai.com/v1/images/generations")
+        .post(format!("{}/images/generations", state.openai_base_url))
         .bearer_auth(&state.openai_api_key)
         .json(&req)
         .send()
         .await
         .context("failed to call OpenAI API")?;
+    decode_openai_image_response(resp).await
+}
+
+async fn edit_openai_image(
+    state: &AppState,
+    image: Vec<u8>,
+    format: SourceFormat,
+    prompt: String,
+    size: String,
+    quality: String,
+) -> Result<(String, Option<String>, Option<GenerationUsage>)> {
+    let image_part = reqwest::multipart::Part::bytes(image)
+        .file_name(format!("source.{}", format.extension()))
+        .mime_str(format.mime())?;
+    let form = reqwest::multipart::Form::new()
+        .text("model", state.model.clone())
+        .text("prompt", prompt)
+        .text("size", size)
+        .text("quality", quality)
+        .text("n", "1")
+        .part("image", image_part);
+    let resp = state
+        .http
+        .post(format!("{}/images/edits", state.openai_base_url))
+        .bearer_auth(&state.openai_api_key)
+        .multipart(form)
+        .send()
+        .await
+        .context("failed to call OpenAI API")?;
+    decode_openai_image_response(resp).await
+}
 
+async fn decode_openai_image_response(
+    resp: reqwest::Response,
+) -> Result<(String, Option<String>, Option<GenerationUsage>)> {
     let status = resp.status();
     let bytes = resp
         .bytes()
@@ -270,6 +481,84 @@ async fn generate_openai_image(
     Ok((b64, first.revised_prompt, usage))
 }
 
+#[derive(Debug, Clone, Copy)]
+enum SourceFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl SourceFormat {
+    /// Sniffs the formats `images/edits` accepts from their magic bytes.
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(Self::Webp)
+        } else {
+            None
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+fn cache_key(model: &str, size: &str, quality: &str, prompt: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [model, size, quality, prompt] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// A cache entry younger than `ttl`; missing, expired or unreadable entries are misses.
+async fn read_cache(path: &Path, ttl: Duration) -> Option<CachedImage> {
+    let modified = tokio::fs::metadata(path).await.ok()?.modified().ok()?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    if age > ttl {
+        return None;
+    }
+    let bytes = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+async fn write_cache(path: &Path, entry: &CachedImage) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .context("failed to create cache dir")?;
+    }
+    // Write then rename so a concurrent reader never sees a half-written entry.
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(entry)?)
+        .await
+        .context("failed to write cache entry")?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .context("failed to move cache entry into place")?;
+    Ok(())
+}
+
+#[allow(clippy::result_large_err)]
 fn require_auth(state: &AppState, headers: &HeaderMap) -> Result<(), Response> {
     let Some(expected) = &state.api_token else {
         return Ok(());
@@ -0,0 +1,194 @@
+//! Request bodies of printerd's render API. printerd reads them, and funnyprint-cli
+//! `--via-printerd` and the Telegram bot send them, so the three cannot drift apart.
+//!
+//! Unset fields take printerd's defaults.
+
+use serde::{Deserialize, Serialize};
+
+/// Font fields of a render request: printerd `--font` names or paths on its host. With neither
+/// `font` nor `font_path`, printerd uses its `--default-font`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FontSelection {
+    /// A `--font` name; alternative to `font_path`.
+    pub font: Option<String>,
+    pub font_path: Option<String>,
+    #[serde(default)]
+    pub fallback_fonts: Vec<String>,
+    #[serde(default)]
+    pub fallback_font_paths: Vec<String>,
+}
+
+/// `POST /api/v1/renders/text`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RenderTextRequest {
+    pub text: String,
+    #[serde(flatten)]
+    pub fonts: FontSelection,
+    pub width_px: Option<u32>,
+    pub height_px: Option<u32>,
+    pub x_px: Option<i32>,
+    pub y_px: Option<i32>,
+    pub font_size_px: Option<f32>,
+    pub line_spacing: Option<f32>,
+    pub threshold: Option<u8>,
+    pub invert: Option<bool>,
+    pub invert_content_only: Option<bool>,
+    pub trim_blank_top_bottom: Option<bool>,
+    /// Blank rows kept above and below the content, whatever `trim_blank_top_bottom` says.
+    pub blank_margin_px: Option<u32>,
+    pub outline_only: Option<bool>,
+    pub outline_thickness_px: Option<u32>,
+    /// `none` (default), `dots` or `lines`: light pattern behind the text. Turns off blank trimming.
+    pub background: Option<String>,
+    pub banner_mode: Option<bool>,
+    /// `cw` or `ccw`: turn the sticker so lines run along the paper feed; `width_px` is then the
+    /// label length and `height_px` the printed width. `banner_mode` implies `cw`.
+    pub rotate_90: Option<String>,
+    /// Mirror the finished sticker left to right (after `rotate_90`), for iron-on transfers and
+    /// window stickers read from the other side.
+    pub mirror_h: Option<bool>,
+    pub density: Option<u8>,
+    /// Reject `/print` with another density; default false.
+    pub lock_density: Option<bool>,
+    pub address: Option<String>,
+    pub preview_scale: Option<u32>,
+    pub include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    pub ephemeral: Option<bool>,
+    pub border_px: Option<u32>,
+    pub border_margin_px: Option<u32>,
+}
+
+/// `POST /api/v1/renders/image`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RenderImageRequest {
+    pub image_base64: String,
+    /// Keep the uploaded bytes so `/renders/{id}/rerender` can reprocess them without a new upload.
+    pub keep_source: Option<bool>,
+    #[serde(flatten)]
+    pub params: ImageRenderParams,
+}
+
+/// Processing options shared by `/renders/image` and `/renders/{id}/rerender`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageRenderParams {
+    /// Turn the image upright by its EXIF orientation before anything else; default true.
+    pub auto_orient: Option<bool>,
+    pub grayscale_mode: Option<GrayscaleMode>,
+    pub width_px: Option<u32>,
+    pub max_height_px: Option<u32>,
+    pub scale_mode: Option<ScaleMode>,
+    /// Source resolution for `scale_mode: "dpi"`, overriding the file's own metadata.
+    pub source_dpi: Option<f32>,
+    pub resize_filter: Option<ResizeFilter>,
+    pub sharpen: Option<f32>,
+    pub threshold: Option<u8>,
+    pub dither_method: Option<DitherMethod>,
+    pub serpentine: Option<bool>,
+    pub error_clamp: Option<f32>,
+    pub invert: Option<bool>,
+    pub trim_blank_top_bottom: Option<bool>,
+    /// Blank rows kept above and below the content, whatever `trim_blank_top_bottom` says.
+    pub blank_margin_px: Option<u32>,
+    pub tile_height_px: Option<u32>,
+    pub mirror_h: Option<bool>,
+    pub density: Option<u8>,
+    /// Reject `/print` with another density; default false.
+    pub lock_density: Option<bool>,
+    pub address: Option<String>,
+    pub preview_scale: Option<u32>,
+    pub include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    pub ephemeral: Option<bool>,
+    pub border_px: Option<u32>,
+    pub border_margin_px: Option<u32>,
+    /// Also keep the resized grayscale image before dithering, served at
+    /// `/renders/{id}/source` (or inline as `source_png_base64` when ephemeral).
+    pub keep_gray_preview: Option<bool>,
+}
+
+/// `POST /api/v1/measure/text`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MeasureTextRequest {
+    pub text: String,
+    #[serde(flatten)]
+    pub fonts: FontSelection,
+    /// Largest size to try.
+    pub font_size_px: Option<f32>,
+    pub min_font_size_px: Option<f32>,
+    pub line_spacing: Option<f32>,
+    pub max_width_px: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherMethod {
+    Threshold,
+    FloydSteinberg,
+}
+
+/// Filter for scaling an image to the print width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    /// Smooth; best for photos.
+    #[default]
+    Lanczos,
+    Catmull,
+    Triangle,
+    /// Keeps hard edges of line art and logos instead of blurring them into gray.
+    Nearest,
+}
+
+/// How an image's width on the tape is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleMode {
+    /// Stretch or shrink to `width_px`.
+    #[default]
+    FillWidth,
+    /// Print at real size from the source resolution; `width_px` is only the cap.
+    Dpi,
+}
+
+/// How a color source is reduced to one channel before resize and dithering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum GrayscaleMode {
+    Luma,
+    Max,
+    Min,
+    Channel(usize),
+}
+
+impl TryFrom<String> for GrayscaleMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "luma" => Ok(Self::Luma),
+            "max" => Ok(Self::Max),
+            "min" => Ok(Self::Min),
+            "channel:r" => Ok(Self::Channel(0)),
+            "channel:g" => Ok(Self::Channel(1)),
+            "channel:b" => Ok(Self::Channel(2)),
+            other => Err(format!(
+                "unknown grayscale_mode {other:?}, expected luma|max|min|channel:r|channel:g|channel:b"
+            )),
+        }
+    }
+}
+
+impl From<GrayscaleMode> for String {
+    fn from(mode: GrayscaleMode) -> Self {
+        match mode {
+            GrayscaleMode::Luma => "luma",
+            GrayscaleMode::Max => "max",
+            GrayscaleMode::Min => "min",
+            GrayscaleMode::Channel(0) => "channel:r",
+            GrayscaleMode::Channel(1) => "channel:g",
+            GrayscaleMode::Channel(_) => "channel:b",
+        }
+        .to_string()
+    }
+}
@@ -1,9 +1,34 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::{Context, Result, bail};
-use clap::{Parser, Subcommand};
-use funnyprint_proto::{MAX_DOTS_PER_LINE, discover_candidates, dpi, print_job};
-use funnyprint_render::{TextRenderOptions, image_to_packed_lines, px_to_mm, render_text_to_image};
+use base64::Engine;
+use chrono_tz::Tz;
+use clap::{Args, Parser, Subcommand};
+use funnyprint_api as api;
+use funnyprint_proto::{
+    AckMode, DEFAULT_TAIL_FEED_MM, MAX_DOTS_PER_LINE, MAX_TAIL_FEED_MM, PackedLine, PrintTuning,
+    PrinterSession, discover_candidates, dpi, mm_to_packed_lines, packed_lines_mm,
+    print_job_with_tuning,
+};
+use funnyprint_render::{
+    Barcode, BarcodeOptions, DitherOptions, FontChain, Rotate90, RulerOptions, TextBackground,
+    TextRenderOptions, border_fits, draw_border, fit_font_size, fit_font_size_rotated,
+    flatten_on_white, floyd_steinberg_binarize, format_now, image_to_packed_lines, px_to_mm,
+    render_barcode_to_image, render_ruler, render_text_to_image, threshold_binarize,
+    trim_blank_lines,
+};
+use image::{DynamicImage, GrayImage, imageops::FilterType};
+use remote::{Printerd, RenderResponse};
+
+mod remote;
+
+/// Printer names `--address` accepts, as `front=AA:BB:CC:DD:EE:FF,back=...`.
+const PRINTERS_ENV: &str = "FUNNYPRINT_PRINTERS";
 
 #[derive(Debug, Parser)]
 #[command(name = "funnyprint")]
@@ -18,126 +43,836 @@ enum Command {
     Scan {
         #[arg(long, default_value_t = 2)]
         seconds: u64,
+        /// Only list devices whose name starts with this (case-insensitive), e.g. `MX`.
+        #[arg(long)]
+        prefix: Option<String>,
     },
     PrintText {
-        #[arg(long)]
-        address: String,
         #[arg(long)]
         text: String,
+        /// Font for the text; optional with `--via-printerd`, which falls back to its default font.
+        #[arg(long, required_unless_present = "via_printerd")]
+        font: Option<PathBuf>,
+        #[command(flatten)]
+        opts: TextArgs,
+    },
+    /// Print an image file, scaled to `--width` and dithered to black and white.
+    PrintImage {
+        #[arg(long)]
+        file: PathBuf,
+        #[command(flatten)]
+        opts: TextArgs,
+    },
+    /// Print text read from stdin, or an image with `--image-stdin`.
+    PrintStdin {
+        #[arg(long, default_value_t = false)]
+        image_stdin: bool,
+        /// Font for the text; optional with `--via-printerd`, which falls back to its default font.
+        #[arg(long, required_unless_present_any = ["via_printerd", "image_stdin"])]
+        font: Option<PathBuf>,
+        #[command(flatten)]
+        opts: TextArgs,
+    },
+    /// Print one sticker per non-empty line of a text file, e.g. name badges or table numbers.
+    PrintBatch {
+        #[arg(long)]
+        file: PathBuf,
+        /// Only render every line and report the count and paper length.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
         #[arg(long)]
         font: PathBuf,
-        #[arg(long, default_value_t = 48.0)]
-        font_size: f32,
-        #[arg(long, default_value_t = 1.0)]
-        line_spacing: f32,
-        #[arg(long, default_value_t = 0)]
-        x: i32,
-        #[arg(long, default_value_t = 0)]
-        y: i32,
-        #[arg(long, default_value_t = MAX_DOTS_PER_LINE as u32)]
-        width: u32,
-        #[arg(long, default_value_t = 192)]
-        height: u32,
-        #[arg(long, default_value_t = 180)]
-        threshold: u8,
-        #[arg(long, default_value_t = 3)]
-        density: u8,
+        #[command(flatten)]
+        opts: TextArgs,
+    },
+    /// Print the current date and time, e.g. as a "made on" label.
+    PrintTime {
+        #[arg(long, default_value = "%Y-%m-%d %H:%M")]
+        format: String,
+        #[arg(long)]
+        timezone: Option<Tz>,
+        /// Font for the text; optional with `--via-printerd`, which falls back to its default font.
+        #[arg(long, required_unless_present = "via_printerd")]
+        font: Option<PathBuf>,
+        #[command(flatten)]
+        opts: TextArgs,
+    },
+    PrintBarcode {
+        /// Printer MAC or `FUNNYPRINT_PRINTERS` name.
+        #[arg(long)]
+        address: String,
+        #[arg(long)]
+        data: String,
+        #[arg(long, default_value = "code128")]
+        symbology: Barcode,
+        #[arg(long, default_value_t = 80)]
+        bar_height: u32,
+        #[arg(long, default_value_t = 2)]
+        module_px: u32,
+        #[arg(long, default_value_t = 10)]
+        quiet_zone: u32,
+        #[arg(long)]
+        text_font: Option<PathBuf>,
+        #[arg(long, default_value_t = 24.0)]
+        text_size: f32,
+        /// Print density 0..=7; defaults to the printer model's.
+        #[arg(long)]
+        density: Option<u8>,
         #[arg(long, default_value = "preview.png")]
         preview: PathBuf,
         #[arg(long, default_value_t = false)]
-        invert: bool,
+        preview_only: bool,
         #[arg(long, default_value_t = false)]
-        no_trim_blank: bool,
+        mirror_h: bool,
+        /// Blank paper fed after the barcode so it clears the tear bar; 0 disables it.
+        #[arg(long, default_value_t = DEFAULT_TAIL_FEED_MM, value_parser = parse_tail_feed_mm)]
+        tail_feed_mm: f32,
+    },
+    /// Print a millimetre ruler along the paper feed, to check the printer really prints at 203
+    /// dpi and to line up die-cut labels.
+    PrintRuler {
+        /// Printer MAC or `FUNNYPRINT_PRINTERS` name.
+        #[arg(long)]
+        address: String,
+        #[arg(long, default_value_t = 100.0)]
+        mm: f32,
+        /// Number every 10 mm tick with this font; without it the ruler has ticks only.
+        #[arg(long)]
+        label_font: Option<PathBuf>,
+        #[arg(long, default_value_t = 24.0)]
+        label_size: f32,
+        /// Print density 0..=7; defaults to the printer model's.
+        #[arg(long)]
+        density: Option<u8>,
+        #[arg(long, default_value = "preview.png")]
+        preview: PathBuf,
         #[arg(long, default_value_t = false)]
         preview_only: bool,
+        /// Blank paper fed after the ruler so it clears the tear bar; 0 disables it.
+        #[arg(long, default_value_t = DEFAULT_TAIL_FEED_MM, value_parser = parse_tail_feed_mm)]
+        tail_feed_mm: f32,
     },
 }
 
+#[derive(Debug, Args)]
+struct TextArgs {
+    /// Printer to use, a MAC or a `FUNNYPRINT_PRINTERS` name; with `--via-printerd` it defaults to
+    /// printerd's `--default-address` and may also be one of printerd's `--printer` names.
+    #[arg(long, required_unless_present = "via_printerd")]
+    address: Option<String>,
+    /// Render and print through the printerd at this URL (e.g. `http://pi:8080`) instead of over
+    /// BLE. `--font` is then a path on the printerd host.
+    #[arg(long, value_name = "URL")]
+    via_printerd: Option<String>,
+    /// `x-api-token` for `--via-printerd`.
+    #[arg(long)]
+    printerd_token: Option<String>,
+    #[arg(long = "fallback-font")]
+    fallback_fonts: Vec<PathBuf>,
+    #[arg(long, default_value_t = 48.0)]
+    font_size: f32,
+    #[arg(long, default_value_t = 1.0)]
+    line_spacing: f32,
+    #[arg(long, default_value_t = 0)]
+    x: i32,
+    #[arg(long, default_value_t = 0)]
+    y: i32,
+    #[arg(long, default_value_t = MAX_DOTS_PER_LINE as u32)]
+    width: u32,
+    #[arg(long, default_value_t = 192)]
+    height: u32,
+    #[arg(long, default_value_t = 180)]
+    threshold: u8,
+    /// How images are reduced to black and white: `floyd_steinberg` (error diffusion) or
+    /// `threshold`. Text is always thresholded.
+    #[arg(long, default_value = "floyd_steinberg")]
+    dither_method: DitherMethod,
+    /// Diffuse error left to right on every row instead of alternating direction.
+    #[arg(long, default_value_t = false)]
+    no_serpentine: bool,
+    /// Cap the error each pixel passes on, e.g. 32 for dense photos; unset is plain Floyd–Steinberg.
+    #[arg(long)]
+    error_clamp: Option<f32>,
+    /// Print density 0..=7; defaults to the printer model's (with `--via-printerd`, printerd's
+    /// `--default-density` for that printer).
+    #[arg(long)]
+    density: Option<u8>,
+    #[arg(long, default_value = "preview.png")]
+    preview: PathBuf,
+    #[arg(long, default_value_t = false)]
+    invert: bool,
+    #[arg(long, default_value_t = false)]
+    no_trim_blank: bool,
+    /// Keep up to this many blank rows above and below the content instead of trimming them all.
+    #[arg(long)]
+    blank_margin_px: Option<u32>,
+    #[arg(long, default_value_t = false)]
+    preview_only: bool,
+    #[arg(long, default_value_t = false)]
+    adaptive_density: bool,
+    /// Reconnect and continue if the BLE link drops mid-print.
+    #[arg(long, default_value_t = false)]
+    resume_on_disconnect: bool,
+    /// `wait_ack` sends each line once the printer stays quiet about the previous one for a short
+    /// window, instead of after a fixed delay.
+    #[arg(long, default_value = "fire_and_forget")]
+    ack_mode: AckMode,
+    #[arg(long, default_value_t = 0)]
+    border_px: u32,
+    #[arg(long, default_value_t = 0)]
+    border_margin_px: u32,
+    #[arg(long, default_value_t = false)]
+    fit_width: bool,
+    /// Turn the sticker a quarter turn (`cw` or `ccw`) so text runs along the paper feed; `--width`
+    /// is then the label length and `--height` the printed width.
+    #[arg(long = "rotate-90")]
+    rotate_90: Option<Rotate90>,
+    /// Mirror the sticker left to right (after `--rotate-90`), for iron-on and window media.
+    #[arg(long, default_value_t = false)]
+    mirror_h: bool,
+    /// Light pattern behind text stickers: `none`, `dots` or `lines`. It fills the whole sticker,
+    /// so blank rows are not trimmed.
+    #[arg(long, default_value = "none")]
+    background: TextBackground,
+    /// Blank paper fed after each sticker so it clears the tear bar; 0 disables it.
+    #[arg(long, default_value_t = DEFAULT_TAIL_FEED_MM, value_parser = parse_tail_feed_mm)]
+    tail_feed_mm: f32,
+}
+
+/// `--dither-method`, as printerd's `dither_method`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DitherMethod {
+    FloydSteinberg,
+    Threshold,
+}
+
+impl From<DitherMethod> for api::DitherMethod {
+    fn from(method: DitherMethod) -> Self {
+        match method {
+            DitherMethod::FloydSteinberg => Self::FloydSteinberg,
+            DitherMethod::Threshold => Self::Threshold,
+        }
+    }
+}
+
+impl FromStr for DitherMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "floyd_steinberg" => Ok(Self::FloydSteinberg),
+            "threshold" => Ok(Self::Threshold),
+            other => Err(format!(
+                "unknown dither method {other:?}, expected floyd_steinberg|threshold"
+            )),
+        }
+    }
+}
+
+/// `--tail-feed-mm`, limited to what printerd accepts.
+fn parse_tail_feed_mm(s: &str) -> Result<f32, String> {
+    let mm: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=MAX_TAIL_FEED_MM).contains(&mm) {
+        return Err(format!("must be in 0..={MAX_TAIL_FEED_MM}"));
+    }
+    Ok(mm)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Command::Scan { seconds } => {
-            let found = discover_candidates(Duration::from_secs(seconds)).await?;
+        Command::Scan { seconds, prefix } => {
+            let found =
+                discover_candidates(Duration::from_secs(seconds), prefix.as_deref()).await?;
             if found.is_empty() {
                 println!("No candidate devices found");
             } else {
                 for p in found {
+                    let model = p.guessed_model.map_or_else(
+                        || "-".to_string(),
+                        |m| format!("{} ({} dots)", m.as_str(), m.dots_per_line()),
+                    );
                     println!(
-                        "{}\t{}",
+                        "{}\t{}\t{}",
                         p.address,
-                        p.local_name.unwrap_or_else(|| "<unknown>".to_string())
+                        p.local_name.unwrap_or_else(|| "<unknown>".to_string()),
+                        model
                     );
                 }
             }
         }
-        Command::PrintText {
-            address,
-            text,
+        Command::PrintText { text, font, opts } => {
+            print_text_sticker(&text, font.as_deref(), &opts).await?
+        }
+        Command::PrintImage { file, opts } => {
+            let bytes = std::fs::read(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            print_image_sticker(&bytes, &opts).await?;
+        }
+        Command::PrintStdin {
+            image_stdin,
+            font,
+            opts,
+        } => {
+            let mut input = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut input)
+                .context("failed to read stdin")?;
+
+            if image_stdin {
+                print_image_sticker(&input, &opts).await?;
+            } else {
+                let text = String::from_utf8(input).context("stdin is not valid UTF-8")?;
+                print_text_sticker(text.trim_end_matches('\n'), font.as_deref(), &opts).await?;
+            }
+        }
+        Command::PrintBatch {
+            file,
+            dry_run,
             font,
-            font_size,
-            line_spacing,
-            x,
-            y,
-            width,
-            height,
-            threshold,
+            opts,
+        } => {
+            if opts.via_printerd.is_some() {
+                bail!("print-batch prints over BLE only; drop --via-printerd");
+            }
+            print_batch(&file, dry_run, &font, &opts).await?
+        }
+        Command::PrintTime {
+            format,
+            timezone,
+            font,
+            opts,
+        } => {
+            let text = format_now(&format, timezone).context("invalid --format")?;
+            println!("Time label: {text}");
+            print_text_sticker(&text, font.as_deref(), &opts).await?;
+        }
+        Command::PrintBarcode {
+            address,
+            data,
+            symbology,
+            bar_height,
+            module_px,
+            quiet_zone,
+            text_font,
+            text_size,
             density,
             preview,
-            invert,
-            no_trim_blank,
             preview_only,
+            mirror_h,
+            tail_feed_mm,
         } => {
-            if width as usize > MAX_DOTS_PER_LINE {
-                bail!(
-                    "width {} exceeds printer max {} dots ({} dpi)",
-                    width,
-                    MAX_DOTS_PER_LINE,
-                    dpi()
-                );
+            let opts = BarcodeOptions {
+                bar_height_px: bar_height,
+                module_px,
+                quiet_zone_modules: quiet_zone,
+                human_readable_font: text_font,
+                text_size_px: text_size,
+            };
+            let mut img = render_barcode_to_image(symbology, &data, &opts)?;
+            if mirror_h {
+                image::imageops::flip_horizontal_in_place(&mut img);
             }
-
-            let opts = TextRenderOptions {
-                width_px: width,
-                height_px: height,
-                x_px: x,
-                y_px: y,
-                font_size_px: font_size,
-                line_spacing,
-                threshold,
-                invert,
-                trim_blank_top_bottom: !no_trim_blank,
+            let packed = image_to_packed_lines(&img, 127, true);
+            save_preview(&img, &packed, &preview)?;
+            if preview_only {
+                return Ok(());
+            }
+            let tuning = PrintTuning {
+                tail_feed_lines: mm_to_packed_lines(tail_feed_mm),
+                ..PrintTuning::default()
             };
-
-            let img = render_text_to_image(&text, &font, &opts)?;
-            img.save(&preview)
-                .with_context(|| format!("failed to save preview PNG to {}", preview.display()))?;
-
-            let packed = image_to_packed_lines(&img, threshold, opts.trim_blank_top_bottom);
-            println!(
-                "Preview saved: {} ({}x{} px, {:.2}x{:.2} mm at {} dpi, {} packed lines)",
-                preview.display(),
-                img.width(),
-                img.height(),
-                px_to_mm(img.width(), dpi()),
-                px_to_mm(img.height(), dpi()),
-                dpi(),
-                packed.len()
-            );
-
+            print_packed(&resolve_address(&address)?, &packed, density, &tuning).await?;
+        }
+        Command::PrintRuler {
+            address,
+            mm,
+            label_font,
+            label_size,
+            density,
+            preview,
+            preview_only,
+            tail_feed_mm,
+        } => {
+            let opts = RulerOptions {
+                label_font,
+                text_size_px: label_size,
+            };
+            let img = render_ruler(mm, &opts)?;
+            // Untrimmed, so the first tick is the first row printed.
+            let packed = image_to_packed_lines(&img, 127, false);
+            save_preview(&img, &packed, &preview)?;
             if preview_only {
                 return Ok(());
             }
+            let tuning = PrintTuning {
+                tail_feed_lines: mm_to_packed_lines(tail_feed_mm),
+                ..PrintTuning::default()
+            };
+            print_packed(&resolve_address(&address)?, &packed, density, &tuning).await?;
+        }
+    }
 
+    Ok(())
+}
+
+impl TextArgs {
+    fn pack(&self, img: &GrayImage, threshold: u8) -> Vec<PackedLine> {
+        let packed = image_to_packed_lines(img, threshold, false);
+        match self.blank_margin_px {
+            Some(margin) => trim_blank_lines(packed, margin),
+            None if !self.no_trim_blank => trim_blank_lines(packed, 0),
+            None => packed,
+        }
+    }
+
+    /// [`Self::pack`] for a text sticker, whose `--background` leaves no blank rows to trim.
+    fn pack_text(&self, img: &GrayImage) -> Vec<PackedLine> {
+        if self.background != TextBackground::None {
+            return image_to_packed_lines(img, self.threshold, false);
+        }
+        self.pack(img, self.threshold)
+    }
+
+    async fn preview_and_print(&self, img: &GrayImage, packed: &[PackedLine]) -> Result<()> {
+        save_preview(img, packed, &self.preview)?;
+        if self.preview_only {
+            return Ok(());
+        }
+
+        print_packed(&self.ble_address()?, packed, self.density, &self.tuning()).await
+    }
+
+    /// [`Self::preview_and_print`] for a render made by printerd.
+    async fn preview_and_print_remote(
+        &self,
+        printerd: &Printerd,
+        render: RenderResponse,
+    ) -> Result<()> {
+        let png = printerd.preview_png(&render).await?;
+        std::fs::write(&self.preview, png)
+            .with_context(|| format!("failed to save preview PNG to {}", self.preview.display()))?;
+        report_preview(
+            &self.preview,
+            render.width_px,
+            render.height_px,
+            render.packed_lines,
+        );
+        if self.preview_only {
+            return Ok(());
+        }
+
+        let job = printerd
+            .print(
+                &render,
+                self.remote_address()?.as_deref(),
+                self.density,
+                self.tail_feed_mm,
+            )
+            .await?;
+        let (throttled, resumes) = job
+            .report
+            .map_or((false, 0), |report| (report.throttled, report.resumes));
+        report_print(&job.address, throttled, resumes);
+        Ok(())
+    }
+
+    fn printerd(&self) -> Option<Printerd> {
+        let url = self.via_printerd.as_deref()?;
+        Some(Printerd::new(url, self.printerd_token.clone()))
+    }
+
+    fn ble_address(&self) -> Result<String> {
+        let address = self
+            .address
+            .as_deref()
+            .context("--address is required without --via-printerd")?;
+        resolve_address(address)
+    }
+
+    /// `--address` for printerd, which resolves its own printer names.
+    fn remote_address(&self) -> Result<Option<String>> {
+        self.address.as_deref().map(resolve_address).transpose()
+    }
+
+    fn fallback_font_paths(&self) -> Vec<String> {
+        self.fallback_fonts
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn tuning(&self) -> PrintTuning {
+        PrintTuning {
+            adaptive_density: self.adaptive_density,
+            resume_on_disconnect: self.resume_on_disconnect,
+            ack_mode: self.ack_mode,
+            tail_feed_lines: mm_to_packed_lines(self.tail_feed_mm),
+            ..PrintTuning::default()
+        }
+    }
+}
+
+/// Renders and prints a text sticker, here or on `--via-printerd`.
+async fn print_text_sticker(text: &str, font: Option<&Path>, opts: &TextArgs) -> Result<()> {
+    let Some(printerd) = opts.printerd() else {
+        let font = font.expect("clap requires --font without --via-printerd");
+        let img = render_text(text, font, opts)?;
+        let packed = opts.pack_text(&img);
+        return opts.preview_and_print(&img, &packed).await;
+    };
+
+    let fonts = api::FontSelection {
+        font_path: font.map(|p| p.to_string_lossy().into_owned()),
+        fallback_font_paths: opts.fallback_font_paths(),
+        ..Default::default()
+    };
+    let font_size = if opts.fit_width {
+        if opts.rotate_90.is_some() {
+            bail!("--fit-width with --rotate-90 needs local fonts; drop --via-printerd");
+        }
+        let req = api::MeasureTextRequest {
+            text: text.to_string(),
+            fonts: fonts.clone(),
+            font_size_px: Some(opts.font_size),
+            min_font_size_px: Some(4.0),
+            line_spacing: Some(opts.line_spacing),
+            max_width_px: Some(opts.width.saturating_sub(opts.x.max(0) as u32)),
+        };
+        let size = printerd.fit_font_size(&req).await?;
+        println!("Fitted font size: {size:.1}px (max {})", opts.font_size);
+        size
+    } else {
+        opts.font_size
+    };
+    let req = api::RenderTextRequest {
+        text: text.to_string(),
+        fonts,
+        width_px: Some(opts.width),
+        height_px: Some(opts.height),
+        x_px: Some(opts.x),
+        y_px: Some(opts.y),
+        font_size_px: Some(font_size),
+        line_spacing: Some(opts.line_spacing),
+        threshold: Some(opts.threshold),
+        invert: Some(opts.invert),
+        trim_blank_top_bottom: Some(!opts.no_trim_blank),
+        blank_margin_px: opts.blank_margin_px,
+        background: match opts.background {
+            TextBackground::None => None,
+            TextBackground::Dots => Some("dots".to_string()),
+            TextBackground::Lines => Some("lines".to_string()),
+        },
+        rotate_90: opts.rotate_90.map(|r| {
+            match r {
+                Rotate90::Cw => "cw",
+                Rotate90::Ccw => "ccw",
+            }
+            .to_string()
+        }),
+        mirror_h: Some(opts.mirror_h),
+        border_px: Some(opts.border_px),
+        border_margin_px: Some(opts.border_margin_px),
+        density: opts.density,
+        address: opts.remote_address()?,
+        ephemeral: Some(opts.preview_only),
+        ..Default::default()
+    };
+    let render = printerd.render_text(&req).await?;
+    opts.preview_and_print_remote(&printerd, render).await
+}
+
+/// Dithers and prints an image, here or on `--via-printerd`.
+async fn print_image_sticker(bytes: &[u8], opts: &TextArgs) -> Result<()> {
+    let Some(printerd) = opts.printerd() else {
+        // Already black and white after dithering.
+        let img = render_image(bytes, opts)?;
+        let packed = opts.pack(&img, 127);
+        return opts.preview_and_print(&img, &packed).await;
+    };
+
+    let req = api::RenderImageRequest {
+        image_base64: base64::engine::general_purpose::STANDARD.encode(bytes),
+        keep_source: None,
+        params: api::ImageRenderParams {
+            width_px: Some(opts.width),
+            threshold: Some(opts.threshold),
+            dither_method: Some(opts.dither_method.into()),
+            serpentine: Some(!opts.no_serpentine),
+            error_clamp: opts.error_clamp,
+            invert: Some(opts.invert),
+            trim_blank_top_bottom: Some(!opts.no_trim_blank),
+            blank_margin_px: opts.blank_margin_px,
+            mirror_h: Some(opts.mirror_h),
+            border_px: Some(opts.border_px),
+            border_margin_px: Some(opts.border_margin_px),
+            density: opts.density,
+            address: opts.remote_address()?,
+            ephemeral: Some(opts.preview_only),
+            ..Default::default()
+        },
+    };
+    let render = printerd.render_image(&req).await?;
+    opts.preview_and_print_remote(&printerd, render).await
+}
+
+fn render_text(text: &str, font: &Path, args: &TextArgs) -> Result<GrayImage> {
+    let (width, height) = (args.width, args.height);
+    let (across_name, across) = match args.rotate_90 {
+        Some(_) => ("height", height),
+        None => ("width", width),
+    };
+    if across as usize > MAX_DOTS_PER_LINE {
+        bail!(
+            "{} {} exceeds printer max {} dots ({} dpi)",
+            across_name,
+            across,
+            MAX_DOTS_PER_LINE,
+            dpi()
+        );
+    }
+    let (border_px, border_margin_px) = (args.border_px, args.border_margin_px);
+    if !border_fits(width, height, border_px, border_margin_px) {
+        bail!(
+            "border of {border_px}px with {border_margin_px}px margin does not fit {width}x{height}"
+        );
+    }
+
+    let font_size = if args.fit_width {
+        let fonts = FontChain::load(font, &args.fallback_fonts)?;
+        let avail = width as f32 - args.x.max(0) as f32;
+        let (size, _) = match args.rotate_90 {
+            // Rotated, the lines also have to fit across the paper.
+            Some(_) => fit_font_size_rotated(
+                &fonts,
+                text,
+                avail,
+                height as f32 - args.y.max(0) as f32,
+                4.0,
+                args.font_size,
+                args.line_spacing,
+            )?,
+            None => fit_font_size(&fonts, text, avail, 4.0, args.font_size, args.line_spacing)?,
+        };
+        println!("Fitted font size: {size:.1}px (max {})", args.font_size);
+        size
+    } else {
+        args.font_size
+    };
+
+    let opts = TextRenderOptions {
+        width_px: width,
+        height_px: height,
+        x_px: args.x,
+        y_px: args.y,
+        font_size_px: font_size,
+        line_spacing: args.line_spacing,
+        threshold: args.threshold,
+        invert: args.invert,
+        trim_blank_top_bottom: !args.no_trim_blank,
+        background: args.background,
+        fallback_font_paths: args.fallback_fonts.clone(),
+        border_px,
+        border_margin_px,
+        rotate_90: args.rotate_90,
+        mirror_h: args.mirror_h,
+        ..TextRenderOptions::default()
+    };
+    render_text_to_image(text, font, &opts)
+}
+
+/// Scales an image to `--width` (keeping the aspect ratio) and dithers it to black and white.
+fn render_image(bytes: &[u8], args: &TextArgs) -> Result<GrayImage> {
+    if args.width == 0 || args.width as usize > MAX_DOTS_PER_LINE {
+        bail!("width must be in 1..={MAX_DOTS_PER_LINE}");
+    }
+    if args
+        .error_clamp
+        .is_some_and(|c| !(c.is_finite() && c > 0.0))
+    {
+        bail!("--error-clamp must be > 0");
+    }
+    let img = image::load_from_memory(bytes).context("not a supported image")?;
+    let img = DynamicImage::ImageRgb8(flatten_on_white(&img));
+    let gray = img.to_luma8();
+    let height = ((gray.height() as f32 * args.width as f32) / gray.width().max(1) as f32)
+        .round()
+        .max(1.0) as u32;
+    let resized = image::imageops::resize(&gray, args.width, height, FilterType::Lanczos3);
+
+    let mut bw = match args.dither_method {
+        DitherMethod::FloydSteinberg => floyd_steinberg_binarize(
+            &resized,
+            args.threshold,
+            args.invert,
+            &DitherOptions {
+                serpentine: !args.no_serpentine,
+                error_clamp: args.error_clamp,
+            },
+        ),
+        DitherMethod::Threshold => threshold_binarize(&resized, args.threshold, args.invert),
+    };
+    if !draw_border(&mut bw, args.border_px, args.border_margin_px) {
+        bail!(
+            "border of {}px with {}px margin does not fit {}x{}",
+            args.border_px,
+            args.border_margin_px,
+            bw.width(),
+            bw.height()
+        );
+    }
+    if args.mirror_h {
+        image::imageops::flip_horizontal_in_place(&mut bw);
+    }
+    Ok(bw)
+}
+
+fn save_preview(img: &GrayImage, packed: &[PackedLine], preview: &Path) -> Result<()> {
+    img.save(preview)
+        .with_context(|| format!("failed to save preview PNG to {}", preview.display()))?;
+    report_preview(preview, img.width(), img.height(), packed.len());
+    Ok(())
+}
+
+fn report_preview(preview: &Path, width: u32, height: u32, packed_lines: usize) {
+    println!(
+        "Preview saved: {} ({}x{} px, {:.2}x{:.2} mm at {} dpi, {} packed lines)",
+        preview.display(),
+        width,
+        height,
+        px_to_mm(width, dpi()),
+        px_to_mm(height, dpi()),
+        dpi(),
+        packed_lines
+    );
+}
+
+/// Renders each non-empty line of `file` as a sticker and prints them over one printer connection,
+/// reconnecting after a failed sticker. Fails at the end if any line failed.
+async fn print_batch(file: &Path, dry_run: bool, font: &Path, opts: &TextArgs) -> Result<()> {
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("failed to read {}", file.display()))?;
+    let lines: Vec<(usize, &str)> = content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    if lines.is_empty() {
+        bail!("{} has no non-empty lines", file.display());
+    }
+
+    let tuning = opts.tuning();
+    let mut session: Option<PrinterSession> = None;
+    let mut total_lines = 0usize;
+    let mut failures = Vec::new();
+    for &(line_no, text) in &lines {
+        let result = async {
+            let img = render_text(text, font, opts)?;
+            let packed = opts.pack_text(&img);
             if packed.is_empty() {
-                bail!("image became empty after trimming blank lines; nothing to print")
+                bail!("sticker is blank");
+            }
+            // The tail feed leaves room to tear each sticker off before the next one.
+            total_lines += packed.len() + tuning.tail_feed_lines;
+            if dry_run || opts.preview_only {
+                return Ok(());
             }
+            let printer = match &mut session {
+                Some(printer) => printer,
+                None => {
+                    session.insert(PrinterSession::connect(&opts.ble_address()?, &tuning).await?)
+                }
+            };
+            let density = opts.density.unwrap_or_else(|| printer.default_density());
+            if let Err(err) = printer.print(&packed, density).await {
+                if let Some(printer) = session.take() {
+                    let _ = printer.disconnect().await;
+                }
+                return Err(err);
+            }
+            Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => println!("Line {line_no}: ok ({text})"),
+            Err(err) => {
+                println!("Line {line_no}: failed ({text}): {err:#}");
+                failures.push(line_no);
+            }
+        }
+    }
+    if let Some(printer) = session {
+        printer.disconnect().await?;
+    }
 
-            print_job(&address, &packed, density).await?;
-            println!("Print job sent to {}", address);
+    println!(
+        "{} of {} stickers {}, {:.0} mm of paper",
+        lines.len() - failures.len(),
+        lines.len(),
+        if dry_run || opts.preview_only {
+            "rendered"
+        } else {
+            "printed"
+        },
+        packed_lines_mm(total_lines)
+    );
+    if !failures.is_empty() {
+        bail!("failed lines: {failures:?}");
+    }
+    Ok(())
+}
+
+/// The MAC behind a `FUNNYPRINT_PRINTERS` name, or `address` itself when it names no printer.
+fn resolve_address(address: &str) -> Result<String> {
+    let address = address.trim();
+    let Ok(printers) = std::env::var(PRINTERS_ENV) else {
+        return Ok(address.to_string());
+    };
+    for entry in printers.split(',').filter(|e| !e.trim().is_empty()) {
+        let (name, mac) = entry
+            .split_once('=')
+            .with_context(|| format!("{PRINTERS_ENV}: expected NAME=ADDRESS, got {entry:?}"))?;
+        if name.trim() == address {
+            return Ok(mac.trim().to_string());
         }
     }
+    Ok(address.to_string())
+}
 
+async fn print_packed(
+    address: &str,
+    packed: &[PackedLine],
+    density: Option<u8>,
+    tuning: &PrintTuning,
+) -> Result<()> {
+    if packed.is_empty() {
+        bail!("image became empty after trimming blank lines; nothing to print")
+    }
+
+    let report = match density {
+        Some(density) => print_job_with_tuning(address, packed, density, tuning).await?,
+        // The model, and so its default density, is only known once connected.
+        None => {
+            let mut session = PrinterSession::connect(address, tuning).await?;
+            let density = session.default_density();
+            let report = session.print(packed, density).await;
+            session.disconnect().await?;
+            report?
+        }
+    };
+    report_print(address, report.throttled, report.resumes);
     Ok(())
 }
+
+fn report_print(address: &str, throttled: bool, resumes: u32) {
+    println!("Print job sent to {}", address);
+    if throttled {
+        println!("Density was lowered temporarily because the printer overheated");
+    }
+    if resumes > 0 {
+        println!("The link dropped and the job resumed {resumes} time(s)");
+    }
+}
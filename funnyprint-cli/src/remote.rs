@@ -0,0 +1,193 @@
+//! Printing through a running printerd instead of a direct BLE connection, for hosts that cannot
+//! reach the printer themselves.
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use funnyprint_api::{MeasureTextRequest, RenderImageRequest, RenderTextRequest};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Longest single `/jobs/{id}/wait` call printerd accepts.
+const WAIT_SECONDS: u64 = 120;
+
+#[derive(Debug, Deserialize)]
+struct MeasureTextResponse {
+    font_size_px: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenderResponse {
+    render_id: Option<String>,
+    preview_url: Option<String>,
+    preview_png_base64: Option<String>,
+    pub width_px: u32,
+    pub height_px: u32,
+    pub packed_lines: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PrintRequest<'a> {
+    render_id: &'a str,
+    address: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    density: Option<u8>,
+    tail_feed_mm: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrintResponse {
+    job_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    pub address: String,
+    status: String,
+    error: Option<String>,
+    pub report: Option<JobReport>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobReport {
+    pub throttled: bool,
+    pub resumes: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    error: String,
+}
+
+pub struct Printerd {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Printerd {
+    pub fn new(base_url: &str, token: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    /// Largest font size up to `req.font_size_px` at which the text fits `req.max_width_px`.
+    pub async fn fit_font_size(&self, req: &MeasureTextRequest) -> Result<f32> {
+        let resp: MeasureTextResponse = self.post("/api/v1/measure/text", req).await?;
+        Ok(resp.font_size_px)
+    }
+
+    pub async fn render_text(&self, req: &RenderTextRequest) -> Result<RenderResponse> {
+        self.post("/api/v1/renders/text", req).await
+    }
+
+    pub async fn render_image(&self, req: &RenderImageRequest) -> Result<RenderResponse> {
+        self.post("/api/v1/renders/image", req).await
+    }
+
+    pub async fn preview_png(&self, render: &RenderResponse) -> Result<Vec<u8>> {
+        if let Some(inline) = &render.preview_png_base64 {
+            return base64::engine::general_purpose::STANDARD
+                .decode(inline)
+                .context("printerd returned an invalid inline preview");
+        }
+        let path = render
+            .preview_url
+            .as_deref()
+            .context("printerd returned no preview")?;
+        let resp = self
+            .with_token(self.http.get(format!("{}{path}", self.base_url)))
+            .send()
+            .await
+            .context("preview request failed")?;
+        let resp = check_status(resp).await?;
+        Ok(resp
+            .bytes()
+            .await
+            .context("failed to read preview")?
+            .to_vec())
+    }
+
+    /// Queues `render` and waits until printerd finishes the job.
+    pub async fn print(
+        &self,
+        render: &RenderResponse,
+        address: Option<&str>,
+        density: Option<u8>,
+        tail_feed_mm: f32,
+    ) -> Result<Job> {
+        let render_id = render
+            .render_id
+            .as_deref()
+            .context("printerd did not keep the render to print")?;
+        let req = PrintRequest {
+            render_id,
+            address,
+            density,
+            tail_feed_mm,
+        };
+        let queued: PrintResponse = self.post("/api/v1/print", &req).await?;
+        println!("Print job {} queued on printerd", queued.job_id);
+
+        loop {
+            let url = format!(
+                "{}/api/v1/jobs/{}/wait?timeout_seconds={WAIT_SECONDS}",
+                self.base_url, queued.job_id
+            );
+            let resp = self
+                .with_token(self.http.get(url))
+                .send()
+                .await
+                .context("wait job request failed")?;
+            let job: Job = check_status(resp)
+                .await?
+                .json()
+                .await
+                .context("failed to decode printerd job")?;
+            match job.status.as_str() {
+                "done" => return Ok(job),
+                "failed" => bail!(
+                    "print job {} failed: {}",
+                    queued.job_id,
+                    job.error.as_deref().unwrap_or("unknown error")
+                ),
+                "cancelled" => bail!("print job {} was cancelled", queued.job_id),
+                _ => {}
+            }
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        let resp = self
+            .with_token(self.http.post(format!("{}{path}", self.base_url)))
+            .json(body)
+            .send()
+            .await
+            .with_context(|| format!("printerd request to {path} failed"))?;
+        check_status(resp)
+            .await?
+            .json()
+            .await
+            .context("failed to decode printerd json response")
+    }
+
+    fn with_token(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => req.header("x-api-token", token),
+            None => req,
+        }
+    }
+}
+
+async fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let text = resp.text().await.unwrap_or_default();
+    match serde_json::from_str::<ApiErrorBody>(&text) {
+        Ok(body) => Err(anyhow!("printerd error {status}: {}", body.error)),
+        Err(_) => Err(anyhow!("printerd error {status}: {text}")),
+    }
+}
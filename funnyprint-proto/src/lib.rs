@@ -1,15 +1,31 @@
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    pin::Pin,
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::{Context, Result, anyhow, bail};
 use btleplug::api::{
-    Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _, ScanFilter,
-    ValueNotification, WriteType,
+    Central, CentralEvent, CharPropFlags, Characteristic, Manager as _, Peripheral as _,
+    PeripheralProperties, ScanFilter, ValueNotification, WriteType,
 };
 use btleplug::platform::{Adapter, Manager, Peripheral};
-use futures::StreamExt;
-use tokio::time::{Instant, sleep, timeout};
+use futures::{FutureExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, sleep, sleep_until, timeout, timeout_at};
+use tracing::{debug, trace, warn};
 use uuid::Uuid;
 
+pub mod protocol;
+
+use protocol::{
+    density_packet, handshake_0a_packet, handshake_0b_packet, hardware_info_packet,
+    print_event_packet, print_line_packet,
+};
+
 pub const WRITE_UUID_STR: &str = "0000ffe1-0000-1000-8000-00805f9b34fb";
 pub const READ_UUID_STR: &str = "0000ffe2-0000-1000-8000-00805f9b34fb";
 
@@ -17,6 +33,9 @@ pub const MAX_DOTS_PER_LINE: usize = 384;
 pub const BYTES_PER_LINE: usize = MAX_DOTS_PER_LINE / 8;
 pub const PACKED_LINE_BYTES: usize = BYTES_PER_LINE * 2;
 
+/// Density (0..=7) for printers whose model is not known, see [`PrinterModel::default_density`].
+pub const DEFAULT_DENSITY: u8 = 3;
+
 const STATUS: [u8; 2] = [0x5a, 0x02];
 const HANDSHAKE_0A: [u8; 2] = [0x5a, 0x0a];
 const HANDSHAKE_0B: [u8; 2] = [0x5a, 0x0b];
@@ -28,9 +47,56 @@ const LOST_PACKET: [u8; 2] = [0x5a, 0x05];
 pub struct PrinterInfo {
     pub address: String,
     pub local_name: Option<String>,
+    pub rssi: Option<i16>,
+    /// Advertised manufacturer-specific data by company identifier.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// Best-effort guess from `local_name`, see [`PrinterModel::from_local_name`].
+    pub guessed_model: Option<PrinterModel>,
+}
+
+/// Printer families this protocol is known to drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrinterModel {
+    /// FunnyPrint / Xiqi 58 mm printers with a 48 mm head.
+    FunnyPrint,
+}
+
+/// Advertised name prefixes (compared case-insensitively) and the model they indicate.
+const MODEL_NAME_PREFIXES: &[(&str, PrinterModel)] = &[
+    ("funnyprint", PrinterModel::FunnyPrint),
+    ("xiqi", PrinterModel::FunnyPrint),
+];
+
+impl PrinterModel {
+    pub fn from_local_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        MODEL_NAME_PREFIXES
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .map(|(_, model)| *model)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::FunnyPrint => "funnyprint",
+        }
+    }
+
+    pub fn dots_per_line(self) -> usize {
+        match self {
+            Self::FunnyPrint => MAX_DOTS_PER_LINE,
+        }
+    }
+
+    /// Density that prints dark enough on this model's usual paper without smearing.
+    pub fn default_density(self) -> u8 {
+        match self {
+            Self::FunnyPrint => 3,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct StatusEvent {
     pub battery: u8,
     pub no_paper: bool,
@@ -50,12 +116,168 @@ enum NotifyEvent {
 
 pub type PackedLine = [u8; PACKED_LINE_BYTES];
 
+/// How the print loop decides that a line went through, see [`PrintTuning::ack_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckMode {
+    /// Send the next line after a fixed [`LINE_DELAY`] and let lost-packet reports rewind.
+    #[default]
+    FireAndForget,
+    /// Send the next line once [`PrintTuning::ack_window`] passes without a lost-packet report.
+    WaitAck,
+}
+
+impl FromStr for AckMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fire_and_forget" => Ok(Self::FireAndForget),
+            "wait_ack" => Ok(Self::WaitAck),
+            other => Err(format!(
+                "unknown ack mode {other:?}, expected fire_and_forget|wait_ack"
+            )),
+        }
+    }
+}
+
+/// Knobs for [`print_job_with_tuning`]. `Default` matches plain [`print_job`] behavior.
+#[derive(Debug, Clone)]
+pub struct PrintTuning {
+    /// Lower density and slow down while the printer reports overheat.
+    pub adaptive_density: bool,
+    /// Density steps to drop while throttled.
+    pub overheat_density_step: u8,
+    /// Extra delay after each line while throttled.
+    pub overheat_extra_delay: Duration,
+    /// How long to wait for each handshake response.
+    pub handshake_timeout: Duration,
+    /// Re-sends of a handshake packet that timed out. Explicit rejections are not retried.
+    pub handshake_retries: u32,
+    /// Largest single BLE write the link accepts (ATT MTU - 3). `None` sends every packet whole.
+    pub max_write_chunk: Option<usize>,
+    /// Refuse jobs with more packed lines than this, see [`PrintError::TooLong`].
+    pub max_lines: Option<usize>,
+    /// Blank packed lines fed after the content so the sticker clears the tear bar. Not counted
+    /// against `max_lines`; 0 stops right after the last line.
+    pub tail_feed_lines: usize,
+    /// When a line write fails mid-job, reconnect, handshake again and carry on from that line, at
+    /// most [`MAX_RESUMES`] times per job.
+    pub resume_on_disconnect: bool,
+    /// Most queued notifications handled between two line writes; whatever is left waits for the
+    /// next line. At least one is always handled.
+    pub max_notifications_per_line: usize,
+    /// Fixed pacing or waiting for each line to go unreported, see [`AckMode`].
+    pub ack_mode: AckMode,
+    /// How long [`AckMode::WaitAck`] listens for a lost-packet report after each line.
+    pub ack_window: Duration,
+}
+
+impl Default for PrintTuning {
+    fn default() -> Self {
+        Self {
+            adaptive_density: false,
+            overheat_density_step: 2,
+            overheat_extra_delay: Duration::from_millis(40),
+            handshake_timeout: Duration::from_secs(5),
+            handshake_retries: 2,
+            max_write_chunk: None,
+            max_lines: None,
+            tail_feed_lines: mm_to_packed_lines(DEFAULT_TAIL_FEED_MM),
+            resume_on_disconnect: false,
+            max_notifications_per_line: 16,
+            ack_mode: AckMode::FireAndForget,
+            ack_window: DEFAULT_ACK_WINDOW,
+        }
+    }
+}
+
+/// What happened during a finished print job.
+#[derive(Debug, Clone, Default)]
+pub struct PrintReport {
+    /// Adaptive density lowered the density at least once.
+    pub throttled: bool,
+    /// The printer reported a pause at least once.
+    pub paused: bool,
+    /// Times the job reconnected after the link dropped, see [`PrintTuning::resume_on_disconnect`].
+    pub resumes: u32,
+}
+
+/// Printer state changes reported while a job is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintEvent {
+    /// The printer stopped taking lines (button pressed, cover opened, ...).
+    Paused,
+    /// The printer asked for lines again after a pause.
+    Resumed,
+    /// The printer sent a status packet.
+    Status(StatusEvent),
+}
+
+/// Job errors callers may want to tell apart; they come wrapped in `anyhow::Error`, so use
+/// `err.downcast_ref::<PrintError>()`.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum PrintError {
+    #[error(
+        "job is too long: {lines} lines ({:.0} mm), max is {max_lines} lines ({:.0} mm)",
+        packed_lines_mm(*.lines),
+        packed_lines_mm(*.max_lines)
+    )]
+    TooLong { lines: usize, max_lines: usize },
+}
+
 pub fn dpi() -> u16 {
     203
 }
 
-pub async fn discover_candidates(scan_time: Duration) -> Result<Vec<PrinterInfo>> {
+/// Paper fed for `lines` packed lines (two dot rows each).
+pub fn packed_lines_mm(lines: usize) -> f32 {
+    (lines * 2) as f32 / dpi() as f32 * 25.4
+}
+
+/// Packed lines needed to feed `mm` of paper, rounded to the nearest line.
+pub fn mm_to_packed_lines(mm: f32) -> usize {
+    (mm.max(0.0) / 25.4 * dpi() as f32 / 2.0).round() as usize
+}
+
+/// Reconnects one job may make with [`PrintTuning::resume_on_disconnect`].
+pub const MAX_RESUMES: u32 = 2;
+
+/// Blank paper [`PrintTuning::default`] feeds after each job, enough to tear the sticker off.
+pub const DEFAULT_TAIL_FEED_MM: f32 = 5.0;
+
+/// Longest tail feed a job may ask for; anything longer only wastes paper.
+pub const MAX_TAIL_FEED_MM: f32 = 50.0;
+
+/// Pause after every line write, before the next line goes out.
+pub const LINE_DELAY: Duration = Duration::from_millis(20);
+
+/// [`PrintTuning::ack_window`] unless configured otherwise.
+pub const DEFAULT_ACK_WINDOW: Duration = Duration::from_millis(15);
+
+/// Typical time to connect and finish both handshake steps, as used by [`estimate_print_duration`].
+pub const SESSION_SETUP_ESTIMATE: Duration = Duration::from_secs(2);
+
+/// Best-effort duration of a job of `lines` packed lines: session setup, then every line and the
+/// tail feed at the nominal [`LINE_DELAY`], or the ack window with [`AckMode::WaitAck`].
+/// Retransmissions, overheat slowdowns and reconnects are not counted.
+pub fn estimate_print_duration(lines: usize, tuning: &PrintTuning) -> Duration {
+    let per_line = match tuning.ack_mode {
+        AckMode::FireAndForget => LINE_DELAY,
+        AckMode::WaitAck => tuning.ack_window,
+    };
+    SESSION_SETUP_ESTIMATE + per_line * (lines + tuning.tail_feed_lines) as u32
+}
+
+/// Scans for `scan_time` and returns devices that look like printers.
+///
+/// With `name_prefix` only devices whose advertised name starts with it (ignoring case) are kept,
+/// e.g. `Some("MX")` in an office full of other BLE gadgets.
+pub async fn discover_candidates(
+    scan_time: Duration,
+    name_prefix: Option<&str>,
+) -> Result<Vec<PrinterInfo>> {
     let adapter = default_adapter().await?;
+    // No service filter at the adapter: some printers only advertise their name, not `ffe6`.
     adapter
         .start_scan(ScanFilter::default())
         .await
@@ -76,107 +298,530 @@ pub async fn discover_candidates(scan_time: Duration) -> Result<Vec<PrinterInfo>
             continue;
         };
 
-        let has_ffe6 = props.services.iter().any(|s| {
-            s.to_string()
-                .eq_ignore_ascii_case("0000ffe6-0000-1000-8000-00805f9b34fb")
-        });
-        if has_ffe6 || props.local_name.is_some() {
-            out.push(PrinterInfo {
-                address: props.address.to_string(),
-                local_name: props.local_name,
-            });
+        if let Some(info) = candidate_info(props, name_prefix) {
+            out.push(info);
         }
     }
 
     Ok(out)
 }
 
-pub async fn print_job(address: &str, lines: &[PackedLine], density: u8) -> Result<()> {
-    if density > 7 {
-        bail!("density must be in range 0..=7");
-    }
-    if lines.is_empty() {
-        bail!("nothing to print: no packed lines provided");
-    }
-
+/// Streams candidates as the adapter reports them instead of sleeping for the whole window.
+///
+/// Each address is sent once. Scanning stops when `scan_time` elapses or the receiver is dropped.
+/// `name_prefix` filters like in [`discover_candidates`].
+pub async fn live_scan(
+    scan_time: Duration,
+    name_prefix: Option<&str>,
+) -> Result<mpsc::Receiver<PrinterInfo>> {
+    let name_prefix = name_prefix.map(str::to_string);
     let adapter = default_adapter().await?;
-    let peripheral = find_peripheral_by_address(&adapter, address, Duration::from_secs(4)).await?;
-    peripheral
-        .connect()
+    let mut events = adapter
+        .events()
         .await
-        .with_context(|| format!("failed to connect to {address}"))?;
-    peripheral
-        .discover_services()
+        .context("failed to subscribe to BLE events")?;
+    adapter
+        .start_scan(ScanFilter::default())
         .await
-        .context("failed to discover services")?;
+        .context("failed to start BLE scan")?;
 
-    let (write_char, read_char) = resolve_chars(&peripheral)?;
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        let deadline = Instant::now() + scan_time;
+        let mut seen = HashSet::new();
+        loop {
+            let event = tokio::select! {
+                ev = events.next() => ev,
+                _ = sleep_until(deadline) => None,
+                _ = tx.closed() => None,
+            };
+            let Some(event) = event else {
+                break;
+            };
+            let id = match event {
+                CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => id,
+                _ => continue,
+            };
+            let Ok(p) = adapter.peripheral(&id).await else {
+                continue;
+            };
+            let Ok(Some(props)) = p.properties().await else {
+                continue;
+            };
+            let Some(info) = candidate_info(props, name_prefix.as_deref()) else {
+                continue;
+            };
+            if seen.insert(info.address.clone()) && tx.send(info).await.is_err() {
+                break;
+            }
+        }
+        let _ = adapter.stop_scan().await;
+    });
 
-    peripheral
-        .subscribe(&read_char)
-        .await
-        .context("failed to subscribe to notify characteristic")?;
-    let mut notifications = peripheral
-        .notifications()
+    Ok(rx)
+}
+
+fn candidate_info(props: PeripheralProperties, name_prefix: Option<&str>) -> Option<PrinterInfo> {
+    let has_ffe6 = props.services.iter().any(|s| {
+        s.to_string()
+            .eq_ignore_ascii_case("0000ffe6-0000-1000-8000-00805f9b34fb")
+    });
+    if !has_ffe6 && props.local_name.is_none() {
+        return None;
+    }
+    if let Some(prefix) = name_prefix {
+        let name = props.local_name.as_deref()?.trim_start();
+        let starts = name
+            .get(..prefix.len())
+            .is_some_and(|head| head.eq_ignore_ascii_case(prefix));
+        if !starts {
+            return None;
+        }
+    }
+    let guessed_model = props
+        .local_name
+        .as_deref()
+        .and_then(PrinterModel::from_local_name);
+    Some(PrinterInfo {
+        address: props.address.to_string(),
+        local_name: props.local_name,
+        rssi: props.rssi,
+        manufacturer_data: props.manufacturer_data,
+        guessed_model,
+    })
+}
+
+pub async fn print_job(address: &str, lines: &[PackedLine], density: u8) -> Result<PrintReport> {
+    print_job_with_tuning(address, lines, density, &PrintTuning::default()).await
+}
+
+pub async fn print_job_with_tuning(
+    address: &str,
+    lines: &[PackedLine],
+    density: u8,
+    tuning: &PrintTuning,
+) -> Result<PrintReport> {
+    check_job(lines, density, tuning.max_lines)?;
+
+    let mut session = PrinterSession::connect(address, tuning).await?;
+    let report = session.print(lines, density).await?;
+    session.disconnect().await?;
+    Ok(report)
+}
+
+/// Runs a whole job over an already connected `transport`: hardware-info request, handshake, then
+/// the lines. [`print_job_with_tuning`] is this over a fresh [`BleTransport`].
+pub async fn print_with_transport<T: PrinterTransport>(
+    transport: &mut T,
+    address: &str,
+    lines: &[PackedLine],
+    density: u8,
+    tuning: &PrintTuning,
+) -> Result<PrintReport> {
+    check_job(lines, density, tuning.max_lines)?;
+    open_session(transport, address, tuning).await?;
+    send_lines(transport, address, lines, density, tuning, &mut |_| {}).await
+}
+
+/// What a [`WirePacket`] does in the job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    HardwareInfo,
+    Handshake0a,
+    Handshake0b,
+    Density,
+    PrintStart,
+    Line,
+    PrintEnd,
+}
+
+impl PacketKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::HardwareInfo => "hardware_info",
+            Self::Handshake0a => "handshake_0a",
+            Self::Handshake0b => "handshake_0b",
+            Self::Density => "density",
+            Self::PrintStart => "print_start",
+            Self::Line => "line",
+            Self::PrintEnd => "print_end",
+        }
+    }
+}
+
+/// One protocol packet as [`PrinterTransport::write`] gets it, before any MTU split.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WirePacket {
+    pub kind: PacketKind,
+    pub bytes: Vec<u8>,
+}
+
+/// Every packet [`print_with_transport`] writes for a job that runs without a hitch, in order:
+/// no handshake retries, lost-line resends, overheat density changes or reconnects.
+pub fn job_packets(
+    address: &str,
+    lines: &[PackedLine],
+    density: u8,
+    tuning: &PrintTuning,
+) -> Result<Vec<WirePacket>> {
+    check_job(lines, density, tuning.max_lines)?;
+    let lines = with_tail_feed(lines, tuning.tail_feed_lines);
+    let total = lines.len() as u16;
+
+    let mut out = vec![
+        (PacketKind::HardwareInfo, hardware_info_packet()),
+        (PacketKind::Handshake0a, handshake_0a_packet()),
+        (PacketKind::Handshake0b, handshake_0b_packet(address)?),
+        (PacketKind::Density, density_packet(density)),
+        (PacketKind::PrintStart, print_event_packet(total, false)),
+    ];
+    for (line_no, line) in lines.iter().enumerate() {
+        out.push((PacketKind::Line, print_line_packet(line_no as u16, line)));
+    }
+    out.push((PacketKind::PrintEnd, print_event_packet(total, true)));
+    Ok(out
+        .into_iter()
+        .map(|(kind, bytes)| WirePacket { kind, bytes })
+        .collect())
+}
+
+pub type NotificationStream = Pin<Box<dyn futures::Stream<Item = ValueNotification> + Send>>;
+
+/// The link a job runs over: protocol packets out, printer notifications in. [`BleTransport`] is
+/// the real printer; tests drive the same protocol code with a scripted one.
+pub trait PrinterTransport: Send {
+    /// Sends one protocol packet, split however the link requires.
+    fn write(&mut self, packet: &[u8]) -> impl Future<Output = Result<()>> + Send;
+    fn notifications(&mut self) -> &mut NotificationStream;
+    /// Drops the current link and connects again, ready for the handshake.
+    fn reconnect(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { bail!("this transport cannot reconnect") }
+    }
+}
+
+/// A connected printer's write and notify characteristics.
+pub struct BleTransport {
+    address: String,
+    /// Advertised name at connect time, if the adapter saw one.
+    local_name: Option<String>,
+    peripheral: Peripheral,
+    write_char: Characteristic,
+    notifications: NotificationStream,
+    max_write_chunk: Option<usize>,
+}
+
+impl BleTransport {
+    /// Finds and connects to `address`, then subscribes to its notifications. No protocol packets
+    /// are sent yet.
+    pub async fn connect(address: &str, max_write_chunk: Option<usize>) -> Result<Self> {
+        let adapter = default_adapter().await?;
+        let peripheral =
+            find_peripheral_by_address(&adapter, address, Duration::from_secs(4)).await?;
+        peripheral
+            .connect()
+            .await
+            .with_context(|| format!("failed to connect to {address}"))?;
+        peripheral
+            .discover_services()
+            .await
+            .context("failed to discover services")?;
+
+        let (write_char, read_char) = resolve_chars(&peripheral)?;
+
+        peripheral
+            .subscribe(&read_char)
+            .await
+            .context("failed to subscribe to notify characteristic")?;
+        let notifications = peripheral
+            .notifications()
+            .await
+            .context("failed to create notifications stream")?;
+        let local_name = peripheral
+            .properties()
+            .await
+            .ok()
+            .flatten()
+            .and_then(|props| props.local_name);
+
+        Ok(Self {
+            address: address.to_string(),
+            local_name,
+            peripheral,
+            write_char,
+            notifications,
+            max_write_chunk,
+        })
+    }
+
+    pub async fn disconnect(self) -> Result<()> {
+        self.peripheral
+            .disconnect()
+            .await
+            .context("failed to disconnect cleanly")
+    }
+}
+
+impl PrinterTransport for BleTransport {
+    async fn write(&mut self, packet: &[u8]) -> Result<()> {
+        let ch = &self.write_char;
+        let write_type = if ch
+            .properties
+            .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        {
+            WriteType::WithoutResponse
+        } else {
+            WriteType::WithResponse
+        };
+
+        trace!(uuid = %ch.uuid, len = packet.len(), packet = %HexBytes(packet), "ble write");
+        for (chunk, write_type) in plan_writes(packet, self.max_write_chunk, write_type) {
+            self.peripheral
+                .write(ch, chunk, write_type)
+                .await
+                .context("BLE write failed")?;
+        }
+        Ok(())
+    }
+
+    fn notifications(&mut self) -> &mut NotificationStream {
+        &mut self.notifications
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        // The old link is usually gone already; this only makes sure btleplug lets go of it.
+        let _ = self.peripheral.disconnect().await;
+        *self = Self::connect(&self.address, self.max_write_chunk).await?;
+        Ok(())
+    }
+}
+
+/// A connected, handshaken printer that can run several jobs before [`PrinterSession::disconnect`].
+pub struct PrinterSession {
+    address: String,
+    transport: BleTransport,
+    tuning: PrintTuning,
+}
+
+impl PrinterSession {
+    pub async fn connect(address: &str, tuning: &PrintTuning) -> Result<Self> {
+        let mut transport = BleTransport::connect(address, tuning.max_write_chunk).await?;
+        open_session(&mut transport, address, tuning).await?;
+
+        Ok(Self {
+            address: address.to_string(),
+            transport,
+            tuning: tuning.clone(),
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Model guessed from the advertised name, see [`PrinterModel::from_local_name`].
+    pub fn model(&self) -> Option<PrinterModel> {
+        self.transport
+            .local_name
+            .as_deref()
+            .and_then(PrinterModel::from_local_name)
+    }
+
+    /// [`PrinterModel::default_density`] of [`Self::model`], or [`DEFAULT_DENSITY`].
+    pub fn default_density(&self) -> u8 {
+        self.model()
+            .map_or(DEFAULT_DENSITY, PrinterModel::default_density)
+    }
+
+    /// Changes [`PrintTuning::tail_feed_lines`] for the jobs printed from now on.
+    pub fn set_tail_feed_lines(&mut self, lines: usize) {
+        self.tuning.tail_feed_lines = lines;
+    }
+
+    /// Whether the link is still usable. A printer that went to sleep or out of range may not
+    /// report the disconnect, so this also sends a harmless hardware-info request.
+    pub async fn is_alive(&mut self) -> bool {
+        // Only the peripheral is borrowed across the await: the notification stream is not `Sync`.
+        let connected = self.transport.peripheral.is_connected().await;
+        if !connected.unwrap_or(false) {
+            return false;
+        }
+        self.transport.write(&hardware_info_packet()).await.is_ok()
+    }
+
+    pub async fn print(&mut self, lines: &[PackedLine], density: u8) -> Result<PrintReport> {
+        self.print_with_events(lines, density, |_| {}).await
+    }
+
+    /// Like [`PrinterSession::print`], calling `on_event` when the printer pauses or resumes.
+    pub async fn print_with_events(
+        &mut self,
+        lines: &[PackedLine],
+        density: u8,
+        mut on_event: impl FnMut(PrintEvent) + Send,
+    ) -> Result<PrintReport> {
+        check_job(lines, density, self.tuning.max_lines)?;
+        send_lines(
+            &mut self.transport,
+            &self.address,
+            lines,
+            density,
+            &self.tuning,
+            &mut on_event,
+        )
         .await
-        .context("failed to create notifications stream")?;
-
-    write(&peripheral, &write_char, &hardware_info_packet()).await?;
-    write(&peripheral, &write_char, &handshake_0a_packet()).await?;
-    wait_for_handshake_0a(&mut notifications).await?;
-    write(
-        &peripheral,
-        &write_char,
-        &handshake_0b_packet(address).context("failed to build handshake 0b")?,
-    )
-    .await?;
-    wait_for_handshake_0b_ok(&mut notifications).await?;
-
-    write(&peripheral, &write_char, &density_packet(density)).await?;
-    write(
-        &peripheral,
-        &write_char,
-        &print_event_packet(lines.len() as u16, false),
-    )
-    .await?;
+    }
+
+    pub async fn disconnect(self) -> Result<()> {
+        self.transport.disconnect().await
+    }
+}
+
+async fn open_session<T: PrinterTransport>(
+    transport: &mut T,
+    address: &str,
+    tuning: &PrintTuning,
+) -> Result<()> {
+    transport.write(&hardware_info_packet()).await?;
+    handshake(transport, address, tuning).await
+}
+
+async fn send_lines<T: PrinterTransport>(
+    transport: &mut T,
+    address: &str,
+    lines: &[PackedLine],
+    density: u8,
+    tuning: &PrintTuning,
+    on_event: &mut (impl FnMut(PrintEvent) + Send),
+) -> Result<PrintReport> {
+    let lines = with_tail_feed(lines, tuning.tail_feed_lines);
+    let lines = lines.as_ref();
+    transport.write(&density_packet(density)).await?;
+    transport
+        .write(&print_event_packet(lines.len() as u16, false))
+        .await?;
 
     let mut cur_line: usize = 0;
     let mut wait_for_event_cnt = 0usize;
+    let mut throttling = false;
+    let mut paused = false;
+    let mut report = PrintReport::default();
+    // The line just written still waits out its ack window.
+    let mut awaiting_ack = false;
 
-    loop {
-        if let Ok(Some(note)) = timeout(Duration::from_millis(5), notifications.next()).await {
+    'job: loop {
+        // Wait briefly for the first notification, then take whatever else is already queued so a
+        // burst of lost-packet reports costs one rewind instead of one per line. A line awaiting its
+        // ack keeps listening until the window closes or the printer asks for a line again.
+        let window = if awaiting_ack {
+            tuning.ack_window
+        } else {
+            Duration::from_millis(5)
+        };
+        let deadline = Instant::now() + window;
+        let mut next = timeout_at(deadline, transport.notifications().next())
+            .await
+            .ok()
+            .flatten();
+        let mut handled = 0;
+        let mut rewind_to: Option<usize> = None;
+        while let Some(note) = next {
             match parse_notify(&note) {
                 NotifyEvent::Lost { line_no } => {
                     wait_for_event_cnt = 0;
-                    cur_line = (line_no.saturating_sub(1)) as usize;
+                    let line = (line_no.saturating_sub(1)) as usize;
+                    rewind_to = Some(rewind_to.map_or(line, |earliest| earliest.min(line)));
+                    if paused {
+                        paused = false;
+                        on_event(PrintEvent::Resumed);
+                    }
                 }
                 NotifyEvent::Paused => {
-                    // Printer can emit pause before a lost-packet event.
+                    // Printer can emit pause before a lost-packet event, which then resumes it.
+                    if !paused {
+                        paused = true;
+                        report.paused = true;
+                        on_event(PrintEvent::Paused);
+                    }
                 }
                 NotifyEvent::Finished => {
-                    break;
+                    break 'job;
                 }
                 NotifyEvent::Status(st) => {
+                    on_event(PrintEvent::Status(st));
                     if st.overheat {
                         eprintln!("warning: printer overheat reported");
                     }
                     if st.no_paper {
                         eprintln!("warning: printer reports no paper");
                     }
+                    if tuning.adaptive_density && st.overheat != throttling {
+                        throttling = st.overheat;
+                        let effective = if throttling {
+                            density.saturating_sub(tuning.overheat_density_step)
+                        } else {
+                            density
+                        };
+                        transport.write(&density_packet(effective)).await?;
+                        report.throttled |= throttling;
+                    }
                 }
                 NotifyEvent::Handshake0a | NotifyEvent::Handshake0b { .. } | NotifyEvent::Other => {
                 }
             }
+            handled += 1;
+            next = if handled >= tuning.max_notifications_per_line {
+                None
+            } else if awaiting_ack && rewind_to.is_none() {
+                timeout_at(deadline, transport.notifications().next())
+                    .await
+                    .ok()
+                    .flatten()
+            } else {
+                transport.notifications().next().now_or_never().flatten()
+            };
+        }
+        awaiting_ack = false;
+        if let Some(line) = rewind_to {
+            cur_line = line;
         }
 
         if cur_line < lines.len() {
-            write(
-                &peripheral,
-                &write_char,
-                &print_line_packet(cur_line as u16, &lines[cur_line]),
-            )
-            .await?;
-            sleep(Duration::from_millis(20)).await;
+            let sent = transport
+                .write(&print_line_packet(cur_line as u16, &lines[cur_line]))
+                .await;
+            if let Err(err) = sent {
+                if !tuning.resume_on_disconnect || report.resumes >= MAX_RESUMES {
+                    return Err(err);
+                }
+                report.resumes += 1;
+                warn!(
+                    line = cur_line,
+                    attempt = report.resumes,
+                    error = %err,
+                    "line write failed, reconnecting to resume"
+                );
+                let effective = if throttling {
+                    density.saturating_sub(tuning.overheat_density_step)
+                } else {
+                    density
+                };
+                resume_job(transport, address, effective, lines.len(), tuning)
+                    .await
+                    .with_context(|| format!("failed to resume the job at line {cur_line}"))?;
+                wait_for_event_cnt = 0;
+                continue;
+            }
+            let mut delay = match tuning.ack_mode {
+                AckMode::FireAndForget => LINE_DELAY,
+                AckMode::WaitAck => {
+                    awaiting_ack = true;
+                    Duration::ZERO
+                }
+            };
+            if throttling {
+                delay += tuning.overheat_extra_delay;
+            }
+            sleep(delay).await;
             cur_line += 1;
         }
 
@@ -189,17 +834,55 @@ pub async fn print_job(address: &str, lines: &[PackedLine], density: u8) -> Resu
         }
     }
 
-    write(
-        &peripheral,
-        &write_char,
-        &print_event_packet(lines.len() as u16, true),
-    )
-    .await?;
+    transport
+        .write(&print_event_packet(lines.len() as u16, true))
+        .await?;
+    Ok(report)
+}
 
-    peripheral
-        .disconnect()
+/// Reconnects and announces the job again; the printer then takes lines from where it stopped.
+async fn resume_job<T: PrinterTransport>(
+    transport: &mut T,
+    address: &str,
+    density: u8,
+    total_lines: usize,
+    tuning: &PrintTuning,
+) -> Result<()> {
+    transport.reconnect().await?;
+    open_session(transport, address, tuning).await?;
+    transport.write(&density_packet(density)).await?;
+    transport
+        .write(&print_event_packet(total_lines as u16, false))
         .await
-        .context("failed to disconnect cleanly")?;
+}
+
+/// `lines` followed by `tail` blank lines, borrowed when there is nothing to add.
+fn with_tail_feed(lines: &[PackedLine], tail: usize) -> Cow<'_, [PackedLine]> {
+    if tail == 0 {
+        return Cow::Borrowed(lines);
+    }
+    let mut out = Vec::with_capacity(lines.len() + tail);
+    out.extend_from_slice(lines);
+    out.resize(lines.len() + tail, [0u8; PACKED_LINE_BYTES]);
+    Cow::Owned(out)
+}
+
+fn check_job(lines: &[PackedLine], density: u8, max_lines: Option<usize>) -> Result<()> {
+    if density > 7 {
+        bail!("density must be in range 0..=7");
+    }
+    if lines.is_empty() {
+        bail!("nothing to print: no packed lines provided");
+    }
+    if let Some(max_lines) = max_lines
+        && lines.len() > max_lines
+    {
+        return Err(PrintError::TooLong {
+            lines: lines.len(),
+            max_lines,
+        }
+        .into());
+    }
     Ok(())
 }
 
@@ -294,23 +977,44 @@ fn resolve_chars(peripheral: &Peripheral) -> Result<(Characteristic, Characteris
     Ok((write_char, read_char))
 }
 
-async fn write(peripheral: &Peripheral, ch: &Characteristic, data: &[u8]) -> Result<()> {
-    let write_type = if ch
-        .properties
-        .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
-    {
-        WriteType::WithoutResponse
-    } else {
-        WriteType::WithResponse
-    };
+/// Splits a packet for links whose MTU is smaller than the packet.
+///
+/// A packet that fits goes out as one write of `preferred` type. Split packets are sent
+/// `WithResponse` so the printer sees every chunk, in order, before the next one.
+fn plan_writes(
+    data: &[u8],
+    max_chunk: Option<usize>,
+    preferred: WriteType,
+) -> Vec<(&[u8], WriteType)> {
+    match max_chunk {
+        Some(max) if max > 0 && data.len() > max => data
+            .chunks(max)
+            .map(|c| (c, WriteType::WithResponse))
+            .collect(),
+        _ => vec![(data, preferred)],
+    }
+}
 
-    peripheral
-        .write(ch, data, write_type)
-        .await
-        .context("BLE write failed")
+/// Formats bytes as hex only when a log line is actually emitted.
+struct HexBytes<'a>(&'a [u8]);
+
+impl fmt::Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
 }
 
 fn parse_notify(note: &ValueNotification) -> NotifyEvent {
+    trace!(uuid = %note.uuid, packet = %HexBytes(&note.value), "ble notify");
+    let event = decode_notify(note);
+    debug!(event = ?event, "printer notification");
+    event
+}
+
+fn decode_notify(note: &ValueNotification) -> NotifyEvent {
     if note.value.len() < 2 {
         return NotifyEvent::Other;
     }
@@ -346,122 +1050,528 @@ fn parse_notify(note: &ValueNotification) -> NotifyEvent {
     }
 }
 
-async fn wait_for_handshake_0a<S>(stream: &mut S) -> Result<()>
+async fn handshake<T: PrinterTransport>(
+    transport: &mut T,
+    address: &str,
+    tuning: &PrintTuning,
+) -> Result<()> {
+    let attempts = tuning.handshake_retries + 1;
+
+    let mut attempt = 1;
+    loop {
+        transport.write(&handshake_0a_packet()).await?;
+        if wait_for_handshake_0a(transport.notifications(), tuning.handshake_timeout).await? {
+            break;
+        }
+        if attempt == attempts {
+            bail!("timeout waiting for handshake 0x5a0a response after {attempts} attempt(s)");
+        }
+        warn!(
+            attempt = attempt,
+            attempts = attempts,
+            "handshake 0x5a0a timed out, retrying"
+        );
+        attempt += 1;
+    }
+
+    let packet_0b = handshake_0b_packet(address).context("failed to build handshake 0b")?;
+    let mut attempt = 1;
+    loop {
+        transport.write(&packet_0b).await?;
+        if wait_for_handshake_0b_ok(transport.notifications(), tuning.handshake_timeout).await? {
+            return Ok(());
+        }
+        if attempt == attempts {
+            bail!("timeout waiting for handshake 0x5a0b confirmation after {attempts} attempt(s)");
+        }
+        warn!(
+            attempt = attempt,
+            attempts = attempts,
+            "handshake 0x5a0b timed out, retrying"
+        );
+        attempt += 1;
+    }
+}
+
+/// `Ok(false)` means the deadline passed without a response.
+async fn wait_for_handshake_0a<S>(stream: &mut S, wait: Duration) -> Result<bool>
 where
     S: futures::Stream<Item = ValueNotification> + Unpin,
 {
-    let deadline = Instant::now() + Duration::from_secs(5);
+    let deadline = Instant::now() + wait;
     while Instant::now() < deadline {
-        if let Ok(Some(note)) = timeout(Duration::from_millis(500), stream.next()).await {
-            if matches!(parse_notify(&note), NotifyEvent::Handshake0a) {
-                return Ok(());
-            }
+        if let Ok(Some(note)) = timeout(Duration::from_millis(500), stream.next()).await
+            && matches!(parse_notify(&note), NotifyEvent::Handshake0a)
+        {
+            return Ok(true);
         }
     }
-    bail!("timeout waiting for handshake 0x5a0a response")
+    Ok(false)
 }
 
-async fn wait_for_handshake_0b_ok<S>(stream: &mut S) -> Result<()>
+/// `Ok(false)` means the deadline passed without a response; a rejection is an error.
+async fn wait_for_handshake_0b_ok<S>(stream: &mut S, wait: Duration) -> Result<bool>
 where
     S: futures::Stream<Item = ValueNotification> + Unpin,
 {
-    let deadline = Instant::now() + Duration::from_secs(5);
+    let deadline = Instant::now() + wait;
     while Instant::now() < deadline {
-        if let Ok(Some(note)) = timeout(Duration::from_millis(500), stream.next()).await {
-            if let NotifyEvent::Handshake0b { ok } = parse_notify(&note) {
-                if ok {
-                    return Ok(());
-                }
-                bail!("printer rejected handshake 0x5a0b response");
+        if let Ok(Some(note)) = timeout(Duration::from_millis(500), stream.next()).await
+            && let NotifyEvent::Handshake0b { ok } = parse_notify(&note)
+        {
+            if ok {
+                return Ok(true);
             }
+            bail!("printer rejected handshake 0x5a0b response");
         }
     }
-    bail!("timeout waiting for handshake 0x5a0b confirmation")
+    Ok(false)
 }
 
-fn hardware_info_packet() -> Vec<u8> {
-    vec![0x5a, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn density_packet(density: u8) -> Vec<u8> {
-    vec![0x5a, 0x0c, density]
-}
+    const ADDRESS: &str = "C0:00:00:00:06:B3";
 
-fn handshake_0a_packet() -> Vec<u8> {
-    let mut packet = vec![0x5a, 0x0a];
-    packet.extend_from_slice(&[0u8; 10]);
-    packet
-}
+    type Reply = Box<dyn FnMut(&[u8]) -> Vec<Vec<u8>> + Send>;
 
-fn handshake_0b_packet(bdaddr: &str) -> Result<Vec<u8>> {
-    let mut mac_hex = bdaddr.replace(':', "");
-    mac_hex = mac_hex.replace('-', "");
-    if mac_hex.len() != 12 {
-        bail!("expected a 6-byte MAC address, got: {bdaddr}");
+    /// Records every packet and answers it with the notifications `reply` scripts for it.
+    struct MockTransport {
+        written: Vec<Vec<u8>>,
+        reply: Reply,
+        /// Line packets whose first send fails as if the link dropped.
+        drop_at_lines: Vec<u16>,
+        reconnects: usize,
+        tx: futures::channel::mpsc::UnboundedSender<ValueNotification>,
+        notifications: NotificationStream,
     }
-    let mut mac = [0u8; 6];
-    for (idx, out) in mac.iter_mut().enumerate() {
-        let from = idx * 2;
-        *out = u8::from_str_radix(&mac_hex[from..from + 2], 16)
-            .with_context(|| format!("invalid MAC address: {bdaddr}"))?;
+
+    impl MockTransport {
+        fn new(reply: impl FnMut(&[u8]) -> Vec<Vec<u8>> + Send + 'static) -> Self {
+            let (tx, rx) = futures::channel::mpsc::unbounded();
+            Self {
+                written: Vec::new(),
+                reply: Box::new(reply),
+                drop_at_lines: Vec::new(),
+                reconnects: 0,
+                tx,
+                notifications: Box::pin(rx),
+            }
+        }
+
+        /// Line numbers of the line packets, in send order.
+        fn sent_lines(&self) -> Vec<u16> {
+            self.written
+                .iter()
+                .filter(|p| p[0] == 0x55)
+                .map(|p| u16::from_be_bytes([p[1], p[2]]))
+                .collect()
+        }
     }
 
-    let mut payload = Vec::with_capacity(7);
-    payload.push(0u8);
-    payload.extend_from_slice(&mac);
+    impl PrinterTransport for MockTransport {
+        async fn write(&mut self, packet: &[u8]) -> Result<()> {
+            if let [0x55, hi, lo, ..] = packet {
+                let line = u16::from_be_bytes([*hi, *lo]);
+                if let Some(i) = self.drop_at_lines.iter().position(|l| *l == line) {
+                    self.drop_at_lines.remove(i);
+                    bail!("link dropped");
+                }
+            }
+            self.written.push(packet.to_vec());
+            for value in (self.reply)(packet) {
+                let note = ValueNotification {
+                    uuid: Uuid::parse_str(READ_UUID_STR).unwrap(),
+                    value,
+                };
+                self.tx.unbounded_send(note).unwrap();
+            }
+            Ok(())
+        }
 
-    let response = ((crc16_xmodem(&payload) >> 8) & 0xff) as u8;
+        fn notifications(&mut self) -> &mut NotificationStream {
+            &mut self.notifications
+        }
 
-    let mut out = vec![0x5a, 0x0b];
-    out.extend(std::iter::repeat_n(response, 10));
-    Ok(out)
-}
+        async fn reconnect(&mut self) -> Result<()> {
+            self.reconnects += 1;
+            Ok(())
+        }
+    }
 
-fn print_event_packet(num_lines: u16, end: bool) -> Vec<u8> {
-    let mut out = vec![0x5a, 0x04];
-    out.extend_from_slice(&num_lines.to_be_bytes());
-    let end_u16: u16 = if end { 1 } else { 0 };
-    out.extend_from_slice(&end_u16.to_le_bytes());
-    out
-}
+    /// A printer that accepts the handshake and reports the job finished once `last_line` arrives.
+    fn happy_printer(last_line: u16) -> impl FnMut(&[u8]) -> Vec<Vec<u8>> + Send {
+        move |packet| match packet {
+            [0x5a, 0x0a, ..] => vec![HANDSHAKE_0A.to_vec()],
+            [0x5a, 0x0b, ..] => vec![vec![0x5a, 0x0b, 0x01]],
+            [0x55, hi, lo, ..] if u16::from_be_bytes([*hi, *lo]) == last_line => {
+                vec![PRINTING_FINISHED.to_vec()]
+            }
+            _ => Vec::new(),
+        }
+    }
 
-fn print_line_packet(line_no: u16, line_data: &PackedLine) -> Vec<u8> {
-    let mut out = vec![0x55];
-    out.extend_from_slice(&line_no.to_be_bytes());
-    out.extend_from_slice(line_data);
-    out.push(0x00);
-    out
-}
+    fn test_lines(n: usize) -> Vec<PackedLine> {
+        (0..n).map(|i| [i as u8; PACKED_LINE_BYTES]).collect()
+    }
 
-fn crc16_xmodem(data: &[u8]) -> u16 {
-    let mut crc: u16 = 0;
-    for byte in data {
-        for bit_idx in 0..8 {
-            let bit = (byte >> (7 - bit_idx)) & 1;
-            let c15 = (crc >> 15) & 1;
-            crc <<= 1;
-            if (c15 ^ bit as u16) != 0 {
-                crc ^= 0x1021;
+    /// Default tuning without the blank tail, so the job is exactly the lines sent.
+    fn no_tail_feed() -> PrintTuning {
+        PrintTuning {
+            tail_feed_lines: 0,
+            ..PrintTuning::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn job_runs_whole_protocol() {
+        let lines = test_lines(3);
+        let mut mock = MockTransport::new(happy_printer(2));
+        let report = print_with_transport(&mut mock, ADDRESS, &lines, 4, &no_tail_feed())
+            .await
+            .unwrap();
+        assert!(!report.throttled);
+
+        let mut expected = vec![
+            hardware_info_packet(),
+            handshake_0a_packet(),
+            handshake_0b_packet(ADDRESS).unwrap(),
+            density_packet(4),
+            print_event_packet(3, false),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            expected.push(print_line_packet(i as u16, line));
+        }
+        expected.push(print_event_packet(3, true));
+        assert_eq!(mock.written, expected);
+
+        let listed = job_packets(ADDRESS, &lines, 4, &no_tail_feed()).unwrap();
+        let listed: Vec<_> = listed.into_iter().map(|p| p.bytes).collect();
+        assert_eq!(listed, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tail_feed_is_sent_before_the_end_packet() {
+        let mut mock = MockTransport::new(happy_printer(4));
+        let tuning = PrintTuning {
+            tail_feed_lines: 2,
+            ..PrintTuning::default()
+        };
+        print_with_transport(&mut mock, ADDRESS, &test_lines(3), 4, &tuning)
+            .await
+            .unwrap();
+        assert_eq!(mock.sent_lines(), [0, 1, 2, 3, 4]);
+        let blank = [0u8; PACKED_LINE_BYTES];
+        assert!(mock.written.contains(&print_line_packet(4, &blank)));
+        assert!(mock.written.contains(&print_event_packet(5, false)));
+        assert_eq!(mock.written.last(), Some(&print_event_packet(5, true)));
+        assert_eq!(mm_to_packed_lines(0.0), 0);
+        assert_eq!(mm_to_packed_lines(DEFAULT_TAIL_FEED_MM), 20);
+        assert_eq!(
+            estimate_print_duration(100, &tuning),
+            SESSION_SETUP_ESTIMATE + LINE_DELAY * 102
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_link_resumes_from_the_failed_line() {
+        let mut mock = MockTransport::new(happy_printer(4));
+        mock.drop_at_lines = vec![2];
+        let tuning = PrintTuning {
+            resume_on_disconnect: true,
+            ..no_tail_feed()
+        };
+        let report = print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &tuning)
+            .await
+            .unwrap();
+        assert_eq!(report.resumes, 1);
+        assert_eq!(mock.reconnects, 1);
+        assert_eq!(mock.sent_lines(), [0, 1, 2, 3, 4]);
+        let job_starts = mock
+            .written
+            .iter()
+            .filter(|p| **p == print_event_packet(5, false))
+            .count();
+        assert_eq!(job_starts, 2);
+
+        // Off by default, and never more than MAX_RESUMES per job.
+        let mut mock = MockTransport::new(happy_printer(4));
+        mock.drop_at_lines = vec![2];
+        assert!(
+            print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &no_tail_feed())
+                .await
+                .is_err()
+        );
+        let mut mock = MockTransport::new(happy_printer(4));
+        mock.drop_at_lines = vec![1, 2, 3];
+        let err = print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &tuning)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("link dropped"), "{err}");
+        assert_eq!(mock.reconnects, MAX_RESUMES as usize);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_packet_resends_from_reported_line() {
+        let mut lost_sent = false;
+        let mut printer = happy_printer(4);
+        let mut mock = MockTransport::new(move |packet| {
+            if !lost_sent && packet.starts_with(&[0x55, 0, 3]) {
+                lost_sent = true;
+                return vec![vec![0x5a, 0x05, 0, 2]];
+            }
+            printer(packet)
+        });
+        print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &no_tail_feed())
+            .await
+            .unwrap();
+        assert_eq!(mock.sent_lines(), [0, 1, 2, 3, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queued_lost_reports_rewind_once() {
+        let burst = |max_notifications_per_line| {
+            let mut lost_sent = false;
+            let mut printer = happy_printer(4);
+            let mock = MockTransport::new(move |packet| {
+                if !lost_sent && packet.starts_with(&[0x55, 0, 3]) {
+                    lost_sent = true;
+                    return vec![vec![0x5a, 0x05, 0, 3], vec![0x5a, 0x05, 0, 2]];
+                }
+                printer(packet)
+            });
+            let tuning = PrintTuning {
+                max_notifications_per_line,
+                ..no_tail_feed()
+            };
+            (mock, tuning)
+        };
+
+        let (mut mock, tuning) = burst(16);
+        print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &tuning)
+            .await
+            .unwrap();
+        assert_eq!(mock.sent_lines(), [0, 1, 2, 3, 1, 2, 3, 4]);
+
+        // Capped at one per line, the second report only lands after another line went out.
+        let (mut mock, tuning) = burst(1);
+        print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &tuning)
+            .await
+            .unwrap();
+        assert_eq!(mock.sent_lines(), [0, 1, 2, 3, 2, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_ack_paces_by_the_ack_window() {
+        let run = |ack_mode| async move {
+            let mut lost_sent = false;
+            let mut printer = happy_printer(4);
+            let mut mock = MockTransport::new(move |packet| {
+                if !lost_sent && packet.starts_with(&[0x55, 0, 3]) {
+                    lost_sent = true;
+                    return vec![vec![0x5a, 0x05, 0, 3]];
+                }
+                printer(packet)
+            });
+            let tuning = PrintTuning {
+                ack_mode,
+                ..no_tail_feed()
+            };
+            let started = Instant::now();
+            print_with_transport(&mut mock, ADDRESS, &test_lines(5), 3, &tuning)
+                .await
+                .unwrap();
+            (mock.sent_lines(), started.elapsed())
+        };
+
+        let (fixed_lines, fixed_time) = run(AckMode::FireAndForget).await;
+        let (acked_lines, acked_time) = run(AckMode::WaitAck).await;
+        assert_eq!(fixed_lines, [0, 1, 2, 3, 2, 3, 4]);
+        assert_eq!(acked_lines, fixed_lines);
+        // Lines answered at once (the lost report, the finish) wait for nothing; the five quiet ones
+        // cost a 15 ms window instead of 5 ms of listening plus 20 ms of delay, and fixed pacing
+        // still sleeps 20 ms after the answered two.
+        assert_eq!(fixed_time - acked_time, Duration::from_millis(90));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pause_and_resume_are_reported() {
+        let mut paused_sent = false;
+        let mut printer = happy_printer(4);
+        let mut mock = MockTransport::new(move |packet| {
+            if !paused_sent && packet.starts_with(&[0x55, 0, 2]) {
+                paused_sent = true;
+                return vec![PRINTING_PAUSED.to_vec(), vec![0x5a, 0x05, 0, 2]];
             }
+            printer(packet)
+        });
+        let tuning = no_tail_feed();
+        open_session(&mut mock, ADDRESS, &tuning).await.unwrap();
+        let mut events = Vec::new();
+        let report = send_lines(&mut mock, ADDRESS, &test_lines(5), 3, &tuning, &mut |ev| {
+            events.push(ev)
+        })
+        .await
+        .unwrap();
+        assert!(report.paused);
+        assert_eq!(events, [PrintEvent::Paused, PrintEvent::Resumed]);
+    }
+
+    #[test]
+    fn status_packet_flags() {
+        let status = |value: Vec<u8>| {
+            let note = ValueNotification {
+                uuid: Uuid::parse_str(READ_UUID_STR).unwrap(),
+                value,
+            };
+            match decode_notify(&note) {
+                NotifyEvent::Status(st) => st,
+                other => panic!("not a status: {other:?}"),
+            }
+        };
+        assert_eq!(
+            status(vec![0x5a, 0x02, 80, 1, 0, 0]),
+            StatusEvent {
+                battery: 80,
+                no_paper: true,
+                overheat: false,
+            }
+        );
+        assert_eq!(
+            status(vec![0x5a, 0x02, 15, 0, 0, 1]),
+            StatusEvent {
+                battery: 15,
+                no_paper: false,
+                overheat: true,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handshake_is_resent_after_timeout() {
+        let mut ignored = false;
+        let mut printer = happy_printer(0);
+        let mut mock = MockTransport::new(move |packet| {
+            if !ignored && packet.starts_with(&HANDSHAKE_0A) {
+                ignored = true;
+                return Vec::new();
+            }
+            printer(packet)
+        });
+        print_with_transport(&mut mock, ADDRESS, &test_lines(1), 3, &no_tail_feed())
+            .await
+            .unwrap();
+        let sent_0a = mock
+            .written
+            .iter()
+            .filter(|p| p.starts_with(&HANDSHAKE_0A))
+            .count();
+        assert_eq!(sent_0a, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejected_handshake_prints_nothing() {
+        let mut mock = MockTransport::new(|packet: &[u8]| match packet {
+            [0x5a, 0x0a, ..] => vec![HANDSHAKE_0A.to_vec()],
+            [0x5a, 0x0b, ..] => vec![vec![0x5a, 0x0b, 0x00]],
+            _ => Vec::new(),
+        });
+        let err = print_with_transport(&mut mock, ADDRESS, &test_lines(2), 3, &no_tail_feed())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("rejected"), "{err}");
+        assert!(mock.sent_lines().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overheat_lowers_density_while_adaptive() {
+        let mut printer = happy_printer(2);
+        let mut mock = MockTransport::new(move |packet| {
+            if packet.starts_with(&[0x55, 0, 0]) {
+                // battery 80, paper ok, overheat
+                return vec![vec![0x5a, 0x02, 80, 0, 0, 1]];
+            }
+            printer(packet)
+        });
+        let tuning = PrintTuning {
+            adaptive_density: true,
+            ..no_tail_feed()
+        };
+        let report = print_with_transport(&mut mock, ADDRESS, &test_lines(3), 5, &tuning)
+            .await
+            .unwrap();
+        assert!(report.throttled);
+        assert!(mock.written.contains(&density_packet(3)));
+    }
+
+    #[test]
+    fn long_job_is_rejected_as_too_long() {
+        let lines = vec![[0u8; PACKED_LINE_BYTES]; 11];
+        assert!(check_job(&lines, 3, Some(11)).is_ok());
+        assert!(check_job(&lines, 3, None).is_ok());
+
+        let err = check_job(&lines, 3, Some(10)).unwrap_err();
+        match err.downcast_ref::<PrintError>() {
+            Some(PrintError::TooLong { lines, max_lines }) => {
+                assert_eq!((*lines, *max_lines), (11, 10));
+            }
+            None => panic!("unexpected error: {err}"),
         }
     }
-    crc
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn model_is_guessed_from_name_prefix() {
+        assert_eq!(
+            PrinterModel::from_local_name("FunnyPrint-06B3"),
+            Some(PrinterModel::FunnyPrint)
+        );
+        assert_eq!(
+            PrinterModel::from_local_name(" XIQI"),
+            Some(PrinterModel::FunnyPrint)
+        );
+        assert_eq!(PrinterModel::from_local_name("Mi Band 7"), None);
+    }
+
+    #[test]
+    fn name_prefix_filters_candidates() {
+        let named = |name: Option<&str>| PeripheralProperties {
+            local_name: name.map(str::to_string),
+            ..PeripheralProperties::default()
+        };
+        assert!(candidate_info(named(Some("Mi Band 7")), None).is_some());
+        assert!(candidate_info(named(Some("MX06")), Some("mx")).is_some());
+        assert!(candidate_info(named(Some("Mi Band 7")), Some("MX")).is_none());
+        assert!(candidate_info(named(Some("M")), Some("MX")).is_none());
+        assert!(candidate_info(named(None), Some("MX")).is_none());
+    }
 
     #[test]
-    fn crc_known_value() {
-        let v = crc16_xmodem(&[0x00, 0xc0, 0x00, 0x00, 0x00, 0x05, 0xab]);
-        assert_ne!(v, 0);
+    fn small_packet_is_single_write() {
+        let packet = density_packet(3);
+        let writes = plan_writes(&packet, Some(20), WriteType::WithoutResponse);
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].0, packet.as_slice());
+        assert_eq!(writes[0].1, WriteType::WithoutResponse);
     }
 
     #[test]
-    fn line_packet_size() {
-        let line = [0u8; PACKED_LINE_BYTES];
-        let p = print_line_packet(1, &line);
-        assert_eq!(p.len(), 1 + 2 + PACKED_LINE_BYTES + 1);
+    fn line_packet_is_chunked_in_order() {
+        let mut line = [0u8; PACKED_LINE_BYTES];
+        for (i, b) in line.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let packet = print_line_packet(7, &line);
+
+        let writes = plan_writes(&packet, Some(20), WriteType::WithoutResponse);
+        assert_eq!(writes.len(), packet.len().div_ceil(20));
+
+        let mut sink = Vec::new();
+        for (chunk, write_type) in writes {
+            assert!(chunk.len() <= 20);
+            assert_eq!(write_type, WriteType::WithResponse);
+            sink.extend_from_slice(chunk);
+        }
+        assert_eq!(sink, packet);
     }
 }
@@ -0,0 +1,146 @@
+//! Builders for the packets written to the printer's `ffe1` characteristic.
+//!
+//! A job is `hardware_info`, the two-step handshake, `density`, a start `print_event`, one `line`
+//! per [`PackedLine`] and an end `print_event`; [`crate::job_packets`] lists them for a job and
+//! [`crate::print_job`] sends them. Each builder returns one whole packet; splitting it for a small
+//! MTU is up to the transport.
+
+use anyhow::{Context, Result, bail};
+
+use crate::PackedLine;
+
+/// `5a 01` followed by 10 zero bytes. Opens every session; the printer answers with its hardware
+/// info.
+pub fn hardware_info_packet() -> Vec<u8> {
+    vec![0x5a, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+}
+
+/// `5a 0c DD`: heat setting `DD` (0..=7, darker as it grows) for the lines that follow. Values
+/// above 7 are sent as is; callers validate them.
+pub fn density_packet(density: u8) -> Vec<u8> {
+    vec![0x5a, 0x0c, density]
+}
+
+/// `5a 0a` followed by 10 zero bytes. First handshake step; the printer replies with a packet
+/// starting `5a 0a`.
+pub fn handshake_0a_packet() -> Vec<u8> {
+    let mut packet = vec![0x5a, 0x0a];
+    packet.extend_from_slice(&[0u8; 10]);
+    packet
+}
+
+/// `5a 0b` followed by 10 copies of one byte: the high byte of the CRC-16/XMODEM of `00` and the
+/// six bytes of `bdaddr`. Second handshake step; the printer confirms with `5a 0b 01`.
+///
+/// `bdaddr` is the printer's MAC, with `:`, `-` or no separators.
+pub fn handshake_0b_packet(bdaddr: &str) -> Result<Vec<u8>> {
+    let mut mac_hex = bdaddr.replace(':', "");
+    mac_hex = mac_hex.replace('-', "");
+    if mac_hex.len() != 12 {
+        bail!("expected a 6-byte MAC address, got: {bdaddr}");
+    }
+    let mut mac = [0u8; 6];
+    for (idx, out) in mac.iter_mut().enumerate() {
+        let from = idx * 2;
+        *out = u8::from_str_radix(&mac_hex[from..from + 2], 16)
+            .with_context(|| format!("invalid MAC address: {bdaddr}"))?;
+    }
+
+    let mut payload = Vec::with_capacity(7);
+    payload.push(0u8);
+    payload.extend_from_slice(&mac);
+
+    let response = ((crc16_xmodem(&payload) >> 8) & 0xff) as u8;
+
+    let mut out = vec![0x5a, 0x0b];
+    out.extend(std::iter::repeat_n(response, 10));
+    Ok(out)
+}
+
+/// `5a 04 NN NN EE EE`: the job's line count `NN NN` (big-endian) and `EE EE`, 0 to announce the
+/// job or 1 to close it (little-endian). The count includes any blank tail feed.
+pub fn print_event_packet(num_lines: u16, end: bool) -> Vec<u8> {
+    let mut out = vec![0x5a, 0x04];
+    out.extend_from_slice(&num_lines.to_be_bytes());
+    let end_u16: u16 = if end { 1 } else { 0 };
+    out.extend_from_slice(&end_u16.to_le_bytes());
+    out
+}
+
+/// `55 LL LL`, the line's [`PackedLine`] bytes, then `00`. `LL LL` is `line_no` (big-endian),
+/// counting from 0 within the job; the printer names it when it asks for lines again.
+pub fn print_line_packet(line_no: u16, line_data: &PackedLine) -> Vec<u8> {
+    let mut out = vec![0x55];
+    out.extend_from_slice(&line_no.to_be_bytes());
+    out.extend_from_slice(line_data);
+    out.push(0x00);
+    out
+}
+
+fn crc16_xmodem(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        for bit_idx in 0..8 {
+            let bit = (byte >> (7 - bit_idx)) & 1;
+            let c15 = (crc >> 15) & 1;
+            crc <<= 1;
+            if (c15 ^ bit as u16) != 0 {
+                crc ^= 0x1021;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PACKED_LINE_BYTES;
+
+    #[test]
+    fn crc_known_value() {
+        // The standard CRC-16/XMODEM check value.
+        assert_eq!(crc16_xmodem(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn fixed_packets_match_known_bytes() {
+        assert_eq!(
+            hardware_info_packet(),
+            [0x5a, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            handshake_0a_packet(),
+            [0x5a, 0x0a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(density_packet(5), [0x5a, 0x0c, 5]);
+        assert_eq!(print_event_packet(0x0102, false), [0x5a, 0x04, 1, 2, 0, 0]);
+        assert_eq!(print_event_packet(0x0102, true), [0x5a, 0x04, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn handshake_0b_depends_only_on_the_mac() {
+        let mut expected = vec![0x5a, 0x0b];
+        expected.extend([0x83; 10]);
+        assert_eq!(handshake_0b_packet("C0:00:00:00:06:B3").unwrap(), expected);
+        assert_eq!(handshake_0b_packet("c0-00-00-00-06-b3").unwrap(), expected);
+        assert_eq!(handshake_0b_packet("C000000006B3").unwrap(), expected);
+
+        assert!(handshake_0b_packet("C0:00:00:00:06").is_err());
+        assert!(handshake_0b_packet("C0:00:00:00:06:ZZ").is_err());
+    }
+
+    #[test]
+    fn line_packet_round_trips() {
+        let mut line = [0u8; PACKED_LINE_BYTES];
+        for (i, b) in line.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let p = print_line_packet(0x1234, &line);
+        assert_eq!(p.len(), 1 + 2 + PACKED_LINE_BYTES + 1);
+        assert_eq!(p[0], 0x55);
+        assert_eq!(u16::from_be_bytes([p[1], p[2]]), 0x1234);
+        assert_eq!(&p[3..3 + PACKED_LINE_BYTES], line.as_slice());
+        assert_eq!(p[p.len() - 1], 0x00);
+    }
+}
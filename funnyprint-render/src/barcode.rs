@@ -0,0 +1,383 @@
+//! Code 128 (ISO/IEC 15417) and EAN-13 (ISO/IEC 15420) encoders.
+//!
+//! Only code sets B and C and plain EAN-13 are needed, which is one symbol table and two digit
+//! tables copied from the standards; the tests check them against worked examples.
+
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{Result, bail};
+use funnyprint_proto::MAX_DOTS_PER_LINE;
+use image::{GrayImage, Luma, imageops};
+
+use crate::{FontChain, TextRenderOptions, measure_text_block, render_text_to_image};
+
+/// Linear symbologies supported by [`render_barcode_to_image`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Barcode {
+    /// Printable ASCII; all-digit input of even length is packed two digits per symbol (code set C).
+    Code128,
+    /// 12 digits (check digit is appended) or 13 digits (check digit is verified).
+    Ean13,
+}
+
+impl FromStr for Barcode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "code128" => Ok(Self::Code128),
+            "ean13" | "ean-13" => Ok(Self::Ean13),
+            other => Err(format!("unknown barcode {other:?}, expected code128|ean13")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BarcodeOptions {
+    pub bar_height_px: u32,
+    /// Dots per narrowest bar.
+    pub module_px: u32,
+    /// Blank margin left and right, in modules.
+    pub quiet_zone_modules: u32,
+    /// Print the data as text under the bars with this font.
+    pub human_readable_font: Option<PathBuf>,
+    pub text_size_px: f32,
+}
+
+impl Default for BarcodeOptions {
+    fn default() -> Self {
+        Self {
+            bar_height_px: 80,
+            module_px: 2,
+            quiet_zone_modules: 10,
+            human_readable_font: None,
+            text_size_px: 24.0,
+        }
+    }
+}
+
+pub fn render_barcode_to_image(
+    symbology: Barcode,
+    data: &str,
+    opts: &BarcodeOptions,
+) -> Result<GrayImage> {
+    if opts.module_px == 0 || opts.bar_height_px == 0 {
+        bail!("module_px and bar_height_px must be > 0");
+    }
+    let (modules, label) = match symbology {
+        Barcode::Code128 => (code128_modules(data)?, data.to_string()),
+        Barcode::Ean13 => {
+            let digits = ean13_digits(data)?;
+            let label = digits.iter().map(|d| char::from(b'0' + d)).collect();
+            (ean13_modules(&digits), label)
+        }
+    };
+
+    let total_modules = modules.len() as u32 + opts.quiet_zone_modules * 2;
+    let width = total_modules * opts.module_px;
+    if width as usize > MAX_DOTS_PER_LINE {
+        bail!(
+            "barcode is {width} dots wide, max is {MAX_DOTS_PER_LINE}; use a smaller module_px, quiet zone or shorter data"
+        );
+    }
+
+    let mut img = GrayImage::from_pixel(width, opts.bar_height_px, Luma([255]));
+    for (i, _) in modules.iter().enumerate().filter(|(_, bar)| **bar) {
+        let x0 = (opts.quiet_zone_modules + i as u32) * opts.module_px;
+        for x in x0..x0 + opts.module_px {
+            for y in 0..opts.bar_height_px {
+                img.put_pixel(x, y, Luma([0]));
+            }
+        }
+    }
+
+    let Some(font) = &opts.human_readable_font else {
+        return Ok(img);
+    };
+    let fonts = FontChain::load(font, &[])?;
+    let (text_w, text_h) = measure_text_block(&fonts, &label, opts.text_size_px, 1.0);
+    let gap = (opts.text_size_px / 4.0).ceil() as u32;
+    let text_opts = TextRenderOptions {
+        width_px: width,
+        height_px: text_h.ceil() as u32,
+        x_px: ((width as f32 - text_w) / 2.0).max(0.0) as i32,
+        font_size_px: opts.text_size_px,
+        ..TextRenderOptions::default()
+    };
+    let text_img = render_text_to_image(&label, font, &text_opts)?;
+
+    let mut out = GrayImage::from_pixel(width, img.height() + gap + text_img.height(), Luma([255]));
+    imageops::replace(&mut out, &img, 0, 0);
+    imageops::replace(&mut out, &text_img, 0, (img.height() + gap) as i64);
+    Ok(out)
+}
+
+/// Bar/space widths for Code 128 symbol values 0..=105 (103-105 are start A/B/C).
+const CODE128_PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232",
+];
+const CODE128_STOP: &str = "2331112";
+const CODE128_START_B: u32 = 104;
+const CODE128_START_C: u32 = 105;
+
+fn code128_values(data: &str) -> Result<Vec<u32>> {
+    if data.is_empty() {
+        bail!("barcode data is empty");
+    }
+
+    let mut values = Vec::new();
+    if data.len().is_multiple_of(2) && data.bytes().all(|b| b.is_ascii_digit()) {
+        values.push(CODE128_START_C);
+        for pair in data.as_bytes().chunks(2) {
+            values.push(((pair[0] - b'0') * 10 + (pair[1] - b'0')) as u32);
+        }
+    } else {
+        values.push(CODE128_START_B);
+        for ch in data.chars() {
+            if !(' '..='~').contains(&ch) {
+                bail!("code128 supports printable ASCII only, got {ch:?}");
+            }
+            values.push(ch as u32 - 32);
+        }
+    }
+
+    let checksum = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| i.max(1) as u32 * v)
+        .sum::<u32>()
+        % 103;
+    values.push(checksum);
+    Ok(values)
+}
+
+fn code128_modules(data: &str) -> Result<Vec<bool>> {
+    let mut modules = Vec::new();
+    for v in code128_values(data)? {
+        push_widths(&mut modules, CODE128_PATTERNS[v as usize]);
+    }
+    push_widths(&mut modules, CODE128_STOP);
+    Ok(modules)
+}
+
+/// Expands alternating bar/space widths, starting with a bar.
+fn push_widths(modules: &mut Vec<bool>, widths: &str) {
+    for (i, w) in widths.bytes().enumerate() {
+        let bar = i % 2 == 0;
+        modules.extend(std::iter::repeat_n(bar, (w - b'0') as usize));
+    }
+}
+
+/// Left-hand odd-parity ("L") codes; "R" codes are their complement and "G" codes the reversed "R".
+const EAN_L: [u8; 10] = [
+    0b0001101, 0b0011001, 0b0010011, 0b0111101, 0b0100011, 0b0110001, 0b0101111, 0b0111011,
+    0b0110111, 0b0001011,
+];
+/// Which of the six left digits use "G" codes, indexed by the first digit (bit 5 = second digit).
+const EAN_PARITY: [u8; 10] = [
+    0b000000, 0b001011, 0b001101, 0b001110, 0b010011, 0b011001, 0b011100, 0b010101, 0b010110,
+    0b011010,
+];
+
+fn ean13_check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits[..12]
+        .iter()
+        .enumerate()
+        .map(|(i, d)| *d as u32 * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+fn ean13_digits(data: &str) -> Result<Vec<u8>> {
+    if !data.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("ean13 data must be digits only");
+    }
+    let mut digits: Vec<u8> = data.bytes().map(|b| b - b'0').collect();
+    match digits.len() {
+        12 => digits.push(ean13_check_digit(&digits)),
+        13 => {
+            let expected = ean13_check_digit(&digits);
+            if digits[12] != expected {
+                bail!("ean13 check digit is {}, expected {expected}", digits[12]);
+            }
+        }
+        n => bail!("ean13 needs 12 or 13 digits, got {n}"),
+    }
+    Ok(digits)
+}
+
+fn ean13_modules(digits: &[u8]) -> Vec<bool> {
+    let push_code = |modules: &mut Vec<bool>, code: u8| {
+        for bit in (0..7).rev() {
+            modules.push(code >> bit & 1 == 1);
+        }
+    };
+
+    let mut modules = vec![true, false, true];
+    let parity = EAN_PARITY[digits[0] as usize];
+    for (i, d) in digits[1..7].iter().enumerate() {
+        let l = EAN_L[*d as usize];
+        let use_g = parity >> (5 - i) & 1 == 1;
+        let code = if use_g {
+            (!l & 0x7f).reverse_bits() >> 1
+        } else {
+            l
+        };
+        push_code(&mut modules, code);
+    }
+    modules.extend([false, true, false, true, false]);
+    for d in &digits[7..] {
+        push_code(&mut modules, !EAN_L[*d as usize] & 0x7f);
+    }
+    modules.extend([true, false, true]);
+    modules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code128_table_is_consistent() {
+        let mut seen = std::collections::HashSet::new();
+        for p in CODE128_PATTERNS {
+            let widths: Vec<u8> = p.bytes().map(|b| b - b'0').collect();
+            assert_eq!(widths.iter().map(|w| *w as u32).sum::<u32>(), 11, "{p}");
+            let bars: u8 = widths.iter().step_by(2).sum();
+            assert_eq!(bars % 2, 0, "{p} has odd bar parity");
+            assert!(seen.insert(p), "{p} repeated");
+        }
+    }
+
+    #[test]
+    fn code128_checksum() {
+        let values = code128_values("Wikipedia").unwrap();
+        assert_eq!(values[0], CODE128_START_B);
+        assert_eq!(*values.last().unwrap(), 88);
+
+        let digits = code128_values("123456").unwrap();
+        assert_eq!(digits[..4], [CODE128_START_C, 12, 34, 56]);
+        assert!(code128_values("é").is_err());
+    }
+
+    #[test]
+    fn ean13_layout() {
+        let digits = ean13_digits("400638133393").unwrap();
+        assert_eq!(digits[12], 1);
+        assert!(ean13_digits("4006381333930").is_err());
+
+        let modules = ean13_modules(&digits);
+        assert_eq!(modules.len(), 95);
+        // Leading 4 selects LGLLGG, so the first left digit (0) is L = 0001101.
+        assert_eq!(
+            modules[3..10],
+            [false, false, false, true, true, false, true]
+        );
+        // Second left digit (0) is G = 0100111.
+        assert_eq!(
+            modules[10..17],
+            [false, true, false, false, true, true, true]
+        );
+    }
+
+    fn bits(modules: &[bool]) -> String {
+        modules.iter().map(|m| if *m { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn code128_set_b_vector() {
+        // Start B, 'A' (33), 'B' (34), check (104 + 33 + 2 * 34) % 103 = 102, stop.
+        let expected = [
+            "11010010000",
+            "10100011000",
+            "10001011000",
+            "11110101110",
+            "1100011101011",
+        ];
+        assert_eq!(bits(&code128_modules("AB").unwrap()), expected.concat());
+    }
+
+    #[test]
+    fn code128_set_c_vector() {
+        // Start C, 12, 34, 56, check (105 + 12 + 2 * 34 + 3 * 56) % 103 = 44, stop.
+        let expected = [
+            "11010011100",
+            "10110011100",
+            "10001011000",
+            "11100010110",
+            "10001101110",
+            "1100011101011",
+        ];
+        assert_eq!(bits(&code128_modules("123456").unwrap()), expected.concat());
+    }
+
+    /// The EAN-13 code tables as printed in the standard, digits 0..=9.
+    const L: [&str; 10] = [
+        "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011",
+        "0110111", "0001011",
+    ];
+    const G: [&str; 10] = [
+        "0100111", "0110011", "0011011", "0100001", "0011101", "0111001", "0000101", "0010001",
+        "0001001", "0010111",
+    ];
+    const R: [&str; 10] = [
+        "1110010", "1100110", "1101100", "1000010", "1011100", "1001110", "1010000", "1000100",
+        "1001000", "1110100",
+    ];
+    const PARITY: [&str; 10] = [
+        "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL",
+        "LGGLGL",
+    ];
+
+    #[test]
+    fn ean13_matches_the_code_tables() {
+        let encode = |digits: &[u8]| {
+            let mut out = String::from("101");
+            for (d, set) in digits[1..7].iter().zip(PARITY[digits[0] as usize].chars()) {
+                out += if set == 'G' { G } else { L }[*d as usize];
+            }
+            out += "01010";
+            for d in &digits[7..] {
+                out += R[*d as usize];
+            }
+            out + "101"
+        };
+
+        // Every first digit, so every parity pattern, and every digit on both halves.
+        for first in 0..10u8 {
+            let mut digits = vec![first];
+            digits.extend((0..11).map(|i| (first + i as u8) % 10));
+            digits.push(ean13_check_digit(&digits));
+            assert_eq!(bits(&ean13_modules(&digits)), encode(&digits), "{digits:?}");
+        }
+
+        let digits = ean13_digits("4006381333931").unwrap();
+        assert_eq!(bits(&ean13_modules(&digits)), encode(&digits));
+    }
+
+    #[test]
+    fn barcode_width_is_checked() {
+        let opts = BarcodeOptions {
+            module_px: 4,
+            ..BarcodeOptions::default()
+        };
+        assert!(render_barcode_to_image(Barcode::Code128, "TOO-LONG-FOR-PAPER", &opts).is_err());
+
+        let img =
+            render_barcode_to_image(Barcode::Ean13, "400638133393", &BarcodeOptions::default())
+                .unwrap();
+        assert_eq!(img.width(), (95 + 20) * 2);
+        assert_eq!(img.height(), 80);
+    }
+}
@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+use ab_glyph::PxScale;
+use anyhow::{Result, bail};
+use image::{GrayImage, Luma};
+
+use crate::{FontChain, content_bbox, draw_border, draw_shaped_line, line_height, shape_line};
+
+/// Where a [`TextBlock`]'s `x_px` sits on each of its lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextAlign {
+    /// `x_px` is the left edge.
+    #[default]
+    Left,
+    /// `x_px` is the middle.
+    Center,
+    /// `x_px` is the right edge.
+    Right,
+}
+
+impl FromStr for TextAlign {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "left" => Ok(Self::Left),
+            "center" | "centre" => Ok(Self::Center),
+            "right" => Ok(Self::Right),
+            other => Err(format!(
+                "unknown align {other:?}, expected left|center|right"
+            )),
+        }
+    }
+}
+
+/// One independently placed run of text; `y_px` is the top of its first line.
+#[derive(Debug, Clone)]
+pub struct TextBlock {
+    pub text: String,
+    pub x_px: i32,
+    pub y_px: i32,
+    pub font_size_px: f32,
+    pub align: TextAlign,
+}
+
+/// The canvas [`render_text_blocks`] draws onto.
+#[derive(Debug, Clone)]
+pub struct CanvasOptions {
+    pub width_px: u32,
+    pub height_px: u32,
+    pub line_spacing: f32,
+    /// Pixels at or below this count as printed when checking that something was drawn.
+    pub threshold: u8,
+    pub invert: bool,
+    pub border_px: u32,
+    pub border_margin_px: u32,
+    /// Mirror the finished canvas left to right.
+    pub mirror_h: bool,
+}
+
+/// Draws every block onto one canvas, e.g. a name, a price and a date on the same label.
+///
+/// Fails if a block's laid-out box leaves the canvas or if nothing printable was drawn.
+pub fn render_text_blocks(
+    fonts: &FontChain,
+    blocks: &[TextBlock],
+    opts: &CanvasOptions,
+) -> Result<GrayImage> {
+    if opts.width_px == 0 || opts.height_px == 0 {
+        bail!("canvas must be at least 1x1");
+    }
+    let mut img = GrayImage::from_pixel(opts.width_px, opts.height_px, Luma([255]));
+
+    for (idx, block) in blocks.iter().enumerate() {
+        if block.font_size_px <= 0.0 {
+            bail!("block {idx}: font_size_px must be > 0");
+        }
+        let scale = PxScale::from(block.font_size_px);
+        let line_h = line_height(fonts, scale, opts.line_spacing);
+        let lines: Vec<&str> = block.text.split('\n').collect();
+
+        let mut placed = Vec::with_capacity(lines.len());
+        for (line_idx, line) in lines.iter().enumerate() {
+            let shaped = shape_line(fonts, scale, line);
+            let width = shaped.width.ceil() as i32;
+            let x = match block.align {
+                TextAlign::Left => block.x_px,
+                TextAlign::Center => block.x_px - width / 2,
+                TextAlign::Right => block.x_px - width,
+            };
+            let y = block.y_px + (line_idx as f32 * line_h).round() as i32;
+            let right = x + width;
+            let bottom = y + line_h.ceil() as i32;
+            if x < 0 || y < 0 || right > opts.width_px as i32 || bottom > opts.height_px as i32 {
+                bail!(
+                    "block {idx} line {line_idx} spans ({x}, {y})-({right}, {bottom}), outside the {}x{} canvas",
+                    opts.width_px,
+                    opts.height_px
+                );
+            }
+            placed.push((x, y, shaped));
+        }
+        for (x, y, shaped) in &placed {
+            draw_shaped_line(&mut img, fonts, scale, *x, *y, shaped);
+        }
+    }
+
+    if content_bbox(&img, opts.threshold).is_none() {
+        bail!("all text blocks rendered blank");
+    }
+
+    if opts.invert {
+        for p in img.pixels_mut() {
+            p.0[0] = 255 - p.0[0];
+        }
+    }
+    draw_border(&mut img, opts.border_px, opts.border_margin_px);
+    if opts.mirror_h {
+        image::imageops::flip_horizontal_in_place(&mut img);
+    }
+
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_fonts;
+
+    fn canvas() -> CanvasOptions {
+        CanvasOptions {
+            width_px: 384,
+            height_px: 120,
+            line_spacing: 1.0,
+            threshold: 180,
+            invert: false,
+            border_px: 0,
+            border_margin_px: 0,
+            mirror_h: false,
+        }
+    }
+
+    fn block(text: &str, x_px: i32, y_px: i32, align: TextAlign) -> TextBlock {
+        TextBlock {
+            text: text.to_string(),
+            x_px,
+            y_px,
+            font_size_px: 32.0,
+            align,
+        }
+    }
+
+    #[test]
+    fn blocks_land_where_aligned() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let blocks = [
+            block("Milk", 0, 0, TextAlign::Left),
+            block("1.99", 384, 60, TextAlign::Right),
+        ];
+        let img = render_text_blocks(&fonts, &blocks, &canvas()).unwrap();
+        let (x0, _, _, _) = content_bbox(&img, 180).unwrap();
+        assert!(x0 < 10, "left block starts at {x0}");
+
+        let bottom = image::imageops::crop_imm(&img, 0, 60, 384, 60).to_image();
+        let (bx0, _, bx1, _) = content_bbox(&bottom, 180).unwrap();
+        assert!(bx0 > 300 && bx1 > 370, "right block spans {bx0}..{bx1}");
+    }
+
+    #[test]
+    fn blocks_outside_canvas_or_blank_fail() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let off_edge = [block("Milk", 380, 0, TextAlign::Left)];
+        assert!(render_text_blocks(&fonts, &off_edge, &canvas()).is_err());
+        let below = [block("Milk", 0, 110, TextAlign::Left)];
+        assert!(render_text_blocks(&fonts, &below, &canvas()).is_err());
+        let blank = [block(" ", 0, 0, TextAlign::Center)];
+        assert!(render_text_blocks(&fonts, &blank, &canvas()).is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use image::{GrayImage, Luma};
+
+/// Black for values `<= threshold`, white otherwise.
+pub fn threshold_binarize(gray: &GrayImage, threshold: u8, invert: bool) -> GrayImage {
+    let mut out = GrayImage::new(gray.width(), gray.height());
+    for (x, y, p) in gray.enumerate_pixels() {
+        let mut v = p.0[0];
+        if invert {
+            v = 255 - v;
+        }
+        let bw = if v <= threshold { 0u8 } else { 255u8 };
+        out.put_pixel(x, y, Luma([bw]));
+    }
+    out
+}
+
+/// Share of pixels that must be near black or near white for [`classify_content`] to call an
+/// image line art; anti-aliased edges make up most of the rest.
+const LINE_ART_EXTREMES: f32 = 0.9;
+
+/// What a grayscale image mostly holds, as judged by [`classify_content`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    /// Text, logos, scans: nearly everything is close to black or white.
+    LineArt,
+    /// Continuous tones that need dithering.
+    Photo,
+}
+
+/// Line art when at least 90% of the pixels are within a quarter of the range from black or
+/// white, photo otherwise.
+pub fn classify_content(gray: &GrayImage) -> ContentKind {
+    let total = gray.width() as usize * gray.height() as usize;
+    let extremes = gray
+        .pixels()
+        .filter(|p| p.0[0] < 64 || p.0[0] >= 192)
+        .count();
+    if total == 0 || extremes as f32 >= total as f32 * LINE_ART_EXTREMES {
+        ContentKind::LineArt
+    } else {
+        ContentKind::Photo
+    }
+}
+
+/// Otsu's method: the threshold (black for values `<=` it) that best separates the histogram into
+/// a dark and a light class.
+pub fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut hist = [0u64; 256];
+    for p in gray.pixels() {
+        hist[p.0[0] as usize] += 1;
+    }
+    let total: u64 = hist.iter().sum();
+    let sum_all: f64 = hist
+        .iter()
+        .enumerate()
+        .map(|(v, n)| v as f64 * *n as f64)
+        .sum();
+
+    let (mut dark, mut sum_dark) = (0u64, 0f64);
+    let (mut best, mut best_var) = (127u8, -1f64);
+    for (v, n) in hist.iter().enumerate() {
+        dark += n;
+        sum_dark += v as f64 * *n as f64;
+        let light = total - dark;
+        if dark == 0 || light == 0 {
+            continue;
+        }
+        let mean_dark = sum_dark / dark as f64;
+        let mean_light = (sum_all - sum_dark) / light as f64;
+        let var = dark as f64 * light as f64 * (mean_dark - mean_light).powi(2);
+        if var > best_var {
+            best_var = var;
+            best = v as u8;
+        }
+    }
+    best
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DitherOptions {
+    /// Scan odd rows right to left with the kernel mirrored, which avoids the diagonal "worm"
+    /// patterns of a fixed scan direction.
+    pub serpentine: bool,
+    /// Limit the quantization error passed on from each pixel to `±error_clamp`. Low values (e.g.
+    /// 32) stop error piling up along hard edges in dense photos, at the cost of flattening
+    /// midtones; `None` is plain Floyd–Steinberg.
+    pub error_clamp: Option<f32>,
+}
+
+/// Floyd–Steinberg error diffusion.
+pub fn floyd_steinberg_binarize(
+    gray: &GrayImage,
+    threshold: u8,
+    invert: bool,
+    opts: &DitherOptions,
+) -> GrayImage {
+    let w = gray.width() as usize;
+    let h = gray.height() as usize;
+    let mut buf = vec![0f32; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let mut v = gray.get_pixel(x as u32, y as u32).0[0] as f32;
+            if invert {
+                v = 255.0 - v;
+            }
+            buf[y * w + x] = v;
+        }
+    }
+
+    let mut out = GrayImage::new(gray.width(), gray.height());
+    diffuse_image(&mut buf, w, h, threshold, opts, |x, y, v| {
+        out.put_pixel(x as u32, y as u32, Luma([v]));
+    });
+    out
+}
+
+/// Quantizes `buf` in scan order, handing each pixel's 0/255 result to `put`. `buf` is left with
+/// the values seen by the quantizer (source plus diffused error).
+fn diffuse_image(
+    buf: &mut [f32],
+    w: usize,
+    h: usize,
+    threshold: u8,
+    opts: &DitherOptions,
+    mut put: impl FnMut(usize, usize, u8),
+) {
+    for y in 0..h {
+        let reverse = opts.serpentine && y % 2 == 1;
+        for i in 0..w {
+            let x = if reverse { w - 1 - i } else { i };
+            let old = buf[y * w + x].clamp(0.0, 255.0);
+            let new = if old <= threshold as f32 { 0.0 } else { 255.0 };
+            put(x, y, new as u8);
+            let mut err = old - new;
+            if let Some(limit) = opts.error_clamp {
+                err = err.clamp(-limit.abs(), limit.abs());
+            }
+            diffuse_error(buf, w, h, x, y, err, reverse);
+        }
+    }
+}
+
+/// Spreads `err` to the unvisited neighbours of `(x, y)`; `reverse` mirrors the kernel for a
+/// right-to-left scan. Error falling outside the image is dropped.
+fn diffuse_error(buf: &mut [f32], w: usize, h: usize, x: usize, y: usize, err: f32, reverse: bool) {
+    let mut add = |dx: isize, dy: usize, weight: f32| {
+        let dx = if reverse { -dx } else { dx };
+        let Some(nx) = x.checked_add_signed(dx).filter(|nx| *nx < w) else {
+            return;
+        };
+        let ny = y + dy;
+        if ny < h {
+            buf[ny * w + nx] += err * weight / 16.0;
+        }
+    };
+    add(1, 0, 7.0);
+    add(-1, 1, 3.0);
+    add(0, 1, 5.0);
+    add(1, 1, 1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16x8 ramp from black to white in scan order, one step of about 2 per pixel.
+    fn ramp() -> GrayImage {
+        GrayImage::from_fn(16, 8, |x, y| Luma([((x + y * 16) * 255 / 127) as u8]))
+    }
+
+    /// `#` for black, `.` for white, one string per row.
+    fn ascii(img: &GrayImage) -> Vec<String> {
+        (0..img.height())
+            .map(|y| {
+                (0..img.width())
+                    .map(|x| {
+                        if img.get_pixel(x, y).0[0] == 0 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn black_ratio(img: &GrayImage) -> f32 {
+        let black = img.pixels().filter(|p| p.0[0] == 0).count();
+        black as f32 / (img.width() * img.height()) as f32
+    }
+
+    #[test]
+    fn threshold_matches_reference() {
+        let out = threshold_binarize(&ramp(), 127, false);
+        let expected: Vec<String> = (0..8)
+            .map(|y| {
+                if y < 4 {
+                    "#".repeat(16)
+                } else {
+                    ".".repeat(16)
+                }
+            })
+            .collect();
+        assert_eq!(ascii(&out), expected);
+
+        let inverted = threshold_binarize(&ramp(), 127, true);
+        let expected: Vec<String> = expected.into_iter().rev().collect();
+        assert_eq!(ascii(&inverted), expected);
+    }
+
+    #[test]
+    fn floyd_steinberg_matches_reference() {
+        const PLAIN: [&str; 8] = [
+            "################",
+            "###########.##.#",
+            "#.#.#.#.#.##.##.",
+            "##.##.#.#.#.#.#.",
+            ".#.#.#.#.#.#..#.",
+            "..#...#.....#...",
+            ".#..#...#.......",
+            "................",
+        ];
+        const SERPENTINE: [&str; 8] = [
+            "################",
+            "#############.##",
+            "#.#.#.#.#.#.##.#",
+            "##.##.#.#.#.#.#.",
+            ".#.#.#.#.#..#..#",
+            "..#...#...#..#..",
+            ".#..#...........",
+            "................",
+        ];
+        for (serpentine, expected) in [(false, PLAIN), (true, SERPENTINE)] {
+            let opts = DitherOptions {
+                serpentine,
+                ..DitherOptions::default()
+            };
+            let out = floyd_steinberg_binarize(&ramp(), 127, false, &opts);
+            assert_eq!(ascii(&out), expected, "serpentine={serpentine}");
+        }
+    }
+
+    #[test]
+    fn error_diffusion_tracks_source_darkness() {
+        let gradient = GrayImage::from_fn(256, 64, |x, _| Luma([x as u8]));
+        for serpentine in [false, true] {
+            let opts = DitherOptions {
+                serpentine,
+                ..DitherOptions::default()
+            };
+            let out = floyd_steinberg_binarize(&gradient, 127, false, &opts);
+            // Whole image, then each quarter of the ramp on its own.
+            assert!(
+                (black_ratio(&out) - 0.5).abs() < 0.02,
+                "serpentine={serpentine}: {}",
+                black_ratio(&out)
+            );
+            for band in 0..4 {
+                let part = image::imageops::crop_imm(&out, band * 64, 0, 64, 64).to_image();
+                let source = image::imageops::crop_imm(&gradient, band * 64, 0, 64, 64).to_image();
+                let mean = source.pixels().map(|p| p.0[0] as f32).sum::<f32>() / (64.0 * 64.0);
+                let darkness = 1.0 - mean / 255.0;
+                assert!(
+                    (black_ratio(&part) - darkness).abs() < 0.05,
+                    "serpentine={serpentine} band {band}: {} vs {darkness}",
+                    black_ratio(&part)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn classifies_line_art_and_photos() {
+        let two_tone = GrayImage::from_fn(64, 64, |x, _| Luma([if x < 20 { 10 } else { 240 }]));
+        assert_eq!(classify_content(&two_tone), ContentKind::LineArt);
+        let threshold = otsu_threshold(&two_tone);
+        assert!((10..240).contains(&threshold), "{threshold}");
+
+        let gradient = GrayImage::from_fn(256, 8, |x, _| Luma([x as u8]));
+        assert_eq!(classify_content(&gradient), ContentKind::Photo);
+        let threshold = otsu_threshold(&gradient);
+        assert!((120..=135).contains(&threshold), "{threshold}");
+    }
+
+    #[test]
+    fn mirrored_kernel_conserves_error() {
+        let (w, h) = (5, 2);
+        let mut forward = vec![0f32; w * h];
+        let mut reverse = vec![0f32; w * h];
+        diffuse_error(&mut forward, w, h, 2, 0, 16.0, false);
+        diffuse_error(&mut reverse, w, h, 2, 0, 16.0, true);
+
+        assert_eq!(forward.iter().sum::<f32>(), 16.0);
+        assert_eq!(reverse.iter().sum::<f32>(), 16.0);
+        for y in 0..h {
+            for x in 0..w {
+                assert_eq!(
+                    forward[y * w + x],
+                    reverse[y * w + (w - 1 - x)],
+                    "({x}, {y})"
+                );
+            }
+        }
+        // Nothing flows back to already visited pixels.
+        assert_eq!(forward[1], 0.0);
+        assert_eq!(reverse[3], 0.0);
+    }
+
+    #[test]
+    fn serpentine_keeps_mid_gray_density() {
+        let gray = GrayImage::from_pixel(64, 64, Luma([128]));
+        for serpentine in [false, true] {
+            let opts = DitherOptions {
+                serpentine,
+                ..DitherOptions::default()
+            };
+            let out = floyd_steinberg_binarize(&gray, 127, false, &opts);
+            let black = out.pixels().filter(|p| p.0[0] == 0).count() as f32;
+            let ratio = black / (64.0 * 64.0);
+            assert!(
+                (ratio - 0.5).abs() < 0.03,
+                "serpentine={serpentine}: {ratio}"
+            );
+        }
+    }
+
+    #[test]
+    fn error_clamp_limits_accumulated_error() {
+        let max_after = |error_clamp| {
+            let (w, h) = (32, 32);
+            let mut buf = vec![200f32; w * h];
+            let opts = DitherOptions {
+                error_clamp,
+                ..DitherOptions::default()
+            };
+            diffuse_image(&mut buf, w, h, 127, &opts, |_, _, _| {});
+            buf.iter().cloned().fold(f32::MIN, f32::max)
+        };
+
+        let unclamped = max_after(None);
+        let clamped = max_after(Some(16.0));
+        // Kernel weights sum to 1, so a pixel can gain at most the clamp value.
+        assert!(clamped <= 216.0, "{clamped}");
+        assert!(clamped < unclamped, "{clamped} vs {unclamped}");
+    }
+}
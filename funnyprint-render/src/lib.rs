@@ -1,10 +1,85 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
-use anyhow::{Context, Result};
-use funnyprint_proto::{BYTES_PER_LINE, MAX_DOTS_PER_LINE, PackedLine};
-use image::{GrayImage, Luma};
-use imageproc::drawing::draw_text_mut;
+use ab_glyph::{Font, FontArc, GlyphId, GlyphImageFormat, PxScale, ScaleFont, point};
+use anyhow::{Context, Result, bail};
+use funnyprint_proto::MAX_DOTS_PER_LINE;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage, imageops::FilterType};
+use imageproc::{drawing::draw_hollow_rect_mut, rect::Rect};
+use unicode_bidi::BidiInfo;
+
+mod barcode;
+mod blocks;
+mod dither;
+mod pack;
+mod qr;
+mod resolution;
+mod ruler;
+mod time_label;
+
+pub use barcode::{Barcode, BarcodeOptions, render_barcode_to_image};
+pub use blocks::{CanvasOptions, TextAlign, TextBlock, render_text_blocks};
+pub use dither::{
+    ContentKind, DitherOptions, classify_content, floyd_steinberg_binarize, otsu_threshold,
+    threshold_binarize,
+};
+pub use pack::{
+    ContentBox, image_to_packed_lines, pack, packed_content_bbox, trim_blank_lines, unpack,
+};
+pub use qr::{QrErrorCorrection, QrOptions, render_qr_to_image};
+pub use resolution::{embedded_dpi, real_size_width};
+pub use ruler::{MAX_RULER_LABEL_PX, MAX_RULER_MM, RulerOptions, render_ruler};
+pub use time_label::format_now;
+
+/// Direction of a [`TextRenderOptions::rotate_90`] quarter turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotate90 {
+    /// Clockwise: the first line ends up on the right, text reads top to bottom.
+    Cw,
+    /// Counter-clockwise: the first line ends up on the left, text reads bottom to top.
+    Ccw,
+}
+
+impl FromStr for Rotate90 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cw" => Ok(Self::Cw),
+            "ccw" => Ok(Self::Ccw),
+            other => Err(format!("unknown rotation {other:?}, expected cw|ccw")),
+        }
+    }
+}
+
+/// Light pattern behind the text of a [`TextRenderOptions`] sticker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextBackground {
+    #[default]
+    None,
+    /// A dot every 4 px in both directions.
+    Dots,
+    /// Diagonal 1 px stripes 8 px apart.
+    Lines,
+}
+
+impl FromStr for TextBackground {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "dots" => Ok(Self::Dots),
+            "lines" => Ok(Self::Lines),
+            other => Err(format!(
+                "unknown background {other:?}, expected none|dots|lines"
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TextRenderOptions {
@@ -16,9 +91,28 @@ pub struct TextRenderOptions {
     pub line_spacing: f32,
     pub threshold: u8,
     pub invert: bool,
+    /// With `invert`, only invert the text's bounding box (padded by a quarter of the font size)
+    /// and leave the margins white: white text on a black pill.
+    pub invert_content_only: bool,
     pub trim_blank_top_bottom: bool,
     pub outline_only: bool,
     pub outline_thickness_px: u32,
+    /// Pattern filling the whole canvas under the text. It leaves no blank rows, so callers should
+    /// not trim a sticker that has one.
+    pub background: TextBackground,
+    /// Fonts tried in order for characters missing from the primary font (e.g. emoji).
+    pub fallback_font_paths: Vec<PathBuf>,
+    /// Thickness of a black frame around the sticker; 0 disables it.
+    pub border_px: u32,
+    /// Gap between the image edge and the frame.
+    pub border_margin_px: u32,
+    /// Turn the finished sticker a quarter turn so lines run along the paper feed. Everything above
+    /// describes the unrotated canvas, so `width_px` becomes the label length and `height_px` the
+    /// printed width, which must then be at most [`MAX_DOTS_PER_LINE`].
+    pub rotate_90: Option<Rotate90>,
+    /// Mirror the finished sticker left to right, after `rotate_90`, for iron-on transfers and
+    /// window stickers read from the other side.
+    pub mirror_h: bool,
 }
 
 impl Default for TextRenderOptions {
@@ -32,9 +126,317 @@ impl Default for TextRenderOptions {
             line_spacing: 1.0,
             threshold: 180,
             invert: false,
+            invert_content_only: false,
             trim_blank_top_bottom: true,
             outline_only: false,
             outline_thickness_px: 1,
+            background: TextBackground::None,
+            fallback_font_paths: Vec::new(),
+            border_px: 0,
+            border_margin_px: 0,
+            rotate_90: None,
+            mirror_h: false,
+        }
+    }
+}
+
+/// A primary font followed by fallbacks consulted per character, in order.
+#[derive(Clone)]
+pub struct FontChain {
+    fonts: Vec<FontArc>,
+}
+
+impl FontChain {
+    pub fn new(primary: FontArc, fallbacks: Vec<FontArc>) -> Self {
+        let mut fonts = Vec::with_capacity(fallbacks.len() + 1);
+        fonts.push(primary);
+        fonts.extend(fallbacks);
+        Self { fonts }
+    }
+
+    pub fn load(primary: &Path, fallbacks: &[PathBuf]) -> Result<Self> {
+        let primary = load_font(primary)?;
+        let fallbacks = fallbacks
+            .iter()
+            .map(|p| load_font(p))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(primary, fallbacks))
+    }
+
+    pub fn primary(&self) -> &FontArc {
+        &self.fonts[0]
+    }
+
+    pub fn font(&self, idx: usize) -> &FontArc {
+        &self.fonts[idx]
+    }
+
+    /// Index of the font that should draw `ch`. Combining marks, joiners and variation
+    /// selectors stay with the previous character's font so clusters aren't split.
+    fn pick(&self, ch: char, prev: Option<usize>) -> usize {
+        if let Some(prev) = prev
+            && is_cluster_continuation(ch)
+        {
+            return prev;
+        }
+        self.fonts
+            .iter()
+            .position(|f| f.glyph_id(ch).0 != 0)
+            .or(prev)
+            .unwrap_or(0)
+    }
+}
+
+fn load_font(path: &Path) -> Result<FontArc> {
+    let bytes =
+        fs::read(path).with_context(|| format!("failed to read font file {}", path.display()))?;
+    FontArc::try_from_vec(bytes).with_context(|| format!("failed to parse font {}", path.display()))
+}
+
+fn is_cluster_continuation(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{200D}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
+/// A glyph placed by the shaper, in pixels relative to the top-left corner of its line.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionedGlyph {
+    /// Index into the [`FontChain`] the glyph belongs to.
+    pub font: usize,
+    pub id: GlyphId,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// One line of text laid out into visual (left-to-right) glyph order.
+#[derive(Debug, Clone, Default)]
+pub struct ShapedLine {
+    pub glyphs: Vec<PositionedGlyph>,
+    pub width: f32,
+}
+
+/// Shapes a single line of text into positioned glyphs.
+///
+/// Uses HarfBuzz-style shaping (via `rustybuzz`) so RTL scripts come out in visual order and
+/// combining marks attach to their base glyphs. Each character is drawn with the first font
+/// in the chain that has it. Falls back to plain advance + kerning layout for fonts whose
+/// tables can't be parsed by the shaper.
+pub fn shape_line(fonts: &FontChain, scale: PxScale, line: &str) -> ShapedLine {
+    if line.is_empty() {
+        return ShapedLine::default();
+    }
+
+    let faces: Vec<Option<rustybuzz::Face<'_>>> = fonts
+        .fonts
+        .iter()
+        .map(|f| rustybuzz::Face::from_slice(f.font_data(), 0))
+        .collect();
+    let baseline = fonts.primary().as_scaled(scale).ascent();
+
+    let mut out = ShapedLine::default();
+
+    // Split the line into directional runs, then each run into same-font segments. The shaper
+    // returns RTL segments already mirrored, so laying segments out in visual order is enough.
+    let bidi = BidiInfo::new(line, None);
+    for para in &bidi.paragraphs {
+        let (levels, runs) = bidi.visual_runs(para, para.range.clone());
+        for run in runs {
+            let rtl = levels[run.start].is_rtl();
+            let mut segments = font_segments(fonts, &line[run.clone()]);
+            if rtl {
+                segments.reverse();
+            }
+            for (font_idx, text) in segments {
+                let font = fonts.font(font_idx);
+                match &faces[font_idx] {
+                    Some(face) => {
+                        shape_segment(face, font, font_idx, scale, baseline, text, rtl, &mut out)
+                    }
+                    None => layout_segment(font, font_idx, scale, baseline, text, &mut out),
+                }
+            }
+        }
+    }
+
+    out.width = out.width.max(0.0);
+    out
+}
+
+fn font_segments<'a>(fonts: &FontChain, text: &'a str) -> Vec<(usize, &'a str)> {
+    let mut out = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (pos, ch) in text.char_indices() {
+        let idx = fonts.pick(ch, current.map(|(idx, _)| idx));
+        match current {
+            Some((cur, _)) if cur == idx => {}
+            Some((cur, start)) => {
+                out.push((cur, &text[start..pos]));
+                current = Some((idx, pos));
+            }
+            None => current = Some((idx, pos)),
+        }
+    }
+    if let Some((idx, start)) = current {
+        out.push((idx, &text[start..]));
+    }
+    out
+}
+
+#[allow(clippy::too_many_arguments)]
+fn shape_segment(
+    face: &rustybuzz::Face<'_>,
+    font: &FontArc,
+    font_idx: usize,
+    scale: PxScale,
+    baseline: f32,
+    text: &str,
+    rtl: bool,
+    out: &mut ShapedLine,
+) {
+    let scaled = font.as_scaled(scale);
+    let sx = scaled.h_scale_factor();
+    let sy = scaled.v_scale_factor();
+
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.guess_segment_properties();
+    buffer.set_direction(if rtl {
+        rustybuzz::Direction::RightToLeft
+    } else {
+        rustybuzz::Direction::LeftToRight
+    });
+    let output = rustybuzz::shape(face, &[], buffer);
+
+    for (info, pos) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+        out.glyphs.push(PositionedGlyph {
+            font: font_idx,
+            id: GlyphId(info.glyph_id as u16),
+            x: out.width + pos.x_offset as f32 * sx,
+            y: baseline - pos.y_offset as f32 * sy,
+        });
+        out.width += pos.x_advance as f32 * sx;
+    }
+}
+
+fn layout_segment(
+    font: &FontArc,
+    font_idx: usize,
+    scale: PxScale,
+    baseline: f32,
+    text: &str,
+    out: &mut ShapedLine,
+) {
+    let scaled = font.as_scaled(scale);
+    let mut prev = None;
+    for ch in text.chars() {
+        let id = scaled.glyph_id(ch);
+        if let Some(prev) = prev {
+            out.width += scaled.kern(prev, id);
+        }
+        out.glyphs.push(PositionedGlyph {
+            font: font_idx,
+            id,
+            x: out.width,
+            y: baseline,
+        });
+        out.width += scaled.h_advance(id);
+        prev = Some(id);
+    }
+}
+
+fn draw_shaped_line(
+    img: &mut GrayImage,
+    fonts: &FontChain,
+    scale: PxScale,
+    x: i32,
+    y: i32,
+    line: &ShapedLine,
+) {
+    let w = img.width() as i32;
+    let h = img.height() as i32;
+    for g in &line.glyphs {
+        let font = fonts.font(g.font);
+        let gx = x as f32 + g.x;
+        let gy = y as f32 + g.y;
+        let glyph = g.id.with_scale_and_position(scale, point(gx, gy));
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            // Color emoji fonts usually ship bitmaps instead of outlines.
+            let top = gy - font.as_scaled(scale).ascent();
+            draw_raster_glyph_outline(img, font, scale, g.id, gx, top);
+            continue;
+        };
+        let bb = outlined.px_bounds();
+        outlined.draw(|ox, oy, coverage| {
+            let px = bb.min.x.round() as i32 + ox as i32;
+            let py = bb.min.y.round() as i32 + oy as i32;
+            if px < 0 || py < 0 || px >= w || py >= h {
+                return;
+            }
+            let coverage = coverage.clamp(0.0, 1.0);
+            let pixel = img.get_pixel_mut(px as u32, py as u32);
+            pixel.0[0] = (pixel.0[0] as f32 * (1.0 - coverage)).round() as u8;
+        });
+    }
+}
+
+/// Draws a bitmap (color) glyph as the 1px outline of its alpha mask: color shading doesn't
+/// survive 1-bit thermal output, but the silhouette does.
+fn draw_raster_glyph_outline(
+    img: &mut GrayImage,
+    font: &FontArc,
+    scale: PxScale,
+    id: GlyphId,
+    x: f32,
+    top: f32,
+) {
+    let scaled = font.as_scaled(scale);
+    let ppem = font
+        .units_per_em()
+        .map(|upem| upem * scaled.h_scale_factor())
+        .unwrap_or(scale.y);
+    let Some(raster) = font.glyph_raster_image2(id, ppem.round().max(1.0) as u16) else {
+        return;
+    };
+    if !matches!(raster.format, GlyphImageFormat::Png) || raster.pixels_per_em == 0 {
+        return;
+    }
+    let Ok(decoded) = image::load_from_memory(raster.data) else {
+        return;
+    };
+
+    let k = ppem / raster.pixels_per_em as f32;
+    let rw = ((decoded.width() as f32 * k).round() as u32).max(1);
+    let rh = ((decoded.height() as f32 * k).round() as u32).max(1);
+    let rgba = image::imageops::resize(&decoded.to_rgba8(), rw, rh, FilterType::Triangle);
+    let mask = GrayImage::from_fn(rw, rh, |mx, my| {
+        Luma([if rgba.get_pixel(mx, my).0[3] >= 128 {
+            0
+        } else {
+            255
+        }])
+    });
+    let outline = outline_from_mask(&mask, 1);
+
+    let ox = (x + raster.origin.x * k).round() as i32;
+    let oy = (top + raster.origin.y * k).round() as i32;
+    for (mx, my, p) in outline.enumerate_pixels() {
+        if p.0[0] != 0 {
+            continue;
+        }
+        let px = ox + mx as i32;
+        let py = oy + my as i32;
+        if px >= 0 && py >= 0 && (px as u32) < img.width() && (py as u32) < img.height() {
+            img.put_pixel(px as u32, py as u32, Luma([0]));
         }
     }
 }
@@ -44,37 +446,332 @@ pub fn render_text_to_image(
     font_path: &Path,
     opts: &TextRenderOptions,
 ) -> Result<GrayImage> {
-    let bytes = fs::read(font_path)
-        .with_context(|| format!("failed to read font file {}", font_path.display()))?;
-    let font = FontArc::try_from_vec(bytes).context("failed to parse font")?;
+    if opts.rotate_90.is_some() && opts.height_px as usize > MAX_DOTS_PER_LINE {
+        bail!(
+            "height {} exceeds printer max {} dots, and it becomes the width once rotated",
+            opts.height_px,
+            MAX_DOTS_PER_LINE
+        );
+    }
+    let fonts = FontChain::load(font_path, &opts.fallback_font_paths)?;
 
     let mut img = GrayImage::from_pixel(opts.width_px, opts.height_px, Luma([255]));
     let scale = PxScale::from(opts.font_size_px);
-    let scaled = font.as_scaled(scale);
-    let line_h =
-        ((scaled.ascent() - scaled.descent() + scaled.line_gap()) * opts.line_spacing).max(1.0);
+    let line_h = line_height(&fonts, scale, opts.line_spacing);
 
     for (idx, line) in text.split('\n').enumerate() {
         if line.is_empty() {
             continue;
         }
         let y = opts.y_px + (idx as f32 * line_h).round() as i32;
-        draw_text_mut(&mut img, Luma([0]), opts.x_px, y, scale, &font, line);
+        let shaped = shape_line(&fonts, scale, line);
+        draw_shaped_line(&mut img, &fonts, scale, opts.x_px, y, &shaped);
     }
 
     if opts.outline_only {
         img = outline_from_mask(&img, opts.outline_thickness_px.max(1));
     }
 
-    if opts.invert {
-        for pixel in img.pixels_mut() {
-            pixel.0[0] = 255u8.saturating_sub(pixel.0[0]);
+    // Taken before the background, which would otherwise make the whole canvas content.
+    let text_bbox = if opts.invert && opts.invert_content_only {
+        content_bbox(&img, opts.threshold)
+    } else {
+        None
+    };
+    draw_background(&mut img, opts.background);
+
+    // A zero-sized canvas has nothing to invert.
+    if opts.invert && img.width() > 0 && img.height() > 0 {
+        let (max_x, max_y) = (img.width() - 1, img.height() - 1);
+        let pad = (opts.font_size_px / 4.0).round() as u32;
+        let region = if opts.invert_content_only {
+            text_bbox.map(|(x0, y0, x1, y1)| {
+                (
+                    x0.saturating_sub(pad).min(max_x),
+                    y0.saturating_sub(pad).min(max_y),
+                    x1.saturating_add(pad).min(max_x),
+                    y1.saturating_add(pad).min(max_y),
+                )
+            })
+        } else {
+            Some((0, 0, max_x, max_y))
+        };
+        if let Some((x0, y0, x1, y1)) = region {
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let p = img.get_pixel_mut(x, y);
+                    p.0[0] = 255u8.saturating_sub(p.0[0]);
+                }
+            }
         }
     }
 
+    draw_border(&mut img, opts.border_px, opts.border_margin_px);
+
+    let mut img = match opts.rotate_90 {
+        None => img,
+        Some(Rotate90::Cw) => image::imageops::rotate90(&img),
+        Some(Rotate90::Ccw) => image::imageops::rotate270(&img),
+    };
+    if opts.mirror_h {
+        image::imageops::flip_horizontal_in_place(&mut img);
+    }
     Ok(img)
 }
 
+/// Darkens `img` with the `background` pattern; the text stays on top as it is already black.
+fn draw_background(img: &mut GrayImage, background: TextBackground) {
+    let on = |x: u32, y: u32| match background {
+        TextBackground::None => false,
+        TextBackground::Dots => x.is_multiple_of(4) && y.is_multiple_of(4),
+        TextBackground::Lines => (x + y).is_multiple_of(8),
+    };
+    for (x, y, p) in img.enumerate_pixels_mut() {
+        if on(x, y) {
+            p.0[0] = 0;
+        }
+    }
+}
+
+/// Inclusive `(x0, y0, x1, y1)` of the pixels at or below `threshold`, i.e. what would print.
+fn content_bbox(img: &GrayImage, threshold: u8) -> Option<(u32, u32, u32, u32)> {
+    let mut bbox: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, p) in img.enumerate_pixels() {
+        if p.0[0] > threshold {
+            continue;
+        }
+        bbox = Some(match bbox {
+            None => (x, y, x, y),
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        });
+    }
+    bbox
+}
+
+fn line_height(fonts: &FontChain, scale: PxScale, line_spacing: f32) -> f32 {
+    let scaled = fonts.primary().as_scaled(scale);
+    ((scaled.ascent() - scaled.descent() + scaled.line_gap()) * line_spacing).max(1.0)
+}
+
+/// Size of `text` as [`render_text_to_image`] lays it out: widest shaped line by total line height.
+pub fn measure_text_block(
+    fonts: &FontChain,
+    text: &str,
+    font_size: f32,
+    line_spacing: f32,
+) -> (f32, f32) {
+    let scale = PxScale::from(font_size);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let width = lines
+        .iter()
+        .map(|line| shape_line(fonts, scale, line).width)
+        .fold(0.0f32, f32::max);
+    let height = line_height(fonts, scale, line_spacing) * lines.len().max(1) as f32;
+    (width, height)
+}
+
+/// Largest font size in `min_size..=max_size` whose widest line fits `max_width`.
+///
+/// Returns the size and the resulting block height.
+pub fn fit_font_size(
+    fonts: &FontChain,
+    text: &str,
+    max_width: f32,
+    min_size: f32,
+    max_size: f32,
+    line_spacing: f32,
+) -> Result<(f32, f32)> {
+    fit_font_size_in_box(
+        fonts,
+        text,
+        max_width,
+        f32::INFINITY,
+        min_size,
+        max_size,
+        line_spacing,
+    )
+}
+
+/// Largest font size in `min_size..=max_size` whose block fits both `max_width` and `max_height`.
+///
+/// Width is checked first, so text too wide at `min_size` fails as too wide whatever the height.
+/// Returns the size and the resulting block height.
+pub fn fit_font_size_in_box(
+    fonts: &FontChain,
+    text: &str,
+    max_width: f32,
+    max_height: f32,
+    min_size: f32,
+    max_size: f32,
+    line_spacing: f32,
+) -> Result<(f32, f32)> {
+    if min_size <= 0.0 || max_size <= 0.0 || min_size > max_size {
+        bail!("invalid font size bounds");
+    }
+
+    let (min_w, min_h) = measure_text_block(fonts, text, min_size, line_spacing);
+    if min_w > max_width {
+        bail!("text is too wide even at minimum font size {:.1}", min_size);
+    }
+    if min_h > max_height {
+        bail!("text is too tall even at minimum font size {:.1}", min_size);
+    }
+
+    let mut lo = min_size;
+    let mut hi = max_size;
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.0;
+        let (w, h) = measure_text_block(fonts, text, mid, line_spacing);
+        if w <= max_width && h <= max_height {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    let (_, h) = measure_text_block(fonts, text, lo, line_spacing);
+    Ok((lo, h.max(min_h)))
+}
+
+/// [`fit_font_size_in_box`] for a [`TextRenderOptions::rotate_90`] sticker: lines run along
+/// `max_length` and the whole block has to fit across the paper, at most `max_across` (itself capped
+/// at [`MAX_DOTS_PER_LINE`]).
+pub fn fit_font_size_rotated(
+    fonts: &FontChain,
+    text: &str,
+    max_length: f32,
+    max_across: f32,
+    min_size: f32,
+    max_size: f32,
+    line_spacing: f32,
+) -> Result<(f32, f32)> {
+    fit_font_size_in_box(
+        fonts,
+        text,
+        max_length,
+        max_across.min(MAX_DOTS_PER_LINE as f32),
+        min_size,
+        max_size,
+        line_spacing,
+    )
+}
+
+/// Largest font size in `min_size..=max_size` whose block height fits `max_height`.
+///
+/// Returns the size and the resulting block height.
+pub fn fit_font_size_by_height(
+    fonts: &FontChain,
+    text: &str,
+    max_height: f32,
+    min_size: f32,
+    max_size: f32,
+    line_spacing: f32,
+) -> Result<(f32, f32)> {
+    if min_size <= 0.0 || max_size <= 0.0 || min_size > max_size {
+        bail!("invalid font size bounds");
+    }
+
+    let (_, min_h) = measure_text_block(fonts, text, min_size, line_spacing);
+    if min_h > max_height {
+        bail!("text is too tall even at minimum font size {:.1}", min_size);
+    }
+
+    let mut lo = min_size;
+    let mut hi = max_size;
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.0;
+        let (_, h) = measure_text_block(fonts, text, mid, line_spacing);
+        if h <= max_height {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let (_, h) = measure_text_block(fonts, text, lo, line_spacing);
+    Ok((lo, h))
+}
+
+/// Greedy word wrap so every line of `text` fits `max_width` at `font_size`.
+///
+/// Existing line breaks are kept. A single word wider than `max_width` is split between characters.
+pub fn wrap_text_to_width(fonts: &FontChain, text: &str, font_size: f32, max_width: f32) -> String {
+    let scale = PxScale::from(font_size);
+    let fits = |s: &str| shape_line(fonts, scale, s).width <= max_width;
+
+    let mut out: Vec<String> = Vec::new();
+    for para in text.split('\n') {
+        let mut line = String::new();
+        for word in para.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{line} {word}")
+            };
+            if fits(&candidate) {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                out.push(std::mem::take(&mut line));
+            }
+            for ch in word.chars() {
+                let mut next = line.clone();
+                next.push(ch);
+                if !line.is_empty() && !fits(&next) {
+                    out.push(std::mem::take(&mut line));
+                    next = ch.to_string();
+                }
+                line = next;
+            }
+        }
+        out.push(line);
+    }
+    out.join("\n")
+}
+
+/// Whether a frame of `thickness` inset by `margin` leaves room inside a `width`x`height` image.
+pub fn border_fits(width: u32, height: u32, thickness: u32, margin: u32) -> bool {
+    if thickness == 0 {
+        return true;
+    }
+    // Sizes come straight from requests; a frame too big to count does not fit either.
+    margin
+        .checked_add(thickness)
+        .and_then(|inset| inset.checked_mul(2))
+        .is_some_and(|span| span < width && span < height)
+}
+
+/// Draws a black frame `thickness` px wide, inset `margin` px from the edges.
+///
+/// Returns `false` (and draws nothing) when the frame does not fit.
+pub fn draw_border(img: &mut GrayImage, thickness: u32, margin: u32) -> bool {
+    if !border_fits(img.width(), img.height(), thickness, margin) {
+        return false;
+    }
+    // `border_fits` keeps every `off * 2` below both sides.
+    for off in margin..margin + thickness {
+        let rect =
+            Rect::at(off as i32, off as i32).of_size(img.width() - off * 2, img.height() - off * 2);
+        draw_hollow_rect_mut(img, rect, Luma([0]));
+    }
+    true
+}
+
+/// Composites an image over white, so transparent areas print as paper instead of the black that
+/// their color channels usually hold.
+pub fn flatten_on_white(img: &DynamicImage) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let a = a as u32;
+        let blend = |c: u8| ((c as u32 * a + 255 * (255 - a) + 127) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
 fn outline_from_mask(src: &GrayImage, radius: u32) -> GrayImage {
     let w = src.width();
     let h = src.height();
@@ -109,47 +806,251 @@ fn outline_from_mask(src: &GrayImage, radius: u32) -> GrayImage {
     out
 }
 
-pub fn image_to_packed_lines(img: &GrayImage, threshold: u8, trim_blank: bool) -> Vec<PackedLine> {
-    let width = img.width().min(MAX_DOTS_PER_LINE as u32) as usize;
-    let height = img.height() as usize;
+pub fn px_to_mm(px: u32, dpi: u16) -> f32 {
+    px as f32 / dpi as f32 * 25.4
+}
 
-    let mut out = Vec::with_capacity(height.div_ceil(2));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for y in (0..height).step_by(2) {
-        let mut line = [0u8; BYTES_PER_LINE * 2];
+    const TEST_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
 
-        for row in 0..2 {
-            let yy = y + row;
-            if yy >= height {
-                continue;
-            }
-            for x in 0..width {
-                let px = img.get_pixel(x as u32, yy as u32).0[0];
-                let is_black = px <= threshold;
-                if is_black {
-                    let byte_idx = row * BYTES_PER_LINE + (x / 8);
-                    let bit = 7 - (x % 8);
-                    line[byte_idx] |= 1u8 << bit;
-                }
+    pub(crate) fn test_fonts() -> Option<FontChain> {
+        let path = Path::new(TEST_FONT);
+        if !path.exists() {
+            eprintln!("skipping: {TEST_FONT} not installed");
+            return None;
+        }
+        Some(FontChain::load(path, &[]).expect("test font loads"))
+    }
+
+    #[test]
+    fn backgrounds_stay_sparse_and_cover_every_edge() {
+        for (background, expected) in [
+            (TextBackground::None, 0.0),
+            (TextBackground::Dots, 1.0 / 16.0),
+            (TextBackground::Lines, 1.0 / 8.0),
+        ] {
+            let mut img = GrayImage::from_pixel(64, 32, Luma([255]));
+            draw_background(&mut img, background);
+            let black = img.pixels().filter(|p| p.0[0] == 0).count() as f32;
+            let ratio = black / (64.0 * 32.0);
+            assert!((ratio - expected).abs() < 0.01, "{background:?}: {ratio}");
+            if background != TextBackground::None {
+                // At most the last of the 16 packed lines is blank, so a trim has nothing to take.
+                let packed = image_to_packed_lines(&img, 127, true);
+                assert!(packed.len() >= 15, "{background:?}: {}", packed.len());
             }
         }
+    }
 
-        out.push(line);
+    #[test]
+    fn kerning_narrows_pairs() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let (pair, _) = measure_text_block(&fonts, "AV", 48.0, 1.0);
+        let (a, _) = measure_text_block(&fonts, "A", 48.0, 1.0);
+        let (v, _) = measure_text_block(&fonts, "V", 48.0, 1.0);
+        assert!(pair < a + v, "AV={pair} A+V={}", a + v);
+    }
+
+    #[test]
+    fn multi_line_block_uses_widest_line_and_all_lines() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let (w_short, h_one) = measure_text_block(&fonts, "hi", 32.0, 1.0);
+        let (w_long, _) = measure_text_block(&fonts, "hello there", 32.0, 1.0);
+        let (w, h) = measure_text_block(&fonts, "hi\nhello there\nhi", 32.0, 1.0);
+        assert!(w_short < w_long);
+        assert_eq!(w, w_long);
+        assert!((h - 3.0 * h_one).abs() < 0.01);
     }
 
-    if !trim_blank {
-        return out;
+    #[test]
+    fn fit_font_size_fills_width() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let (size, _) = fit_font_size(&fonts, "Hello\nsticker", 300.0, 8.0, 200.0, 1.0).unwrap();
+        let (w, _) = measure_text_block(&fonts, "Hello\nsticker", size, 1.0);
+        assert!(w <= 300.0 && w > 295.0, "width {w} at size {size}");
+        assert!(fit_font_size(&fonts, "Hello", 5.0, 8.0, 200.0, 1.0).is_err());
     }
 
-    let first = out.iter().position(|l| l.iter().any(|b| *b != 0));
-    let last = out.iter().rposition(|l| l.iter().any(|b| *b != 0));
+    #[test]
+    fn fit_in_box_caps_short_words_by_height() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let (wide, wide_h) = fit_font_size(&fonts, "hi", 300.0, 8.0, 200.0, 1.0).unwrap();
+        let (size, h) = fit_font_size_in_box(&fonts, "hi", 300.0, 60.0, 8.0, 200.0, 1.0).unwrap();
+        assert!(wide_h > 60.0, "height {wide_h} at size {wide}");
+        assert!(size < wide, "{size} vs {wide}");
+        assert!(h <= 60.0 && h > 55.0, "height {h} at size {size}");
+        assert!(fit_font_size_in_box(&fonts, "hi", 300.0, 2.0, 8.0, 200.0, 1.0).is_err());
+    }
 
-    match (first, last) {
-        (Some(start), Some(end)) => out[start..=end].to_vec(),
-        _ => Vec::new(),
+    #[test]
+    fn wrap_keeps_every_line_within_width() {
+        let Some(fonts) = test_fonts() else {
+            return;
+        };
+        let text = "the quick brown fox jumps over the lazy dog\nsupercalifragilistic";
+        let wrapped = wrap_text_to_width(&fonts, text, 32.0, 150.0);
+        assert!(wrapped.lines().count() > 2);
+        for line in wrapped.lines() {
+            let (w, _) = measure_text_block(&fonts, line, 32.0, 1.0);
+            assert!(w <= 150.0, "{line:?} is {w}px");
+        }
     }
-}
 
-pub fn px_to_mm(px: u32, dpi: u16) -> f32 {
-    px as f32 / dpi as f32 * 25.4
+    #[test]
+    fn invert_content_only_keeps_margins_white() {
+        if test_fonts().is_none() {
+            return;
+        }
+        let opts = TextRenderOptions {
+            x_px: 120,
+            y_px: 60,
+            font_size_px: 32.0,
+            invert: true,
+            invert_content_only: true,
+            ..TextRenderOptions::default()
+        };
+        let img = render_text_to_image("Hi", Path::new(TEST_FONT), &opts).unwrap();
+        for (x, y) in [(0, 0), (383, 0), (0, 191), (383, 191), (60, 80)] {
+            assert_eq!(img.get_pixel(x, y).0[0], 255, "({x}, {y})");
+        }
+        // The text origin sits in the padding left of the first glyph, inside the pill.
+        assert_eq!(img.get_pixel(120, 80).0[0], 0);
+    }
+
+    #[test]
+    fn oversized_borders_do_not_fit() {
+        assert!(border_fits(100, 100, 0, u32::MAX));
+        assert!(border_fits(100, 60, 4, 25));
+        assert!(!border_fits(100, 60, 5, 25));
+        assert!(!border_fits(100, 100, u32::MAX, 1));
+        assert!(!border_fits(100, 100, 1, u32::MAX));
+        assert!(!border_fits(100, 100, u32::MAX / 2, u32::MAX / 2));
+
+        let mut img = GrayImage::from_pixel(100, 60, Luma([255]));
+        assert!(!draw_border(&mut img, u32::MAX, u32::MAX));
+        assert!(img.pixels().all(|p| p.0[0] == 255));
+        assert!(draw_border(&mut img, 4, 25));
+        assert_eq!(img.get_pixel(25, 25).0[0], 0);
+        assert_eq!(img.get_pixel(74, 34).0[0], 0);
+        assert_eq!(img.get_pixel(24, 25).0[0], 255);
+    }
+
+    #[test]
+    fn inverting_an_empty_canvas_is_a_no_op() {
+        if test_fonts().is_none() {
+            return;
+        }
+        for invert_content_only in [false, true] {
+            let opts = TextRenderOptions {
+                height_px: 0,
+                invert: true,
+                invert_content_only,
+                ..TextRenderOptions::default()
+            };
+            let img = render_text_to_image("Hi", Path::new(TEST_FONT), &opts).unwrap();
+            assert_eq!(img.height(), 0);
+        }
+    }
+
+    #[test]
+    fn rotate_90_turns_the_canvas_both_ways() {
+        if test_fonts().is_none() {
+            return;
+        }
+        let opts = |rotate_90| TextRenderOptions {
+            width_px: 600,
+            height_px: 100,
+            font_size_px: 48.0,
+            rotate_90,
+            ..TextRenderOptions::default()
+        };
+        let font = Path::new(TEST_FONT);
+
+        // Text starts at the top-left of the 600x100 canvas.
+        let cw = render_text_to_image("Spine", font, &opts(Some(Rotate90::Cw))).unwrap();
+        assert_eq!(cw.dimensions(), (100, 600));
+        let (_, y0, x1, _) = content_bbox(&cw, 180).unwrap();
+        assert!(
+            x1 > 50 && y0 < 10,
+            "cw content ends at x {x1}, starts at y {y0}"
+        );
+
+        let ccw = render_text_to_image("Spine", font, &opts(Some(Rotate90::Ccw))).unwrap();
+        assert_eq!(ccw.dimensions(), (100, 600));
+        let (x0, _, _, y1) = content_bbox(&ccw, 180).unwrap();
+        assert!(
+            x0 < 50 && y1 > 590,
+            "ccw content starts at x {x0}, ends at y {y1}"
+        );
+
+        let too_wide = TextRenderOptions {
+            height_px: MAX_DOTS_PER_LINE as u32 + 1,
+            ..opts(Some(Rotate90::Cw))
+        };
+        assert!(render_text_to_image("Spine", font, &too_wide).is_err());
+    }
+
+    #[test]
+    fn mirror_h_flips_the_finished_sticker() {
+        if test_fonts().is_none() {
+            return;
+        }
+        let font = Path::new(TEST_FONT);
+        for (rotate_90, invert) in [(None, false), (Some(Rotate90::Cw), true)] {
+            let opts = TextRenderOptions {
+                width_px: 300,
+                height_px: 100,
+                invert,
+                rotate_90,
+                ..TextRenderOptions::default()
+            };
+            let plain = render_text_to_image("Iron", font, &opts).unwrap();
+            let mirrored = render_text_to_image(
+                "Iron",
+                font,
+                &TextRenderOptions {
+                    mirror_h: true,
+                    ..opts
+                },
+            )
+            .unwrap();
+            assert_eq!(mirrored, image::imageops::flip_horizontal(&plain));
+        }
+    }
+
+    #[test]
+    fn transparent_corners_pack_as_blank() {
+        // A black logo whose corners are transparent black, as many exporters write them.
+        let logo = image::RgbaImage::from_fn(16, 16, |x, y| {
+            let corner = !(4..12).contains(&x) && !(4..12).contains(&y);
+            image::Rgba([0, 0, 0, if corner { 0 } else { 255 }])
+        });
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(logo)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let decoded = image::load_from_memory(&png).unwrap();
+
+        let gray = DynamicImage::ImageRgb8(flatten_on_white(&decoded)).to_luma8();
+        let lines = image_to_packed_lines(&gray, 127, false);
+        // Rows 0..4 are packed lines 0 and 1: only dots 4..12 print.
+        assert_eq!(lines[0][..2], [0b0000_1111, 0b1111_0000]);
+        assert_eq!(lines[7][..2], [0b0000_1111, 0b1111_0000]);
+        assert_eq!(lines[3][..2], [0xff, 0xff]);
+        // Semi-transparent black lands in the middle.
+        let half = image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 128]));
+        let half = DynamicImage::ImageRgba8(half);
+        assert_eq!(flatten_on_white(&half).get_pixel(0, 0).0, [127; 3]);
+    }
 }
@@ -0,0 +1,236 @@
+use funnyprint_proto::{BYTES_PER_LINE, MAX_DOTS_PER_LINE, PackedLine};
+use image::{GrayImage, Luma};
+
+use crate::threshold_binarize;
+
+/// Packs a black-and-white image into printer lines.
+///
+/// Each line carries two dot rows, top row first, [`BYTES_PER_LINE`] bytes each with the MSB as
+/// the leftmost dot. Only pixels of value 0 print; columns past [`MAX_DOTS_PER_LINE`] are dropped
+/// and an odd last row leaves the second half of the last line blank.
+pub fn pack(img: &GrayImage) -> Vec<PackedLine> {
+    let width = img.width().min(MAX_DOTS_PER_LINE as u32) as usize;
+    let height = img.height() as usize;
+
+    let mut out = Vec::with_capacity(height.div_ceil(2));
+    for y in (0..height).step_by(2) {
+        let mut line = [0u8; BYTES_PER_LINE * 2];
+        for row in 0..2 {
+            let yy = y + row;
+            if yy >= height {
+                continue;
+            }
+            for x in 0..width {
+                if img.get_pixel(x as u32, yy as u32).0[0] == 0 {
+                    line[row * BYTES_PER_LINE + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        out.push(line);
+    }
+    out
+}
+
+/// The inverse of [`pack`]: a [`MAX_DOTS_PER_LINE`]-wide black-and-white image, two rows per line.
+pub fn unpack(lines: &[PackedLine]) -> GrayImage {
+    let mut img = GrayImage::from_pixel(
+        MAX_DOTS_PER_LINE as u32,
+        lines.len() as u32 * 2,
+        Luma([255]),
+    );
+    for (i, line) in lines.iter().enumerate() {
+        for (row, dots) in line.chunks(BYTES_PER_LINE).enumerate() {
+            for x in 0..MAX_DOTS_PER_LINE {
+                if dots[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    img.put_pixel(x as u32, (i * 2 + row) as u32, Luma([0]));
+                }
+            }
+        }
+    }
+    img
+}
+
+/// [`pack`] for a grayscale image: values `<= threshold` print.
+pub fn image_to_packed_lines(img: &GrayImage, threshold: u8, trim_blank: bool) -> Vec<PackedLine> {
+    let out = pack(&threshold_binarize(img, threshold, false));
+    if !trim_blank {
+        return out;
+    }
+    trim_blank_lines(out, 0)
+}
+
+/// Drops blank lines above and below the content, keeping up to `margin_px` blank rows (rounded up
+/// to whole packed lines) on each side. Returns nothing if every line is blank.
+pub fn trim_blank_lines(mut lines: Vec<PackedLine>, margin_px: u32) -> Vec<PackedLine> {
+    let keep = margin_px.div_ceil(2) as usize;
+    let first = lines.iter().position(|l| l.iter().any(|b| *b != 0));
+    let last = lines.iter().rposition(|l| l.iter().any(|b| *b != 0));
+
+    match (first, last) {
+        (Some(start), Some(end)) => {
+            lines.truncate((end + keep + 1).min(lines.len()));
+            lines.drain(..start.saturating_sub(keep));
+            lines
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Smallest rectangle holding every printed dot, in pixels of the packed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Where the ink sits in `lines`, row 0 being the top row of the first line; `None` when every
+/// line is blank. Run it before [`trim_blank_lines`] to get coordinates in the rendered image.
+pub fn packed_content_bbox(lines: &[PackedLine]) -> Option<ContentBox> {
+    let (mut min_x, mut max_x) = (usize::MAX, 0);
+    let (mut min_y, mut max_y) = (usize::MAX, 0);
+    for (i, line) in lines.iter().enumerate() {
+        for (row, dots) in line.chunks(BYTES_PER_LINE).enumerate() {
+            let Some(first) = dots.iter().position(|b| *b != 0) else {
+                continue;
+            };
+            let last = dots.iter().rposition(|b| *b != 0).unwrap_or(first);
+            min_x = min_x.min(first * 8 + dots[first].leading_zeros() as usize);
+            max_x = max_x.max(last * 8 + 7 - dots[last].trailing_zeros() as usize);
+            let y = i * 2 + row;
+            min_y = min_y.min(y);
+            max_y = y;
+        }
+    }
+    (min_y != usize::MAX).then(|| ContentBox {
+        x: min_x as u32,
+        y: min_y as u32,
+        width: (max_x - min_x + 1) as u32,
+        height: (max_y - min_y + 1) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use funnyprint_proto::PACKED_LINE_BYTES;
+
+    use super::*;
+
+    fn white(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_pixel(width, height, Luma([255]))
+    }
+
+    #[test]
+    fn bits_run_msb_first_with_the_top_row_first() {
+        let mut img = white(MAX_DOTS_PER_LINE as u32, 2);
+        for (x, y) in [(0, 0), (7, 0), (8, 0), (383, 0), (1, 1)] {
+            img.put_pixel(x, y, Luma([0]));
+        }
+        let lines = pack(&img);
+        assert_eq!(lines.len(), 1);
+        let line = lines[0];
+        assert_eq!(line[0], 0b1000_0001);
+        assert_eq!(line[1], 0b1000_0000);
+        assert_eq!(line[BYTES_PER_LINE - 1], 0b0000_0001);
+        assert_eq!(line[BYTES_PER_LINE], 0b0100_0000);
+        let set: usize = line.iter().map(|b| b.count_ones() as usize).sum();
+        assert_eq!(set, 5);
+    }
+
+    #[test]
+    fn odd_height_leaves_the_last_bottom_row_blank() {
+        let img = GrayImage::from_pixel(16, 3, Luma([0]));
+        let lines = pack(&img);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(&lines[1][..2], [0xff, 0xff]);
+        assert!(lines[1][BYTES_PER_LINE..].iter().all(|b| *b == 0));
+        assert!(pack(&white(8, 0)).is_empty());
+    }
+
+    #[test]
+    fn only_black_prints_and_extra_columns_are_dropped() {
+        let mut img = white(MAX_DOTS_PER_LINE as u32 + 8, 2);
+        img.put_pixel(0, 0, Luma([1]));
+        img.put_pixel(MAX_DOTS_PER_LINE as u32, 0, Luma([0]));
+        assert_eq!(pack(&img), [[0u8; PACKED_LINE_BYTES]]);
+    }
+
+    #[test]
+    fn unpack_restores_the_packed_dots() {
+        let mut img = white(MAX_DOTS_PER_LINE as u32, 3);
+        for (x, y) in [(0, 0), (9, 1), (383, 2)] {
+            img.put_pixel(x, y, Luma([0]));
+        }
+        let lines = pack(&img);
+        let back = unpack(&lines);
+        assert_eq!(back.dimensions(), (MAX_DOTS_PER_LINE as u32, 4));
+        assert_eq!(pack(&back), lines);
+        assert_eq!(back.get_pixel(9, 1).0[0], 0);
+        assert_eq!(back.get_pixel(10, 1).0[0], 255);
+    }
+
+    #[test]
+    fn gray_packing_thresholds_inclusively() {
+        let mut img = white(8, 2);
+        img.put_pixel(0, 0, Luma([180]));
+        img.put_pixel(1, 0, Luma([181]));
+        let lines = image_to_packed_lines(&img, 180, false);
+        assert_eq!(lines[0][0], 0b1000_0000);
+    }
+
+    #[test]
+    fn trim_keeps_only_the_content_extent() {
+        let mut img = white(8, 11);
+        img.put_pixel(0, 4, Luma([0]));
+        img.put_pixel(0, 7, Luma([0]));
+        // Rows 4..=7 live in packed lines 2 and 3.
+        let lines = image_to_packed_lines(&img, 127, true);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][0], 0b1000_0000);
+        assert_eq!(lines[1][BYTES_PER_LINE], 0b1000_0000);
+        assert!(image_to_packed_lines(&white(8, 5), 127, true).is_empty());
+    }
+
+    #[test]
+    fn bbox_spans_the_outermost_dots() {
+        let mut img = white(MAX_DOTS_PER_LINE as u32, 9);
+        for (x, y) in [(10, 3), (200, 4), (17, 7), (383, 6)] {
+            img.put_pixel(x, y, Luma([0]));
+        }
+        let bbox = packed_content_bbox(&pack(&img)).unwrap();
+        assert_eq!(
+            bbox,
+            ContentBox {
+                x: 10,
+                y: 3,
+                width: 374,
+                height: 5
+            }
+        );
+
+        let mut dot = white(8, 2);
+        dot.put_pixel(5, 1, Luma([0]));
+        let bbox = packed_content_bbox(&pack(&dot)).unwrap();
+        assert_eq!((bbox.x, bbox.y, bbox.width, bbox.height), (5, 1, 1, 1));
+        assert_eq!(packed_content_bbox(&pack(&white(8, 4))), None);
+    }
+
+    #[test]
+    fn trim_keeps_requested_blank_margin() {
+        let ink = [0xffu8; PACKED_LINE_BYTES];
+        let blank = [0u8; PACKED_LINE_BYTES];
+        let mut lines = vec![blank; 10];
+        lines[4] = ink;
+        lines[5] = ink;
+
+        assert_eq!(trim_blank_lines(lines.clone(), 0), [ink, ink]);
+        // 3 rows round up to 2 packed lines each side.
+        assert_eq!(
+            trim_blank_lines(lines.clone(), 3),
+            [blank, blank, ink, ink, blank, blank]
+        );
+        assert_eq!(trim_blank_lines(lines.clone(), 100).len(), 10);
+        assert!(trim_blank_lines(vec![blank; 3], 4).is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use funnyprint_proto::MAX_DOTS_PER_LINE;
+use image::{GrayImage, Luma};
+use qrcode::{Color, EcLevel, QrCode};
+
+/// QR error correction levels, from the smallest symbol (L, 7% recoverable) to the sturdiest
+/// (H, 30%).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QrErrorCorrection {
+    L,
+    #[default]
+    M,
+    Q,
+    H,
+}
+
+impl FromStr for QrErrorCorrection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "L" => Ok(Self::L),
+            "M" => Ok(Self::M),
+            "Q" => Ok(Self::Q),
+            "H" => Ok(Self::H),
+            other => Err(format!(
+                "unknown error correction {other:?}, expected L|M|Q|H"
+            )),
+        }
+    }
+}
+
+impl From<QrErrorCorrection> for EcLevel {
+    fn from(level: QrErrorCorrection) -> Self {
+        match level {
+            QrErrorCorrection::L => EcLevel::L,
+            QrErrorCorrection::M => EcLevel::M,
+            QrErrorCorrection::Q => EcLevel::Q,
+            QrErrorCorrection::H => EcLevel::H,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QrOptions {
+    /// Dots per module; `None` picks the largest that fits the paper width.
+    pub module_px: Option<u32>,
+    /// Blank margin on every side, in modules. The spec asks for 4.
+    pub quiet_zone_modules: u32,
+    pub error_correction: QrErrorCorrection,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            module_px: None,
+            quiet_zone_modules: 4,
+            error_correction: QrErrorCorrection::M,
+        }
+    }
+}
+
+pub fn render_qr_to_image(data: &str, opts: &QrOptions) -> Result<GrayImage> {
+    if data.is_empty() {
+        bail!("qr data is empty");
+    }
+    let code = match QrCode::with_error_correction_level(data, opts.error_correction.into()) {
+        Ok(v) => v,
+        Err(err) => bail!("qr encode failed: {err}"),
+    };
+
+    let side_modules = code.width() as u32 + opts.quiet_zone_modules * 2;
+    let module_px = match opts.module_px {
+        Some(0) => bail!("module_px must be > 0"),
+        Some(v) => v,
+        None => (MAX_DOTS_PER_LINE as u32 / side_modules).max(1),
+    };
+    let side = side_modules * module_px;
+    if side as usize > MAX_DOTS_PER_LINE {
+        bail!(
+            "qr code is {side} dots wide, max is {MAX_DOTS_PER_LINE}; use a smaller module_px, quiet zone, lower error correction or shorter data"
+        );
+    }
+
+    let mut img = GrayImage::from_pixel(side, side, Luma([255]));
+    let width = code.width();
+    for (i, _) in code
+        .to_colors()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| **c == Color::Dark)
+    {
+        let x0 = (opts.quiet_zone_modules + (i % width) as u32) * module_px;
+        let y0 = (opts.quiet_zone_modules + (i / width) as u32) * module_px;
+        for y in y0..y0 + module_px {
+            for x in x0..x0 + module_px {
+                img.put_pixel(x, y, Luma([0]));
+            }
+        }
+    }
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qr_fits_the_paper_and_keeps_the_quiet_zone() {
+        // "hello" is a version 1 symbol: 21 modules plus the quiet zone on both sides.
+        let img = render_qr_to_image("hello", &QrOptions::default()).unwrap();
+        let module = MAX_DOTS_PER_LINE as u32 / 29;
+        assert_eq!((img.width(), img.height()), (module * 29, module * 29));
+        // The top-left finder pattern starts right after the quiet zone.
+        assert_eq!(img.get_pixel(module * 4 - 1, module * 4).0[0], 255);
+        assert_eq!(img.get_pixel(module * 4, module * 4).0[0], 0);
+
+        let opts = QrOptions {
+            module_px: Some(20),
+            ..QrOptions::default()
+        };
+        assert!(render_qr_to_image("hello", &opts).is_err());
+        assert!(render_qr_to_image("", &QrOptions::default()).is_err());
+        assert_eq!("h".parse(), Ok(QrErrorCorrection::H));
+    }
+}
@@ -0,0 +1,122 @@
+use funnyprint_proto::dpi;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Horizontal resolution stored in an image file: the PNG `pHYs` chunk or the JPEG JFIF header.
+///
+/// `None` when the file has no physical resolution (e.g. JFIF with aspect-ratio units only).
+pub fn embedded_dpi(bytes: &[u8]) -> Option<f32> {
+    let dpi = if let Some(chunks) = bytes.strip_prefix(PNG_SIGNATURE) {
+        png_dpi(chunks)?
+    } else if let Some(segments) = bytes.strip_prefix(&[0xff, 0xd8]) {
+        jfif_dpi(segments)?
+    } else {
+        return None;
+    };
+    (dpi.is_finite() && dpi > 0.0).then_some(dpi)
+}
+
+/// Printer dots covering an image `width` pixels wide at `source_dpi`, i.e. its width printed at
+/// real size, capped at `max_width`.
+pub fn real_size_width(width: u32, source_dpi: f32, max_width: u32) -> u32 {
+    let dots = (width as f32 * dpi() as f32 / source_dpi).round();
+    (dots as u32).clamp(1, max_width.max(1))
+}
+
+fn png_dpi(mut chunks: &[u8]) -> Option<f32> {
+    while chunks.len() >= 8 {
+        let len = u32::from_be_bytes(chunks[..4].try_into().ok()?) as usize;
+        let kind = &chunks[4..8];
+        let data = chunks.get(8..8 + len)?;
+        match kind {
+            b"pHYs" if len == 9 => {
+                // Unit 1 is pixels per metre; 0 only gives the aspect ratio.
+                if data[8] != 1 {
+                    return None;
+                }
+                let per_metre = u32::from_be_bytes(data[..4].try_into().ok()?);
+                return Some(per_metre as f32 * 0.0254);
+            }
+            // pHYs must come before the image data.
+            b"IDAT" | b"IEND" => return None,
+            _ => {}
+        }
+        chunks = chunks.get(8 + len + 4..)?;
+    }
+    None
+}
+
+fn jfif_dpi(mut segments: &[u8]) -> Option<f32> {
+    while let [0xff, marker, hi, lo, rest @ ..] = segments {
+        let len = (u16::from_be_bytes([*hi, *lo]) as usize).checked_sub(2)?;
+        let data = rest.get(..len)?;
+        match marker {
+            0xe0 if data.starts_with(b"JFIF\0") && data.len() >= 12 => {
+                let density = u16::from_be_bytes([data[8], data[9]]) as f32;
+                return match data[7] {
+                    1 => Some(density),
+                    2 => Some(density * 2.54),
+                    _ => None,
+                };
+            }
+            // Start of scan: the headers are over.
+            0xda => return None,
+            _ => {}
+        }
+        segments = &rest[len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_with_phys(per_metre: u32, unit: u8) -> Vec<u8> {
+        let mut out = PNG_SIGNATURE.to_vec();
+        out.extend_from_slice(&13u32.to_be_bytes());
+        out.extend_from_slice(b"IHDR");
+        out.extend_from_slice(&[0; 13 + 4]);
+        out.extend_from_slice(&9u32.to_be_bytes());
+        out.extend_from_slice(b"pHYs");
+        out.extend_from_slice(&per_metre.to_be_bytes());
+        out.extend_from_slice(&per_metre.to_be_bytes());
+        out.push(unit);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn jpeg_with_jfif(units: u8, density: u16) -> Vec<u8> {
+        let mut out = vec![0xff, 0xd8, 0xff, 0xe0, 0, 16];
+        out.extend_from_slice(b"JFIF\0");
+        out.extend_from_slice(&[1, 1, units]);
+        out.extend_from_slice(&density.to_be_bytes());
+        out.extend_from_slice(&density.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&[0xff, 0xda, 0, 2]);
+        out
+    }
+
+    #[test]
+    fn reads_png_and_jfif_resolution() {
+        // 11811 px/m is 300 dpi.
+        let png = embedded_dpi(&png_with_phys(11811, 1)).unwrap();
+        assert!((png - 300.0).abs() < 0.1, "{png}");
+        assert_eq!(embedded_dpi(&png_with_phys(11811, 0)), None);
+
+        assert_eq!(embedded_dpi(&jpeg_with_jfif(1, 150)), Some(150.0));
+        assert_eq!(embedded_dpi(&jpeg_with_jfif(2, 100)), Some(254.0));
+        assert_eq!(embedded_dpi(&jpeg_with_jfif(0, 1)), None);
+        assert_eq!(embedded_dpi(b"GIF89a"), None);
+        assert_eq!(embedded_dpi(&png_with_phys(11811, 1)[..30]), None);
+    }
+
+    #[test]
+    fn real_size_keeps_physical_width() {
+        // One inch at 406 dpi is one inch of printer dots.
+        assert_eq!(real_size_width(406, 406.0, 384), dpi() as u32);
+        assert_eq!(real_size_width(100, dpi() as f32, 384), 100);
+        assert_eq!(real_size_width(3000, 300.0, 384), 384);
+        assert_eq!(real_size_width(1, 10_000.0, 384), 1);
+    }
+}
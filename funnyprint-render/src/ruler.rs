@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use ab_glyph::PxScale;
+use anyhow::{Context, Result, bail};
+use funnyprint_proto::{MAX_DOTS_PER_LINE, dpi};
+use image::{GrayImage, Luma};
+
+use crate::{FontChain, draw_shaped_line, line_height, shape_line};
+
+/// Dot rows per tick, so a 1 mm tick survives printing as a full packed line.
+const TICK_THICKNESS_PX: u32 = 2;
+const MINOR_TICK_PX: u32 = 24;
+const HALF_TICK_PX: u32 = 48;
+const MAJOR_TICK_PX: u32 = 80;
+/// Gap between a label and the ticks around it.
+const LABEL_GAP_PX: u32 = 8;
+/// Longest ruler [`render_ruler`] draws, a metre of paper.
+pub const MAX_RULER_MM: f32 = 1000.0;
+/// Largest label size; a label taller than that would not sit between two major ticks anyway.
+pub const MAX_RULER_LABEL_PX: f32 = 200.0;
+
+#[derive(Debug, Clone)]
+pub struct RulerOptions {
+    /// Print the millimetre count under every major tick with this font.
+    pub label_font: Option<PathBuf>,
+    pub text_size_px: f32,
+}
+
+impl Default for RulerOptions {
+    fn default() -> Self {
+        Self {
+            label_font: None,
+            text_size_px: 24.0,
+        }
+    }
+}
+
+/// A calibration strip `length_mm` long along the paper feed: a tick every millimetre, a longer
+/// one every 5 mm and a major, labelled one every 10 mm, placed with [`dpi`].
+///
+/// Measuring the printout with a real ruler shows whether the printer matches the assumed
+/// resolution; the ticks start at the very top, so hold the ruler's zero on the first one.
+pub fn render_ruler(length_mm: f32, opts: &RulerOptions) -> Result<GrayImage> {
+    if !(1.0..=MAX_RULER_MM).contains(&length_mm) {
+        bail!("ruler length must be in 1..={MAX_RULER_MM} mm, got {length_mm}");
+    }
+    if !(opts.text_size_px > 0.0 && opts.text_size_px <= MAX_RULER_LABEL_PX) {
+        bail!(
+            "label size must be in (0, {MAX_RULER_LABEL_PX}] px, got {}",
+            opts.text_size_px
+        );
+    }
+    let whole_mm = length_mm.floor() as u32;
+    let row = |mm: u32| (mm as f32 * dpi() as f32 / 25.4).round() as u32;
+
+    let fonts = match &opts.label_font {
+        Some(path) => Some(FontChain::load(path, &[])?),
+        None => None,
+    };
+    let scale = PxScale::from(opts.text_size_px);
+    // The last label hangs below the last major tick.
+    let label_h = fonts.as_ref().map_or(0, |fonts| {
+        LABEL_GAP_PX + line_height(fonts, scale, 1.0).ceil() as u32
+    });
+    let height = row(whole_mm)
+        .checked_add(TICK_THICKNESS_PX)
+        .and_then(|h| h.checked_add(label_h))
+        .context("ruler is too long to draw")?;
+    let width = MAX_DOTS_PER_LINE as u32;
+
+    let mut img = GrayImage::from_pixel(width, height, Luma([255]));
+    for y in 0..row(whole_mm) + TICK_THICKNESS_PX {
+        for x in 0..TICK_THICKNESS_PX {
+            img.put_pixel(x, y, Luma([0]));
+        }
+    }
+    for mm in 0..=whole_mm {
+        let len = match mm {
+            mm if mm % 10 == 0 => MAJOR_TICK_PX,
+            mm if mm % 5 == 0 => HALF_TICK_PX,
+            _ => MINOR_TICK_PX,
+        };
+        let y0 = row(mm);
+        for y in y0..y0 + TICK_THICKNESS_PX {
+            for x in 0..len {
+                img.put_pixel(x, y, Luma([0]));
+            }
+        }
+        if let Some(fonts) = &fonts
+            && mm % 10 == 0
+        {
+            let label = shape_line(fonts, scale, &mm.to_string());
+            // Clear of the millimetre ticks the label sits next to.
+            let x = (MINOR_TICK_PX + LABEL_GAP_PX) as i32;
+            let y = (y0 + TICK_THICKNESS_PX + LABEL_GAP_PX) as i32;
+            draw_shaped_line(&mut img, fonts, scale, x, y, &label);
+        }
+    }
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_land_on_the_printer_dot_grid() {
+        let img = render_ruler(20.5, &RulerOptions::default()).unwrap();
+        // 20 mm at 203 dpi is 159.8 rows, rounded to 160, plus the last tick's thickness.
+        assert_eq!(img.dimensions(), (MAX_DOTS_PER_LINE as u32, 162));
+        let black = |x, y| img.get_pixel(x, y).0[0] == 0;
+        // 1 mm, 5 mm and 10 mm ticks reach progressively further across.
+        assert!(black(MINOR_TICK_PX - 1, 8) && !black(MINOR_TICK_PX, 8));
+        assert!(black(HALF_TICK_PX - 1, 40) && !black(HALF_TICK_PX, 40));
+        assert!(black(MAJOR_TICK_PX - 1, 80) && !black(MAJOR_TICK_PX, 80));
+        assert!(black(MAJOR_TICK_PX - 1, 161));
+        assert!(!black(MINOR_TICK_PX - 1, 4));
+
+        assert!(render_ruler(0.5, &RulerOptions::default()).is_err());
+        assert!(render_ruler(f32::NAN, &RulerOptions::default()).is_err());
+        assert!(render_ruler(MAX_RULER_MM + 1.0, &RulerOptions::default()).is_err());
+        assert!(render_ruler(1e10, &RulerOptions::default()).is_err());
+        for text_size_px in [
+            0.0,
+            -4.0,
+            f32::NAN,
+            MAX_RULER_LABEL_PX + 1.0,
+            1e30,
+            f32::INFINITY,
+        ] {
+            let opts = RulerOptions {
+                text_size_px,
+                ..RulerOptions::default()
+            };
+            assert!(render_ruler(20.0, &opts).is_err());
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt::Write;
+
+use anyhow::{Result, anyhow};
+use chrono::{Local, Utc};
+use chrono_tz::Tz;
+
+/// Current time in `time_zone`, or the host one (`TZ`) when unset, as strftime `format`.
+pub fn format_now(format: &str, time_zone: Option<Tz>) -> Result<String> {
+    let mut out = String::new();
+    // Formatting through `write!` reports a bad specifier instead of panicking.
+    let written = match time_zone {
+        Some(tz) => write!(out, "{}", Utc::now().with_timezone(&tz).format(format)),
+        None => write!(out, "{}", Local::now().format(format)),
+    };
+    written.map_err(|_| anyhow!("invalid time format {format:?}"))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_specifiers_are_errors() {
+        let year = format_now("%Y", Some(chrono_tz::Europe::Moscow)).unwrap();
+        assert_eq!(year.len(), 4);
+        assert_eq!(format_now("year", None).unwrap(), "year");
+        assert!(format_now("%Q", None).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     io::Cursor,
     net::SocketAddr,
     path::PathBuf,
@@ -7,27 +7,96 @@ use std::{
         Arc,
         atomic::{AtomicU64, Ordering},
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
     Router,
-    extract::{DefaultBodyLimit, Path, Query, State},
+    extract::{
+        ConnectInfo, DefaultBodyLimit, Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
     http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
 use base64::Engine;
+use chrono::{DateTime, Utc};
 use clap::Parser;
-use funnyprint_proto::{MAX_DOTS_PER_LINE, PackedLine, discover_candidates, dpi, print_job};
-use funnyprint_render::{TextRenderOptions, image_to_packed_lines, px_to_mm, render_text_to_image};
-use image::{DynamicImage, GrayImage, ImageFormat, Luma, imageops::FilterType};
+use funnyprint_api::{
+    DitherMethod, GrayscaleMode, ImageRenderParams, MeasureTextRequest, RenderImageRequest,
+    RenderTextRequest, ResizeFilter, ScaleMode,
+};
+use funnyprint_proto::{
+    AckMode, DEFAULT_DENSITY, DEFAULT_TAIL_FEED_MM, MAX_DOTS_PER_LINE, MAX_TAIL_FEED_MM,
+    PACKED_LINE_BYTES, PackedLine, PrintError, PrintEvent, PrintReport, PrintTuning, PrinterInfo,
+    PrinterModel, PrinterSession, StatusEvent, discover_candidates, dpi, estimate_print_duration,
+    job_packets, live_scan, mm_to_packed_lines, packed_lines_mm,
+};
+use funnyprint_render::{
+    Barcode, BarcodeOptions, CanvasOptions, ContentBox, ContentKind, DitherOptions, FontChain,
+    MAX_RULER_LABEL_PX, MAX_RULER_MM, QrErrorCorrection, QrOptions, Rotate90, RulerOptions,
+    TextAlign, TextBackground, TextBlock, TextRenderOptions, border_fits, classify_content,
+    draw_border, embedded_dpi, fit_font_size, flatten_on_white, floyd_steinberg_binarize,
+    image_to_packed_lines, measure_text_block, otsu_threshold, pack, packed_content_bbox, px_to_mm,
+    real_size_width, render_barcode_to_image, render_qr_to_image, render_text_blocks,
+    render_text_to_image, threshold_binarize, trim_blank_lines, unpack,
+};
+use hmac::{Hmac, Mac};
+use image::{
+    DynamicImage, ExtendedColorType, GrayImage, ImageDecoder, ImageEncoder, ImageFormat, Luma,
+    codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder},
+    imageops::FilterType,
+    metadata::Orientation,
+};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{RwLock, mpsc};
-use tracing::{error, info, warn};
+use sha2::Sha256;
+use tokio::sync::{Mutex, RwLock, mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::{EnvFilter, fmt};
 
-const MAX_HTTP_BODY_BYTES: usize = 16 * 1024 * 1024;
+const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+const DEFAULT_MAX_IMAGE_PIXELS: u64 = 24_000_000;
+/// About half a meter of paper.
+const DEFAULT_MAX_LINES: usize = 2000;
+const MAX_HIGH_PRIORITY_STREAK: u32 = 4;
+const MAX_COPIES: u8 = 20;
+const MAX_TEXT_BLOCKS: usize = 32;
+const COPY_GAP_LINES: usize = 12;
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+const DEFAULT_PREVIEW_URL_TTL_SECONDS: u64 = 300;
+const MAX_PREVIEW_URL_TTL_SECONDS: u64 = 7 * 24 * 3600;
+const DEFAULT_PREVIEW_SCALE: u32 = 3;
+const MAX_PREVIEW_SCALE: u32 = 8;
+const TILE_GAP_ROWS: u32 = 24;
+const MIN_TILE_HEIGHT_PX: u32 = 16;
+const MIN_FIT_FONT_SIZE_PX: f32 = 8.0;
+const MAX_FIT_FONT_SIZE_PX: f32 = 200.0;
+const MAX_BLACK_RATIO: f32 = 0.98;
+/// `/renders/validate` warns above this much black: slow to print and prone to overheating.
+const MOSTLY_BLACK_RATIO: f32 = 0.6;
+/// `/renders/validate` warns below this much black: a few specks, likely a threshold mistake.
+const MOSTLY_BLANK_RATIO: f32 = 0.002;
+/// Most of an error body `/renders/validate` reads back.
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+const MAX_SHARPEN_AMOUNT: f32 = 5.0;
+const SHARPEN_SIGMA: f32 = 1.0;
+/// `/renders/ruler` length; `--max-lines` usually stops it sooner.
+/// Threshold for images without one that look like line art; keeps anti-aliased edges solid.
+const LINE_ART_THRESHOLD: u8 = 180;
+const FLUSHED_BY_OPERATOR: &str = "flushed by operator";
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+/// Per priority and printer.
+const PRINT_QUEUE_CAPACITY: usize = 64;
+const MAX_FANOUT_ADDRESSES: usize = 8;
+const QUEUE_FULL_RETRY_AFTER_SECONDS: u64 = 10;
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+const CALLBACK_ATTEMPTS: u32 = 3;
+const CALLBACK_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const UI_INDEX_HTML: &str = include_str!("../ui/index.html");
+const UI_APP_JS: &str = include_str!("../ui/app.js");
 
 #[derive(Debug, Parser)]
 #[command(name = "printerd")]
@@ -35,32 +104,195 @@ const MAX_HTTP_BODY_BYTES: usize = 16 * 1024 * 1024;
 struct Args {
     #[arg(long, default_value = "0.0.0.0:8080")]
     listen: String,
+    /// Printer for requests without an `address`; a MAC or a `--printer` name.
     #[arg(long)]
     default_address: Option<String>,
+    /// Named printer that every `address` field accepts instead of the MAC; repeat or
+    /// comma-separate.
+    #[arg(long = "printer", value_name = "NAME=ADDRESS", value_parser = parse_printer_alias, value_delimiter = ',')]
+    printers: Vec<(String, String)>,
+    /// Density for jobs that do not set one, per printer (a MAC or a `--printer` name); repeat or
+    /// comma-separate. Other printers use their model's default.
+    #[arg(long = "default-density", value_name = "PRINTER=DENSITY", value_parser = parse_printer_density, value_delimiter = ',')]
+    default_densities: Vec<(String, u8)>,
     #[arg(long)]
     api_token: Option<String>,
+    /// Accept requests from loopback addresses without `--api-token`.
+    #[arg(long, default_value_t = false)]
+    trust_localhost: bool,
     #[arg(long)]
     debug_image_dir: Option<PathBuf>,
+    #[arg(long, default_value_t = false)]
+    adaptive_density: bool,
+    /// Reconnect and continue a job whose BLE link drops mid-print.
+    #[arg(long, default_value_t = false)]
+    resume_on_disconnect: bool,
+    #[arg(long, default_value_t = 5000)]
+    handshake_timeout_ms: u64,
+    #[arg(long, default_value_t = 2)]
+    handshake_retries: u32,
+    #[arg(long)]
+    max_write_chunk: Option<usize>,
+    /// Printer notifications handled between two line writes.
+    #[arg(long, default_value_t = 16)]
+    max_notifications_per_line: usize,
+    /// `fire_and_forget` paces lines with a fixed delay; `wait_ack` sends the next line once the
+    /// printer has not asked for the previous one again within `--ack-window-ms`.
+    #[arg(long, default_value = "fire_and_forget")]
+    ack_mode: AckMode,
+    #[arg(long, default_value_t = 15)]
+    ack_window_ms: u64,
+    #[arg(long)]
+    default_font: Option<PathBuf>,
+    /// Named font that requests can pick with `"font": "<name>"`; repeat or comma-separate.
+    #[arg(long = "font", value_name = "NAME=PATH", value_parser = parse_named_font, value_delimiter = ',')]
+    fonts: Vec<(String, PathBuf)>,
+    #[arg(long, default_value_t = DEFAULT_MAX_BODY_BYTES)]
+    max_body_bytes: usize,
+    #[arg(long, default_value_t = DEFAULT_MAX_IMAGE_PIXELS)]
+    max_image_pixels: u64,
+    #[arg(long, default_value_t = DEFAULT_MAX_LINES)]
+    max_lines: usize,
+    #[arg(long)]
+    preview_signing_key: Option<String>,
+    /// zlib level (1-9) for preview PNGs; higher is smaller and slower. Unset uses fast compression.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=9))]
+    preview_png_level: Option<u8>,
+    /// Renders kept in memory; storing one more evicts the least recently previewed render that no
+    /// queued or printing job needs.
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u32).range(1..))]
+    max_renders: u32,
+    #[arg(long, default_value_t = 600)]
+    idempotency_ttl_seconds: u64,
+    #[arg(long, default_value_t = 0)]
+    keep_warm_seconds: u64,
+    /// Fail jobs that wait in a printer's queue longer than this (0 = wait forever).
+    #[arg(long, default_value_t = 0)]
+    max_queue_age_seconds: u64,
+    /// Blank paper fed after every job so the sticker clears the tear bar (0 = none). Print
+    /// requests can override it with `tail_feed_mm`.
+    #[arg(long, default_value_t = DEFAULT_TAIL_FEED_MM)]
+    tail_feed_mm: f32,
 }
 
 #[derive(Clone)]
 struct AppState {
     api_token: Option<String>,
+    trust_localhost: bool,
     default_address: Option<String>,
     renders: Arc<RwLock<HashMap<String, RenderArtifact>>>,
+    /// `--max-renders`.
+    max_renders: usize,
     jobs: Arc<RwLock<HashMap<String, JobRecord>>>,
     render_seq: Arc<AtomicU64>,
     job_seq: Arc<AtomicU64>,
-    queue_tx: mpsc::Sender<PrintCommand>,
+    queue: PrintQueue,
+    scheduled: Arc<Mutex<Vec<ScheduledJob>>>,
     debug_image_dir: Option<PathBuf>,
+    print_tuning: PrintTuning,
+    max_image_pixels: u64,
+    preview_signing_key: Option<String>,
+    preview_png_compression: CompressionType,
+    default_font: Option<PathBuf>,
+    /// `--font` names.
+    fonts: Arc<BTreeMap<String, PathBuf>>,
+    /// `--printer` names.
+    printers: Arc<BTreeMap<String, String>>,
+    /// `--default-density` by resolved address.
+    default_densities: Arc<HashMap<String, u8>>,
+    /// Models seen in scans and on connect, by resolved address.
+    printer_models: Arc<RwLock<HashMap<String, PrinterModel>>>,
+    /// Last status packet each printer sent during a job, by resolved address.
+    printer_status: Arc<RwLock<HashMap<String, ReportedStatus>>>,
+    idempotency_keys: Arc<Mutex<HashMap<String, IdempotentPrint>>>,
+    idempotency_ttl: Duration,
+    warm_sessions: Arc<Mutex<HashMap<String, WarmSession>>>,
+    keep_warm: Duration,
+    max_queue_age: Option<Duration>,
+    /// Posts finished jobs to their `callback_url`.
+    callback_http: reqwest::Client,
+}
+
+/// A connected printer kept open after a job so the next one to the same address skips the
+/// connect and handshake.
+struct WarmSession {
+    session: PrinterSession,
+    last_used: Instant,
+}
+
+/// The job a client's `Idempotency-Key` resolved to, so a retried `/print` does not print twice.
+struct IdempotentPrint {
+    job_id: String,
+    render_id: String,
+    created_at: Instant,
+}
+
+/// Print lanes by printer address. Each lane has its own worker, so different printers print at
+/// the same time while jobs for one printer keep their priority order.
+#[derive(Clone, Default)]
+struct PrintQueue {
+    lanes: Arc<Mutex<HashMap<String, PrintLane>>>,
+}
+
+impl PrintQueue {
+    /// Jobs waiting on every printer.
+    async fn len(&self) -> usize {
+        self.lanes.lock().await.values().map(PrintLane::len).sum()
+    }
+}
+
+#[derive(Clone)]
+struct PrintLane {
+    high: mpsc::Sender<PrintCommand>,
+    normal: mpsc::Sender<PrintCommand>,
+    /// Aborts the job being printed; set by the worker for the duration of each job.
+    in_flight: Arc<Mutex<Option<CancellationToken>>>,
+}
+
+impl PrintLane {
+    fn sender(&self, priority: JobPriority) -> &mpsc::Sender<PrintCommand> {
+        match priority {
+            JobPriority::High => &self.high,
+            JobPriority::Normal => &self.normal,
+        }
+    }
+
+    /// Jobs waiting in both channels, not counting the one being printed.
+    fn len(&self) -> usize {
+        [&self.high, &self.normal]
+            .iter()
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum()
+    }
+}
+
+/// A job held back until `not_before`, then handed to the print queue by `scheduler_loop`.
+struct ScheduledJob {
+    not_before: DateTime<Utc>,
+    priority: JobPriority,
+    cmd: PrintCommand,
 }
 
 #[derive(Clone)]
 struct RenderArtifact {
     preview_png: Vec<u8>,
+    /// Non-PNG previews, encoded on first request.
+    preview_cache: HashMap<PreviewFormat, Vec<u8>>,
     packed_lines: Vec<PackedLine>,
-    density: u8,
+    /// Width of the rendered image in dots; `packed_lines` are padded to the full head.
+    width_px: u32,
+    /// The render request's density; `None` leaves it to the printer, see [`default_density`].
+    density: Option<u8>,
+    /// `/print` with a different density is rejected with `invalid_density`; leaving the density
+    /// out prints at this one.
+    lock_density: bool,
     address_override: Option<String>,
+    /// Original upload of an image render made with `keep_source`.
+    source_image: Option<Arc<Vec<u8>>>,
+    /// Resized grayscale before dithering, kept for image renders made with `keep_gray_preview`.
+    gray_preview_png: Option<Vec<u8>>,
+    /// Creation or last preview fetch; the oldest goes first when `--max-renders` is reached.
+    last_access: Instant,
 }
 
 #[derive(Clone, Serialize)]
@@ -70,6 +302,7 @@ enum JobStatus {
     Printing,
     Done,
     Failed,
+    Cancelled,
 }
 
 #[derive(Clone, Serialize)]
@@ -78,8 +311,95 @@ struct JobRecord {
     render_id: String,
     address: String,
     density: u8,
+    copies: u8,
+    priority: JobPriority,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    not_before: Option<DateTime<Utc>>,
     status: JobStatus,
     error: Option<String>,
+    created_at: DateTime<Utc>,
+    /// When the printer started on the job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    started_at: Option<DateTime<Utc>>,
+    /// When the job reached `done`, `failed` or `cancelled`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<DateTime<Utc>>,
+    /// When the job entered its printer's queue; unset while it waits for `not_before`.
+    #[serde(skip)]
+    queued_at: Option<Instant>,
+    /// How long the job waited in the queue before printing started or it was given up on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_wait_ms: Option<u64>,
+    /// What the printer is doing right now, e.g. "printer paused"; only set while printing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    report: Option<JobReport>,
+    /// Where the finished job is posted; may carry credentials, so it is never shown.
+    #[serde(skip)]
+    callback_url: Option<String>,
+}
+
+impl JobRecord {
+    /// Moves the job to a final status and stamps `finished_at`.
+    fn finish(&mut self, status: JobStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+        self.finished_at = Some(Utc::now());
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobPriority {
+    High,
+    #[default]
+    Normal,
+}
+
+#[derive(Serialize)]
+struct FlushResponse {
+    address: String,
+    /// Jobs failed by the flush, including the aborted one.
+    flushed_jobs: usize,
+    aborted_in_flight: bool,
+}
+
+#[derive(Clone, Copy)]
+struct ReportedStatus {
+    status: StatusEvent,
+    at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct PrinterStatusResponse {
+    address: String,
+    battery: u8,
+    no_paper: bool,
+    overheat: bool,
+    reported_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct PrinterAlias {
+    name: String,
+    address: String,
+    /// When the printer last finished a job, as far as the in-memory job list goes back.
+    last_printed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    address: String,
+    idle_seconds: u64,
+    expires_in_seconds: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct JobReport {
+    throttled: bool,
+    paused: bool,
+    /// Reconnects after the link dropped mid-job.
+    resumes: u32,
 }
 
 #[derive(Debug)]
@@ -88,62 +408,252 @@ struct PrintCommand {
     render_id: String,
     address: String,
     density: u8,
+    copies: u8,
+    tail_feed_lines: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct HealthQuery {
+    deep: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeepHealthResponse {
+    status: &'static str,
+    render_ms: f64,
+    packed_lines: usize,
 }
 
 #[derive(Debug, Deserialize)]
 struct ScanQuery {
     seconds: Option<u64>,
+    /// Only devices whose name starts with this, ignoring case.
+    prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviewQuery {
+    exp: Option<u64>,
+    sig: Option<String>,
+    format: Option<PreviewFormat>,
+}
+
+/// The `/print` fields that shape the packets, for `/renders/{id}/wire`.
+#[derive(Debug, Deserialize)]
+struct WireQuery {
+    address: Option<String>,
+    density: Option<u8>,
+    copies: Option<u8>,
+    tail_feed_mm: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct WireResponse {
+    address: String,
+    density: u8,
+    /// Packets longer than this go out in several BLE writes; `null` writes each whole.
+    max_write_chunk: Option<usize>,
+    total_bytes: usize,
+    packets: Vec<WirePacketHex>,
+}
+
+#[derive(Serialize)]
+struct WirePacketHex {
+    kind: &'static str,
+    hex: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum PreviewFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl PreviewFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedPreviewQuery {
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct SignedPreviewResponse {
+    preview_url: String,
+    expires_at: u64,
+}
+
+/// Where a render's preview lives: under its id, or inline for an ephemeral render.
+#[derive(Debug, Serialize)]
+struct StoredRender {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    render_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preview_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preview_png_base64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_png_base64: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-struct RenderTextRequest {
+struct TextBlockRequest {
     text: String,
-    font_path: String,
-    width_px: Option<u32>,
-    height_px: Option<u32>,
     x_px: Option<i32>,
     y_px: Option<i32>,
     font_size_px: Option<f32>,
+    /// `left` (default), `center` or `right`: which edge of each line `x_px` marks.
+    align: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenderTextBlocksRequest {
+    blocks: Vec<TextBlockRequest>,
+    font: Option<String>,
+    font_path: Option<String>,
+    #[serde(default)]
+    fallback_fonts: Vec<String>,
+    #[serde(default)]
+    fallback_font_paths: Vec<String>,
+    width_px: Option<u32>,
+    height_px: Option<u32>,
     line_spacing: Option<f32>,
     threshold: Option<u8>,
     invert: Option<bool>,
     trim_blank_top_bottom: Option<bool>,
-    outline_only: Option<bool>,
-    outline_thickness_px: Option<u32>,
-    banner_mode: Option<bool>,
+    /// See [`apply_blank_trim`].
+    blank_margin_px: Option<u32>,
+    border_px: Option<u32>,
+    border_margin_px: Option<u32>,
+    mirror_h: Option<bool>,
     density: Option<u8>,
+    /// See [`RenderArtifact::lock_density`]; default false.
+    lock_density: Option<bool>,
     address: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Clone, Copy)]
-#[serde(rename_all = "snake_case")]
-enum DitherMethod {
-    Threshold,
-    FloydSteinberg,
+    preview_scale: Option<u32>,
+    include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    ephemeral: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
-struct RenderImageRequest {
-    image_base64: String,
-    width_px: Option<u32>,
-    max_height_px: Option<u32>,
+struct RepackRequest {
+    width_px: u32,
+    /// Applied to the rescaled print lines; default 127.
     threshold: Option<u8>,
-    dither_method: Option<DitherMethod>,
-    invert: Option<bool>,
     trim_blank_top_bottom: Option<bool>,
-    density: Option<u8>,
-    address: Option<String>,
+    /// See [`apply_blank_trim`].
+    blank_margin_px: Option<u32>,
+    preview_scale: Option<u32>,
+    include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    ephemeral: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
 struct RenderTextResponse {
-    render_id: String,
+    #[serde(flatten)]
+    stored: StoredRender,
     width_px: u32,
     height_px: u32,
     width_mm: f32,
     height_mm: f32,
     packed_lines: usize,
-    preview_url: String,
+    /// Rough print time of one copy with the configured tail feed, see [`estimated_seconds`].
+    estimated_seconds: f32,
+    /// Where the ink sits in the `width_px` x `height_px` image, before blank rows are trimmed;
+    /// `null` when nothing prints.
+    content_bbox: Option<ContentBbox>,
+    /// Base64 of the packed lines back to back: each line is `PACKED_LINE_BYTES` bytes, two raster
+    /// rows of `BYTES_PER_LINE` bytes (MSB = leftmost dot, 1 = black), top row first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packed_lines_base64: Option<String>,
+    /// How an image render was turned black and white.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binarization: Option<Binarization>,
+}
+
+/// `/renders/validate` body: a text or image render request with `"kind": "text"` or `"image"`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ValidateRenderRequest {
+    Text(RenderTextRequest),
+    Image(RenderImageRequest),
+}
+
+#[derive(Debug, Serialize)]
+struct ValidateRenderResponse {
+    /// Whether the same request to `/renders/text` or `/renders/image` would succeed.
+    ok: bool,
+    /// What the render would fail with, as in an error body.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    width_px: Option<u32>,
+    height_px: Option<u32>,
+    packed_lines: Option<usize>,
+    warnings: Vec<RenderWarning>,
+}
+
+#[derive(Debug, Serialize)]
+struct RenderWarning {
+    code: &'static str,
+    message: String,
+}
+
+/// A text or image render that passed its checks, before the preview is encoded or stored.
+struct RenderedLines {
+    /// What the preview shows: the grayscale text render or the 1-bit image.
+    image: GrayImage,
+    packed: Vec<PackedLine>,
+    content_bbox: Option<ContentBox>,
+}
+
+struct RenderedImage {
+    lines: RenderedLines,
+    /// The resized grayscale source, for `keep_gray_preview`.
+    resized: GrayImage,
+    binarization: Binarization,
+}
+
+#[derive(Debug, Serialize)]
+struct ContentBbox {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl From<ContentBox> for ContentBbox {
+    fn from(b: ContentBox) -> Self {
+        Self {
+            x: b.x,
+            y: b.y,
+            w: b.width,
+            h: b.height,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Binarization {
+    threshold: u8,
+    dither_method: DitherMethod,
+    /// `line_art` or `photo` when the threshold or method was picked from the image's histogram.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'static str>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -151,12 +661,129 @@ struct PrintRequest {
     render_id: String,
     address: Option<String>,
     density: Option<u8>,
+    copies: Option<u8>,
+    /// Blank paper fed after the job, overriding `--tail-feed-mm`.
+    tail_feed_mm: Option<f32>,
+    #[serde(default)]
+    priority: JobPriority,
+    not_before: Option<DateTime<Utc>>,
+    idempotency_key: Option<String>,
+    /// `http(s)` URL that gets the final job record once the job is done, failed or cancelled.
+    callback_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FanoutPrintRequest {
+    render_id: String,
+    addresses: Vec<String>,
+    density: Option<u8>,
+    copies: Option<u8>,
+    tail_feed_mm: Option<f32>,
+    #[serde(default)]
+    priority: JobPriority,
+    not_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+struct FanoutPrintResponse {
+    jobs: Vec<FanoutJob>,
+}
+
+/// One printer of a fan-out print: its job, or why it could not be queued.
+#[derive(Debug, Serialize)]
+struct FanoutJob {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListJobsQuery {
+    render_id: Option<String>,
+    address: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 struct PrintResponse {
     job_id: String,
     status_url: String,
+    /// Jobs waiting ahead of this one on its printer (or on all printers, for a replay).
+    queue_length: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenderBarcodeRequest {
+    data: String,
+    symbology: Option<String>,
+    bar_height_px: Option<u32>,
+    module_px: Option<u32>,
+    quiet_zone_modules: Option<u32>,
+    human_readable: Option<bool>,
+    font: Option<String>,
+    font_path: Option<String>,
+    text_size_px: Option<f32>,
+    mirror_h: Option<bool>,
+    density: Option<u8>,
+    /// See [`RenderArtifact::lock_density`]; default false.
+    lock_density: Option<bool>,
+    address: Option<String>,
+    preview_scale: Option<u32>,
+    include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    ephemeral: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenderRulerRequest {
+    length_mm: f32,
+    /// Number every 10 mm tick; default true, which needs `font`, `font_path` or `--default-font`.
+    labels: Option<bool>,
+    font: Option<String>,
+    font_path: Option<String>,
+    text_size_px: Option<f32>,
+    density: Option<u8>,
+    /// See [`RenderArtifact::lock_density`]; default false.
+    lock_density: Option<bool>,
+    address: Option<String>,
+    preview_scale: Option<u32>,
+    include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    ephemeral: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenderQrRequest {
+    data: String,
+    /// Dots per module; defaults to the largest that fits the paper width.
+    module_px: Option<u32>,
+    quiet_zone_modules: Option<u32>,
+    /// `L`, `M` (default), `Q` or `H`.
+    error_correction: Option<String>,
+    density: Option<u8>,
+    /// See [`RenderArtifact::lock_density`]; default false.
+    lock_density: Option<bool>,
+    address: Option<String>,
+    preview_scale: Option<u32>,
+    include_packed: Option<bool>,
+    /// Return the preview inline as `preview_png_base64` and keep nothing server-side.
+    ephemeral: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct MeasureTextResponse {
+    font_size_px: f32,
+    width_px: u32,
+    height_px: u32,
+    width_mm: f32,
+    height_mm: f32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -164,15 +791,83 @@ struct WaitQuery {
     timeout_seconds: Option<u64>,
 }
 
+/// Machine-readable reason in every error body, next to the human `error` message.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    Unauthorized,
+    /// A request field is malformed or out of range and no more specific code applies.
+    InvalidRequest,
+    InvalidSize,
+    InvalidDensity,
+    InvalidFont,
+    UnknownFont,
+    InvalidImage,
+    ImageTooLarge,
+    EmptyText,
+    TextDoesNotFit,
+    BorderDoesNotFit,
+    RenderFailed,
+    BlankResult,
+    TooDark,
+    TooLong,
+    RenderNotFound,
+    NoSourceImage,
+    SigningDisabled,
+    MissingAddress,
+    InvalidIdempotencyKey,
+    IdempotencyKeyReused,
+    JobNotFound,
+    JobNotCancellable,
+    StatusUnknown,
+    QueueFull,
+    QueueUnavailable,
+    ScanFailed,
+    RenderCheckFailed,
+    Internal,
+}
+
 #[derive(Debug, Serialize)]
 struct ErrorBody {
+    code: ErrorCode,
     error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    black_ratio: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length_mm: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_length_mm: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
 struct ScanDevice {
     address: String,
     local_name: Option<String>,
+    rssi: Option<i16>,
+    /// Hex payload by company identifier.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    manufacturer_data: BTreeMap<u16, String>,
+    guessed_model: Option<&'static str>,
+    dots_per_line: Option<usize>,
+}
+
+impl From<PrinterInfo> for ScanDevice {
+    fn from(d: PrinterInfo) -> Self {
+        Self {
+            address: d.address,
+            local_name: d.local_name,
+            rssi: d.rssi,
+            manufacturer_data: d
+                .manufacturer_data
+                .into_iter()
+                .map(|(id, data)| (id, hex::encode(data)))
+                .collect(),
+            guessed_model: d.guessed_model.map(|m| m.as_str()),
+            dots_per_line: d.guessed_model.map(|m| m.dots_per_line()),
+        }
+    }
 }
 
 #[tokio::main]
@@ -185,177 +880,676 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
     let listen_addr: SocketAddr = args.listen.parse()?;
+    for (name, path) in &args.fonts {
+        if !path.is_file() {
+            anyhow::bail!("--font {name}: {} is not a file", path.display());
+        }
+    }
 
-    let (tx, rx) = mpsc::channel::<PrintCommand>(64);
+    let printers: BTreeMap<String, String> = args.printers.into_iter().collect();
+    let default_densities: HashMap<String, u8> = args
+        .default_densities
+        .into_iter()
+        .map(|(printer, density)| (resolve_printer(&printers, &printer), density))
+        .collect();
 
+    let preview_signing_key = args.preview_signing_key.or_else(|| args.api_token.clone());
     let state = AppState {
         api_token: args.api_token,
-        default_address: args.default_address,
+        trust_localhost: args.trust_localhost,
+        default_address: args
+            .default_address
+            .map(|address| resolve_printer(&printers, &address)),
         renders: Arc::new(RwLock::new(HashMap::new())),
+        max_renders: args.max_renders as usize,
         jobs: Arc::new(RwLock::new(HashMap::new())),
         render_seq: Arc::new(AtomicU64::new(1)),
         job_seq: Arc::new(AtomicU64::new(1)),
-        queue_tx: tx,
+        queue: PrintQueue::default(),
         debug_image_dir: args.debug_image_dir,
+        print_tuning: PrintTuning {
+            adaptive_density: args.adaptive_density,
+            resume_on_disconnect: args.resume_on_disconnect,
+            handshake_timeout: Duration::from_millis(args.handshake_timeout_ms),
+            handshake_retries: args.handshake_retries,
+            max_write_chunk: args.max_write_chunk,
+            max_notifications_per_line: args.max_notifications_per_line,
+            ack_mode: args.ack_mode,
+            ack_window: Duration::from_millis(args.ack_window_ms),
+            max_lines: Some(args.max_lines),
+            tail_feed_lines: mm_to_packed_lines(args.tail_feed_mm),
+            ..PrintTuning::default()
+        },
+        scheduled: Arc::new(Mutex::new(Vec::new())),
+        max_image_pixels: args.max_image_pixels,
+        preview_signing_key,
+        preview_png_compression: args
+            .preview_png_level
+            .map_or(CompressionType::Fast, CompressionType::Level),
+        default_font: args.default_font,
+        fonts: Arc::new(args.fonts.into_iter().collect()),
+        default_densities: Arc::new(default_densities),
+        printer_models: Arc::new(RwLock::new(HashMap::new())),
+        printer_status: Arc::new(RwLock::new(HashMap::new())),
+        printers: Arc::new(printers),
+        idempotency_keys: Arc::new(Mutex::new(HashMap::new())),
+        idempotency_ttl: Duration::from_secs(args.idempotency_ttl_seconds),
+        warm_sessions: Arc::new(Mutex::new(HashMap::new())),
+        keep_warm: Duration::from_secs(args.keep_warm_seconds),
+        max_queue_age: (args.max_queue_age_seconds > 0)
+            .then(|| Duration::from_secs(args.max_queue_age_seconds)),
+        callback_http: reqwest::Client::builder()
+            .timeout(CALLBACK_TIMEOUT)
+            .build()?,
     };
 
-    tokio::spawn(worker_loop(state.clone(), rx));
+    if !state.keep_warm.is_zero() {
+        tokio::spawn(session_reaper_loop(state.clone()));
+    }
+    tokio::spawn(scheduler_loop(state.clone()));
+    if state.max_queue_age.is_some() {
+        tokio::spawn(queue_age_loop(state.clone()));
+    }
 
     let app = Router::new()
+        .route("/", get(ui_index))
+        .route("/assets/app.js", get(ui_app_js))
         .route("/health", get(health))
+        .route("/api/v1/printers", get(list_printers))
         .route("/api/v1/printers/scan", get(scan_printers))
+        .route("/api/v1/printers/scan/ws", get(scan_printers_ws))
+        .route("/api/v1/printers/sessions", get(list_sessions))
+        .route("/api/v1/measure/text", post(measure_text))
         .route("/api/v1/renders/text", post(render_text))
+        .route("/api/v1/renders/text/multi", post(render_text_multi))
         .route("/api/v1/renders/image", post(render_image))
+        .route("/api/v1/renders/barcode", post(render_barcode))
+        .route("/api/v1/renders/ruler", post(render_ruler))
+        .route("/api/v1/renders/qr", post(render_qr))
+        .route("/api/v1/renders/validate", post(validate_render))
+        .route("/api/v1/renders/{id}/rerender", post(rerender_image))
+        .route("/api/v1/renders/{id}/repack", post(repack_render))
         .route("/api/v1/renders/{id}/preview", get(get_preview))
+        .route("/api/v1/renders/{id}/source", get(get_gray_preview))
+        .route("/api/v1/renders/{id}/wire", get(get_wire_packets))
+        .route(
+            "/api/v1/renders/{id}/preview_url",
+            get(get_signed_preview_url),
+        )
         .route("/api/v1/print", post(queue_print))
+        .route("/api/v1/print/fanout", post(queue_print_fanout))
+        .route("/api/v1/jobs", get(list_jobs))
         .route("/api/v1/jobs/{id}", get(get_job))
         .route("/api/v1/jobs/{id}/wait", get(wait_job))
-        .layer(DefaultBodyLimit::max(MAX_HTTP_BODY_BYTES))
+        .route("/api/v1/jobs/{id}/cancel", post(cancel_job))
+        .route("/api/v1/printers/{address}/flush", post(flush_printer))
+        .route("/api/v1/printers/{address}/status", get(printer_status))
+        .layer(DefaultBodyLimit::max(args.max_body_bytes))
         .with_state(state);
 
     let listener = tokio::net::TcpListener::bind(listen_addr).await?;
     info!("printerd listening on http://{}", listen_addr);
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
-async fn health() -> impl IntoResponse {
-    (StatusCode::OK, "ok")
+/// The manual printing page. It holds no data, so it is served without auth; its API calls send
+/// the token typed into the page.
+async fn ui_index() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        UI_INDEX_HTML,
+    )
+        .into_response()
+}
+
+async fn ui_app_js() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/javascript; charset=utf-8")],
+        UI_APP_JS,
+    )
+        .into_response()
 }
 
-async fn scan_printers(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Query(query): Query<ScanQuery>,
-) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
-        return resp;
+async fn health(State(state): State<AppState>, Query(query): Query<HealthQuery>) -> Response {
+    if query.deep.unwrap_or(0) == 0 {
+        return (StatusCode::OK, "ok").into_response();
     }
 
-    let secs = query.seconds.unwrap_or(3).clamp(1, 15);
-    info!(scan_seconds = secs, "starting BLE scan");
-    match discover_candidates(Duration::from_secs(secs)).await {
-        Ok(list) => {
-            let devices: Vec<ScanDevice> = list
-                .into_iter()
-                .map(|d| ScanDevice {
-                    address: d.address,
-                    local_name: d.local_name,
-                })
-                .collect();
-            info!(found = devices.len(), "BLE scan completed");
-            (StatusCode::OK, axum::Json(devices)).into_response()
+    let started = std::time::Instant::now();
+    match render_probe(state.default_font.as_deref()) {
+        Ok(packed_lines) => {
+            let resp = DeepHealthResponse {
+                status: "ok",
+                render_ms: started.elapsed().as_secs_f64() * 1000.0,
+                packed_lines,
+            };
+            (StatusCode::OK, axum::Json(resp)).into_response()
         }
         Err(err) => {
-            error!(error = %err, "BLE scan failed");
-            error_response(StatusCode::BAD_GATEWAY, format!("scan failed: {err}"))
+            warn!(error = %err, "deep health check failed");
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::RenderCheckFailed,
+                format!("render check failed: {err}"),
+            )
         }
     }
 }
 
-async fn render_text(
+/// Renders and packs a fixed string with the default font, as a print would.
+fn render_probe(font: Option<&std::path::Path>) -> anyhow::Result<usize> {
+    let Some(font) = font else {
+        anyhow::bail!("no --default-font configured");
+    };
+    let opts = TextRenderOptions {
+        height_px: 64,
+        x_px: 4,
+        y_px: 4,
+        font_size_px: 32.0,
+        ..TextRenderOptions::default()
+    };
+    let image = render_text_to_image("Health 123", font, &opts)?;
+    let packed = image_to_packed_lines(&image, opts.threshold, opts.trim_blank_top_bottom);
+    if packed.is_empty() {
+        anyhow::bail!("reference text rendered blank");
+    }
+    Ok(packed.len())
+}
+
+/// Emergency stop for one printer: aborts the job it is printing and fails every job queued or
+/// scheduled for it.
+async fn flush_printer(
     State(state): State<AppState>,
     headers: HeaderMap,
-    axum::Json(req): axum::Json<RenderTextRequest>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(address): Path<String>,
 ) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
         return resp;
     }
 
-    if req.text.trim().is_empty() {
-        return error_response(StatusCode::BAD_REQUEST, "text is empty".to_string());
+    let address = resolve_printer(&state.printers, &address);
+    let lane = state.queue.lanes.lock().await.get(&address).cloned();
+    let mut aborted_in_flight = false;
+    if let Some(lane) = lane
+        && let Some(cancel) = lane.in_flight.lock().await.as_ref()
+    {
+        aborted_in_flight = !cancel.is_cancelled();
+        cancel.cancel();
     }
 
-    let banner_mode = req.banner_mode.unwrap_or(false);
-    let width_px = req.width_px.unwrap_or(MAX_DOTS_PER_LINE as u32);
-    if width_px == 0 {
-        return error_response(StatusCode::BAD_REQUEST, "width_px must be > 0".to_string());
-    }
-    if !banner_mode && width_px as usize > MAX_DOTS_PER_LINE {
-        return error_response(
-            StatusCode::BAD_REQUEST,
-            format!("width_px exceeds max {}", MAX_DOTS_PER_LINE),
-        );
-    }
-    if banner_mode && width_px > 20000 {
-        return error_response(
-            StatusCode::BAD_REQUEST,
-            "width_px too large for banner mode (max 20000)".to_string(),
-        );
+    // Jobs already handed to the lane stay in its channel; the worker skips them once failed.
+    state
+        .scheduled
+        .lock()
+        .await
+        .retain(|s| s.cmd.address != address);
+    let mut failed_queued = 0;
+    for job in state.jobs.write().await.values_mut() {
+        if job.address == address && matches!(job.status, JobStatus::Queued) {
+            job.finish(JobStatus::Failed, Some(FLUSHED_BY_OPERATOR.to_string()));
+            send_job_callback(&state, job);
+            failed_queued += 1;
+        }
     }
+    warn!(
+        address = %address,
+        aborted_in_flight = aborted_in_flight,
+        failed_queued = failed_queued,
+        "printer flushed"
+    );
 
-    let opts = TextRenderOptions {
-        width_px,
-        height_px: req.height_px.unwrap_or(192),
-        x_px: req.x_px.unwrap_or(0),
-        y_px: req.y_px.unwrap_or(0),
-        font_size_px: req.font_size_px.unwrap_or(48.0),
-        line_spacing: req.line_spacing.unwrap_or(1.0),
-        threshold: req.threshold.unwrap_or(180),
-        invert: req.invert.unwrap_or(false),
-        trim_blank_top_bottom: req.trim_blank_top_bottom.unwrap_or(true),
-        outline_only: req.outline_only.unwrap_or(false),
-        outline_thickness_px: req.outline_thickness_px.unwrap_or(1).max(1),
+    let resp = FlushResponse {
+        flushed_jobs: failed_queued + usize::from(aborted_in_flight),
+        aborted_in_flight,
+        address,
     };
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
 
-    let font_path = PathBuf::from(req.font_path);
-    let mut image = match render_text_to_image(&req.text, &font_path, &opts) {
-        Ok(v) => v,
-        Err(err) => {
-            return error_response(StatusCode::BAD_REQUEST, format!("render failed: {err}"));
-        }
+async fn list_printers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let jobs = state.jobs.read().await;
+    let printers: Vec<PrinterAlias> = state
+        .printers
+        .iter()
+        .map(|(name, address)| {
+            let address = normalize_address(address);
+            PrinterAlias {
+                name: name.clone(),
+                last_printed_at: jobs
+                    .values()
+                    .filter(|j| j.address == address && matches!(j.status, JobStatus::Done))
+                    .filter_map(|j| j.finished_at)
+                    .max(),
+                address,
+            }
+        })
+        .collect();
+    (StatusCode::OK, axum::Json(printers)).into_response()
+}
+
+async fn printer_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(address): Path<String>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let address = resolve_printer(&state.printers, &address);
+    let reported = state.printer_status.read().await.get(&address).copied();
+    let Some(ReportedStatus { status, at }) = reported else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::StatusUnknown,
+            format!("{address} has not reported its status since printerd started"),
+        );
+    };
+    let resp = PrinterStatusResponse {
+        address,
+        battery: status.battery,
+        no_paper: status.no_paper,
+        overheat: status.overheat,
+        reported_at: at,
+    };
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+async fn list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let sessions: Vec<SessionInfo> = state
+        .warm_sessions
+        .lock()
+        .await
+        .iter()
+        .map(|(address, warm)| {
+            let idle = warm.last_used.elapsed();
+            SessionInfo {
+                address: address.clone(),
+                idle_seconds: idle.as_secs(),
+                expires_in_seconds: state.keep_warm.saturating_sub(idle).as_secs(),
+            }
+        })
+        .collect();
+    (StatusCode::OK, axum::Json(sessions)).into_response()
+}
+
+async fn scan_printers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<ScanQuery>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let secs = query.seconds.unwrap_or(3).clamp(1, 15);
+    info!(scan_seconds = secs, prefix = ?query.prefix, "starting BLE scan");
+    match discover_candidates(Duration::from_secs(secs), query.prefix.as_deref()).await {
+        Ok(list) => {
+            remember_models(&state, &list).await;
+            let devices: Vec<ScanDevice> = list.into_iter().map(ScanDevice::from).collect();
+            info!(found = devices.len(), "BLE scan completed");
+            (StatusCode::OK, axum::Json(devices)).into_response()
+        }
+        Err(err) => {
+            error!(error = %err, "BLE scan failed");
+            error_response(
+                StatusCode::BAD_GATEWAY,
+                ErrorCode::ScanFailed,
+                format!("scan failed: {err}"),
+            )
+        }
+    }
+}
+
+async fn scan_printers_ws(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<ScanQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let secs = query.seconds.unwrap_or(10).clamp(1, 60);
+    ws.on_upgrade(move |socket| stream_scan(state, socket, secs, query.prefix))
+}
+
+async fn stream_scan(state: AppState, mut socket: WebSocket, secs: u64, prefix: Option<String>) {
+    info!(scan_seconds = secs, prefix = ?prefix, "starting live BLE scan");
+    let mut rx = match live_scan(Duration::from_secs(secs), prefix.as_deref()).await {
+        Ok(rx) => rx,
+        Err(err) => {
+            error!(error = %err, "live BLE scan failed");
+            let body = serde_json::json!({ "error": format!("scan failed: {err}") });
+            let _ = socket.send(Message::Text(body.to_string().into())).await;
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
     };
 
-    if banner_mode {
-        image = image::imageops::rotate90(&image);
-        if image.width() as usize > MAX_DOTS_PER_LINE {
+    let mut found = 0usize;
+    loop {
+        tokio::select! {
+            device = rx.recv() => {
+                let Some(d) = device else {
+                    break;
+                };
+                remember_models(&state, std::slice::from_ref(&d)).await;
+                let event = ScanDevice::from(d);
+                let Ok(json) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+                found += 1;
+            }
+            msg = socket.recv() => match msg {
+                None | Some(Err(_)) | Some(Ok(Message::Close(_))) => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+    info!(found = found, "live BLE scan finished");
+}
+
+async fn measure_text(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<MeasureTextRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let font_path = match resolve_font(
+        &state,
+        req.fonts.font.as_deref(),
+        req.fonts.font_path.as_deref(),
+    ) {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let fallbacks = match resolve_fallback_fonts(
+        &state,
+        &req.fonts.fallback_fonts,
+        &req.fonts.fallback_font_paths,
+    ) {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let fonts = match FontChain::load(&font_path, &fallbacks) {
+        Ok(v) => v,
+        Err(err) => {
             return error_response(
                 StatusCode::BAD_REQUEST,
-                format!("banner result width exceeds max {}", MAX_DOTS_PER_LINE),
+                ErrorCode::InvalidFont,
+                format!("font load failed: {err}"),
             );
         }
+    };
+    let line_spacing = req.line_spacing.unwrap_or(1.0);
+
+    let font_size = match req.max_width_px {
+        Some(max_width) => {
+            let max_size = req.font_size_px.unwrap_or(MAX_FIT_FONT_SIZE_PX);
+            let min_size = req.min_font_size_px.unwrap_or(MIN_FIT_FONT_SIZE_PX);
+            match fit_font_size(
+                &fonts,
+                &req.text,
+                max_width as f32,
+                min_size,
+                max_size,
+                line_spacing,
+            ) {
+                Ok((size, _)) => size,
+                Err(err) => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        ErrorCode::TextDoesNotFit,
+                        err.to_string(),
+                    );
+                }
+            }
+        }
+        None => req.font_size_px.unwrap_or(48.0),
+    };
+
+    let (width, height) = measure_text_block(&fonts, &req.text, font_size, line_spacing);
+    let width_px = width.ceil() as u32;
+    let height_px = height.ceil() as u32;
+    let resp = MeasureTextResponse {
+        font_size_px: font_size,
+        width_px,
+        height_px,
+        width_mm: px_to_mm(width_px, dpi()),
+        height_mm: px_to_mm(height_px, dpi()),
+    };
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+/// The `/renders/text` pipeline up to the packed lines, with every check a render must pass.
+#[allow(clippy::result_large_err)]
+fn render_text_lines(state: &AppState, req: &RenderTextRequest) -> Result<RenderedLines, Response> {
+    if req.text.trim().is_empty() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::EmptyText,
+            "text is empty".to_string(),
+        ));
     }
 
-    let packed = image_to_packed_lines(&image, opts.threshold, opts.trim_blank_top_bottom);
-    if packed.is_empty() {
-        return error_response(
+    let rotate_90 = match req.rotate_90.as_deref().map(str::parse::<Rotate90>) {
+        None if req.banner_mode.unwrap_or(false) => Some(Rotate90::Cw),
+        None => None,
+        Some(Ok(v)) => Some(v),
+        Some(Err(err)) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidRequest,
+                err,
+            ));
+        }
+    };
+    let background = match req.background.as_deref().map(str::parse::<TextBackground>) {
+        None => TextBackground::None,
+        Some(Ok(v)) => v,
+        Some(Err(err)) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidRequest,
+                err,
+            ));
+        }
+    };
+    // The pattern reaches every edge, so trimming has nothing blank to take.
+    let trim_blank = background == TextBackground::None;
+    let width_px = req.width_px.unwrap_or(MAX_DOTS_PER_LINE as u32);
+    let height_px = req.height_px.unwrap_or(192);
+    if width_px == 0 {
+        return Err(error_response(
             StatusCode::BAD_REQUEST,
-            "render result is blank after trim".to_string(),
-        );
+            ErrorCode::InvalidSize,
+            "width_px must be > 0".to_string(),
+        ));
+    }
+    if rotate_90.is_none() && width_px as usize > MAX_DOTS_PER_LINE {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            format!("width_px exceeds max {}", MAX_DOTS_PER_LINE),
+        ));
+    }
+    if rotate_90.is_some() && width_px > 20000 {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            "width_px too large for a rotated sticker (max 20000)".to_string(),
+        ));
+    }
+    if rotate_90.is_some() && height_px as usize > MAX_DOTS_PER_LINE {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            format!(
+                "height_px exceeds max {} (it is the printed width once rotated)",
+                MAX_DOTS_PER_LINE
+            ),
+        ));
+    }
+    let font_path = resolve_font(
+        state,
+        req.fonts.font.as_deref(),
+        req.fonts.font_path.as_deref(),
+    )?;
+    let fallback_font_paths = resolve_fallback_fonts(
+        state,
+        &req.fonts.fallback_fonts,
+        &req.fonts.fallback_font_paths,
+    )?;
+
+    let opts = TextRenderOptions {
+        width_px,
+        height_px,
+        x_px: req.x_px.unwrap_or(0),
+        y_px: req.y_px.unwrap_or(0),
+        font_size_px: req.font_size_px.unwrap_or(48.0),
+        line_spacing: req.line_spacing.unwrap_or(1.0),
+        threshold: req.threshold.unwrap_or(180),
+        invert: req.invert.unwrap_or(false),
+        invert_content_only: req.invert_content_only.unwrap_or(false),
+        trim_blank_top_bottom: trim_blank && req.trim_blank_top_bottom.unwrap_or(true),
+        outline_only: req.outline_only.unwrap_or(false),
+        outline_thickness_px: req.outline_thickness_px.unwrap_or(1).max(1),
+        background,
+        fallback_font_paths,
+        border_px: req.border_px.unwrap_or(0),
+        border_margin_px: req.border_margin_px.unwrap_or(0),
+        rotate_90,
+        mirror_h: req.mirror_h.unwrap_or(false),
+    };
+    if !border_fits(
+        opts.width_px,
+        opts.height_px,
+        opts.border_px,
+        opts.border_margin_px,
+    ) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BorderDoesNotFit,
+            "border_px + border_margin_px do not fit the sticker".to_string(),
+        ));
     }
 
-    let png = match encode_png(&image) {
+    let image = match render_text_to_image(&req.text, &font_path, &opts) {
         Ok(v) => v,
         Err(err) => {
-            return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("png encode failed: {err}"),
-            );
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::RenderFailed,
+                format!("render failed: {err}"),
+            ));
         }
     };
 
-    let density = req.density.unwrap_or(3);
-    if density > 7 {
-        return error_response(
+    let (packed, content_bbox) = apply_blank_trim(
+        image_to_packed_lines(&image, opts.threshold, false),
+        opts.trim_blank_top_bottom,
+        req.blank_margin_px.filter(|_| trim_blank),
+    );
+    check_coverage(&image, opts.threshold, packed.is_empty())?;
+    check_print_length(state, packed.len())?;
+    if req.density.is_some_and(|d| d > 7) {
+        return Err(error_response(
             StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
             "density must be in 0..=7".to_string(),
-        );
+        ));
+    }
+    Ok(RenderedLines {
+        image,
+        packed,
+        content_bbox,
+    })
+}
+
+async fn render_text(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<RenderTextRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
     }
 
+    let RenderedLines {
+        image,
+        packed,
+        content_bbox,
+    } = match render_text_lines(&state, &req) {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+
+    let png = match encode_preview_png(&state, &upscale_preview(&image, req.preview_scale)) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("png encode failed: {err}"),
+            );
+        }
+    };
+
+    let density = req.density;
     let render_id = next_id("r", &state.render_seq);
     let artifact = RenderArtifact {
         preview_png: png,
+        preview_cache: HashMap::new(),
         packed_lines: packed.clone(),
+        width_px: image.width(),
         density,
+        lock_density: req.lock_density.unwrap_or(false),
         address_override: req.address,
+        source_image: None,
+        gray_preview_png: None,
+        last_access: Instant::now(),
     };
 
-    state
-        .renders
-        .write()
-        .await
-        .insert(render_id.clone(), artifact);
+    let stored = store_render(&state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
     info!(
         render_id = %render_id,
         width_px = image.width(),
@@ -365,253 +1559,1834 @@ async fn render_text(
     );
 
     let resp = RenderTextResponse {
-        render_id: render_id.clone(),
+        stored,
         width_px: image.width(),
         height_px: image.height(),
         width_mm: px_to_mm(image.width(), dpi()),
         height_mm: px_to_mm(image.height(), dpi()),
         packed_lines: packed.len(),
-        preview_url: format!("/api/v1/renders/{render_id}/preview"),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed)),
+        binarization: None,
     };
 
     (StatusCode::OK, axum::Json(resp)).into_response()
 }
 
-async fn render_image(
+async fn render_text_multi(
     State(state): State<AppState>,
     headers: HeaderMap,
-    axum::Json(req): axum::Json<RenderImageRequest>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<RenderTextBlocksRequest>,
 ) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
         return resp;
     }
 
+    if req.blocks.is_empty() || req.blocks.len() > MAX_TEXT_BLOCKS {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidRequest,
+            format!("blocks must hold 1..={MAX_TEXT_BLOCKS} entries"),
+        );
+    }
     let width_px = req.width_px.unwrap_or(MAX_DOTS_PER_LINE as u32);
     if width_px == 0 || width_px as usize > MAX_DOTS_PER_LINE {
         return error_response(
             StatusCode::BAD_REQUEST,
-            format!("width_px must be in 1..={}", MAX_DOTS_PER_LINE),
+            ErrorCode::InvalidSize,
+            format!("width_px must be in 1..={MAX_DOTS_PER_LINE}"),
         );
     }
-    let render_id = next_id("r", &state.render_seq);
+    let mut blocks = Vec::with_capacity(req.blocks.len());
+    for (idx, block) in req.blocks.into_iter().enumerate() {
+        let align = match block.align.as_deref().map(str::parse::<TextAlign>) {
+            None => TextAlign::Left,
+            Some(Ok(v)) => v,
+            Some(Err(err)) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    ErrorCode::InvalidRequest,
+                    format!("block {idx}: {err}"),
+                );
+            }
+        };
+        blocks.push(TextBlock {
+            text: block.text,
+            x_px: block.x_px.unwrap_or(0),
+            y_px: block.y_px.unwrap_or(0),
+            font_size_px: block.font_size_px.unwrap_or(48.0),
+            align,
+        });
+    }
 
-    let image_bytes = match base64::engine::general_purpose::STANDARD.decode(req.image_base64) {
+    let font_path = match resolve_font(&state, req.font.as_deref(), req.font_path.as_deref()) {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let fallbacks =
+        match resolve_fallback_fonts(&state, &req.fallback_fonts, &req.fallback_font_paths) {
+            Ok(v) => v,
+            Err(resp) => return resp,
+        };
+    let fonts = match FontChain::load(&font_path, &fallbacks) {
         Ok(v) => v,
         Err(err) => {
             return error_response(
                 StatusCode::BAD_REQUEST,
-                format!("invalid image_base64: {err}"),
+                ErrorCode::InvalidFont,
+                format!("font load failed: {err}"),
             );
         }
     };
 
-    let dyn_img = match image::load_from_memory(&image_bytes) {
+    let threshold = req.threshold.unwrap_or(180);
+    let opts = CanvasOptions {
+        width_px,
+        height_px: req.height_px.unwrap_or(192),
+        line_spacing: req.line_spacing.unwrap_or(1.0),
+        threshold,
+        invert: req.invert.unwrap_or(false),
+        border_px: req.border_px.unwrap_or(0),
+        border_margin_px: req.border_margin_px.unwrap_or(0),
+        mirror_h: req.mirror_h.unwrap_or(false),
+    };
+    if !border_fits(
+        opts.width_px,
+        opts.height_px,
+        opts.border_px,
+        opts.border_margin_px,
+    ) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BorderDoesNotFit,
+            "border_px + border_margin_px do not fit the sticker".to_string(),
+        );
+    }
+    let image = match render_text_blocks(&fonts, &blocks, &opts) {
         Ok(v) => v,
         Err(err) => {
             return error_response(
                 StatusCode::BAD_REQUEST,
-                format!("invalid image data: {err}"),
+                ErrorCode::RenderFailed,
+                format!("render failed: {err}"),
             );
         }
     };
 
-    let gray = dyn_img.to_luma8();
-    maybe_dump_debug_image(
-        state.debug_image_dir.as_deref(),
-        &render_id,
-        "src_gray",
-        &gray,
+    let (packed, content_bbox) = apply_blank_trim(
+        image_to_packed_lines(&image, threshold, false),
+        req.trim_blank_top_bottom.unwrap_or(true),
+        req.blank_margin_px,
     );
-    let src_w = gray.width().max(1);
-    let src_h = gray.height().max(1);
-    let mut target_h = ((src_h as f32 * width_px as f32) / src_w as f32).round() as u32;
-    target_h = target_h.max(1);
-    if let Some(max_h) = req.max_height_px {
-        target_h = target_h.min(max_h.max(1));
+    if let Err(resp) = check_coverage(&image, threshold, packed.is_empty()) {
+        return resp;
     }
-
-    let resized = image::imageops::resize(&gray, width_px, target_h, FilterType::Lanczos3);
-    maybe_dump_debug_image(
-        state.debug_image_dir.as_deref(),
-        &render_id,
-        "resized_gray",
-        &resized,
-    );
-    let threshold = req.threshold.unwrap_or(180);
-    let dither = req.dither_method.unwrap_or(DitherMethod::FloydSteinberg);
-    let invert = req.invert.unwrap_or(false);
-    let trim_blank = req.trim_blank_top_bottom.unwrap_or(true);
-
-    let bw_preview = binarize_preview(&resized, threshold, dither, invert);
-    maybe_dump_debug_image(
-        state.debug_image_dir.as_deref(),
-        &render_id,
-        "bw_preview",
-        &bw_preview,
-    );
-    let packed_lines = pack_bw_image(&bw_preview, trim_blank);
-    if packed_lines.is_empty() {
-        return error_response(
-            StatusCode::BAD_REQUEST,
-            "render result is blank after trim".to_string(),
-        );
+    if let Err(resp) = check_print_length(&state, packed.len()) {
+        return resp;
     }
 
-    let preview_png = match encode_png(&bw_preview) {
+    let png = match encode_preview_png(&state, &upscale_preview(&image, req.preview_scale)) {
         Ok(v) => v,
         Err(err) => {
             return error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
                 format!("png encode failed: {err}"),
             );
         }
     };
 
-    let density = req.density.unwrap_or(3);
-    if density > 7 {
+    let density = req.density;
+    if density.is_some_and(|d| d > 7) {
         return error_response(
             StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
             "density must be in 0..=7".to_string(),
         );
     }
 
+    let render_id = next_id("r", &state.render_seq);
     let artifact = RenderArtifact {
-        preview_png,
-        packed_lines: packed_lines.clone(),
+        preview_png: png,
+        preview_cache: HashMap::new(),
+        packed_lines: packed.clone(),
+        width_px: image.width(),
         density,
+        lock_density: req.lock_density.unwrap_or(false),
         address_override: req.address,
+        source_image: None,
+        gray_preview_png: None,
+        last_access: Instant::now(),
     };
-    state
-        .renders
-        .write()
-        .await
-        .insert(render_id.clone(), artifact);
-
+    let stored = store_render(&state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
     info!(
         render_id = %render_id,
-        width_px = bw_preview.width(),
-        height_px = bw_preview.height(),
-        packed_lines = packed_lines.len(),
-        "rendered image preview"
+        blocks = blocks.len(),
+        width_px = image.width(),
+        height_px = image.height(),
+        packed_lines = packed.len(),
+        "rendered text blocks preview"
     );
 
     let resp = RenderTextResponse {
-        render_id: render_id.clone(),
-        width_px: bw_preview.width(),
-        height_px: bw_preview.height(),
-        width_mm: px_to_mm(bw_preview.width(), dpi()),
-        height_mm: px_to_mm(bw_preview.height(), dpi()),
-        packed_lines: packed_lines.len(),
-        preview_url: format!("/api/v1/renders/{render_id}/preview"),
+        stored,
+        width_px: image.width(),
+        height_px: image.height(),
+        width_mm: px_to_mm(image.width(), dpi()),
+        height_mm: px_to_mm(image.height(), dpi()),
+        packed_lines: packed.len(),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed)),
+        binarization: None,
     };
 
     (StatusCode::OK, axum::Json(resp)).into_response()
 }
 
-async fn get_preview(
+async fn render_image(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Path(id): Path<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<RenderImageRequest>,
 ) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
         return resp;
     }
 
-    let renders = state.renders.read().await;
-    let Some(artifact) = renders.get(&id) else {
-        return error_response(StatusCode::NOT_FOUND, "render not found".to_string());
+    let image_bytes = match decode_image_base64(&req.image_base64) {
+        Ok(v) => v,
+        Err(resp) => return resp,
     };
 
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "image/png")],
-        artifact.preview_png.clone(),
+    let keep_source = req.keep_source.unwrap_or(false);
+    if keep_source && req.params.ephemeral.unwrap_or(false) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidRequest,
+            "keep_source needs a stored render; drop ephemeral".to_string(),
+        );
+    }
+    render_image_source(&state, Arc::new(image_bytes), req.params, keep_source).await
+}
+
+#[allow(clippy::result_large_err)]
+fn decode_image_base64(image_base64: &str) -> Result<Vec<u8>, Response> {
+    base64::engine::general_purpose::STANDARD
+        .decode(image_base64)
+        .map_err(|err| {
+            error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidImage,
+                format!("invalid image_base64: {err}"),
+            )
+        })
+}
+
+/// Runs the text or image render pipeline without storing anything, to check a form before the
+/// real render. Rejections come back as `200` with `ok: false`; only auth and server errors keep
+/// their status.
+async fn validate_render(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<ValidateRenderRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    // Borders and background patterns reach the edges on purpose, and so do photos.
+    let check_edges = match &req {
+        ValidateRenderRequest::Text(req) => {
+            req.border_px.unwrap_or(0) == 0
+                && req
+                    .background
+                    .as_deref()
+                    .is_none_or(|b| b.eq_ignore_ascii_case("none"))
+        }
+        ValidateRenderRequest::Image(_) => false,
+    };
+    let rendered = match &req {
+        ValidateRenderRequest::Text(req) => render_text_lines(&state, req),
+        ValidateRenderRequest::Image(req) => match decode_image_base64(&req.image_base64) {
+            Ok(bytes) => {
+                let render_id = next_id("r", &state.render_seq);
+                render_image_lines(&state, &bytes, &req.params, &render_id).map(|r| r.lines)
+            }
+            Err(resp) => Err(resp),
+        },
+    };
+
+    let result = match rendered {
+        Ok(lines) => ValidateRenderResponse {
+            ok: true,
+            code: None,
+            error: None,
+            width_px: Some(lines.image.width()),
+            height_px: Some(lines.image.height()),
+            packed_lines: Some(lines.packed.len()),
+            warnings: render_warnings(&lines, check_edges),
+        },
+        Err(resp) if resp.status().is_server_error() => return resp,
+        Err(resp) => {
+            // Error bodies are a short `{"code","error"}`.
+            let body = axum::body::to_bytes(resp.into_body(), MAX_ERROR_BODY_BYTES)
+                .await
+                .unwrap_or_default();
+            let mut error: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            ValidateRenderResponse {
+                ok: false,
+                code: error.get_mut("code").map(serde_json::Value::take),
+                error: error
+                    .get("error")
+                    .and_then(serde_json::Value::as_str)
+                    .map(str::to_string),
+                width_px: None,
+                height_px: None,
+                packed_lines: None,
+                warnings: Vec::new(),
+            }
+        }
+    };
+    (StatusCode::OK, axum::Json(result)).into_response()
+}
+
+/// Things about a render that would succeed that the client may still want to fix.
+fn render_warnings(lines: &RenderedLines, check_edges: bool) -> Vec<RenderWarning> {
+    let mut warnings = Vec::new();
+    let (width_px, height_px) = lines.image.dimensions();
+    let black_dots: u64 = lines
+        .packed
+        .iter()
+        .flatten()
+        .map(|b| u64::from(b.count_ones()))
+        .sum();
+    let ratio = black_dots as f32 / (width_px as f32 * height_px as f32).max(1.0);
+    if ratio >= MOSTLY_BLACK_RATIO {
+        warnings.push(RenderWarning {
+            code: "mostly_black",
+            message: format!(
+                "{:.0}% of the sticker is black; it prints slowly and may overheat the head",
+                ratio * 100.0
+            ),
+        });
+    } else if ratio < MOSTLY_BLANK_RATIO {
+        warnings.push(RenderWarning {
+            code: "mostly_blank",
+            message: format!(
+                "only {black_dots} dots print; check the threshold or the image contrast"
+            ),
+        });
+    }
+    if check_edges
+        && let Some(b) = &lines.content_bbox
+        && (b.x == 0 || b.y == 0 || b.x + b.width >= width_px || b.y + b.height >= height_px)
+    {
+        warnings.push(RenderWarning {
+            code: "touches_edge",
+            message: "text reaches the sticker edge and may be cut off; use a smaller font or \
+                      move it"
+                .to_string(),
+        });
+    }
+    warnings
+}
+
+async fn rerender_image(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    axum::Json(params): axum::Json<ImageRenderParams>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let source = match state.renders.read().await.get(&id) {
+        Some(artifact) => artifact.source_image.clone(),
+        None => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                ErrorCode::RenderNotFound,
+                "render not found".to_string(),
+            );
+        }
+    };
+    let Some(source) = source else {
+        return error_response(
+            StatusCode::CONFLICT,
+            ErrorCode::NoSourceImage,
+            "render has no source image; create it via /renders/image with keep_source".to_string(),
+        );
+    };
+
+    render_image_source(&state, source, params, true).await
+}
+
+/// Rescales a render's preview to another width and packs it as a new render, so it can go to a
+/// printer with a narrower head without the source.
+async fn repack_render(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    axum::Json(req): axum::Json<RepackRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    if req.width_px == 0 || req.width_px as usize > MAX_DOTS_PER_LINE {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            format!("width_px must be in 1..={MAX_DOTS_PER_LINE} (printer max)"),
+        );
+    }
+
+    let (lines, width, density, lock_density, address_override, source_image) =
+        match state.renders.read().await.get(&id) {
+            Some(artifact) => (
+                artifact.packed_lines.clone(),
+                artifact.width_px,
+                artifact.density,
+                artifact.lock_density,
+                artifact.address_override.clone(),
+                artifact.source_image.clone(),
+            ),
+            None => {
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    ErrorCode::RenderNotFound,
+                    "render not found".to_string(),
+                );
+            }
+        };
+    // Rescale the dots that would print; the crop drops the padding out to the full head.
+    let printed = unpack(&lines);
+    let printed =
+        image::imageops::crop_imm(&printed, 0, 0, width.max(1), printed.height()).to_image();
+
+    let target_h = ((printed.height() as f32 * req.width_px as f32) / printed.width() as f32)
+        .round()
+        .max(1.0) as u32;
+    let resized = image::imageops::resize(&printed, req.width_px, target_h, FilterType::Triangle);
+    let bw = threshold_binarize(&resized, req.threshold.unwrap_or(127), false);
+    let (packed, content_bbox) = apply_blank_trim(
+        pack(&bw),
+        req.trim_blank_top_bottom.unwrap_or(true),
+        req.blank_margin_px,
+    );
+    if let Err(resp) = check_coverage(&bw, 0, packed.is_empty()) {
+        return resp;
+    }
+    if let Err(resp) = check_print_length(&state, packed.len()) {
+        return resp;
+    }
+
+    let png = match encode_preview_png(&state, &upscale_preview(&bw, req.preview_scale)) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("png encode failed: {err}"),
+            );
+        }
+    };
+
+    let render_id = next_id("r", &state.render_seq);
+    let artifact = RenderArtifact {
+        preview_png: png,
+        preview_cache: HashMap::new(),
+        packed_lines: packed.clone(),
+        width_px: bw.width(),
+        density,
+        lock_density,
+        address_override,
+        source_image,
+        gray_preview_png: None,
+        last_access: Instant::now(),
+    };
+    let stored = store_render(&state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
+    info!(
+        render_id = %render_id,
+        from = %id,
+        width_px = bw.width(),
+        height_px = bw.height(),
+        packed_lines = packed.len(),
+        "repacked render"
+    );
+
+    let resp = RenderTextResponse {
+        stored,
+        width_px: bw.width(),
+        height_px: bw.height(),
+        width_mm: px_to_mm(bw.width(), dpi()),
+        height_mm: px_to_mm(bw.height(), dpi()),
+        packed_lines: packed.len(),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed)),
+        binarization: None,
+    };
+
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+/// The `/renders/image` pipeline up to the packed lines, with every check a render must pass.
+/// `render_id` names the debug images.
+#[allow(clippy::result_large_err)]
+fn render_image_lines(
+    state: &AppState,
+    image_bytes: &[u8],
+    req: &ImageRenderParams,
+    render_id: &str,
+) -> Result<RenderedImage, Response> {
+    let width_px = req.width_px.unwrap_or(MAX_DOTS_PER_LINE as u32);
+    if width_px == 0 || width_px as usize > MAX_DOTS_PER_LINE {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            format!("width_px must be in 1..={}", MAX_DOTS_PER_LINE),
+        ));
+    }
+    if req.source_dpi.is_some_and(|d| !(d.is_finite() && d > 0.0)) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidRequest,
+            "source_dpi must be > 0".to_string(),
+        ));
+    }
+
+    let (img_w, img_h) = match image::ImageReader::new(Cursor::new(image_bytes))
+        .with_guessed_format()
+        .map_err(image::ImageError::from)
+        .and_then(|r| r.into_dimensions())
+    {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidImage,
+                format!("invalid image data: {err}"),
+            ));
+        }
+    };
+    let pixels = img_w as u64 * img_h as u64;
+    if pixels > state.max_image_pixels {
+        return Err(error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::ImageTooLarge,
+            format!(
+                "image is {}x{} ({} pixels), limit is {} pixels",
+                img_w, img_h, pixels, state.max_image_pixels
+            ),
+        ));
+    }
+
+    let dyn_img = match decode_image(image_bytes, req.auto_orient.unwrap_or(true)) {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidImage,
+                format!("invalid image data: {err}"),
+            ));
+        }
+    };
+    let dyn_img = DynamicImage::ImageRgb8(flatten_on_white(&dyn_img));
+
+    let gray = to_gray_with_mode(&dyn_img, req.grayscale_mode.unwrap_or(GrayscaleMode::Luma));
+    maybe_dump_debug_image(
+        state.debug_image_dir.as_deref(),
+        render_id,
+        "src_gray",
+        &gray,
+    );
+    let src_w = gray.width().max(1);
+    let src_h = gray.height().max(1);
+    let width_px = match req.scale_mode.unwrap_or_default() {
+        ScaleMode::FillWidth => width_px,
+        ScaleMode::Dpi => {
+            let Some(source_dpi) = req.source_dpi.or_else(|| embedded_dpi(image_bytes)) else {
+                return Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    ErrorCode::InvalidRequest,
+                    "image has no resolution metadata, pass source_dpi".to_string(),
+                ));
+            };
+            real_size_width(src_w, source_dpi, width_px)
+        }
+    };
+    let mut target_h = ((src_h as f32 * width_px as f32) / src_w as f32).round() as u32;
+    target_h = target_h.max(1);
+    if let Some(max_h) = req.max_height_px {
+        target_h = target_h.min(max_h.max(1));
+    }
+
+    let filter = resize_filter_type(req.resize_filter.unwrap_or_default());
+    let mut resized = image::imageops::resize(&gray, width_px, target_h, filter);
+    if let Some(amount) = req.sharpen.filter(|a| *a > 0.0) {
+        resized = unsharp_mask(&resized, amount.min(MAX_SHARPEN_AMOUNT));
+    }
+    maybe_dump_debug_image(
+        state.debug_image_dir.as_deref(),
+        render_id,
+        "resized_gray",
+        &resized,
+    );
+    let invert = req.invert.unwrap_or(false);
+    // Missing settings are picked from the histogram: line art thresholds cleanly, photos dither
+    // around their Otsu threshold.
+    let content = (req.threshold.is_none() || req.dither_method.is_none())
+        .then(|| classify_content(&resized));
+    let (threshold, dither) = match content {
+        None => (
+            req.threshold.unwrap_or(LINE_ART_THRESHOLD),
+            req.dither_method.unwrap_or(DitherMethod::FloydSteinberg),
+        ),
+        Some(ContentKind::LineArt) => (
+            req.threshold.unwrap_or(LINE_ART_THRESHOLD),
+            req.dither_method.unwrap_or(DitherMethod::Threshold),
+        ),
+        Some(ContentKind::Photo) => {
+            let otsu = otsu_threshold(&resized);
+            (
+                // Inverting flips the histogram, and the split with it.
+                req.threshold
+                    .unwrap_or(if invert { 254 - otsu } else { otsu }),
+                req.dither_method.unwrap_or(DitherMethod::FloydSteinberg),
+            )
+        }
+    };
+    let trim_blank = req.trim_blank_top_bottom.unwrap_or(true);
+
+    if req.error_clamp.is_some_and(|c| c <= 0.0) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidRequest,
+            "error_clamp must be > 0".to_string(),
+        ));
+    }
+    let dither_opts = DitherOptions {
+        serpentine: req.serpentine.unwrap_or(false),
+        error_clamp: req.error_clamp,
+    };
+
+    let mut bw_preview = binarize_preview(&resized, threshold, dither, invert, &dither_opts);
+    if let Some(tile_h) = req.tile_height_px {
+        if tile_h < MIN_TILE_HEIGHT_PX {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidRequest,
+                format!("tile_height_px must be >= {MIN_TILE_HEIGHT_PX}"),
+            ));
+        }
+        if bw_preview.height() > tile_h {
+            bw_preview = tile_bw_image(&bw_preview, tile_h, trim_blank);
+        }
+    }
+    let border_px = req.border_px.unwrap_or(0);
+    if !draw_border(
+        &mut bw_preview,
+        border_px,
+        req.border_margin_px.unwrap_or(0),
+    ) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::BorderDoesNotFit,
+            "border_px + border_margin_px do not fit the image".to_string(),
+        ));
+    }
+    if req.mirror_h.unwrap_or(false) {
+        image::imageops::flip_horizontal_in_place(&mut bw_preview);
+    }
+    maybe_dump_debug_image(
+        state.debug_image_dir.as_deref(),
+        render_id,
+        "bw_preview",
+        &bw_preview,
+    );
+    let (packed_lines, content_bbox) =
+        apply_blank_trim(pack(&bw_preview), trim_blank, req.blank_margin_px);
+    check_coverage(&bw_preview, 0, packed_lines.is_empty())?;
+    check_print_length(state, packed_lines.len())?;
+    if req.density.is_some_and(|d| d > 7) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
+            "density must be in 0..=7".to_string(),
+        ));
+    }
+    Ok(RenderedImage {
+        lines: RenderedLines {
+            image: bw_preview,
+            packed: packed_lines,
+            content_bbox,
+        },
+        resized,
+        binarization: Binarization {
+            threshold,
+            dither_method: dither,
+            content: content.map(|c| match c {
+                ContentKind::LineArt => "line_art",
+                ContentKind::Photo => "photo",
+            }),
+        },
+    })
+}
+
+async fn render_image_source(
+    state: &AppState,
+    image_bytes: Arc<Vec<u8>>,
+    req: ImageRenderParams,
+    keep_source: bool,
+) -> Response {
+    let render_id = next_id("r", &state.render_seq);
+    let RenderedImage {
+        lines:
+            RenderedLines {
+                image: bw_preview,
+                packed: packed_lines,
+                content_bbox,
+            },
+        resized,
+        binarization,
+    } = match render_image_lines(state, &image_bytes, &req, &render_id) {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+
+    let preview = upscale_preview(&bw_preview, req.preview_scale);
+    let preview_png = match encode_preview_png(state, &preview) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("png encode failed: {err}"),
+            );
+        }
+    };
+    let gray_preview_png = if req.keep_gray_preview.unwrap_or(false) {
+        match encode_preview_png(state, &upscale_preview(&resized, req.preview_scale)) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                return error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ErrorCode::Internal,
+                    format!("png encode failed: {err}"),
+                );
+            }
+        }
+    } else {
+        None
+    };
+
+    let density = req.density;
+    let artifact = RenderArtifact {
+        preview_png,
+        preview_cache: HashMap::new(),
+        packed_lines: packed_lines.clone(),
+        width_px: bw_preview.width(),
+        density,
+        lock_density: req.lock_density.unwrap_or(false),
+        address_override: req.address,
+        source_image: keep_source.then_some(image_bytes),
+        gray_preview_png,
+        last_access: Instant::now(),
+    };
+    let stored = store_render(state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
+
+    info!(
+        render_id = %render_id,
+        width_px = bw_preview.width(),
+        height_px = bw_preview.height(),
+        packed_lines = packed_lines.len(),
+        "rendered image preview"
+    );
+
+    let resp = RenderTextResponse {
+        stored,
+        width_px: bw_preview.width(),
+        height_px: bw_preview.height(),
+        width_mm: px_to_mm(bw_preview.width(), dpi()),
+        height_mm: px_to_mm(bw_preview.height(), dpi()),
+        packed_lines: packed_lines.len(),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed_lines.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed_lines)),
+        binarization: Some(binarization),
+    };
+
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+async fn render_barcode(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<RenderBarcodeRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let symbology = match req
+        .symbology
+        .as_deref()
+        .unwrap_or("code128")
+        .parse::<Barcode>()
+    {
+        Ok(v) => v,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, err),
+    };
+    let human_readable_font = if req.human_readable.unwrap_or(false) {
+        match resolve_font(&state, req.font.as_deref(), req.font_path.as_deref()) {
+            Ok(v) => Some(v),
+            Err(resp) => return resp,
+        }
+    } else {
+        None
+    };
+    let defaults = BarcodeOptions::default();
+    let opts = BarcodeOptions {
+        bar_height_px: req.bar_height_px.unwrap_or(defaults.bar_height_px),
+        module_px: req.module_px.unwrap_or(defaults.module_px),
+        quiet_zone_modules: req
+            .quiet_zone_modules
+            .unwrap_or(defaults.quiet_zone_modules),
+        human_readable_font,
+        text_size_px: req.text_size_px.unwrap_or(defaults.text_size_px),
+    };
+
+    let mut image = match render_barcode_to_image(symbology, &req.data, &opts) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::RenderFailed,
+                format!("render failed: {err}"),
+            );
+        }
+    };
+    if req.mirror_h.unwrap_or(false) {
+        image::imageops::flip_horizontal_in_place(&mut image);
+    }
+    let (packed, content_bbox) =
+        apply_blank_trim(image_to_packed_lines(&image, 127, false), true, None);
+    if let Err(resp) = check_print_length(&state, packed.len()) {
+        return resp;
+    }
+
+    let png = match encode_preview_png(&state, &upscale_preview(&image, req.preview_scale)) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("png encode failed: {err}"),
+            );
+        }
+    };
+
+    let density = req.density;
+    if density.is_some_and(|d| d > 7) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
+            "density must be in 0..=7".to_string(),
+        );
+    }
+
+    let render_id = next_id("r", &state.render_seq);
+    let artifact = RenderArtifact {
+        preview_png: png,
+        preview_cache: HashMap::new(),
+        packed_lines: packed.clone(),
+        width_px: image.width(),
+        density,
+        lock_density: req.lock_density.unwrap_or(false),
+        address_override: req.address,
+        source_image: None,
+        gray_preview_png: None,
+        last_access: Instant::now(),
+    };
+    let stored = store_render(&state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
+    info!(
+        render_id = %render_id,
+        symbology = ?symbology,
+        width_px = image.width(),
+        height_px = image.height(),
+        packed_lines = packed.len(),
+        "rendered barcode preview"
+    );
+
+    let resp = RenderTextResponse {
+        stored,
+        width_px: image.width(),
+        height_px: image.height(),
+        width_mm: px_to_mm(image.width(), dpi()),
+        height_mm: px_to_mm(image.height(), dpi()),
+        packed_lines: packed.len(),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed)),
+        binarization: None,
+    };
+
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+async fn render_ruler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<RenderRulerRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let label_font = if req.labels.unwrap_or(true) {
+        match resolve_font(&state, req.font.as_deref(), req.font_path.as_deref()) {
+            Ok(v) => Some(v),
+            Err(resp) => return resp,
+        }
+    } else {
+        None
+    };
+    let opts = RulerOptions {
+        label_font,
+        text_size_px: req
+            .text_size_px
+            .unwrap_or(RulerOptions::default().text_size_px),
+    };
+
+    if !(1.0..=MAX_RULER_MM).contains(&req.length_mm) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            format!("length_mm must be in 1..={MAX_RULER_MM}"),
+        );
+    }
+    if !(opts.text_size_px > 0.0 && opts.text_size_px <= MAX_RULER_LABEL_PX) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidSize,
+            format!("text_size_px must be in (0, {MAX_RULER_LABEL_PX}]"),
+        );
+    }
+    // The handler shares its name with the renderer.
+    let image = match funnyprint_render::render_ruler(req.length_mm, &opts) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::RenderFailed,
+                format!("render failed: {err}"),
+            );
+        }
+    };
+    // Untrimmed, so the first tick is the first row printed.
+    let (packed, content_bbox) =
+        apply_blank_trim(image_to_packed_lines(&image, 127, false), false, None);
+    if let Err(resp) = check_print_length(&state, packed.len()) {
+        return resp;
+    }
+
+    let png = match encode_preview_png(&state, &upscale_preview(&image, req.preview_scale)) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("png encode failed: {err}"),
+            );
+        }
+    };
+
+    let density = req.density;
+    if density.is_some_and(|d| d > 7) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
+            "density must be in 0..=7".to_string(),
+        );
+    }
+
+    let render_id = next_id("r", &state.render_seq);
+    let artifact = RenderArtifact {
+        preview_png: png,
+        preview_cache: HashMap::new(),
+        packed_lines: packed.clone(),
+        width_px: image.width(),
+        density,
+        lock_density: req.lock_density.unwrap_or(false),
+        address_override: req.address,
+        source_image: None,
+        gray_preview_png: None,
+        last_access: Instant::now(),
+    };
+    let stored = store_render(&state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
+    info!(
+        render_id = %render_id,
+        length_mm = req.length_mm,
+        height_px = image.height(),
+        packed_lines = packed.len(),
+        "rendered ruler preview"
+    );
+
+    let resp = RenderTextResponse {
+        stored,
+        width_px: image.width(),
+        height_px: image.height(),
+        width_mm: px_to_mm(image.width(), dpi()),
+        height_mm: px_to_mm(image.height(), dpi()),
+        packed_lines: packed.len(),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed)),
+        binarization: None,
+    };
+
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+async fn render_qr(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<RenderQrRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let defaults = QrOptions::default();
+    let error_correction = match req.error_correction.as_deref() {
+        Some(level) => match level.parse::<QrErrorCorrection>() {
+            Ok(v) => v,
+            Err(err) => {
+                return error_response(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, err);
+            }
+        },
+        None => defaults.error_correction,
+    };
+    let opts = QrOptions {
+        module_px: req.module_px,
+        quiet_zone_modules: req
+            .quiet_zone_modules
+            .unwrap_or(defaults.quiet_zone_modules),
+        error_correction,
+    };
+
+    let image = match render_qr_to_image(&req.data, &opts) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::RenderFailed,
+                format!("render failed: {err}"),
+            );
+        }
+    };
+    let (packed, content_bbox) =
+        apply_blank_trim(image_to_packed_lines(&image, 127, false), true, None);
+    if let Err(resp) = check_print_length(&state, packed.len()) {
+        return resp;
+    }
+
+    let png = match encode_preview_png(&state, &upscale_preview(&image, req.preview_scale)) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("png encode failed: {err}"),
+            );
+        }
+    };
+
+    let density = req.density;
+    if density.is_some_and(|d| d > 7) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
+            "density must be in 0..=7".to_string(),
+        );
+    }
+
+    let render_id = next_id("r", &state.render_seq);
+    let artifact = RenderArtifact {
+        preview_png: png,
+        preview_cache: HashMap::new(),
+        packed_lines: packed.clone(),
+        width_px: image.width(),
+        density,
+        lock_density: req.lock_density.unwrap_or(false),
+        address_override: req.address,
+        source_image: None,
+        gray_preview_png: None,
+        last_access: Instant::now(),
+    };
+    let stored = store_render(&state, &render_id, artifact, req.ephemeral.unwrap_or(false)).await;
+    info!(
+        render_id = %render_id,
+        width_px = image.width(),
+        packed_lines = packed.len(),
+        "rendered qr preview"
+    );
+
+    let resp = RenderTextResponse {
+        stored,
+        width_px: image.width(),
+        height_px: image.height(),
+        width_mm: px_to_mm(image.width(), dpi()),
+        height_mm: px_to_mm(image.height(), dpi()),
+        packed_lines: packed.len(),
+        estimated_seconds: estimated_seconds(&state.print_tuning, packed.len()),
+        content_bbox: content_bbox.map(ContentBbox::from),
+        packed_lines_base64: req
+            .include_packed
+            .unwrap_or(false)
+            .then(|| encode_packed_lines(&packed)),
+        binarization: None,
+    };
+
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+async fn get_preview(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<PreviewQuery>,
+) -> Response {
+    match (query.exp, query.sig.as_deref()) {
+        (Some(exp), Some(sig)) => {
+            if let Err(resp) = verify_preview_signature(&state, &id, exp, sig) {
+                return resp;
+            }
+        }
+        _ => {
+            if let Err(resp) = require_auth(&state, &headers, peer) {
+                return resp;
+            }
+        }
+    }
+
+    let format = query.format.unwrap_or(PreviewFormat::Png);
+    let png = {
+        let mut renders = state.renders.write().await;
+        let Some(artifact) = renders.get_mut(&id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                ErrorCode::RenderNotFound,
+                "render not found".to_string(),
+            );
+        };
+        artifact.last_access = Instant::now();
+        let cached = match format {
+            PreviewFormat::Png => Some(&artifact.preview_png),
+            _ => artifact.preview_cache.get(&format),
+        };
+        if let Some(bytes) = cached {
+            return (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, format.content_type())],
+                bytes.clone(),
+            )
+                .into_response();
+        }
+        artifact.preview_png.clone()
+    };
+
+    let bytes = match reencode_preview(&png, format) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorCode::Internal,
+                format!("preview encode failed: {err}"),
+            );
+        }
+    };
+    if let Some(artifact) = state.renders.write().await.get_mut(&id) {
+        artifact.preview_cache.insert(format, bytes.clone());
+    }
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, format.content_type())],
+        bytes,
+    )
+        .into_response()
+}
+
+async fn get_gray_preview(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let renders = state.renders.read().await;
+    let Some(artifact) = renders.get(&id) else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::RenderNotFound,
+            "render not found".to_string(),
+        );
+    };
+    let Some(png) = &artifact.gray_preview_png else {
+        return error_response(
+            StatusCode::CONFLICT,
+            ErrorCode::NoSourceImage,
+            "render has no grayscale source; create it via /renders/image with keep_gray_preview"
+                .to_string(),
+        );
+    };
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "image/png")],
+        png.clone(),
     )
         .into_response()
 }
 
+async fn get_signed_preview_url(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<SignedPreviewQuery>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let Some(key) = state.preview_signing_key.as_deref() else {
+        return error_response(
+            StatusCode::CONFLICT,
+            ErrorCode::SigningDisabled,
+            "signed previews need --preview-signing-key or --api-token".to_string(),
+        );
+    };
+    if !state.renders.read().await.contains_key(&id) {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::RenderNotFound,
+            "render not found".to_string(),
+        );
+    }
+
+    let ttl = query
+        .ttl_seconds
+        .unwrap_or(DEFAULT_PREVIEW_URL_TTL_SECONDS)
+        .clamp(1, MAX_PREVIEW_URL_TTL_SECONDS);
+    let expires_at = unix_now() + ttl;
+    let sig = hex::encode(preview_mac(key, &id, expires_at).finalize().into_bytes());
+
+    let resp = SignedPreviewResponse {
+        preview_url: format!("/api/v1/renders/{id}/preview?exp={expires_at}&sig={sig}"),
+        expires_at,
+    };
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
+/// The packets a `/print` of the render with the same parameters would write, for protocol
+/// debugging. Nothing is queued and the printer is not contacted.
+async fn get_wire_packets(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    Query(query): Query<WireQuery>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let Some(artifact) = state.renders.read().await.get(&id).cloned() else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::RenderNotFound,
+            "render not found".to_string(),
+        );
+    };
+
+    let address = match query
+        .address
+        .or_else(|| artifact.address_override.clone())
+        .or_else(|| state.default_address.clone())
+    {
+        Some(v) => resolve_printer(&state.printers, &v),
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::MissingAddress,
+                "address is missing and no --default-address configured".to_string(),
+            );
+        }
+    };
+
+    let (density, copies, tail_feed_lines) = match check_print_params(
+        &state,
+        &artifact,
+        query.density,
+        query.copies,
+        query.tail_feed_mm,
+    ) {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let density = match density {
+        Some(density) => density,
+        None => default_density(&state, &address).await,
+    };
+
+    let tuning = PrintTuning {
+        tail_feed_lines,
+        ..state.print_tuning.clone()
+    };
+    let lines = repeat_copies(&artifact.packed_lines, copies);
+    let packets = match job_packets(&address, &lines, density, &tuning) {
+        Ok(v) => v,
+        Err(err) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidRequest,
+                format!("{err:#}"),
+            );
+        }
+    };
+
+    let resp = WireResponse {
+        address,
+        density,
+        max_write_chunk: tuning.max_write_chunk,
+        total_bytes: packets.iter().map(|p| p.bytes.len()).sum(),
+        packets: packets
+            .into_iter()
+            .map(|p| WirePacketHex {
+                kind: p.kind.as_str(),
+                hex: hex::encode(p.bytes),
+            })
+            .collect(),
+    };
+    (StatusCode::OK, axum::Json(resp)).into_response()
+}
+
 async fn queue_print(
     State(state): State<AppState>,
     headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     axum::Json(req): axum::Json<PrintRequest>,
 ) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
         return resp;
     }
 
     let Some(artifact) = state.renders.read().await.get(&req.render_id).cloned() else {
-        return error_response(StatusCode::NOT_FOUND, "render not found".to_string());
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::RenderNotFound,
+            "render not found".to_string(),
+        );
     };
 
     let address = match req
         .address
-        .or(artifact.address_override)
+        .or_else(|| artifact.address_override.clone())
         .or_else(|| state.default_address.clone())
     {
-        Some(v) => v,
+        Some(v) => resolve_printer(&state.printers, &v),
         None => {
             return error_response(
                 StatusCode::BAD_REQUEST,
+                ErrorCode::MissingAddress,
                 "address is missing and no --default-address configured".to_string(),
             );
         }
     };
 
-    let density = req.density.unwrap_or(artifact.density);
-    if density > 7 {
+    let (density, copies, tail_feed_lines) =
+        match check_print_params(&state, &artifact, req.density, req.copies, req.tail_feed_mm) {
+            Ok(v) => v,
+            Err(resp) => return resp,
+        };
+
+    let not_before = req.not_before.filter(|t| *t > Utc::now());
+
+    if let Some(url) = &req.callback_url
+        && !reqwest::Url::parse(url).is_ok_and(|u| matches!(u.scheme(), "http" | "https"))
+    {
         return error_response(
             StatusCode::BAD_REQUEST,
-            "density must be in 0..=7".to_string(),
+            ErrorCode::InvalidRequest,
+            "callback_url must be an http or https URL".to_string(),
+        );
+    }
+
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(v) => match v.to_str() {
+            Ok(v) => Some(v.to_string()),
+            Err(_) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    ErrorCode::InvalidIdempotencyKey,
+                    "Idempotency-Key must be ASCII".to_string(),
+                );
+            }
+        },
+        None => req.idempotency_key,
+    };
+    if let Some(key) = &idempotency_key
+        && (key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidIdempotencyKey,
+            format!("idempotency key must be 1..={MAX_IDEMPOTENCY_KEY_LEN} bytes"),
+        );
+    }
+
+    // Held until the job is queued, so a concurrent retry with the same key waits and then replays.
+    let mut idempotency_keys = state.idempotency_keys.lock().await;
+    idempotency_keys.retain(|_, v| v.created_at.elapsed() < state.idempotency_ttl);
+    if let Some(key) = &idempotency_key
+        && let Some(existing) = idempotency_keys.get(key)
+    {
+        if existing.render_id != req.render_id {
+            return error_response(
+                StatusCode::CONFLICT,
+                ErrorCode::IdempotencyKeyReused,
+                format!(
+                    "idempotency key was already used for render {}",
+                    existing.render_id
+                ),
+            );
+        }
+        info!(job_id = %existing.job_id, "replayed print job for idempotency key");
+        let resp = PrintResponse {
+            job_id: existing.job_id.clone(),
+            status_url: format!("/api/v1/jobs/{}", existing.job_id),
+            queue_length: state.queue.len().await,
+        };
+        return (StatusCode::OK, axum::Json(resp)).into_response();
+    }
+
+    let (job_id, queue_length) = match enqueue_job(
+        &state,
+        &req.render_id,
+        address,
+        density,
+        copies,
+        tail_feed_lines,
+        req.priority,
+        not_before,
+        req.callback_url,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(err) => return err.into_response(),
+    };
+    if let Some(key) = idempotency_key {
+        idempotency_keys.insert(
+            key,
+            IdempotentPrint {
+                job_id: job_id.clone(),
+                render_id: req.render_id.clone(),
+                created_at: Instant::now(),
+            },
+        );
+    }
+    drop(idempotency_keys);
+
+    let resp = PrintResponse {
+        job_id: job_id.clone(),
+        status_url: format!("/api/v1/jobs/{job_id}"),
+        queue_length,
+    };
+
+    (StatusCode::ACCEPTED, axum::Json(resp)).into_response()
+}
+
+/// Density and copies for printing `artifact`, checked against the printer's limits. The density
+/// stays `None` when neither the request nor the render set one; [`enqueue_job`] then picks the
+/// printer's default.
+#[allow(clippy::result_large_err)]
+fn check_print_params(
+    state: &AppState,
+    artifact: &RenderArtifact,
+    density: Option<u8>,
+    copies: Option<u8>,
+    tail_feed_mm: Option<f32>,
+) -> Result<(Option<u8>, u8, usize), Response> {
+    if artifact.lock_density
+        && let Some(density) = density
+        && Some(density) != artifact.density
+    {
+        let locked = artifact.density.map_or_else(
+            || "the printer's default density".to_string(),
+            |d| format!("density {d}"),
         );
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
+            format!("render is locked to {locked}"),
+        ));
+    }
+    let density = density.or(artifact.density);
+    if density.is_some_and(|d| d > 7) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDensity,
+            "density must be in 0..=7".to_string(),
+        ));
+    }
+
+    let tail_feed_lines = match tail_feed_mm {
+        Some(mm) if !(0.0..=MAX_TAIL_FEED_MM).contains(&mm) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidRequest,
+                format!("tail_feed_mm must be in 0..={MAX_TAIL_FEED_MM}"),
+            ));
+        }
+        Some(mm) => mm_to_packed_lines(mm),
+        None => state.print_tuning.tail_feed_lines,
+    };
+
+    let copies = copies.unwrap_or(1).clamp(1, MAX_COPIES);
+    let total_lines =
+        (artifact.packed_lines.len() + COPY_GAP_LINES) * copies as usize - COPY_GAP_LINES;
+    // The tail feed goes out in the same job, so it counts against the protocol's line number.
+    if total_lines + tail_feed_lines > u16::MAX as usize {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::TooLong,
+            format!(
+                "{copies} copies make {total_lines} lines, printer limit is {}",
+                u16::MAX
+            ),
+        ));
+    }
+    check_print_length(state, total_lines)?;
+    Ok((density, copies, tail_feed_lines))
+}
+
+/// Why [`enqueue_job`] could not queue a job.
+enum EnqueueError {
+    /// The printer's lane is full; carries the jobs waiting on it.
+    Full(usize),
+    Closed,
+    /// Evicted under `--max-renders` since the caller looked it up.
+    RenderGone,
+}
+
+impl EnqueueError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::Full(_) => ErrorCode::QueueFull,
+            Self::Closed => ErrorCode::QueueUnavailable,
+            Self::RenderGone => ErrorCode::RenderNotFound,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Full(queue_length) => {
+                format!("print queue is full ({queue_length} jobs waiting), retry later")
+            }
+            Self::Closed => "print queue is not available".to_string(),
+            Self::RenderGone => "render was evicted, render it again".to_string(),
+        }
     }
 
+    fn into_response(self) -> Response {
+        match self {
+            Self::Full(queue_length) => queue_full_response(queue_length),
+            Self::Closed => {
+                error_response(StatusCode::SERVICE_UNAVAILABLE, self.code(), self.message())
+            }
+            Self::RenderGone => error_response(StatusCode::NOT_FOUND, self.code(), self.message()),
+        }
+    }
+}
+
+/// Records a job and hands it to its printer's lane, or to the scheduler when `not_before` is set.
+///
+/// Returns the job id and the number of jobs waiting on that printer.
+#[allow(clippy::too_many_arguments)]
+async fn enqueue_job(
+    state: &AppState,
+    render_id: &str,
+    address: String,
+    density: Option<u8>,
+    copies: u8,
+    tail_feed_lines: usize,
+    priority: JobPriority,
+    not_before: Option<DateTime<Utc>>,
+    callback_url: Option<String>,
+) -> Result<(String, usize), EnqueueError> {
+    let density = match density {
+        Some(density) => density,
+        None => default_density(state, &address).await,
+    };
     let job_id = next_id("j", &state.job_seq);
     let record = JobRecord {
         id: job_id.clone(),
-        render_id: req.render_id.clone(),
+        render_id: render_id.to_string(),
         address: address.clone(),
         density,
+        copies,
+        priority,
+        not_before,
         status: JobStatus::Queued,
         error: None,
+        created_at: Utc::now(),
+        started_at: None,
+        finished_at: None,
+        queued_at: not_before.is_none().then(Instant::now),
+        queue_wait_ms: None,
+        note: None,
+        report: None,
+        callback_url,
     };
-    state.jobs.write().await.insert(job_id.clone(), record);
+    {
+        // Held across the insert so an eviction cannot drop the render in between, see
+        // [`evict_renders`].
+        let renders = state.renders.read().await;
+        if !renders.contains_key(render_id) {
+            return Err(EnqueueError::RenderGone);
+        }
+        state.jobs.write().await.insert(job_id.clone(), record);
+    }
     info!(
         job_id = %job_id,
-        render_id = %req.render_id,
+        render_id = %render_id,
         address = %address,
         density = density,
+        copies = copies,
+        priority = ?priority,
+        not_before = ?not_before,
         "queued print job"
     );
 
+    let lane = print_lane(state, &address).await;
     let cmd = PrintCommand {
         job_id: job_id.clone(),
-        render_id: req.render_id,
+        render_id: render_id.to_string(),
         address,
         density,
+        copies,
+        tail_feed_lines,
+    };
+
+    let queue_length = lane.len();
+    if let Some(not_before) = not_before {
+        state.scheduled.lock().await.push(ScheduledJob {
+            not_before,
+            priority,
+            cmd,
+        });
+        return Ok((job_id, queue_length));
+    }
+    // Never wait for room here: a stalled printer would otherwise pile up handlers (and the
+    // idempotency lock) until the whole daemon stops answering.
+    let err = match lane.sender(priority).try_send(cmd) {
+        Ok(()) => return Ok((job_id, queue_length)),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            warn!(job_id = %job_id, queue_length = queue_length, "print queue is full");
+            EnqueueError::Full(queue_length)
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => EnqueueError::Closed,
+    };
+    state.jobs.write().await.remove(&job_id);
+    Err(err)
+}
+
+/// The lane for `address`, starting its worker on first use.
+async fn print_lane(state: &AppState, address: &str) -> PrintLane {
+    let mut lanes = state.queue.lanes.lock().await;
+    if let Some(lane) = lanes.get(address) {
+        return lane.clone();
+    }
+    let (high_tx, high_rx) = mpsc::channel(PRINT_QUEUE_CAPACITY);
+    let (normal_tx, normal_rx) = mpsc::channel(PRINT_QUEUE_CAPACITY);
+    let in_flight = Arc::new(Mutex::new(None));
+    tokio::spawn(worker_loop(
+        state.clone(),
+        address.to_string(),
+        high_rx,
+        normal_rx,
+        in_flight.clone(),
+    ));
+    let lane = PrintLane {
+        high: high_tx,
+        normal: normal_tx,
+        in_flight,
+    };
+    lanes.insert(address.to_string(), lane.clone());
+    lane
+}
+
+async fn queue_print_fanout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    axum::Json(req): axum::Json<FanoutPrintRequest>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let Some(artifact) = state.renders.read().await.get(&req.render_id).cloned() else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::RenderNotFound,
+            "render not found".to_string(),
+        );
     };
 
-    if state.queue_tx.send(cmd).await.is_err() {
+    let mut addresses: Vec<String> = Vec::with_capacity(req.addresses.len());
+    for address in req.addresses {
+        let address = resolve_printer(&state.printers, &address);
+        if !address.is_empty() && !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    if addresses.is_empty() || addresses.len() > MAX_FANOUT_ADDRESSES {
         return error_response(
-            StatusCode::SERVICE_UNAVAILABLE,
-            "print queue is not available".to_string(),
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidRequest,
+            format!("addresses must hold 1..={MAX_FANOUT_ADDRESSES} distinct printers"),
         );
     }
 
-    let resp = PrintResponse {
-        job_id: job_id.clone(),
-        status_url: format!("/api/v1/jobs/{job_id}"),
-    };
+    let (density, copies, tail_feed_lines) =
+        match check_print_params(&state, &artifact, req.density, req.copies, req.tail_feed_mm) {
+            Ok(v) => v,
+            Err(resp) => return resp,
+        };
+    let not_before = req.not_before.filter(|t| *t > Utc::now());
+
+    // Each printer has its own lane, so a full or stuck one does not hold back the rest.
+    let mut jobs = Vec::with_capacity(addresses.len());
+    let mut first_err = None;
+    for address in addresses {
+        let job = match enqueue_job(
+            &state,
+            &req.render_id,
+            address.clone(),
+            density,
+            copies,
+            tail_feed_lines,
+            req.priority,
+            not_before,
+            None,
+        )
+        .await
+        {
+            Ok((job_id, queue_length)) => FanoutJob {
+                address,
+                status_url: Some(format!("/api/v1/jobs/{job_id}")),
+                job_id: Some(job_id),
+                queue_length: Some(queue_length),
+                code: None,
+                error: None,
+            },
+            Err(err) => {
+                let job = FanoutJob {
+                    address,
+                    job_id: None,
+                    status_url: None,
+                    queue_length: None,
+                    code: Some(err.code()),
+                    error: Some(err.message()),
+                };
+                first_err.get_or_insert(err);
+                job
+            }
+        };
+        jobs.push(job);
+    }
+
+    if jobs.iter().all(|j| j.job_id.is_none())
+        && let Some(err) = first_err
+    {
+        return err.into_response();
+    }
+    info!(
+        render_id = %req.render_id,
+        printers = jobs.len(),
+        queued = jobs.iter().filter(|j| j.job_id.is_some()).count(),
+        "queued fan-out print"
+    );
+    (
+        StatusCode::ACCEPTED,
+        axum::Json(FanoutPrintResponse { jobs }),
+    )
+        .into_response()
+}
 
-    (StatusCode::ACCEPTED, axum::Json(resp)).into_response()
+fn queue_full_response(queue_length: usize) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(
+            header::RETRY_AFTER,
+            QUEUE_FULL_RETRY_AFTER_SECONDS.to_string(),
+        )],
+        axum::Json(ErrorBody {
+            code: ErrorCode::QueueFull,
+            error: format!("print queue is full ({queue_length} jobs waiting), retry later"),
+            black_ratio: None,
+            queue_length: Some(queue_length),
+            length_mm: None,
+            max_length_mm: None,
+        }),
+    )
+        .into_response()
 }
 
 async fn wait_job(
     State(state): State<AppState>,
     headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Path(id): Path<String>,
     Query(query): Query<WaitQuery>,
 ) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
         return resp;
     }
 
@@ -621,11 +3396,15 @@ async fn wait_job(
     loop {
         let maybe_job = { state.jobs.read().await.get(&id).cloned() };
         let Some(job) = maybe_job else {
-            return error_response(StatusCode::NOT_FOUND, "job not found".to_string());
+            return error_response(
+                StatusCode::NOT_FOUND,
+                ErrorCode::JobNotFound,
+                "job not found".to_string(),
+            );
         };
 
         match job.status {
-            JobStatus::Done | JobStatus::Failed => {
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled => {
                 return (StatusCode::OK, axum::Json(job)).into_response();
             }
             JobStatus::Queued | JobStatus::Printing => {}
@@ -639,68 +3418,522 @@ async fn wait_job(
     }
 }
 
+async fn list_jobs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<ListJobsQuery>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let mut jobs: Vec<JobRecord> = state
+        .jobs
+        .read()
+        .await
+        .values()
+        .filter(|j| query.render_id.as_ref().is_none_or(|id| *id == j.render_id))
+        .filter(|j| {
+            query
+                .address
+                .as_ref()
+                .is_none_or(|a| resolve_printer(&state.printers, a) == j.address)
+        })
+        .cloned()
+        .collect();
+    jobs.sort_by_key(|j| job_seq(&j.id));
+
+    (StatusCode::OK, axum::Json(jobs)).into_response()
+}
+
+/// Creation order of a `j_<n>` job id.
+fn job_seq(id: &str) -> u64 {
+    id.strip_prefix("j_")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
 async fn get_job(
     State(state): State<AppState>,
     headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Path(id): Path<String>,
 ) -> Response {
-    if let Err(resp) = require_auth(&state, &headers) {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
         return resp;
     }
 
     let jobs = state.jobs.read().await;
     let Some(job) = jobs.get(&id) else {
-        return error_response(StatusCode::NOT_FOUND, "job not found".to_string());
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::JobNotFound,
+            "job not found".to_string(),
+        );
     };
 
     (StatusCode::OK, axum::Json(job)).into_response()
 }
 
-async fn worker_loop(state: AppState, mut rx: mpsc::Receiver<PrintCommand>) {
-    while let Some(cmd) = rx.recv().await {
-        info!(
-            job_id = %cmd.job_id,
-            render_id = %cmd.render_id,
-            address = %cmd.address,
-            density = cmd.density,
-            "starting print job"
+async fn cancel_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(resp) = require_auth(&state, &headers, peer) {
+        return resp;
+    }
+
+    let mut jobs = state.jobs.write().await;
+    let Some(job) = jobs.get_mut(&id) else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::JobNotFound,
+            "job not found".to_string(),
+        );
+    };
+    if !matches!(job.status, JobStatus::Queued) {
+        return error_response(
+            StatusCode::CONFLICT,
+            ErrorCode::JobNotCancellable,
+            "only queued or scheduled jobs can be cancelled".to_string(),
         );
+    }
+
+    // Scheduled jobs are dropped here; ones already in the queue are skipped by the worker.
+    job.finish(JobStatus::Cancelled, None);
+    send_job_callback(&state, job);
+    state.scheduled.lock().await.retain(|s| s.cmd.job_id != id);
+    info!(job_id = %id, "print job cancelled");
+
+    (StatusCode::OK, axum::Json(job.clone())).into_response()
+}
+
+async fn scheduler_loop(state: AppState) {
+    let mut tick = tokio::time::interval(SCHEDULER_TICK);
+    loop {
+        tick.tick().await;
+
+        let due: Vec<ScheduledJob> = {
+            let mut scheduled = state.scheduled.lock().await;
+            if scheduled.is_empty() {
+                continue;
+            }
+            let now = Utc::now();
+            let (due, pending) = std::mem::take(&mut *scheduled)
+                .into_iter()
+                .partition(|j| j.not_before <= now);
+            *scheduled = pending;
+            due
+        };
+
+        for job in due {
+            let job_id = job.cmd.job_id.clone();
+            info!(job_id = %job_id, "scheduled print job is due");
+            if let Some(record) = state.jobs.write().await.get_mut(&job_id) {
+                record.queued_at = Some(Instant::now());
+            }
+            let lane = print_lane(&state, &job.cmd.address).await;
+            if lane.sender(job.priority).send(job.cmd).await.is_err() {
+                if let Some(job) = state.jobs.write().await.get_mut(&job_id) {
+                    job.finish(
+                        JobStatus::Failed,
+                        Some("print queue is not available".to_string()),
+                    );
+                    send_job_callback(&state, job);
+                }
+                error!(job_id = %job_id, "print queue closed, scheduled job dropped");
+            }
+        }
+    }
+}
+
+/// Posts a finished job to its `callback_url` in the background, retrying a few times. A callback
+/// that keeps failing is only logged; it never changes the job.
+fn send_job_callback(state: &AppState, job: &JobRecord) {
+    let Some(url) = job.callback_url.clone() else {
+        return;
+    };
+    let http = state.callback_http.clone();
+    let job = job.clone();
+    tokio::spawn(async move {
+        let mut delay = CALLBACK_RETRY_BACKOFF;
+        for attempt in 1..=CALLBACK_ATTEMPTS {
+            match http.post(&url).json(&job).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    info!(job_id = %job.id, "job callback delivered");
+                    return;
+                }
+                Ok(resp) => {
+                    warn!(job_id = %job.id, attempt = attempt, status = %resp.status(), "job callback rejected");
+                }
+                Err(err) => {
+                    warn!(job_id = %job.id, attempt = attempt, error = %err, "job callback failed");
+                }
+            }
+            if attempt < CALLBACK_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        error!(job_id = %job.id, "giving up on job callback");
+    });
+}
+
+/// Prints the jobs of one printer lane, one at a time.
+async fn worker_loop(
+    state: AppState,
+    address: String,
+    mut high_rx: mpsc::Receiver<PrintCommand>,
+    mut normal_rx: mpsc::Receiver<PrintCommand>,
+    in_flight: Arc<Mutex<Option<CancellationToken>>>,
+) {
+    info!(address = %address, "print lane started");
+    let mut high_streak = 0u32;
+    while let Some(cmd) = next_command(&mut high_rx, &mut normal_rx, &mut high_streak).await {
         {
             let mut jobs = state.jobs.write().await;
             if let Some(job) = jobs.get_mut(&cmd.job_id) {
+                // Cancelled by a client, or failed by `queue_age_loop`.
+                if !matches!(job.status, JobStatus::Queued) {
+                    info!(job_id = %cmd.job_id, "skipping print job that is no longer queued");
+                    continue;
+                }
+                let waited = job.queued_at.map(|t| t.elapsed()).unwrap_or_default();
+                job.queue_wait_ms = Some(waited.as_millis() as u64);
+                if state.max_queue_age.is_some_and(|max| waited > max) {
+                    fail_stale_job(&state, job);
+                    continue;
+                }
                 job.status = JobStatus::Printing;
                 job.error = None;
+                job.started_at = Some(Utc::now());
             }
         }
+        info!(
+            job_id = %cmd.job_id,
+            render_id = %cmd.render_id,
+            address = %cmd.address,
+            density = cmd.density,
+            copies = cmd.copies,
+            "starting print job"
+        );
 
         let packed = {
             let renders = state.renders.read().await;
-            renders.get(&cmd.render_id).map(|r| r.packed_lines.clone())
+            renders
+                .get(&cmd.render_id)
+                .map(|r| repeat_copies(&r.packed_lines, cmd.copies))
+        };
+
+        // Printer events arrive inside the print loop; the job record and the printer status are
+        // updated from a separate task so the loop never waits on their locks.
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let note_task = {
+            let jobs = state.jobs.clone();
+            let printer_status = state.printer_status.clone();
+            let job_id = cmd.job_id.clone();
+            let address = cmd.address.clone();
+            tokio::spawn(async move {
+                while let Some(event) = event_rx.recv().await {
+                    let note = match event {
+                        PrintEvent::Paused => {
+                            info!(job_id = %job_id, "printer paused");
+                            Some("printer paused".to_string())
+                        }
+                        PrintEvent::Resumed => {
+                            info!(job_id = %job_id, "printer resumed");
+                            None
+                        }
+                        PrintEvent::Status(status) => {
+                            let reported = ReportedStatus {
+                                status,
+                                at: Utc::now(),
+                            };
+                            printer_status
+                                .write()
+                                .await
+                                .insert(address.clone(), reported);
+                            continue;
+                        }
+                    };
+                    if let Some(job) = jobs.write().await.get_mut(&job_id) {
+                        job.note = note;
+                    }
+                }
+            })
         };
 
+        let cancel = CancellationToken::new();
+        *in_flight.lock().await = Some(cancel.clone());
         let result = match packed {
-            Some(lines) => print_job(&cmd.address, &lines, cmd.density).await,
-            None => Err(anyhow::anyhow!("render {} not found", cmd.render_id)),
+            Some(lines) => {
+                print_with_session(
+                    &state,
+                    &cmd.address,
+                    &lines,
+                    cmd.density,
+                    cmd.tail_feed_lines,
+                    &cancel,
+                    move |event| {
+                        let _ = event_tx.send(event);
+                    },
+                )
+                .await
+            }
+            None => {
+                // Nothing will send events; close the channel so the note task ends.
+                drop(event_tx);
+                Err(anyhow::anyhow!("render {} not found", cmd.render_id))
+            }
         };
+        *in_flight.lock().await = None;
+        let _ = note_task.await;
 
         let mut jobs = state.jobs.write().await;
         if let Some(job) = jobs.get_mut(&cmd.job_id) {
+            job.note = None;
             match result {
-                Ok(()) => {
-                    job.status = JobStatus::Done;
-                    job.error = None;
-                    info!(job_id = %cmd.job_id, "print job completed");
+                Ok(report) => {
+                    job.finish(JobStatus::Done, None);
+                    job.report = Some(JobReport {
+                        throttled: report.throttled,
+                        paused: report.paused,
+                        resumes: report.resumes,
+                    });
+                    info!(job_id = %cmd.job_id, throttled = report.throttled, "print job completed");
                 }
                 Err(err) => {
-                    job.status = JobStatus::Failed;
-                    job.error = Some(err.to_string());
+                    job.finish(JobStatus::Failed, Some(err.to_string()));
                     warn!(job_id = %cmd.job_id, error = %err, "print job failed");
                 }
             }
+            send_job_callback(&state, job);
+        }
+    }
+}
+
+/// Prints over the warm session for `address` if it is still alive, reconnecting otherwise. With
+/// `--keep-warm-seconds` the session is parked for the next job instead of disconnected.
+async fn print_with_session(
+    state: &AppState,
+    address: &str,
+    lines: &[PackedLine],
+    density: u8,
+    tail_feed_lines: usize,
+    cancel: &CancellationToken,
+    on_event: impl FnMut(PrintEvent) + Send,
+) -> anyhow::Result<PrintReport> {
+    let warm = state.warm_sessions.lock().await.remove(address);
+    let connect = async {
+        match warm {
+            Some(mut warm) => {
+                if warm.session.is_alive().await {
+                    info!(address = %address, "reusing warm printer session");
+                    Ok(warm.session)
+                } else {
+                    info!(address = %address, "warm printer session dropped, reconnecting");
+                    let _ = warm.session.disconnect().await;
+                    PrinterSession::connect(address, &state.print_tuning).await
+                }
+            }
+            None => PrinterSession::connect(address, &state.print_tuning).await,
+        }
+    };
+    let mut session = tokio::select! {
+        session = connect => session?,
+        () = cancel.cancelled() => anyhow::bail!(FLUSHED_BY_OPERATOR),
+    };
+    // Warm sessions outlive the job, so the tail is set for every job, not only on connect.
+    session.set_tail_feed_lines(tail_feed_lines);
+    if let Some(model) = session.model() {
+        state
+            .printer_models
+            .write()
+            .await
+            .insert(address.to_string(), model);
+    }
+
+    // On a flush the print is dropped mid-stream and the session is disconnected below.
+    let result = tokio::select! {
+        result = session.print_with_events(lines, density, on_event) => result,
+        () = cancel.cancelled() => Err(anyhow::anyhow!(FLUSHED_BY_OPERATOR)),
+    };
+    if result.is_ok() && !state.keep_warm.is_zero() {
+        state.warm_sessions.lock().await.insert(
+            address.to_string(),
+            WarmSession {
+                session,
+                last_used: Instant::now(),
+            },
+        );
+    } else if let Err(err) = session.disconnect().await {
+        warn!(address = %address, error = %err, "failed to disconnect printer");
+    }
+    result
+}
+
+/// Fails queued jobs older than `--max-queue-age-seconds`, so a printer that hangs mid-job does
+/// not leave everything behind it queued forever.
+async fn queue_age_loop(state: AppState) {
+    let Some(max_age) = state.max_queue_age else {
+        return;
+    };
+    let mut tick = tokio::time::interval(SCHEDULER_TICK);
+    loop {
+        tick.tick().await;
+
+        let mut jobs = state.jobs.write().await;
+        for job in jobs.values_mut() {
+            if !matches!(job.status, JobStatus::Queued) {
+                continue;
+            }
+            let Some(waited) = job.queued_at.map(|t| t.elapsed()) else {
+                continue;
+            };
+            if waited > max_age {
+                job.queue_wait_ms = Some(waited.as_millis() as u64);
+                fail_stale_job(&state, job);
+            }
+        }
+    }
+}
+
+fn fail_stale_job(state: &AppState, job: &mut JobRecord) {
+    job.finish(JobStatus::Failed, Some("queued too long".to_string()));
+    send_job_callback(state, job);
+    warn!(
+        job_id = %job.id,
+        address = %job.address,
+        queue_wait_ms = job.queue_wait_ms,
+        "print job queued too long"
+    );
+}
+
+/// Disconnects warm sessions that sat idle longer than `--keep-warm-seconds`.
+async fn session_reaper_loop(state: AppState) {
+    let mut tick = tokio::time::interval(SCHEDULER_TICK);
+    loop {
+        tick.tick().await;
+
+        let expired: Vec<(String, WarmSession)> = {
+            let mut sessions = state.warm_sessions.lock().await;
+            let keys: Vec<String> = sessions
+                .iter()
+                .filter(|(_, w)| w.last_used.elapsed() >= state.keep_warm)
+                .map(|(k, _)| k.clone())
+                .collect();
+            keys.into_iter()
+                .filter_map(|k| sessions.remove_entry(&k))
+                .collect()
+        };
+
+        for (address, warm) in expired {
+            info!(address = %address, "closing idle printer session");
+            if let Err(err) = warm.session.disconnect().await {
+                warn!(address = %address, error = %err, "failed to disconnect printer");
+            }
         }
     }
 }
 
+/// Keeps `artifact` under `render_id`, unless `ephemeral`, in which case it is dropped and only
+/// its preview goes back to the caller.
+async fn store_render(
+    state: &AppState,
+    render_id: &str,
+    artifact: RenderArtifact,
+    ephemeral: bool,
+) -> StoredRender {
+    if ephemeral {
+        return StoredRender {
+            render_id: None,
+            preview_url: None,
+            preview_png_base64: Some(
+                base64::engine::general_purpose::STANDARD.encode(&artifact.preview_png),
+            ),
+            source_url: None,
+            source_png_base64: artifact
+                .gray_preview_png
+                .map(|png| base64::engine::general_purpose::STANDARD.encode(png)),
+        };
+    }
+    let source_url = artifact
+        .gray_preview_png
+        .is_some()
+        .then(|| format!("/api/v1/renders/{render_id}/source"));
+    let mut renders = state.renders.write().await;
+    if renders.len() >= state.max_renders {
+        evict_renders(state, &mut renders).await;
+    }
+    renders.insert(render_id.to_string(), artifact);
+    drop(renders);
+    StoredRender {
+        render_id: Some(render_id.to_string()),
+        preview_url: Some(format!("/api/v1/renders/{render_id}/preview")),
+        preview_png_base64: None,
+        source_url,
+        source_png_base64: None,
+    }
+}
+
+/// Drops least recently previewed renders until one more fits under `--max-renders`. Renders of
+/// queued, scheduled or printing jobs are kept even if that leaves the map over the cap.
+///
+/// Takes the jobs lock while `renders` is held, the same order [`enqueue_job`] uses, so a job
+/// recorded for a render always sees it stored.
+async fn evict_renders(state: &AppState, renders: &mut HashMap<String, RenderArtifact>) {
+    let pinned: HashSet<String> = state
+        .jobs
+        .read()
+        .await
+        .values()
+        .filter(|job| matches!(job.status, JobStatus::Queued | JobStatus::Printing))
+        .map(|job| job.render_id.clone())
+        .collect();
+    let mut candidates: Vec<(Instant, String)> = renders
+        .iter()
+        .filter(|(id, _)| !pinned.contains(*id))
+        .map(|(id, artifact)| (artifact.last_access, id.clone()))
+        .collect();
+    candidates.sort_unstable();
+    let excess = renders.len() + 1 - state.max_renders;
+    for (_, id) in candidates.into_iter().take(excess) {
+        renders.remove(&id);
+        debug!(render_id = %id, "evicted render over --max-renders");
+    }
+    if renders.len() >= state.max_renders {
+        warn!(
+            renders = renders.len(),
+            "every stored render belongs to a pending job, going over --max-renders"
+        );
+    }
+}
+
+/// [`estimate_print_duration`] in seconds, rounded to a tenth.
+fn estimated_seconds(tuning: &PrintTuning, lines: usize) -> f32 {
+    (estimate_print_duration(lines, tuning).as_secs_f32() * 10.0).round() / 10.0
+}
+
+fn encode_packed_lines(lines: &[PackedLine]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(lines.concat())
+}
+
+/// PNG for previews handed to clients, at `--preview-png-level`.
+fn encode_preview_png(state: &AppState, image: &GrayImage) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    PngEncoder::new_with_quality(&mut out, state.preview_png_compression, PngFilter::Adaptive)
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::L8,
+        )?;
+    Ok(out)
+}
+
 fn encode_png(image: &GrayImage) -> anyhow::Result<Vec<u8>> {
     let dyn_img = DynamicImage::ImageLuma8(image.clone());
     let mut cursor = Cursor::new(Vec::<u8>::new());
@@ -708,7 +3941,84 @@ fn encode_png(image: &GrayImage) -> anyhow::Result<Vec<u8>> {
     Ok(cursor.into_inner())
 }
 
-fn maybe_dump_debug_image(debug_dir: Option<&std::path::Path>, render_id: &str, stage: &str, image: &GrayImage) {
+fn reencode_preview(png: &[u8], format: PreviewFormat) -> anyhow::Result<Vec<u8>> {
+    let image_format = match format {
+        PreviewFormat::Png => return Ok(png.to_vec()),
+        PreviewFormat::Jpeg => ImageFormat::Jpeg,
+        PreviewFormat::Webp => ImageFormat::WebP,
+    };
+    let img = image::load_from_memory_with_format(png, ImageFormat::Png)?;
+    let mut cursor = Cursor::new(Vec::<u8>::new());
+    img.write_to(&mut cursor, image_format)?;
+    Ok(cursor.into_inner())
+}
+
+/// Nearest-neighbor upscale for the returned preview only; packed lines stay at print resolution.
+fn upscale_preview(image: &GrayImage, scale: Option<u32>) -> GrayImage {
+    let scale = scale
+        .unwrap_or(DEFAULT_PREVIEW_SCALE)
+        .clamp(1, MAX_PREVIEW_SCALE);
+    if scale == 1 {
+        return image.clone();
+    }
+    image::imageops::resize(
+        image,
+        image.width() * scale,
+        image.height() * scale,
+        FilterType::Nearest,
+    )
+}
+
+/// Copies are joined with a short blank feed so they can be torn apart.
+fn repeat_copies(lines: &[PackedLine], copies: u8) -> Vec<PackedLine> {
+    let copies = copies.max(1) as usize;
+    let mut out = Vec::with_capacity(lines.len() * copies + COPY_GAP_LINES * (copies - 1));
+    for i in 0..copies {
+        if i > 0 {
+            out.extend(std::iter::repeat_n(
+                [0u8; PACKED_LINE_BYTES],
+                COPY_GAP_LINES,
+            ));
+        }
+        out.extend_from_slice(lines);
+    }
+    out
+}
+
+/// High-priority jobs go first, but after `MAX_HIGH_PRIORITY_STREAK` of them in a row a waiting
+/// normal job is served so batches are never starved.
+async fn next_command(
+    high_rx: &mut mpsc::Receiver<PrintCommand>,
+    normal_rx: &mut mpsc::Receiver<PrintCommand>,
+    high_streak: &mut u32,
+) -> Option<PrintCommand> {
+    if *high_streak >= MAX_HIGH_PRIORITY_STREAK
+        && let Ok(cmd) = normal_rx.try_recv()
+    {
+        *high_streak = 0;
+        return Some(cmd);
+    }
+
+    tokio::select! {
+        biased;
+        Some(cmd) = high_rx.recv() => {
+            *high_streak += 1;
+            Some(cmd)
+        }
+        Some(cmd) = normal_rx.recv() => {
+            *high_streak = 0;
+            Some(cmd)
+        }
+        else => None,
+    }
+}
+
+fn maybe_dump_debug_image(
+    debug_dir: Option<&std::path::Path>,
+    render_id: &str,
+    stage: &str,
+    image: &GrayImage,
+) {
     let Some(debug_dir) = debug_dir else {
         return;
     };
@@ -732,111 +4042,315 @@ fn maybe_dump_debug_image(debug_dir: Option<&std::path::Path>, render_id: &str,
     }
 }
 
+fn resize_filter_type(filter: ResizeFilter) -> FilterType {
+    match filter {
+        ResizeFilter::Lanczos => FilterType::Lanczos3,
+        ResizeFilter::Catmull => FilterType::CatmullRom,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::Nearest => FilterType::Nearest,
+    }
+}
+
+fn to_gray_with_mode(img: &DynamicImage, mode: GrayscaleMode) -> GrayImage {
+    if let GrayscaleMode::Luma = mode {
+        return img.to_luma8();
+    }
+    let rgb = img.to_rgb8();
+    GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0;
+        let v = match mode {
+            GrayscaleMode::Luma => unreachable!(),
+            GrayscaleMode::Max => r.max(g).max(b),
+            GrayscaleMode::Min => r.min(g).min(b),
+            GrayscaleMode::Channel(c) => [r, g, b][c],
+        };
+        Luma([v])
+    })
+}
+
+/// `out = src + amount * (src - blur(src))`, which restores edges softened by downscaling.
+fn unsharp_mask(src: &GrayImage, amount: f32) -> GrayImage {
+    let blurred = imageproc::filter::gaussian_blur_f32(src, SHARPEN_SIGMA);
+    let mut out = src.clone();
+    for (dst, blur) in out.pixels_mut().zip(blurred.pixels()) {
+        let v = dst.0[0] as f32;
+        dst.0[0] = (v + amount * (v - blur.0[0] as f32))
+            .round()
+            .clamp(0.0, 255.0) as u8;
+    }
+    out
+}
+
 fn binarize_preview(
     gray: &GrayImage,
     threshold: u8,
     method: DitherMethod,
     invert: bool,
+    opts: &DitherOptions,
 ) -> GrayImage {
     match method {
         DitherMethod::Threshold => threshold_binarize(gray, threshold, invert),
-        DitherMethod::FloydSteinberg => floyd_steinberg_binarize(gray, threshold, invert),
+        DitherMethod::FloydSteinberg => floyd_steinberg_binarize(gray, threshold, invert, opts),
     }
 }
 
-fn threshold_binarize(gray: &GrayImage, threshold: u8, invert: bool) -> GrayImage {
-    let mut out = GrayImage::new(gray.width(), gray.height());
-    for (x, y, p) in gray.enumerate_pixels() {
-        let mut v = p.0[0];
-        if invert {
-            v = 255 - v;
+/// Splits a tall binarized image into `tile_h`-row pages stacked with a blank gap and a dashed
+/// tear line between them, so it prints as one continuous job. Blank rows are trimmed per page
+/// when `trim_blank` is set; fully blank pages are dropped.
+fn tile_bw_image(img: &GrayImage, tile_h: u32, trim_blank: bool) -> GrayImage {
+    let w = img.width();
+    let is_blank_row = |y: u32| (0..w).all(|x| img.get_pixel(x, y).0[0] != 0);
+
+    let mut pages: Vec<(u32, u32)> = Vec::new();
+    for start in (0..img.height()).step_by(tile_h as usize) {
+        let end = (start + tile_h).min(img.height());
+        if !trim_blank {
+            pages.push((start, end));
+            continue;
+        }
+        let first = (start..end).find(|&y| !is_blank_row(y));
+        let last = (start..end).rev().find(|&y| !is_blank_row(y));
+        if let (Some(first), Some(last)) = (first, last) {
+            pages.push((first, last + 1));
         }
-        let bw = if v <= threshold { 0u8 } else { 255u8 };
-        out.put_pixel(x, y, Luma([bw]));
     }
-    out
-}
+    if pages.is_empty() {
+        return img.clone();
+    }
 
-fn floyd_steinberg_binarize(gray: &GrayImage, threshold: u8, invert: bool) -> GrayImage {
-    let w = gray.width() as usize;
-    let h = gray.height() as usize;
-    let mut buf = vec![0f32; w * h];
-    for y in 0..h {
-        for x in 0..w {
-            let mut v = gray.get_pixel(x as u32, y as u32).0[0] as f32;
-            if invert {
-                v = 255.0 - v;
+    let content_h: u32 = pages.iter().map(|(a, b)| b - a).sum();
+    let total_h = content_h + TILE_GAP_ROWS * (pages.len() as u32 - 1);
+    let mut out = GrayImage::from_pixel(w, total_h, Luma([255]));
+    let mut y_out = 0;
+    for (idx, (start, end)) in pages.iter().enumerate() {
+        if idx > 0 {
+            let tear_y = y_out + TILE_GAP_ROWS / 2;
+            for x in (0..w).filter(|x| x % 8 < 4) {
+                out.put_pixel(x, tear_y, Luma([0]));
+            }
+            y_out += TILE_GAP_ROWS;
+        }
+        for y in *start..*end {
+            for x in 0..w {
+                out.put_pixel(x, y_out, *img.get_pixel(x, y));
             }
-            buf[y * w + x] = v;
+            y_out += 1;
         }
     }
+    out
+}
 
-    let mut out = GrayImage::new(gray.width(), gray.height());
-    for y in 0..h {
-        for x in 0..w {
-            let idx = y * w + x;
-            let old = buf[idx].clamp(0.0, 255.0);
-            let new = if old <= threshold as f32 { 0.0 } else { 255.0 };
-            let err = old - new;
-            out.put_pixel(x as u32, y as u32, Luma([new as u8]));
+/// Rejects renders that would print nothing or a solid black slab, saying which way to move
+/// the threshold. Pixels `<= black_threshold` count as black.
+#[allow(clippy::result_large_err)]
+fn check_coverage(
+    img: &GrayImage,
+    black_threshold: u8,
+    packed_empty: bool,
+) -> Result<(), Response> {
+    let total = (img.width() as u64 * img.height() as u64).max(1);
+    let black = img.pixels().filter(|p| p.0[0] <= black_threshold).count() as u64;
+    let ratio = black as f32 / total as f32;
 
-            if x + 1 < w {
-                buf[idx + 1] += err * 7.0 / 16.0;
-            }
-            if y + 1 < h {
-                if x > 0 {
-                    buf[idx + w - 1] += err * 3.0 / 16.0;
-                }
-                buf[idx + w] += err * 5.0 / 16.0;
-                if x + 1 < w {
-                    buf[idx + w + 1] += err * 1.0 / 16.0;
-                }
-            }
+    let (code, hint) = if packed_empty {
+        (
+            ErrorCode::BlankResult,
+            "nothing would print at this threshold — raise threshold or adjust contrast",
+        )
+    } else if ratio >= MAX_BLACK_RATIO {
+        (
+            ErrorCode::TooDark,
+            "sticker is almost entirely black — lower threshold or adjust contrast",
+        )
+    } else {
+        return Ok(());
+    };
+    Err((
+        StatusCode::BAD_REQUEST,
+        axum::Json(ErrorBody {
+            code,
+            error: format!("{hint} (black ratio {ratio:.3})"),
+            black_ratio: Some(ratio),
+            queue_length: None,
+            length_mm: None,
+            max_length_mm: None,
+        }),
+    )
+        .into_response())
+}
+
+/// Rejects jobs longer than `--max-lines` so a huge image or font cannot feed meters of paper.
+#[allow(clippy::result_large_err)]
+fn check_print_length(state: &AppState, lines: usize) -> Result<(), Response> {
+    let Some(max_lines) = state.print_tuning.max_lines else {
+        return Ok(());
+    };
+    if lines <= max_lines {
+        return Ok(());
+    }
+    Err((
+        StatusCode::BAD_REQUEST,
+        axum::Json(ErrorBody {
+            code: ErrorCode::TooLong,
+            error: PrintError::TooLong { lines, max_lines }.to_string(),
+            black_ratio: None,
+            queue_length: None,
+            length_mm: Some(packed_lines_mm(lines)),
+            max_length_mm: Some(packed_lines_mm(max_lines)),
+        }),
+    )
+        .into_response())
+}
+
+/// Blank rows above and below the content: kept up to `blank_margin_px` when a request sets it,
+/// whatever its `trim_blank_top_bottom`; otherwise all dropped with `trim_blank`, or left alone.
+/// Also returns where the ink sits in the untrimmed lines.
+fn apply_blank_trim(
+    lines: Vec<PackedLine>,
+    trim_blank: bool,
+    blank_margin_px: Option<u32>,
+) -> (Vec<PackedLine>, Option<ContentBox>) {
+    let bbox = packed_content_bbox(&lines);
+    let lines = match blank_margin_px {
+        Some(margin) => trim_blank_lines(lines, margin),
+        None if trim_blank => trim_blank_lines(lines, 0),
+        None => lines,
+    };
+    (lines, bbox)
+}
+
+/// `NAME=PATH` for `--font`.
+fn parse_named_font(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.trim().is_empty() && !path.is_empty() => {
+            Ok((name.trim().to_string(), PathBuf::from(path)))
         }
+        _ => Err(format!("expected NAME=PATH, got {s:?}")),
     }
-    out
 }
 
-fn pack_bw_image(img: &GrayImage, trim_blank: bool) -> Vec<PackedLine> {
-    let width = img.width().min(MAX_DOTS_PER_LINE as u32) as usize;
-    let height = img.height() as usize;
-    let bytes_per_line = MAX_DOTS_PER_LINE / 8;
-    let mut out = Vec::with_capacity(height.div_ceil(2));
+/// `PRINTER=DENSITY` for `--default-density`.
+fn parse_printer_density(s: &str) -> Result<(String, u8), String> {
+    let parsed = s.split_once('=').and_then(|(printer, density)| {
+        let density = density.trim().parse::<u8>().ok().filter(|d| *d <= 7)?;
+        (!printer.trim().is_empty()).then(|| (printer.trim().to_string(), density))
+    });
+    parsed.ok_or_else(|| format!("expected PRINTER=DENSITY with DENSITY in 0..=7, got {s:?}"))
+}
 
-    for y in (0..height).step_by(2) {
-        let mut line = [0u8; 96];
-        for row in 0..2 {
-            let yy = y + row;
-            if yy >= height {
-                continue;
-            }
-            for x in 0..width {
-                let px = img.get_pixel(x as u32, yy as u32).0[0];
-                if px == 0 {
-                    let byte_idx = row * bytes_per_line + (x / 8);
-                    let bit = 7 - (x % 8);
-                    line[byte_idx] |= 1u8 << bit;
-                }
-            }
+/// Density for a job on `address` that did not ask for one: its `--default-density`, else its
+/// model's default once a scan or connection has identified it, else [`DEFAULT_DENSITY`].
+async fn default_density(state: &AppState, address: &str) -> u8 {
+    if let Some(density) = state.default_densities.get(address) {
+        return *density;
+    }
+    state
+        .printer_models
+        .read()
+        .await
+        .get(address)
+        .map_or(DEFAULT_DENSITY, |model| model.default_density())
+}
+
+/// Notes the models of scanned printers for [`default_density`].
+async fn remember_models(state: &AppState, devices: &[PrinterInfo]) {
+    let mut models = state.printer_models.write().await;
+    for device in devices {
+        if let Some(model) = device.guessed_model {
+            models.insert(normalize_address(&device.address), model);
+        }
+    }
+}
+
+/// `NAME=ADDRESS` for `--printer`.
+fn parse_printer_alias(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, address)) if !name.trim().is_empty() && !address.trim().is_empty() => {
+            Ok((name.trim().to_string(), address.trim().to_string()))
         }
-        out.push(line);
+        _ => Err(format!("expected NAME=ADDRESS, got {s:?}")),
     }
+}
+
+/// A `--printer` name's address, or `address` itself, in [`normalize_address`] form. Lanes, job
+/// records and the per-printer maps are all keyed on this.
+fn resolve_printer(printers: &BTreeMap<String, String>, address: &str) -> String {
+    let address = address.trim();
+    normalize_address(printers.get(address).map_or(address, String::as_str))
+}
 
-    if !trim_blank {
-        return out;
+/// Upper case with `:` separators, the form funnyprint-proto matches scanned devices in, so
+/// `aa-bb-…` and `AA:BB:…` name the same printer.
+fn normalize_address(address: &str) -> String {
+    address.trim().replace('-', ":").to_ascii_uppercase()
+}
+
+/// The primary font of a request: a `--font` name, a path, or `--default-font` if neither is given.
+#[allow(clippy::result_large_err)]
+fn resolve_font(
+    state: &AppState,
+    name: Option<&str>,
+    path: Option<&str>,
+) -> Result<PathBuf, Response> {
+    match (name, path) {
+        (Some(_), Some(_)) => Err(error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidFont,
+            "pass either font or font_path, not both".to_string(),
+        )),
+        (Some(name), None) => lookup_font(state, name),
+        (None, Some(path)) => Ok(PathBuf::from(path)),
+        (None, None) => state.default_font.clone().ok_or_else(|| {
+            error_response(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidFont,
+                "font or font_path is required (no --default-font configured)".to_string(),
+            )
+        }),
     }
-    let first = out.iter().position(|l| l.iter().any(|b| *b != 0));
-    let last = out.iter().rposition(|l| l.iter().any(|b| *b != 0));
-    match (first, last) {
-        (Some(start), Some(end)) => out[start..=end].to_vec(),
-        _ => Vec::new(),
+}
+
+/// Named fallbacks first, then the ones given by path.
+#[allow(clippy::result_large_err)]
+fn resolve_fallback_fonts(
+    state: &AppState,
+    names: &[String],
+    paths: &[String],
+) -> Result<Vec<PathBuf>, Response> {
+    let mut out = Vec::with_capacity(names.len() + paths.len());
+    for name in names {
+        out.push(lookup_font(state, name)?);
     }
+    out.extend(paths.iter().map(PathBuf::from));
+    Ok(out)
+}
+
+#[allow(clippy::result_large_err)]
+fn lookup_font(state: &AppState, name: &str) -> Result<PathBuf, Response> {
+    state.fonts.get(name).cloned().ok_or_else(|| {
+        let known: Vec<&str> = state.fonts.keys().map(String::as_str).collect();
+        let known = if known.is_empty() {
+            "none, start printerd with --font NAME=PATH".to_string()
+        } else {
+            known.join(", ")
+        };
+        error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::UnknownFont,
+            format!("unknown font {name:?}; known fonts: {known}"),
+        )
+    })
 }
 
-fn require_auth(state: &AppState, headers: &HeaderMap) -> Result<(), Response> {
+#[allow(clippy::result_large_err)]
+fn require_auth(state: &AppState, headers: &HeaderMap, peer: SocketAddr) -> Result<(), Response> {
     let Some(expected) = &state.api_token else {
         return Ok(());
     };
+    // `::ffff:127.0.0.1` is how a dual-stack listener sees IPv4 loopback.
+    if state.trust_localhost && peer.ip().to_canonical().is_loopback() {
+        return Ok(());
+    }
 
     let got = headers
         .get("x-api-token")
@@ -848,13 +4362,80 @@ fn require_auth(state: &AppState, headers: &HeaderMap) -> Result<(), Response> {
     } else {
         Err(error_response(
             StatusCode::UNAUTHORIZED,
+            ErrorCode::Unauthorized,
             "unauthorized".to_string(),
         ))
     }
 }
 
-fn error_response(status: StatusCode, message: String) -> Response {
-    (status, axum::Json(ErrorBody { error: message })).into_response()
+#[allow(clippy::result_large_err)]
+fn verify_preview_signature(
+    state: &AppState,
+    id: &str,
+    exp: u64,
+    sig: &str,
+) -> Result<(), Response> {
+    let unauthorized = |msg: &str| {
+        error_response(
+            StatusCode::UNAUTHORIZED,
+            ErrorCode::Unauthorized,
+            msg.to_string(),
+        )
+    };
+    let Some(key) = state.preview_signing_key.as_deref() else {
+        return Err(unauthorized("signed previews are disabled"));
+    };
+    if exp < unix_now() {
+        return Err(unauthorized("preview link expired"));
+    }
+    let sig = hex::decode(sig).map_err(|_| unauthorized("invalid signature"))?;
+    preview_mac(key, id, exp)
+        .verify_slice(&sig)
+        .map_err(|_| unauthorized("invalid signature"))
+}
+
+fn preview_mac(key: &str, id: &str, exp: u64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(format!("{id}:{exp}").as_bytes());
+    mac
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn error_response(status: StatusCode, code: ErrorCode, message: String) -> Response {
+    (
+        status,
+        axum::Json(ErrorBody {
+            code,
+            error: message,
+            black_ratio: None,
+            queue_length: None,
+            length_mm: None,
+            max_length_mm: None,
+        }),
+    )
+        .into_response()
+}
+
+/// Decodes an upload; with `auto_orient` applies its EXIF orientation so phone photos print upright.
+fn decode_image(bytes: &[u8], auto_orient: bool) -> image::ImageResult<DynamicImage> {
+    let mut decoder = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(image::ImageError::from)?
+        .into_decoder()?;
+    // A broken EXIF block should not reject an otherwise readable image.
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    if auto_orient {
+        img.apply_orientation(orientation);
+    }
+    Ok(img)
 }
 
 fn next_id(prefix: &str, seq: &AtomicU64) -> String {
@@ -0,0 +1,132 @@
+"use strict";
+
+const $ = (id) => document.getElementById(id);
+let renderId = null;
+
+$("token").value = localStorage.getItem("printerd-token") || "";
+$("token").addEventListener("change", () => localStorage.setItem("printerd-token", $("token").value));
+
+for (const radio of document.querySelectorAll("input[name=kind]")) {
+  radio.addEventListener("change", () => {
+    const image = radio.value === "image" && radio.checked;
+    $("text-fields").hidden = image;
+    $("image-fields").hidden = !image;
+    resetPreview();
+  });
+}
+
+function setStatus(text, isError = false) {
+  $("status").textContent = text;
+  $("status").className = isError ? "error" : "";
+}
+
+function resetPreview() {
+  renderId = null;
+  $("print-btn").disabled = true;
+  $("preview").style.display = "none";
+}
+
+async function api(path, options = {}) {
+  const headers = { ...options.headers };
+  const token = $("token").value;
+  if (token) headers["x-api-token"] = token;
+  const resp = await fetch(path, { ...options, headers });
+  if (!resp.ok) {
+    const body = await resp.json().catch(() => null);
+    throw new Error(body && body.error ? body.error : `HTTP ${resp.status}`);
+  }
+  return resp;
+}
+
+async function postJson(path, body) {
+  const resp = await api(path, {
+    method: "POST",
+    headers: { "content-type": "application/json" },
+    body: JSON.stringify(body),
+  });
+  return resp.json();
+}
+
+function readBase64(file) {
+  return new Promise((resolve, reject) => {
+    const reader = new FileReader();
+    reader.onload = () => resolve(reader.result.split(",", 2)[1]);
+    reader.onerror = () => reject(reader.error);
+    reader.readAsDataURL(file);
+  });
+}
+
+function numberOrNull(id) {
+  const value = $(id).value.trim();
+  return value === "" ? null : Number(value);
+}
+
+async function render() {
+  const common = {
+    density: numberOrNull("density"),
+    address: $("address").value.trim() || null,
+  };
+  if (document.querySelector("input[name=kind]:checked").value === "image") {
+    const file = $("image").files[0];
+    if (!file) throw new Error("choose an image first");
+    return postJson("/api/v1/renders/image", {
+      ...common,
+      image_base64: await readBase64(file),
+      dither_method: $("dither").value,
+    });
+  }
+  const text = $("text").value;
+  if (!text.trim()) throw new Error("type some text first");
+  return postJson("/api/v1/renders/text", {
+    ...common,
+    text,
+    font_size_px: numberOrNull("font-size"),
+  });
+}
+
+$("preview-btn").addEventListener("click", async () => {
+  resetPreview();
+  setStatus("Rendering...");
+  try {
+    const rendered = await render();
+    const png = await (await api(rendered.preview_url)).blob();
+    URL.revokeObjectURL($("preview").src);
+    $("preview").src = URL.createObjectURL(png);
+    $("preview").style.display = "block";
+    renderId = rendered.render_id;
+    $("print-btn").disabled = false;
+    setStatus(`${rendered.width_px}×${rendered.height_px} px`);
+  } catch (err) {
+    setStatus(err.message, true);
+  }
+});
+
+$("print-btn").addEventListener("click", async () => {
+  $("print-btn").disabled = true;
+  try {
+    const queued = await postJson("/api/v1/print", {
+      render_id: renderId,
+      address: $("address").value.trim() || null,
+      density: numberOrNull("density"),
+      copies: numberOrNull("copies"),
+    });
+    setStatus(`Job ${queued.job_id} queued (${queued.queue_length} ahead)`);
+    for (;;) {
+      const resp = await api(`/api/v1/jobs/${queued.job_id}/wait?timeout_seconds=60`);
+      const job = await resp.json();
+      if (job.status === "done") {
+        setStatus(`Job ${job.id} printed`);
+        break;
+      }
+      if (job.status === "failed" || job.status === "cancelled") {
+        setStatus(`Job ${job.id} ${job.status}${job.error ? ": " + job.error : ""}`, true);
+        break;
+      }
+      setStatus(`Job ${job.id} ${job.status}${job.note ? ": " + job.note : ""}`);
+    }
+  } catch (err) {
+    setStatus(err.message, true);
+  } finally {
+    $("print-btn").disabled = renderId === null;
+  }
+});
@@ -1,24 +1,59 @@
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    fmt::Write as _,
+    io::Cursor,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
 use anyhow::{Context, Result, anyhow, bail};
 use base64::Engine;
+use chrono::{DateTime, Local};
+use chrono_tz::Tz;
 use clap::Parser;
+use funnyprint_api::{
+    DitherMethod, FontSelection, ImageRenderParams, RenderImageRequest, RenderTextRequest,
+    ResizeFilter,
+};
+use funnyprint_render::{
+    CanvasOptions, FontChain, TextAlign, TextBlock, fit_font_size, fit_font_size_by_height,
+    fit_font_size_in_box, format_now, measure_text_block, render_text_blocks, wrap_text_to_width,
+};
+use image::{GrayImage, Luma, imageops};
 use serde::{Deserialize, Serialize};
 use teloxide::{
     dispatching::UpdateFilterExt,
     prelude::*,
     types::{
-        ChatAction, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, KeyboardButton,
-        KeyboardMarkup,
+        ChatAction, Contact, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, KeyboardButton,
+        KeyboardMarkup, Location,
     },
     utils::command::BotCommands,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, Semaphore};
 use tokio_rusqlite::{Connection, rusqlite};
 use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, fmt};
 
+const DEFAULT_PRINTERD_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_PRINTERD_RETRY_BACKOFF_MS: u64 = 300;
+const DEFAULT_WAIT_JOB_DEADLINE_SECONDS: u64 = 120;
+const WHOAMI_COOLDOWN: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_DOWNLOAD_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_AI_MAX_CONCURRENT: usize = 2;
+const DEFAULT_THRESHOLD_SWEEP: [u8; 4] = [120, 150, 180, 210];
+const MAX_THRESHOLD_SWEEP: usize = 9;
+const SWEEP_LABEL_HEIGHT_PX: u32 = 40;
+const SWEEP_GAP_PX: u32 = 8;
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+const DEFAULT_SPLIT_LINES_PER_PAGE: usize = 4;
+const MAX_SPLIT_PAGES: usize = 10;
+/// Unconfirmed "print all pages" batches kept; the oldest go first.
+const MAX_PAGE_BATCHES: usize = 100;
+/// `/wifi` payloads kept for printing again; the oldest stickers lose theirs first.
+const MAX_WIFI_PAYLOADS: usize = 100;
+
 #[derive(Debug, Parser)]
 #[command(name = "telegram-bot")]
 struct Args {
@@ -35,6 +70,8 @@ struct Config {
     sticker: StickerConfig,
     image_sticker: ImageStickerConfig,
     access: AccessConfig,
+    #[serde(default)]
+    time_label: TimeLabelConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -42,12 +79,38 @@ struct PrinterdConfig {
     base_url: String,
     api_token: Option<String>,
     address: Option<String>,
+    /// Length of one `/jobs/{id}/wait` call per copy; printerd caps a call at 120 s.
     wait_job_timeout_seconds: Option<u64>,
+    /// Total wait per copy before a job still queued or printing is reported as not finished in
+    /// time; the bot keeps calling `/wait` until then.
+    wait_job_deadline_seconds: Option<u64>,
+    /// Tries per render, preview and print request when printerd is unreachable or answers 5xx.
+    retry_attempts: Option<u32>,
+    /// Wait before the first retry; doubled for each further one.
+    retry_backoff_ms: Option<u64>,
+    /// Printers behind the "На все принтеры" button, shown once two or more are listed.
+    #[serde(default)]
+    fanout_addresses: Vec<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl PrinterdConfig {
+    fn fanout_enabled(&self) -> bool {
+        self.fanout_addresses.len() > 1
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct StickerConfig {
     font_path: String,
+    #[serde(default)]
+    fallback_font_paths: Vec<String>,
+    /// printerd `--font` name of the same font. When set, renders name it instead of sending
+    /// `font_path`, which then only needs to exist on the bot's host for measuring.
+    #[serde(default)]
+    printerd_font: Option<String>,
+    /// printerd `--font` names sent as fallbacks along with `printerd_font`.
+    #[serde(default)]
+    printerd_fallback_fonts: Vec<String>,
     printer_width_px: u32,
     margin_left_px: u32,
     margin_right_px: u32,
@@ -59,23 +122,114 @@ struct StickerConfig {
     threshold: u8,
     density: u8,
     invert: bool,
+    #[serde(default)]
+    invert_content_only: bool,
     trim_blank_top_bottom: bool,
+    #[serde(default)]
+    fixed_height_px: Option<u32>,
+    /// Upper bound on the text block height in regular mode, so short words do not balloon to
+    /// `max_font_size_px`. Never shrinks text below `min_font_size_px`.
+    #[serde(default)]
+    max_content_height_px: Option<u32>,
+    /// Turn every text sticker a quarter turn (`cw` or `ccw`) so lines run along the tape, laid out
+    /// like banner mode; `fixed_height_px` and `max_content_height_px` then do not apply.
+    #[serde(default)]
+    rotate_90: Option<TextRotation>,
+    #[serde(default)]
+    border_px: u32,
+    #[serde(default)]
+    border_margin_px: u32,
+    /// Light pattern printed behind the text of every text sticker; none when unset.
+    #[serde(default)]
+    background: Option<TextBackground>,
+    /// Simple stickers that would need a font smaller than this are wrapped at this size and split
+    /// over several stickers instead; off when unset.
+    #[serde(default)]
+    split_font_size_px: Option<f32>,
+    /// Lines per sticker when splitting; 4 when unset. With `fixed_height_px`, as many lines as fit.
+    #[serde(default)]
+    split_lines_per_page: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct ImageStickerConfig {
     threshold: u8,
     dither_method: DitherMethod,
+    /// printerd's resize filter; `lanczos` (its default) for photos, `nearest` for line art.
+    #[serde(default)]
+    resize_filter: Option<ResizeFilter>,
+    #[serde(default)]
+    sharpen: Option<f32>,
+    /// Let printerd turn photos upright by their EXIF orientation; printerd's default (on) when unset.
+    #[serde(default)]
+    auto_orient: Option<bool>,
+    #[serde(default)]
+    border_px: u32,
+    #[serde(default)]
+    border_margin_px: u32,
     density: u8,
     invert: bool,
     trim_blank_top_bottom: bool,
+    /// Largest photo taken from Telegram; 10 MiB when unset.
+    #[serde(default)]
+    max_download_bytes: Option<u64>,
+    /// Give up on a Telegram photo download after this long; 30 s when unset.
+    #[serde(default)]
+    download_timeout_seconds: Option<u64>,
+    /// Thresholds shown by "Сетка порогов"; 120, 150, 180 and 210 when unset.
+    #[serde(default)]
+    threshold_sweep: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TextRotation {
+    Cw,
+    Ccw,
+}
+
+impl TextRotation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Cw => "cw",
+            Self::Ccw => "ccw",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
-enum DitherMethod {
-    Threshold,
-    FloydSteinberg,
+enum TextBackground {
+    None,
+    Dots,
+    Lines,
+}
+
+impl TextBackground {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Dots => "dots",
+            Self::Lines => "lines",
+        }
+    }
+}
+
+/// Defaults for `/time`.
+#[derive(Debug, Clone, Deserialize)]
+struct TimeLabelConfig {
+    format: String,
+    /// IANA name such as `Europe/Moscow`; the host timezone when unset.
+    timezone: Option<String>,
+}
+
+impl Default for TimeLabelConfig {
+    fn default() -> Self {
+        Self {
+            format: DEFAULT_TIME_FORMAT.to_string(),
+            timezone: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -92,6 +246,8 @@ struct AiServiceConfig {
     api_token: Option<String>,
     default_size: Option<String>,
     default_quality: Option<String>,
+    /// Generations running at once; further prompts wait for a free slot.
+    max_concurrent: Option<usize>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -109,8 +265,28 @@ struct AppState {
     db: Db,
     printerd: PrinterdClient,
     ai: AiServiceClient,
-    font: FontArc,
+    /// Bounds concurrent AI generations to `ai_service.max_concurrent`.
+    ai_slots: Arc<Semaphore>,
+    /// Fetches Telegram files; has the image download timeout.
+    downloads: reqwest::Client,
+    fonts: FontChain,
+    time_zone: Option<Tz>,
     user_modes: Arc<RwLock<std::collections::HashMap<i64, InputMode>>>,
+    /// History sticker each user asked to edit; their next text message re-renders it.
+    pending_edits: Arc<RwLock<std::collections::HashMap<i64, i64>>>,
+    /// Last `/whoami` answer per user; the command works without the allowlist, so it is throttled.
+    whoami_replies: Arc<RwLock<std::collections::HashMap<i64, Instant>>>,
+    /// Split texts not printed yet, by the id of their first page.
+    page_batches: Arc<RwLock<std::collections::BTreeMap<i64, PageBatch>>>,
+    /// `WIFI:` payloads of this run's latest `/wifi` stickers, by sticker id; never written to the
+    /// database.
+    wifi_payloads: Arc<RwLock<std::collections::BTreeMap<i64, String>>>,
+}
+
+/// Pages of a split text left to print, in order.
+struct PageBatch {
+    user_id: i64,
+    sticker_ids: Vec<i64>,
 }
 
 #[derive(Clone)]
@@ -124,6 +300,8 @@ struct PrinterdClient {
     base_url: String,
     token: Option<String>,
     default_address: Option<String>,
+    retry_attempts: u32,
+    retry_backoff: Duration,
 }
 
 #[derive(Clone)]
@@ -150,9 +328,17 @@ struct StickerRecord {
     trim_blank_top_bottom: bool,
     density: u8,
     dither_method: Option<DitherMethod>,
+    rotate_90: Option<TextRotation>,
     source_image_bytes: Option<Vec<u8>>,
     preview_png: Vec<u8>,
+    /// `[sticker]` settings a text sticker was laid out with, so edits and reprints keep its look
+    /// after the config changes; `None` for other kinds and stickers made before they were stored.
+    text_settings: Option<StickerConfig>,
+    /// Kept on purpose with "Сохранить" rather than just previewed.
+    saved: bool,
     created_at: String,
+    /// printerd's print time estimate; not stored, so `None` for stickers loaded from history.
+    estimated_seconds: Option<f32>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -162,47 +348,59 @@ enum StickerKind {
     TextBanner,
     TextBannerOutline,
     Image,
+    /// QR code; the sticker text is the encoded payload.
+    Qr,
+    /// `/wifi` QR code. The sticker text is only the network name and no preview is stored: the
+    /// payload carries the password, so it stays in [`AppState::wifi_payloads`] and is gone after a
+    /// restart.
+    WifiQr,
 }
 
-#[derive(Debug, Serialize)]
-struct RenderTextRequest {
-    text: String,
-    font_path: String,
-    width_px: u32,
-    height_px: u32,
-    x_px: i32,
-    y_px: i32,
-    font_size_px: f32,
-    line_spacing: f32,
-    threshold: u8,
-    invert: bool,
-    trim_blank_top_bottom: bool,
-    outline_only: bool,
-    outline_thickness_px: u32,
-    banner_mode: bool,
-    density: u8,
-    address: Option<String>,
+impl StickerKind {
+    fn is_text(self) -> bool {
+        matches!(
+            self,
+            Self::Text | Self::TextOutline | Self::TextBanner | Self::TextBannerOutline
+        )
+    }
+}
+
+/// printerd font fields for `cfg`: its `printerd_font` name if set, else its local font paths.
+fn font_selection(cfg: &StickerConfig) -> FontSelection {
+    match &cfg.printerd_font {
+        Some(name) => FontSelection {
+            font: Some(name.clone()),
+            font_path: None,
+            fallback_fonts: cfg.printerd_fallback_fonts.clone(),
+            fallback_font_paths: Vec::new(),
+        },
+        None => FontSelection {
+            font: None,
+            font_path: Some(cfg.font_path.clone()),
+            fallback_fonts: Vec::new(),
+            fallback_font_paths: cfg.fallback_font_paths.clone(),
+        },
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct RenderTextResponse {
-    render_id: String,
+    /// Absent for ephemeral renders, which printerd does not keep.
+    render_id: Option<String>,
     width_px: u32,
     height_px: u32,
-    preview_url: String,
+    preview_url: Option<String>,
+    preview_png_base64: Option<String>,
+    /// Absent from printerd versions that do not estimate print time.
+    estimated_seconds: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
-struct RenderImageRequest {
-    image_base64: String,
-    width_px: u32,
-    max_height_px: Option<u32>,
-    threshold: u8,
-    dither_method: DitherMethod,
-    invert: bool,
-    trim_blank_top_bottom: bool,
+struct RenderQrRequest {
+    data: String,
     density: u8,
     address: Option<String>,
+    ephemeral: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -235,6 +433,8 @@ struct PrintRequest {
     render_id: String,
     address: Option<String>,
     density: u8,
+    copies: u8,
+    priority: &'static str,
 }
 
 #[derive(Debug, Deserialize)]
@@ -242,15 +442,71 @@ struct PrintResponse {
     job_id: String,
 }
 
+#[derive(Debug, Serialize)]
+struct FanoutPrintRequest<'a> {
+    render_id: &'a str,
+    addresses: &'a [String],
+    density: u8,
+    copies: u8,
+    priority: &'static str,
+}
+
+#[derive(Debug, Deserialize)]
+struct FanoutPrintResponse {
+    jobs: Vec<FanoutJob>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FanoutJob {
+    address: String,
+    job_id: Option<String>,
+    error: Option<String>,
+}
+
+/// Where a print goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PrintTarget {
+    /// `[printerd] address`, or printerd's default.
+    Default,
+    /// Every `[printerd] fanout_addresses` printer at once.
+    AllPrinters,
+}
+
+/// A named printer from printerd's `GET /api/v1/printers`.
+#[derive(Debug, Deserialize)]
+struct NamedPrinter {
+    name: String,
+    address: String,
+    last_printed_at: Option<String>,
+}
+
+/// printerd's `GET /api/v1/printers/{address}/status`: the last status packet the printer sent.
+#[derive(Debug, Deserialize)]
+struct PrinterStatus {
+    address: String,
+    battery: u8,
+    no_paper: bool,
+    overheat: bool,
+    reported_at: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct JobResponse {
     status: String,
     error: Option<String>,
+    /// Set by printerd while the printer reports a pause.
+    note: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ApiErrorBody {
     error: String,
+    /// printerd's machine-readable reason, e.g. `blank_result`.
+    #[serde(default)]
+    code: Option<String>,
+    /// Set by printerd when a sticker is over its `--max-lines`.
+    length_mm: Option<f32>,
+    max_length_mm: Option<f32>,
 }
 
 #[derive(BotCommands, Clone)]
@@ -272,6 +528,10 @@ enum Command {
     Ai,
     #[command(description = "последние стикеры")]
     History,
+    #[command(description = "поиск по истории: /find <текст>")]
+    Find(String),
+    #[command(description = "повторить печать последнего стикера")]
+    Last,
     #[command(description = "статистика AI и пользователей")]
     Stats,
     #[command(description = "список пользователей (admin)")]
@@ -280,6 +540,16 @@ enum Command {
     UserAdd(String),
     #[command(description = "удалить пользователя: /user_del <telegram_user_id> (admin)")]
     UserDel(String),
+    #[command(description = "ваш Telegram ID для добавления в список доступа")]
+    Whoami,
+    #[command(description = "стикер с текущими датой и временем: /time [формат strftime]")]
+    Time(String),
+    #[command(description = "QR для Wi-Fi: /wifi <сеть> <пароль> [WPA|WEP|nopass]")]
+    Wifi(String),
+    #[command(description = "выбор принтера для печати")]
+    Printers,
+    #[command(description = "состояние принтера: бумага, батарея, перегрев")]
+    Status,
 }
 
 #[tokio::main]
@@ -305,11 +575,36 @@ async fn main() -> Result<()> {
     if cfg.sticker.printer_width_px == 0 {
         bail!("sticker.printer_width_px must be > 0");
     }
+    let ai_max_concurrent = cfg
+        .ai_service
+        .max_concurrent
+        .unwrap_or(DEFAULT_AI_MAX_CONCURRENT);
+    if ai_max_concurrent == 0 {
+        bail!("ai_service.max_concurrent must be > 0");
+    }
+    if cfg.sticker.split_font_size_px.is_some_and(|s| s <= 0.0) {
+        bail!("sticker.split_font_size_px must be > 0");
+    }
+    if cfg.sticker.split_lines_per_page == Some(0) {
+        bail!("sticker.split_lines_per_page must be > 0");
+    }
+    let time_zone = match &cfg.time_label.timezone {
+        Some(name) => Some(
+            name.parse::<Tz>()
+                .map_err(|err| anyhow!("time_label.timezone {name:?}: {err}"))?,
+        ),
+        None => None,
+    };
+    format_now(&cfg.time_label.format, time_zone).context("invalid time_label.format")?;
 
-    let font_bytes = tokio::fs::read(&cfg.sticker.font_path)
-        .await
-        .with_context(|| format!("failed to read font {}", cfg.sticker.font_path))?;
-    let font = FontArc::try_from_vec(font_bytes).context("failed to parse font")?;
+    let fallback_paths: Vec<PathBuf> = cfg
+        .sticker
+        .fallback_font_paths
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    let fonts = FontChain::load(std::path::Path::new(&cfg.sticker.font_path), &fallback_paths)
+        .context("failed to load sticker fonts")?;
 
     let db = Db::open(&cfg.sqlite_path).await?;
     db.init().await?;
@@ -322,14 +617,29 @@ async fn main() -> Result<()> {
 
     let printerd = PrinterdClient::new(cfg.printerd.clone());
     let ai = AiServiceClient::new(cfg.ai_service.clone());
+    let downloads = reqwest::Client::builder()
+        .timeout(Duration::from_secs(
+            cfg.image_sticker
+                .download_timeout_seconds
+                .unwrap_or(DEFAULT_DOWNLOAD_TIMEOUT_SECONDS),
+        ))
+        .build()
+        .context("failed to build download client")?;
 
     let state = Arc::new(AppState {
         cfg: cfg.clone(),
         db,
         printerd,
         ai,
-        font,
+        ai_slots: Arc::new(Semaphore::new(ai_max_concurrent)),
+        downloads,
+        fonts,
+        time_zone,
         user_modes: Arc::new(RwLock::new(std::collections::HashMap::new())),
+        pending_edits: Arc::new(RwLock::new(std::collections::HashMap::new())),
+        whoami_replies: Arc::new(RwLock::new(std::collections::HashMap::new())),
+        page_batches: Arc::new(RwLock::new(std::collections::BTreeMap::new())),
+        wifi_payloads: Arc::new(RwLock::new(std::collections::BTreeMap::new())),
     });
 
     let bot = Bot::new(cfg.telegram_token);
@@ -354,6 +664,18 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
     };
     let user_id = user.id.0 as i64;
 
+    // Answered before the allowlist check: new users need their ID to be added.
+    if let Some(text) = msg.text()
+        && matches!(Command::parse(text, "bot"), Ok(Command::Whoami))
+    {
+        if whoami_allowed(&state, user_id).await {
+            send_whoami(&bot, &msg, &state, user).await?;
+        } else {
+            warn!(user_id = user_id, "whoami rate limited");
+        }
+        return Ok(());
+    }
+
     if !state.db.is_allowed(user_id).await.unwrap_or(false) {
         warn!(user_id = user_id, "telegram user denied by allowlist");
         bot.send_message(
@@ -381,6 +703,12 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
             return Ok(());
         }
 
+        let pending_edit = state.pending_edits.write().await.remove(&user_id);
+        if let Some(sticker_id) = pending_edit {
+            edit_text_sticker(&bot, &msg, &state, user_id, sticker_id, text).await?;
+            return Ok(());
+        }
+
         let mode = {
             let modes = state.user_modes.read().await;
             modes
@@ -391,6 +719,10 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
 
         match mode {
             InputMode::SimpleText => {
+                if let Some(pages) = split_text_pages(&state.cfg.sticker, &state.fonts, text) {
+                    send_text_pages(&bot, msg.chat.id, &state, user_id, pages).await?;
+                    return Ok(());
+                }
                 match create_text_sticker(
                     &state,
                     user_id,
@@ -407,15 +739,16 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
                             "created text sticker preview"
                         );
                         let caption = format!(
-                            "Превью стикера.\nШрифт: {:.1}px\nНажмите кнопку для печати.",
-                            record.font_size_px
+                            "Превью стикера.\nШрифт: {:.1}px{}\nНажмите кнопку для печати.",
+                            record.font_size_px,
+                            print_time_hint(&record)
                         );
                         bot.send_photo(
                             msg.chat.id,
                             InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
                         )
                         .caption(caption)
-                        .reply_markup(print_keyboard(record.id))
+                        .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
                         .await?;
                     }
                     Err(err) => {
@@ -442,7 +775,7 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
                             InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
                         )
                         .caption("Превью контурного текста.\nНажмите кнопку для печати.")
-                        .reply_markup(print_keyboard(record.id))
+                        .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
                         .await?;
                     }
                     Err(err) => {
@@ -469,7 +802,7 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
                             InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
                         )
                         .caption("Превью баннера.\nНажмите кнопку для печати.")
-                        .reply_markup(print_keyboard(record.id))
+                        .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
                         .await?;
                     }
                     Err(err) => {
@@ -496,7 +829,7 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
                             InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
                         )
                         .caption("Превью баннера (контур).\nНажмите кнопку для печати.")
-                        .reply_markup(print_keyboard(record.id))
+                        .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
                         .await?;
                     }
                     Err(err) => {
@@ -507,10 +840,35 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
                 }
             }
             InputMode::AiImage => {
-                let progress_msg = bot
-                    .send_message(msg.chat.id, "Готовится изображение...")
-                    .await
-                    .ok();
+                // Held until this prompt is answered, successfully or not.
+                let mut queued_msg = None;
+                let _ai_slot = match state.ai_slots.try_acquire() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        queued_msg = bot
+                            .send_message(
+                                msg.chat.id,
+                                "Сейчас генерируются другие картинки, вы в очереди...",
+                            )
+                            .await
+                            .ok();
+                        // The semaphore is never closed.
+                        let Ok(permit) = state.ai_slots.acquire().await else {
+                            return Ok(());
+                        };
+                        permit
+                    }
+                };
+                let progress_msg = match queued_msg {
+                    Some(queued) => bot
+                        .edit_message_text(msg.chat.id, queued.id, "Готовится изображение...")
+                        .await
+                        .ok(),
+                    None => bot
+                        .send_message(msg.chat.id, "Готовится изображение...")
+                        .await
+                        .ok(),
+                };
                 let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
                 let bot_for_action = bot.clone();
                 let chat_id = msg.chat.id;
@@ -547,7 +905,7 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
                             InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
                         )
                         .caption(caption)
-                        .reply_markup(print_keyboard(record.id))
+                        .reply_markup(image_preview_keyboard(record.id, &state.cfg.printerd))
                         .await?;
                     }
                     Err(err) => {
@@ -582,32 +940,196 @@ async fn handle_message(bot: Bot, msg: Message, state: Arc<AppState>) -> Respons
         return Ok(());
     }
 
-    if let Some(photos) = msg.photo() {
-        if let Some(photo) = photos.last() {
-            match create_image_sticker(&bot, &state, user_id, msg.chat.id.0, photo).await {
-                Ok(record) => {
-                    info!(
-                        user_id = user_id,
-                        sticker_id = record.id,
-                        "created image sticker preview"
-                    );
-                    bot.send_photo(
-                        msg.chat.id,
-                        InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
-                    )
-                    .caption("Превью изображения для печати.\nНажмите кнопку для печати.")
-                    .reply_markup(print_keyboard(record.id))
+    if let Some(photos) = msg.photo()
+        && let Some(photo) = photos.last()
+    {
+        match create_image_sticker(&bot, &state, user_id, msg.chat.id.0, photo).await {
+            Ok(record) => {
+                info!(
+                    user_id = user_id,
+                    sticker_id = record.id,
+                    "created image sticker preview"
+                );
+                bot.send_photo(
+                    msg.chat.id,
+                    InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+                )
+                .caption(format!(
+                    "Превью изображения для печати.{}\nНажмите кнопку для печати.",
+                    print_time_hint(&record)
+                ))
+                .reply_markup(image_preview_keyboard(record.id, &state.cfg.printerd))
+                .await?;
+            }
+            Err(err) => {
+                error!(user_id = user_id, error = %err, "failed to create image sticker preview");
+                bot.send_message(msg.chat.id, format!("Ошибка обработки изображения: {err}"))
                     .await?;
-                }
-                Err(err) => {
-                    error!(user_id = user_id, error = %err, "failed to create image sticker preview");
-                    bot.send_message(msg.chat.id, format!("Ошибка обработки изображения: {err}"))
-                        .await?;
-                }
             }
         }
+        return Ok(());
+    }
+
+    if let Some(card) = SharedCard::from_message(&msg) {
+        send_shared_card(&bot, &msg, &state, user_id, card).await?;
+        return Ok(());
+    }
+
+    // Group chats carry service messages too, and a reply to each would be noise.
+    if msg.chat.is_private() {
+        bot.send_message(
+            msg.chat.id,
+            "Такие сообщения не печатаются. Отправьте текст, фото, геопозицию или контакт. /help",
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// A shared location, venue or contact turned into a text label plus a QR code.
+struct SharedCard {
+    /// Caption noun, `геопозиция` or `контакт`.
+    what: &'static str,
+    label: String,
+    /// A map link for places, a vCard for contacts.
+    qr_payload: String,
+}
+
+impl SharedCard {
+    fn from_message(msg: &Message) -> Option<Self> {
+        if let Some(location) = msg.location() {
+            return Some(Self {
+                what: "геопозиция",
+                label: coordinates_text(location),
+                qr_payload: map_url(location),
+            });
+        }
+        if let Some(venue) = msg.venue() {
+            return Some(Self {
+                what: "геопозиция",
+                label: format!(
+                    "{}\n{}\n{}",
+                    venue.title,
+                    venue.address,
+                    coordinates_text(&venue.location)
+                ),
+                qr_payload: map_url(&venue.location),
+            });
+        }
+        let contact = msg.contact()?;
+        Some(Self {
+            what: "контакт",
+            label: format!("{}\n{}", contact_name(contact), contact.phone_number),
+            qr_payload: contact_vcard(contact),
+        })
+    }
+}
+
+/// Sends the label preview, then the QR preview; each prints and lands in `/history` on its own.
+async fn send_shared_card(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    user_id: i64,
+    card: SharedCard,
+) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    match create_text_sticker(state, user_id, chat_id.0, &card.label, StickerKind::Text).await {
+        Ok(record) => {
+            info!(
+                user_id = user_id,
+                sticker_id = record.id,
+                "created shared card label preview"
+            );
+            bot.send_photo(
+                chat_id,
+                InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+            )
+            .caption(format!(
+                "Превью: {}.{}\nНажмите кнопку для печати.",
+                card.what,
+                print_time_hint(&record)
+            ))
+            .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
+            .await?;
+        }
+        Err(err) => {
+            error!(user_id = user_id, error = %err, "failed to create shared card label preview");
+            bot.send_message(chat_id, format!("Ошибка рендера: {err}"))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    match create_qr_sticker(
+        state,
+        user_id,
+        chat_id.0,
+        &card.qr_payload,
+        StickerKind::Qr,
+        &card.qr_payload,
+    )
+    .await
+    {
+        Ok(record) => {
+            info!(
+                user_id = user_id,
+                sticker_id = record.id,
+                "created shared card qr preview"
+            );
+            bot.send_photo(
+                chat_id,
+                InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+            )
+            .caption(format!(
+                "Превью: {}, QR-код.\nНажмите кнопку для печати.",
+                card.what
+            ))
+            .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
+            .await?;
+        }
+        Err(err) => {
+            error!(user_id = user_id, error = %err, "failed to create shared card qr preview");
+            bot.send_message(chat_id, format!("Ошибка рендера QR: {err}"))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn whoami_allowed(state: &AppState, user_id: i64) -> bool {
+    let mut replies = state.whoami_replies.write().await;
+    replies.retain(|_, at| at.elapsed() < WHOAMI_COOLDOWN);
+    if replies.contains_key(&user_id) {
+        return false;
     }
+    replies.insert(user_id, Instant::now());
+    true
+}
 
+async fn send_whoami(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    user: &teloxide::types::User,
+) -> ResponseResult<()> {
+    let user_id = user.id.0 as i64;
+    let username = user
+        .username
+        .as_ref()
+        .map(|u| format!("@{u}"))
+        .unwrap_or_else(|| "—".to_string());
+    let access = if state.db.is_allowed(user_id).await.unwrap_or(false) {
+        "разрешён"
+    } else {
+        "запрещён (перешлите ID администратору для /user_add)"
+    };
+    bot.send_message(
+        msg.chat.id,
+        format!("Ваш Telegram ID: {user_id}\nUsername: {username}\nДоступ: {access}"),
+    )
+    .await?;
     Ok(())
 }
 
@@ -619,12 +1141,14 @@ async fn handle_command(
     cmd: Command,
 ) -> ResponseResult<()> {
     let is_admin = state.db.is_admin(user_id).await.unwrap_or(false);
+    // Any command or menu button abandons a pending "edit text".
+    state.pending_edits.write().await.remove(&user_id);
 
     match cmd {
         Command::Help | Command::Start => {
             bot.send_message(
                 msg.chat.id,
-                "Режимы:\n• 🏷 Простой стикер: отправьте текст.\n• ✏️ Контур текста: буквы без заливки.\n• 🧾 Баннер: печать вдоль ленты.\n• 🧾✏️ Баннер контуром.\n• 🤖 ИИ картинка: отправьте описание изображения.\nТакже можно отправить готовую картинку.\n• 📊 Статистика: пользователи и токены AI.\nПосле превью нажмите Печатать.",
+                "Режимы:\n• 🏷 Простой стикер: отправьте текст.\n• ✏️ Контур текста: буквы без заливки.\n• 🧾 Баннер: печать вдоль ленты.\n• 🧾✏️ Баннер контуром.\n• 🤖 ИИ картинка: отправьте описание изображения.\nТакже можно отправить готовую картинку, геопозицию или контакт.\n• 🔁 Повторить: печать последнего стикера ещё раз.\n• 📊 Статистика: пользователи и токены AI.\n• /find <текст>: поиск по истории.\n• /printers: выбор принтера.\n• /status: бумага, батарея и перегрев принтера.\nПосле превью нажмите Печатать.",
             )
             .reply_markup(main_menu_keyboard())
             .await?;
@@ -696,15 +1220,8 @@ async fn handle_command(
                     .await?;
             }
             Ok(items) => {
-                for item in items {
-                    let caption = format!("{}\n{}", item.created_at, item.text);
-                    bot.send_photo(
-                        msg.chat.id,
-                        InputFile::memory(item.preview_png.clone()).file_name("preview.png"),
-                    )
-                    .caption(caption)
-                    .reply_markup(history_item_keyboard(item.id))
-                    .await?;
+                for item in &items {
+                    send_history_item(bot, msg, state, item).await?;
                 }
                 bot.send_message(msg.chat.id, "Действия с историей:")
                     .reply_markup(clear_history_keyboard())
@@ -716,6 +1233,64 @@ async fn handle_command(
                     .await?;
             }
         },
+        Command::Find(query) => {
+            let query = query.trim();
+            if query.is_empty() {
+                bot.send_message(msg.chat.id, "Использование: /find <текст стикера>")
+                    .reply_markup(main_menu_keyboard())
+                    .await?;
+                return Ok(());
+            }
+            match state.db.search_for_user(user_id, query, 10).await {
+                Ok(items) if items.is_empty() => {
+                    bot.send_message(msg.chat.id, format!("В истории нет стикеров с «{query}»."))
+                        .reply_markup(main_menu_keyboard())
+                        .await?;
+                }
+                Ok(items) => {
+                    for item in &items {
+                        send_history_item(bot, msg, state, item).await?;
+                    }
+                }
+                Err(err) => {
+                    bot.send_message(msg.chat.id, format!("Ошибка поиска: {err}"))
+                        .reply_markup(main_menu_keyboard())
+                        .await?;
+                }
+            }
+        }
+        Command::Last => match state.db.last_for_user(user_id).await {
+            Ok(None) => {
+                bot.send_message(msg.chat.id, "История пуста, повторять нечего.")
+                    .reply_markup(main_menu_keyboard())
+                    .await?;
+            }
+            Ok(Some(item)) => {
+                match process_print_action(state, user_id, item.id, 1, PrintTarget::Default).await {
+                    Ok((job_id, _)) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!(
+                                "Повторная печать «{}»: задание {job_id} выполнено.",
+                                item.text
+                            ),
+                        )
+                        .reply_markup(main_menu_keyboard())
+                        .await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(msg.chat.id, format!("Ошибка печати: {err}"))
+                            .reply_markup(main_menu_keyboard())
+                            .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, format!("Ошибка чтения истории: {err}"))
+                    .reply_markup(main_menu_keyboard())
+                    .await?;
+            }
+        },
         Command::Stats => match state.db.ai_stats().await {
             Ok(stats) => {
                 let mut text = format!(
@@ -819,25 +1394,246 @@ async fn handle_command(
                 }
             }
         }
-    }
-
-    Ok(())
-}
-
-async fn handle_callback(bot: Bot, q: CallbackQuery, state: Arc<AppState>) -> ResponseResult<()> {
-    let user_id = q.from.id.0 as i64;
-    if !state.db.is_allowed(user_id).await.unwrap_or(false) {
-        let _ = bot
-            .answer_callback_query(q.id)
-            .text("Доступ запрещён")
-            .await;
-        return Ok(());
-    }
-
-    let Some(data) = q.data.as_deref() else {
-        return Ok(());
-    };
-
+        Command::Printers => send_printer_picker(bot, msg, state, user_id).await?,
+        Command::Status => send_printer_status(bot, msg, state, user_id).await?,
+        Command::Whoami => {
+            // Normally answered in `handle_message` before the allowlist check.
+            if let Some(user) = msg.from.as_ref() {
+                send_whoami(bot, msg, state, user).await?;
+            }
+        }
+        Command::Time(format) => {
+            let format = match format.trim() {
+                "" => state.cfg.time_label.format.as_str(),
+                custom => custom,
+            };
+            let Ok(text) = format_now(format, state.time_zone) else {
+                bot.send_message(msg.chat.id, format!("Неверный формат времени: {format}"))
+                    .reply_markup(main_menu_keyboard())
+                    .await?;
+                return Ok(());
+            };
+            // Same look as a typed sticker in the user's current mode.
+            let mode = state.user_modes.read().await.get(&user_id).copied();
+            let kind = match mode {
+                Some(InputMode::OutlineText) => StickerKind::TextOutline,
+                Some(InputMode::Banner) => StickerKind::TextBanner,
+                Some(InputMode::BannerOutline) => StickerKind::TextBannerOutline,
+                Some(InputMode::SimpleText | InputMode::AiImage) | None => StickerKind::Text,
+            };
+            match create_text_sticker(state, user_id, msg.chat.id.0, &text, kind).await {
+                Ok(record) => {
+                    info!(
+                        user_id = user_id,
+                        sticker_id = record.id,
+                        "created time label preview"
+                    );
+                    bot.send_photo(
+                        msg.chat.id,
+                        InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+                    )
+                    .caption(format!("Превью: {text}\nНажмите кнопку для печати."))
+                    .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
+                    .await?;
+                }
+                Err(err) => {
+                    error!(user_id = user_id, error = %err, "failed to create time label preview");
+                    bot.send_message(msg.chat.id, format!("Ошибка рендера: {err}"))
+                        .await?;
+                }
+            }
+        }
+        Command::Wifi(args) => {
+            let Some((ssid, password, security)) = parse_wifi_args(&args) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Использование: /wifi <сеть> <пароль> [WPA|WEP|nopass]\n\
+                     Если в пароле есть пробелы, укажите сеть, пароль и тип на отдельных строках.",
+                )
+                .reply_markup(main_menu_keyboard())
+                .await?;
+                return Ok(());
+            };
+            let payload = wifi_payload(&ssid, &password, &security);
+            let label = format!("Wi-Fi {ssid}");
+            let created = create_qr_sticker(
+                state,
+                user_id,
+                msg.chat.id.0,
+                &payload,
+                StickerKind::WifiQr,
+                &label,
+            )
+            .await;
+            match created {
+                Ok(record) => {
+                    {
+                        let mut payloads = state.wifi_payloads.write().await;
+                        payloads.insert(record.id, payload);
+                        while payloads.len() > MAX_WIFI_PAYLOADS {
+                            payloads.pop_first();
+                        }
+                    }
+                    info!(
+                        user_id = user_id,
+                        sticker_id = record.id,
+                        "created wifi qr preview"
+                    );
+                    bot.send_photo(
+                        msg.chat.id,
+                        InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+                    )
+                    .caption(format!("Превью: {label}\nНажмите кнопку для печати."))
+                    .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
+                    .await?;
+                }
+                Err(err) => {
+                    error!(user_id = user_id, error = %err, "failed to create wifi qr preview");
+                    bot.send_message(msg.chat.id, format!("Ошибка рендера: {err}"))
+                        .await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// One history sticker: its preview with the date and text, and the print buttons.
+async fn send_history_item(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    item: &StickerRecord,
+) -> ResponseResult<()> {
+    let mark = if item.saved { "💾 " } else { "" };
+    let caption = format!("{mark}{}\n{}", item.created_at, item.text);
+    let keyboard = history_item_keyboard(item.id, item.kind, &state.cfg.printerd);
+    if item.preview_png.is_empty() {
+        bot.send_message(msg.chat.id, caption)
+            .reply_markup(keyboard)
+            .await?;
+        return Ok(());
+    }
+    bot.send_photo(
+        msg.chat.id,
+        InputFile::memory(item.preview_png.clone()).file_name("preview.png"),
+    )
+    .caption(caption)
+    .reply_markup(keyboard)
+    .await?;
+    Ok(())
+}
+
+/// `/printers`: printerd's named printers as buttons; the pick is used for this user's prints.
+async fn send_printer_picker(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    user_id: i64,
+) -> ResponseResult<()> {
+    let listed = async {
+        let printers = state.printerd.list_printers().await?;
+        let current = state.db.printer_for_user(user_id).await?;
+        anyhow::Ok((printers, current))
+    }
+    .await;
+    let (printers, current) = match listed {
+        Ok(listed) => listed,
+        Err(err) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Не удалось получить список принтеров: {err}"),
+            )
+            .reply_markup(main_menu_keyboard())
+            .await?;
+            return Ok(());
+        }
+    };
+    if printers.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "В printerd нет именованных принтеров (флаг --printer), печать идёт на принтер по умолчанию.",
+        )
+        .reply_markup(main_menu_keyboard())
+        .await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("Принтеры:");
+    for printer in &printers {
+        let last = printer.last_printed_at.as_deref().map_or_else(
+            || "ещё не печатал".to_string(),
+            |at| format!("печатал {}", format_printed_at(at, state.time_zone)),
+        );
+        let _ = write!(text, "\n• {} ({}), {last}", printer.name, printer.address);
+    }
+    let _ = write!(
+        text,
+        "\nСейчас: {}",
+        current.as_deref().unwrap_or("по умолчанию")
+    );
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(printer_keyboard(&printers, current.as_deref()))
+        .await?;
+    Ok(())
+}
+
+/// `/status`: what the user's printer last reported, to check paper and battery before a retry.
+async fn send_printer_status(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    user_id: i64,
+) -> ResponseResult<()> {
+    let status = async {
+        let address = match state.db.printer_for_user(user_id).await? {
+            Some(name) => Some(name),
+            None => state.cfg.printerd.address.clone(),
+        };
+        let Some(address) = address else {
+            bail!("принтер по умолчанию задан только в printerd; выберите принтер через /printers");
+        };
+        state.printerd.printer_status(&address).await
+    }
+    .await;
+    let text = match status {
+        Ok(status) => {
+            let paper = if status.no_paper {
+                "нет"
+            } else {
+                "есть"
+            };
+            let overheat = if status.overheat { "да" } else { "нет" };
+            format!(
+                "Принтер {}:\n• Бумага: {paper}\n• Батарея: {}%\n• Перегрев: {overheat}\nДанные на {}.",
+                status.address,
+                status.battery,
+                format_printed_at(&status.reported_at, state.time_zone)
+            )
+        }
+        Err(err) => format!("Не удалось получить состояние принтера: {err}"),
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(main_menu_keyboard())
+        .await?;
+    Ok(())
+}
+
+async fn handle_callback(bot: Bot, q: CallbackQuery, state: Arc<AppState>) -> ResponseResult<()> {
+    let user_id = q.from.id.0 as i64;
+    if !state.db.is_allowed(user_id).await.unwrap_or(false) {
+        let _ = bot
+            .answer_callback_query(q.id)
+            .text("Доступ запрещён")
+            .await;
+        return Ok(());
+    }
+
+    let Some(data) = q.data.as_deref() else {
+        return Ok(());
+    };
+
     if data == "clear_history" {
         match state.db.clear_history_for_user(user_id).await {
             Ok(count) => {
@@ -855,69 +1651,733 @@ async fn handle_callback(bot: Bot, q: CallbackQuery, state: Arc<AppState>) -> Re
         return Ok(());
     }
 
-    let Some((action, id_str)) = data.split_once(':') else {
-        return Ok(());
-    };
-    if action != "print" && action != "reprint" && action != "delete" {
-        return Ok(());
+    if let Some(choice) = data.strip_prefix("threshold:") {
+        return apply_threshold(&bot, &q, &state, user_id, choice).await;
+    }
+
+    if let Some(batch_id) = data.strip_prefix("printpages:") {
+        return print_page_batch(&bot, &q, &state, user_id, batch_id).await;
+    }
+
+    if let Some(name) = data.strip_prefix("printer:") {
+        return select_printer(&bot, &q, &state, user_id, name).await;
+    }
+
+    let Some((action, id_str)) = data.split_once(':') else {
+        return Ok(());
+    };
+    let copies = match action {
+        "print" | "reprint" | "delete" | "save" | "printall" | "edit" | "sweep" => 1,
+        "print5" => 5,
+        _ => return Ok(()),
+    };
+
+    let Ok(sticker_id) = id_str.parse::<i64>() else {
+        return Ok(());
+    };
+
+    if action == "save" {
+        match state.db.mark_saved_for_user(sticker_id, user_id).await {
+            Ok(true) => {
+                bot.answer_callback_query(q.id.clone())
+                    .text("Сохранено. Напечатать можно из /history")
+                    .await?;
+                if let Some(message) = q.message {
+                    let _ = bot
+                        .edit_message_reply_markup(message.chat().id, message.id())
+                        .reply_markup(InlineKeyboardMarkup::default())
+                        .await;
+                }
+            }
+            Ok(false) => {
+                bot.answer_callback_query(q.id)
+                    .show_alert(true)
+                    .text("Не найдено")
+                    .await?;
+            }
+            Err(err) => {
+                bot.answer_callback_query(q.id)
+                    .show_alert(true)
+                    .text(format!("Ошибка сохранения: {err}"))
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if action == "sweep" {
+        return send_threshold_sweep(&bot, q, &state, user_id, sticker_id).await;
+    }
+
+    if action == "edit" {
+        match state.db.get_sticker_for_user(sticker_id, user_id).await {
+            Ok(Some(sticker)) if sticker.kind.is_text() => {
+                state
+                    .pending_edits
+                    .write()
+                    .await
+                    .insert(user_id, sticker_id);
+                bot.answer_callback_query(q.id).await?;
+                if let Some(message) = q.message {
+                    bot.send_message(
+                        message.chat().id,
+                        format!(
+                            "Отправьте новый текст для стикера «{}». Любая команда отменяет изменение.",
+                            sticker.text
+                        ),
+                    )
+                    .await?;
+                }
+            }
+            Ok(_) => {
+                bot.answer_callback_query(q.id)
+                    .show_alert(true)
+                    .text("Не найдено")
+                    .await?;
+            }
+            Err(err) => {
+                bot.answer_callback_query(q.id)
+                    .show_alert(true)
+                    .text(format!("Ошибка чтения истории: {err}"))
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if action == "delete" {
+        let result = state.db.delete_sticker_for_user(sticker_id, user_id).await;
+        match result {
+            Ok(true) => {
+                bot.answer_callback_query(q.id.clone())
+                    .text("Удалено из истории")
+                    .await?;
+                if let Some(message) = q.message {
+                    let _ = bot
+                        .edit_message_reply_markup(message.chat().id, message.id())
+                        .reply_markup(InlineKeyboardMarkup::default())
+                        .await;
+                }
+            }
+            Ok(false) => {
+                bot.answer_callback_query(q.id)
+                    .show_alert(true)
+                    .text("Не найдено")
+                    .await?;
+            }
+            Err(err) => {
+                bot.answer_callback_query(q.id)
+                    .show_alert(true)
+                    .text(format!("Ошибка удаления: {err}"))
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    let target = if action == "printall" {
+        PrintTarget::AllPrinters
+    } else {
+        PrintTarget::Default
+    };
+    let result = process_print_action(&state, user_id, sticker_id, copies, target).await;
+
+    match result {
+        Ok((job_id, kind)) => {
+            bot.answer_callback_query(q.id.clone())
+                .text(format!("Задание отправлено: {job_id}"))
+                .await?;
+            if let Some(message) = q.message {
+                let _ = bot
+                    .edit_message_reply_markup(message.chat().id, message.id())
+                    .reply_markup(history_item_keyboard(sticker_id, kind, &state.cfg.printerd))
+                    .await;
+            }
+        }
+        Err(err) => {
+            bot.answer_callback_query(q.id)
+                .show_alert(true)
+                .text(format!(
+                    "Ошибка печати: {err}\n\nЧто с принтером, покажет /status. Когда он будет готов, нажмите «🔁 Повторить»."
+                ))
+                .await?;
+            if let Some(message) = q.message {
+                let _ = bot
+                    .edit_message_reply_markup(message.chat().id, message.id())
+                    .reply_markup(retry_keyboard(action, sticker_id))
+                    .await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Answers "Сетка порогов": the image sticker rendered at every configured threshold in one labelled
+/// grid, with a button per threshold to apply it.
+async fn send_threshold_sweep(
+    bot: &Bot,
+    q: CallbackQuery,
+    state: &AppState,
+    user_id: i64,
+    sticker_id: i64,
+) -> ResponseResult<()> {
+    let Some(message) = q.message.as_ref() else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    bot.answer_callback_query(q.id.clone())
+        .text("Готовлю сетку порогов...")
+        .await?;
+    let _ = bot.send_chat_action(chat_id, ChatAction::UploadPhoto).await;
+
+    let thresholds = sweep_thresholds(&state.cfg.image_sticker);
+    match threshold_sweep_png(state, user_id, sticker_id, &thresholds).await {
+        Ok(png) => {
+            let buttons: Vec<InlineKeyboardButton> = thresholds
+                .iter()
+                .map(|t| {
+                    InlineKeyboardButton::callback(
+                        t.to_string(),
+                        format!("threshold:{sticker_id}:{t}"),
+                    )
+                })
+                .collect();
+            let keyboard = InlineKeyboardMarkup::new(
+                buttons
+                    .chunks(sweep_columns(thresholds.len()) as usize)
+                    .map(|row| row.to_vec()),
+            );
+            bot.send_photo(chat_id, InputFile::memory(png).file_name("thresholds.png"))
+                .caption("Сетка порогов: меньше порог — светлее. Выберите подходящий.")
+                .reply_markup(keyboard)
+                .await?;
+        }
+        Err(err) => {
+            error!(user_id = user_id, sticker_id = sticker_id, error = %err, "threshold sweep failed");
+            bot.send_message(chat_id, format!("Ошибка рендера: {err}"))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// A tap on the `/printers` picker; an empty `name` goes back to the configured default.
+async fn select_printer(
+    bot: &Bot,
+    q: &CallbackQuery,
+    state: &AppState,
+    user_id: i64,
+    name: &str,
+) -> ResponseResult<()> {
+    let choice = (!name.is_empty()).then(|| name.to_string());
+    if let Err(err) = state.db.set_printer_for_user(user_id, choice.clone()).await {
+        bot.answer_callback_query(q.id.clone())
+            .show_alert(true)
+            .text(format!("Ошибка сохранения: {err}"))
+            .await?;
+        return Ok(());
+    }
+    info!(user_id = user_id, printer = ?choice, "printer selected");
+    let answer = match &choice {
+        Some(name) => format!("Печать на {name}"),
+        None => "Печать на принтер по умолчанию".to_string(),
+    };
+    bot.answer_callback_query(q.id.clone()).text(answer).await?;
+    if let Some(message) = &q.message
+        && let Ok(printers) = state.printerd.list_printers().await
+    {
+        let _ = bot
+            .edit_message_reply_markup(message.chat().id, message.id())
+            .reply_markup(printer_keyboard(&printers, choice.as_deref()))
+            .await;
+    }
+    Ok(())
+}
+
+/// Sets the threshold picked from the grid (`choice` is `<sticker_id>:<threshold>`) on the sticker
+/// and sends its new preview.
+async fn apply_threshold(
+    bot: &Bot,
+    q: &CallbackQuery,
+    state: &AppState,
+    user_id: i64,
+    choice: &str,
+) -> ResponseResult<()> {
+    let Some((sticker_id, threshold)) = choice
+        .split_once(':')
+        .and_then(|(id, t)| Some((id.parse::<i64>().ok()?, t.parse::<u8>().ok()?)))
+    else {
+        return Ok(());
+    };
+
+    let result = async {
+        let Some(mut sticker) = state.db.get_sticker_for_user(sticker_id, user_id).await? else {
+            bail!("стикер не найден");
+        };
+        sticker.threshold = threshold;
+        let render = state
+            .printerd
+            .render_image(&image_render_request(state, &sticker, true)?)
+            .await?;
+        let preview_png = state.printerd.preview_png(&render).await?;
+        if !state
+            .db
+            .set_threshold_for_user(sticker_id, user_id, threshold, preview_png.clone())
+            .await?
+        {
+            bail!("стикер не найден");
+        }
+        Ok(preview_png)
+    }
+    .await;
+
+    match result {
+        Ok(preview_png) => {
+            bot.answer_callback_query(q.id.clone())
+                .text(format!("Порог {threshold}"))
+                .await?;
+            if let Some(message) = &q.message {
+                bot.send_photo(
+                    message.chat().id,
+                    InputFile::memory(preview_png).file_name("preview.png"),
+                )
+                .caption(format!(
+                    "Превью с порогом {threshold}.\nНажмите кнопку для печати."
+                ))
+                .reply_markup(image_preview_keyboard(sticker_id, &state.cfg.printerd))
+                .await?;
+            }
+        }
+        Err(err) => {
+            bot.answer_callback_query(q.id.clone())
+                .show_alert(true)
+                .text(format!("Ошибка рендера: {err}"))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+fn sweep_thresholds(cfg: &ImageStickerConfig) -> Vec<u8> {
+    let mut thresholds = cfg
+        .threshold_sweep
+        .clone()
+        .unwrap_or_else(|| DEFAULT_THRESHOLD_SWEEP.to_vec());
+    thresholds.truncate(MAX_THRESHOLD_SWEEP);
+    thresholds
+}
+
+/// Grid columns for `cells` cells: 2x2 for four, 3x3 for nine.
+fn sweep_columns(cells: usize) -> u32 {
+    (cells.max(1) as f32).sqrt().ceil() as u32
+}
+
+/// Renders image sticker `sticker_id` at each threshold and lays the previews out in one PNG, each
+/// under its threshold.
+async fn threshold_sweep_png(
+    state: &AppState,
+    user_id: i64,
+    sticker_id: i64,
+    thresholds: &[u8],
+) -> Result<Vec<u8>> {
+    let Some(mut sticker) = state.db.get_sticker_for_user(sticker_id, user_id).await? else {
+        bail!("стикер не найден");
+    };
+    if sticker.kind != StickerKind::Image {
+        bail!("сетка порогов есть только у картинок");
+    }
+    if thresholds.is_empty() {
+        bail!("в [image_sticker] threshold_sweep не задано ни одного порога");
+    }
+
+    let mut cells = Vec::with_capacity(thresholds.len());
+    for &threshold in thresholds {
+        sticker.threshold = threshold;
+        let mut req = image_render_request(state, &sticker, true)?;
+        req.params.preview_scale = Some(1);
+        let render = state.printerd.render_image(&req).await?;
+        let png = state.printerd.preview_png(&render).await?;
+        let preview = image::load_from_memory(&png)
+            .context("printerd returned an unreadable preview")?
+            .to_luma8();
+        cells.push((threshold, preview));
+    }
+
+    let cols = sweep_columns(cells.len());
+    let rows = (cells.len() as u32).div_ceil(cols);
+    // Wide enough for the label even if the image is a narrow strip.
+    let cell_w = cells
+        .iter()
+        .map(|(_, img)| img.width())
+        .max()
+        .unwrap_or(0)
+        .max(96);
+    let cell_h =
+        cells.iter().map(|(_, img)| img.height()).max().unwrap_or(0) + SWEEP_LABEL_HEIGHT_PX;
+    let mut grid = GrayImage::from_pixel(
+        cols * (cell_w + SWEEP_GAP_PX) + SWEEP_GAP_PX,
+        rows * (cell_h + SWEEP_GAP_PX) + SWEEP_GAP_PX,
+        Luma([160]),
+    );
+    let label_canvas = CanvasOptions {
+        width_px: cell_w,
+        height_px: SWEEP_LABEL_HEIGHT_PX,
+        line_spacing: 1.0,
+        threshold: 180,
+        invert: false,
+        border_px: 0,
+        border_margin_px: 0,
+        mirror_h: false,
+    };
+    for (i, (threshold, preview)) in cells.iter().enumerate() {
+        let x = SWEEP_GAP_PX + (i as u32 % cols) * (cell_w + SWEEP_GAP_PX);
+        let y = SWEEP_GAP_PX + (i as u32 / cols) * (cell_h + SWEEP_GAP_PX);
+        let label = TextBlock {
+            text: threshold.to_string(),
+            x_px: cell_w as i32 / 2,
+            y_px: 4,
+            font_size_px: 28.0,
+            align: TextAlign::Center,
+        };
+        let label = render_text_blocks(&state.fonts, &[label], &label_canvas)?;
+        imageops::overlay(&mut grid, &label, x as i64, y as i64);
+        imageops::overlay(
+            &mut grid,
+            preview,
+            x as i64,
+            (y + SWEEP_LABEL_HEIGHT_PX) as i64,
+        );
+    }
+
+    let mut png = Vec::new();
+    grid.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("failed to encode the threshold grid")?;
+    Ok(png)
+}
+
+/// Re-renders history sticker `sticker_id` with new text in the same mode and sends a new preview;
+/// the original stays in history.
+async fn edit_text_sticker(
+    bot: &Bot,
+    msg: &Message,
+    state: &AppState,
+    user_id: i64,
+    sticker_id: i64,
+    text: &str,
+) -> ResponseResult<()> {
+    let result = match state.db.get_sticker_for_user(sticker_id, user_id).await {
+        Ok(Some(original)) => {
+            create_text_sticker_with_settings(
+                state,
+                text_settings(&original, &state.cfg.sticker),
+                user_id,
+                msg.chat.id.0,
+                text,
+                original.kind,
+                None,
+            )
+            .await
+        }
+        Ok(None) => Err(anyhow!("стикер не найден")),
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(record) => {
+            info!(
+                user_id = user_id,
+                sticker_id = record.id,
+                edited_from = sticker_id,
+                "created edited text sticker preview"
+            );
+            bot.send_photo(
+                msg.chat.id,
+                InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+            )
+            .caption("Превью изменённого стикера.\nНажмите кнопку для печати.")
+            .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
+            .await?;
+        }
+        Err(err) => {
+            error!(user_id = user_id, error = %err, "failed to edit text sticker");
+            bot.send_message(msg.chat.id, format!("Ошибка рендера: {err}"))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The `[sticker]` settings `sticker` was made with, or `current` when it predates storing them.
+fn text_settings<'a>(sticker: &'a StickerRecord, current: &'a StickerConfig) -> &'a StickerConfig {
+    sticker.text_settings.as_ref().unwrap_or(current)
+}
+
+/// Pages of a long simple sticker: the text wrapped at `split_font_size_px` and cut into groups of
+/// lines. `None` when splitting is off or the regular layout keeps at least that font size.
+fn split_text_pages(cfg: &StickerConfig, fonts: &FontChain, text: &str) -> Option<Vec<String>> {
+    let split_size = cfg.split_font_size_px?;
+    // Rotated stickers grow along the tape instead of shrinking.
+    if cfg.rotate_90.is_some() {
+        return None;
+    }
+    let content_width = cfg
+        .printer_width_px
+        .saturating_sub(cfg.margin_left_px)
+        .saturating_sub(cfg.margin_right_px) as f32;
+    let content_height = cfg.fixed_height_px.map(|h| {
+        h.saturating_sub(cfg.margin_top_px)
+            .saturating_sub(cfg.margin_bottom_px) as f32
+    });
+
+    // The size `create_text_sticker` would end up with; fitting fails when even the minimum is
+    // too big, which calls for splitting too.
+    let regular_size = fit_font_size(
+        fonts,
+        text,
+        content_width,
+        cfg.min_font_size_px,
+        cfg.max_font_size_px,
+        cfg.line_spacing,
+    )
+    .and_then(|(size, _)| match content_height {
+        Some(height) => fit_font_size_by_height(
+            fonts,
+            text,
+            height,
+            cfg.min_font_size_px,
+            size,
+            cfg.line_spacing,
+        )
+        .map(|(size, _)| size),
+        None => Ok(size),
+    });
+    if regular_size.is_ok_and(|size| size >= split_size) {
+        return None;
+    }
+
+    let lines_per_page = match content_height {
+        Some(height) => {
+            let (_, line_h) = measure_text_block(fonts, "Ag", split_size, cfg.line_spacing);
+            ((height / line_h).floor() as usize).max(1)
+        }
+        None => cfg
+            .split_lines_per_page
+            .unwrap_or(DEFAULT_SPLIT_LINES_PER_PAGE),
+    };
+    let wrapped = wrap_text_to_width(fonts, text, split_size, content_width);
+    let lines: Vec<&str> = wrapped.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some(
+        lines
+            .chunks(lines_per_page)
+            .map(|page| page.join("\n"))
+            .collect(),
+    )
+}
+
+/// Sends a preview per page of a split text, then one button to print them all in order.
+async fn send_text_pages(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &AppState,
+    user_id: i64,
+    pages: Vec<String>,
+) -> ResponseResult<()> {
+    let total = pages.len();
+    if total > MAX_SPLIT_PAGES {
+        bot.send_message(
+            chat_id,
+            format!(
+                "Текст слишком длинный: получается стикеров {total}, максимум {MAX_SPLIT_PAGES}."
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // The pages were wrapped at this size, so they all fit it; fitting each page on its own would
+    // print a short last page larger than the rest.
+    let cfg = &state.cfg.sticker;
+    let mut ids = Vec::with_capacity(total);
+    for (idx, page) in pages.iter().enumerate() {
+        let created = create_text_sticker_with_settings(
+            state,
+            cfg,
+            user_id,
+            chat_id.0,
+            page,
+            StickerKind::Text,
+            cfg.split_font_size_px,
+        )
+        .await;
+        match created {
+            Ok(record) => {
+                let caption = if total > 1 {
+                    format!(
+                        "Стикер {}/{total}.\nШрифт: {:.1}px",
+                        idx + 1,
+                        record.font_size_px
+                    )
+                } else {
+                    format!(
+                        "Превью стикера.\nШрифт: {:.1}px\nНажмите кнопку для печати.",
+                        record.font_size_px
+                    )
+                };
+                bot.send_photo(
+                    chat_id,
+                    InputFile::memory(record.preview_png.clone()).file_name("preview.png"),
+                )
+                .caption(caption)
+                .reply_markup(print_keyboard(record.id, &state.cfg.printerd))
+                .await?;
+                ids.push(record.id);
+            }
+            Err(err) => {
+                error!(user_id = user_id, error = %err, "failed to create split text preview");
+                bot.send_message(chat_id, format!("Ошибка рендера: {err}"))
+                    .await?;
+                return Ok(());
+            }
+        }
+    }
+    info!(
+        user_id = user_id,
+        pages = total,
+        "created split text previews"
+    );
+    if total < 2 {
+        return Ok(());
+    }
+
+    let batch_id = ids[0];
+    {
+        let mut batches = state.page_batches.write().await;
+        batches.insert(
+            batch_id,
+            PageBatch {
+                user_id,
+                sticker_ids: ids,
+            },
+        );
+        while batches.len() > MAX_PAGE_BATCHES {
+            batches.pop_first();
+        }
     }
+    bot.send_message(
+        chat_id,
+        format!(
+            "Текст не помещается на один стикер и разбит на {total}. Напечатать все по порядку?"
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            format!("🖨 Напечатать все ({total})"),
+            format!("printpages:{batch_id}"),
+        ),
+    ]]))
+    .await?;
+    Ok(())
+}
 
-    let Ok(sticker_id) = id_str.parse::<i64>() else {
+/// Prints the pages of a split text one after another. After a failure the pages not printed yet
+/// stay in the batch, so "🔁 Повторить" carries on from there.
+async fn print_page_batch(
+    bot: &Bot,
+    q: &CallbackQuery,
+    state: &AppState,
+    user_id: i64,
+    batch_id: &str,
+) -> ResponseResult<()> {
+    let Ok(batch_id) = batch_id.parse::<i64>() else {
         return Ok(());
     };
-
-    if action == "delete" {
-        let result = state.db.delete_sticker_for_user(sticker_id, user_id).await;
-        match result {
-            Ok(true) => {
+    // Taken out while printing, so a second tap does not print the pages twice.
+    let ids = {
+        let mut batches = state.page_batches.write().await;
+        match batches.get(&batch_id) {
+            Some(batch) if batch.user_id != user_id => None,
+            Some(_) => batches.remove(&batch_id).map(|batch| batch.sticker_ids),
+            None => {
+                drop(batches);
                 bot.answer_callback_query(q.id.clone())
-                    .text("Удалено из истории")
-                    .await?;
-                if let Some(message) = q.message {
-                    let _ = bot
-                        .edit_message_reply_markup(message.chat().id, message.id())
-                        .reply_markup(InlineKeyboardMarkup::default())
-                        .await;
-                }
-            }
-            Ok(false) => {
-                bot.answer_callback_query(q.id)
                     .show_alert(true)
-                    .text("Не найдено")
+                    .text("Уже напечатано или печатается")
                     .await?;
+                return Ok(());
             }
+        }
+    };
+    let Some(ids) = ids else {
+        bot.answer_callback_query(q.id.clone())
+            .show_alert(true)
+            .text("Это не ваши стикеры")
+            .await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(q.id.clone())
+        .text(format!("Печатаю стикеров: {}", ids.len()))
+        .await?;
+
+    let total = ids.len();
+    let mut printed = 0;
+    let mut failure = None;
+    for &sticker_id in &ids {
+        match process_print_action(state, user_id, sticker_id, 1, PrintTarget::Default).await {
+            Ok(_) => printed += 1,
             Err(err) => {
-                bot.answer_callback_query(q.id)
-                    .show_alert(true)
-                    .text(format!("Ошибка удаления: {err}"))
-                    .await?;
+                failure = Some(err);
+                break;
             }
         }
-        return Ok(());
     }
 
-    let result = process_print_action(&state, user_id, sticker_id).await;
-
-    match result {
-        Ok(job_id) => {
-            bot.answer_callback_query(q.id.clone())
-                .text(format!("Задание отправлено: {job_id}"))
-                .await?;
-            if let Some(message) = q.message {
-                let _ = bot
-                    .edit_message_reply_markup(message.chat().id, message.id())
-                    .reply_markup(history_item_keyboard(sticker_id))
-                    .await;
-            }
+    let Some(message) = q.message.as_ref() else {
+        return Ok(());
+    };
+    match failure {
+        None => {
+            info!(
+                user_id = user_id,
+                batch_id = batch_id,
+                pages = total,
+                "printed split text"
+            );
+            let _ = bot
+                .edit_message_text(
+                    message.chat().id,
+                    message.id(),
+                    format!("Напечатаны все стикеры: {total}."),
+                )
+                .reply_markup(InlineKeyboardMarkup::default())
+                .await;
         }
-        Err(err) => {
-            bot.answer_callback_query(q.id)
-                .show_alert(true)
-                .text(format!("Ошибка печати: {err}"))
-                .await?;
+        Some(err) => {
+            warn!(user_id = user_id, batch_id = batch_id, printed = printed, error = %err, "split text print failed");
+            state.page_batches.write().await.insert(
+                batch_id,
+                PageBatch {
+                    user_id,
+                    sticker_ids: ids[printed..].to_vec(),
+                },
+            );
+            let _ = bot
+                .edit_message_text(
+                    message.chat().id,
+                    message.id(),
+                    format!(
+                        "Напечатано {printed} из {total}. Ошибка печати: {err}\n\n\
+                         Когда принтер будет готов, нажмите «🔁 Повторить» — печать продолжится \
+                         с {}-го стикера.",
+                        printed + 1
+                    ),
+                )
+                .reply_markup(retry_keyboard("printpages", batch_id))
+                .await;
         }
     }
-
     Ok(())
 }
 
@@ -928,27 +2388,51 @@ async fn create_text_sticker(
     text: &str,
     kind: StickerKind,
 ) -> Result<StickerRecord> {
-    let cfg = &state.cfg.sticker;
+    create_text_sticker_with_settings(
+        state,
+        &state.cfg.sticker,
+        user_id,
+        chat_id,
+        text,
+        kind,
+        None,
+    )
+    .await
+}
+
+/// Lays out and renders a text sticker with `cfg` in place of the current `[sticker]` settings,
+/// and stores them with it. `font_size_px` is used as is instead of fitting the text, for the
+/// pages of a split text; only simple stickers take it.
+async fn create_text_sticker_with_settings(
+    state: &AppState,
+    cfg: &StickerConfig,
+    user_id: i64,
+    chat_id: i64,
+    text: &str,
+    kind: StickerKind,
+    font_size_px: Option<f32>,
+) -> Result<StickerRecord> {
     let is_banner = matches!(kind, StickerKind::TextBanner | StickerKind::TextBannerOutline);
     let outline_only = matches!(kind, StickerKind::TextOutline | StickerKind::TextBannerOutline);
 
-    let (width_px, height_px, x_px, y_px, font_size) = if is_banner {
+    // Rotated stickers run along the tape like banners, so they share the banner layout.
+    let (width_px, height_px, x_px, y_px, font_size) = if is_banner || cfg.rotate_90.is_some() {
         let content_height = cfg
             .printer_width_px
             .saturating_sub(cfg.margin_top_px)
             .saturating_sub(cfg.margin_bottom_px);
         if content_height < 12 {
-            bail!("configured margins leave no content height for banner mode");
+            bail!("configured margins leave no content height for a rotated sticker");
         }
         let (font_size, _) = fit_font_size_by_height(
-            &state.font,
+            &state.fonts,
             text,
             content_height as f32,
             cfg.min_font_size_px,
             cfg.max_font_size_px,
             cfg.line_spacing,
         )?;
-        let (text_width, text_height) = measure_text_block(&state.font, text, font_size, cfg.line_spacing);
+        let (text_width, text_height) = measure_text_block(&state.fonts, text, font_size, cfg.line_spacing);
         let width_px = (cfg.margin_left_px + cfg.margin_right_px + text_width.ceil() as u32 + 2).max(16);
         let y_px = cfg.margin_top_px as i32
             + ((content_height as i32 - text_height.ceil() as i32).max(0) / 2);
@@ -968,47 +2452,101 @@ async fn create_text_sticker(
             bail!("configured margins leave no content width");
         }
 
-        let (font_size, text_height) = fit_font_size(
-            &state.font,
-            text,
-            content_width as f32,
-            cfg.min_font_size_px,
-            cfg.max_font_size_px,
-            cfg.line_spacing,
-        )?;
+        // Width stays the hard limit; the height cap only trims what width alone would allow, and
+        // gives way to text that is taller than the cap even at the minimum size.
+        let max_text_height = cfg.max_content_height_px.map_or(f32::INFINITY, |cap| {
+            let (_, min_h) =
+                measure_text_block(&state.fonts, text, cfg.min_font_size_px, cfg.line_spacing);
+            (cap as f32).max(min_h)
+        });
+        let (font_size, text_height) = match font_size_px {
+            Some(size) => {
+                let (_, height) = measure_text_block(&state.fonts, text, size, cfg.line_spacing);
+                (size, height)
+            }
+            None => fit_font_size_in_box(
+                &state.fonts,
+                text,
+                content_width as f32,
+                max_text_height,
+                cfg.min_font_size_px,
+                cfg.max_font_size_px,
+                cfg.line_spacing,
+            )?,
+        };
 
-        let height_px =
-            (cfg.margin_top_px + cfg.margin_bottom_px + text_height.ceil() as u32 + 2).max(16);
-        (
-            cfg.printer_width_px,
-            height_px,
-            cfg.margin_left_px as i32,
-            cfg.margin_top_px as i32,
-            font_size,
-        )
+        if let Some(fixed_height) = cfg.fixed_height_px {
+            let content_height = fixed_height
+                .saturating_sub(cfg.margin_top_px)
+                .saturating_sub(cfg.margin_bottom_px);
+            if content_height < 12 {
+                bail!("fixed_height_px leaves no content height after margins");
+            }
+            // Width already capped the size; now grow or shrink it to fill the label height.
+            let (font_size, text_height) = match font_size_px {
+                Some(_) => (font_size, text_height),
+                None => fit_font_size_by_height(
+                    &state.fonts,
+                    text,
+                    content_height as f32,
+                    cfg.min_font_size_px,
+                    font_size,
+                    cfg.line_spacing,
+                )?,
+            };
+            let y_px = cfg.margin_top_px as i32
+                + ((content_height as i32 - text_height.ceil() as i32).max(0) / 2);
+            (
+                cfg.printer_width_px,
+                fixed_height,
+                cfg.margin_left_px as i32,
+                y_px,
+                font_size,
+            )
+        } else {
+            let height_px =
+                (cfg.margin_top_px + cfg.margin_bottom_px + text_height.ceil() as u32 + 2).max(16);
+            (
+                cfg.printer_width_px,
+                height_px,
+                cfg.margin_left_px as i32,
+                cfg.margin_top_px as i32,
+                font_size,
+            )
+        }
     };
 
+    // Trimming would undo the fixed label height.
+    let trim_blank_top_bottom = cfg.trim_blank_top_bottom && cfg.fixed_height_px.is_none();
     let req = RenderTextRequest {
         text: text.to_string(),
-        font_path: cfg.font_path.clone(),
-        width_px,
-        height_px,
-        x_px,
-        y_px,
-        font_size_px: font_size,
-        line_spacing: cfg.line_spacing,
-        threshold: cfg.threshold,
-        invert: cfg.invert,
-        trim_blank_top_bottom: cfg.trim_blank_top_bottom,
-        outline_only,
-        outline_thickness_px: 1,
-        banner_mode: is_banner,
-        density: cfg.density,
+        fonts: font_selection(cfg),
+        width_px: Some(width_px),
+        height_px: Some(height_px),
+        x_px: Some(x_px),
+        y_px: Some(y_px),
+        font_size_px: Some(font_size),
+        line_spacing: Some(cfg.line_spacing),
+        threshold: Some(cfg.threshold),
+        invert: Some(cfg.invert),
+        invert_content_only: Some(cfg.invert_content_only),
+        trim_blank_top_bottom: Some(trim_blank_top_bottom),
+        outline_only: Some(outline_only),
+        outline_thickness_px: Some(1),
+        banner_mode: Some(is_banner),
+        rotate_90: cfg.rotate_90.map(|r| r.as_str().to_string()),
+        background: cfg.background.map(|b| b.as_str().to_string()),
+        border_px: Some(cfg.border_px),
+        border_margin_px: Some(cfg.border_margin_px),
+        density: Some(cfg.density),
         address: state.cfg.printerd.address.clone(),
+        // Printing re-renders from history, so the preview render need not be kept.
+        ephemeral: Some(true),
+        ..Default::default()
     };
 
     let render = state.printerd.render_text(&req).await?;
-    let preview_png = state.printerd.get_preview(&render.preview_url).await?;
+    let preview_png = state.printerd.preview_png(&render).await?;
 
     let id = state
         .db
@@ -1017,18 +2555,20 @@ async fn create_text_sticker(
             chat_id,
             kind,
             text: text.to_string(),
-            width_px: req.width_px,
-            height_px: req.height_px,
-            x_px: req.x_px,
-            y_px: req.y_px,
-            font_size_px: req.font_size_px,
-            threshold: req.threshold,
-            invert: req.invert,
-            trim_blank_top_bottom: req.trim_blank_top_bottom,
-            density: req.density,
+            width_px,
+            height_px,
+            x_px,
+            y_px,
+            font_size_px: font_size,
+            threshold: cfg.threshold,
+            invert: cfg.invert,
+            trim_blank_top_bottom,
+            density: cfg.density,
             dither_method: None,
+            rotate_90: cfg.rotate_90,
             source_image_bytes: None,
             preview_png: preview_png.clone(),
+            text_settings: Some(cfg.clone()),
         })
         .await?;
 
@@ -1036,19 +2576,23 @@ async fn create_text_sticker(
         id,
         kind,
         text: text.to_string(),
-        width_px: req.width_px,
-        height_px: req.height_px,
-        x_px: req.x_px,
-        y_px: req.y_px,
-        font_size_px: req.font_size_px,
-        threshold: req.threshold,
-        invert: req.invert,
-        trim_blank_top_bottom: req.trim_blank_top_bottom,
-        density: req.density,
+        width_px,
+        height_px,
+        x_px,
+        y_px,
+        font_size_px: font_size,
+        threshold: cfg.threshold,
+        invert: cfg.invert,
+        trim_blank_top_bottom,
+        density: cfg.density,
         dither_method: None,
+        rotate_90: cfg.rotate_90,
         source_image_bytes: None,
         preview_png,
+        text_settings: Some(cfg.clone()),
+        saved: false,
         created_at: "now".to_string(),
+        estimated_seconds: render.estimated_seconds,
     })
 }
 
@@ -1059,21 +2603,64 @@ async fn create_image_sticker(
     chat_id: i64,
     photo: &teloxide::types::PhotoSize,
 ) -> Result<StickerRecord> {
+    let max_bytes = state
+        .cfg
+        .image_sticker
+        .max_download_bytes
+        .unwrap_or(DEFAULT_MAX_DOWNLOAD_BYTES);
+    if u64::from(photo.file.size) > max_bytes {
+        bail!(file_too_large(max_bytes));
+    }
     let file = bot
         .get_file(photo.file.id.clone())
         .await
         .context("failed to get telegram file metadata")?;
-    let file_url = format!(
+    let bytes = download_telegram_file(state, &file.path, max_bytes).await?;
+    create_image_sticker_from_bytes(state, user_id, chat_id, "Изображение", bytes).await
+}
+
+/// Downloads a file from Telegram, refusing bodies over `max_bytes` without reading them whole.
+///
+/// The file URL embeds the bot token, so it is stripped from every error.
+async fn download_telegram_file(state: &AppState, path: &str, max_bytes: u64) -> Result<Vec<u8>> {
+    let url = format!(
         "https://api.telegram.org/file/bot{}/{}",
-        state.cfg.telegram_token, file.path
+        state.cfg.telegram_token, path
     );
-    let bytes = reqwest::get(file_url)
+    let mut resp = state
+        .downloads
+        .get(&url)
+        .send()
         .await
-        .context("failed to download telegram image")?
-        .bytes()
+        .map_err(reqwest::Error::without_url)
+        .context("failed to download telegram file")?;
+    if !resp.status().is_success() {
+        bail!("telegram file download failed with {}", resp.status());
+    }
+    if resp.content_length().is_some_and(|len| len > max_bytes) {
+        bail!(file_too_large(max_bytes));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
         .await
-        .context("failed to read telegram image body")?;
-    create_image_sticker_from_bytes(state, user_id, chat_id, "Изображение", bytes.to_vec()).await
+        .map_err(reqwest::Error::without_url)
+        .context("failed to read telegram file")?
+    {
+        if (body.len() + chunk.len()) as u64 > max_bytes {
+            bail!(file_too_large(max_bytes));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+fn file_too_large(max_bytes: u64) -> String {
+    format!(
+        "файл слишком большой, максимум {:.1} МБ",
+        max_bytes as f64 / (1024.0 * 1024.0)
+    )
 }
 
 async fn create_ai_image_sticker(
@@ -1142,6 +2729,7 @@ async fn create_image_sticker_from_bytes(
     .await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn create_image_sticker_from_bytes_with_options(
     state: &AppState,
     user_id: i64,
@@ -1155,18 +2743,27 @@ async fn create_image_sticker_from_bytes_with_options(
     let image_cfg = &state.cfg.image_sticker;
     let req = RenderImageRequest {
         image_base64: base64::engine::general_purpose::STANDARD.encode(&source),
-        width_px: state.cfg.sticker.printer_width_px,
-        max_height_px: None,
-        threshold,
-        dither_method,
-        invert,
-        trim_blank_top_bottom: image_cfg.trim_blank_top_bottom,
-        density: image_cfg.density,
-        address: state.cfg.printerd.address.clone(),
+        keep_source: None,
+        params: ImageRenderParams {
+            auto_orient: image_cfg.auto_orient,
+            width_px: Some(state.cfg.sticker.printer_width_px),
+            resize_filter: image_cfg.resize_filter,
+            sharpen: image_cfg.sharpen,
+            threshold: Some(threshold),
+            dither_method: Some(dither_method),
+            invert: Some(invert),
+            trim_blank_top_bottom: Some(image_cfg.trim_blank_top_bottom),
+            border_px: Some(image_cfg.border_px),
+            border_margin_px: Some(image_cfg.border_margin_px),
+            density: Some(image_cfg.density),
+            address: state.cfg.printerd.address.clone(),
+            ephemeral: Some(true),
+            ..Default::default()
+        },
     };
 
     let render = state.printerd.render_image(&req).await?;
-    let preview_png = state.printerd.get_preview(&render.preview_url).await?;
+    let preview_png = state.printerd.preview_png(&render).await?;
 
     let id = state
         .db
@@ -1180,13 +2777,15 @@ async fn create_image_sticker_from_bytes_with_options(
             x_px: 0,
             y_px: 0,
             font_size_px: 0.0,
-            threshold: req.threshold,
-            invert: req.invert,
-            trim_blank_top_bottom: req.trim_blank_top_bottom,
-            density: req.density,
-            dither_method: Some(req.dither_method),
+            threshold,
+            invert,
+            trim_blank_top_bottom: image_cfg.trim_blank_top_bottom,
+            density: image_cfg.density,
+            dither_method: Some(dither_method),
+            rotate_90: None,
             source_image_bytes: Some(source.clone()),
             preview_png: preview_png.clone(),
+            text_settings: None,
         })
         .await?;
 
@@ -1199,18 +2798,99 @@ async fn create_image_sticker_from_bytes_with_options(
         x_px: 0,
         y_px: 0,
         font_size_px: 0.0,
-        threshold: req.threshold,
-        invert: req.invert,
-        trim_blank_top_bottom: req.trim_blank_top_bottom,
-        density: req.density,
-        dither_method: Some(req.dither_method),
+        threshold,
+        invert,
+        trim_blank_top_bottom: image_cfg.trim_blank_top_bottom,
+        density: image_cfg.density,
+        dither_method: Some(dither_method),
+        rotate_90: None,
         source_image_bytes: Some(source),
         preview_png,
+        text_settings: None,
+        saved: false,
+        created_at: "now".to_string(),
+        estimated_seconds: render.estimated_seconds,
+    })
+}
+
+/// Renders `payload` as a QR code and keeps it in history as a `kind` sticker with `text`. A
+/// `WifiQr` sticker is stored without its preview, which would show the password to `/history`.
+async fn create_qr_sticker(
+    state: &AppState,
+    user_id: i64,
+    chat_id: i64,
+    payload: &str,
+    kind: StickerKind,
+    text: &str,
+) -> Result<StickerRecord> {
+    let req = RenderQrRequest {
+        data: payload.to_string(),
+        density: state.cfg.sticker.density,
+        address: state.cfg.printerd.address.clone(),
+        ephemeral: true,
+    };
+    let render = state.printerd.render_qr(&req).await?;
+    let preview_png = state.printerd.preview_png(&render).await?;
+
+    let id = state
+        .db
+        .insert_sticker(NewSticker {
+            user_id,
+            chat_id,
+            kind,
+            text: text.to_string(),
+            width_px: render.width_px,
+            height_px: render.height_px,
+            x_px: 0,
+            y_px: 0,
+            font_size_px: 0.0,
+            threshold: 127,
+            invert: false,
+            trim_blank_top_bottom: true,
+            density: req.density,
+            dither_method: None,
+            rotate_90: None,
+            source_image_bytes: None,
+            preview_png: if kind == StickerKind::WifiQr {
+                Vec::new()
+            } else {
+                preview_png.clone()
+            },
+            text_settings: None,
+        })
+        .await?;
+
+    Ok(StickerRecord {
+        id,
+        kind,
+        text: text.to_string(),
+        width_px: render.width_px,
+        height_px: render.height_px,
+        x_px: 0,
+        y_px: 0,
+        font_size_px: 0.0,
+        threshold: 127,
+        invert: false,
+        trim_blank_top_bottom: true,
+        density: req.density,
+        dither_method: None,
+        rotate_90: None,
+        source_image_bytes: None,
+        preview_png,
+        text_settings: None,
+        saved: false,
         created_at: "now".to_string(),
+        estimated_seconds: render.estimated_seconds,
     })
 }
 
-async fn process_print_action(state: &AppState, user_id: i64, sticker_id: i64) -> Result<String> {
+async fn process_print_action(
+    state: &AppState,
+    user_id: i64,
+    sticker_id: i64,
+    copies: u8,
+    target: PrintTarget,
+) -> Result<(String, StickerKind)> {
     let Some(sticker) = state.db.get_sticker_for_user(sticker_id, user_id).await? else {
         bail!("стикер не найден");
     };
@@ -1228,150 +2908,222 @@ async fn process_print_action(state: &AppState, user_id: i64, sticker_id: i64) -
                 sticker.kind,
                 StickerKind::TextBanner | StickerKind::TextBannerOutline
             );
+            let cfg = text_settings(&sticker, &state.cfg.sticker);
             let req = RenderTextRequest {
                 text: sticker.text.clone(),
-                font_path: state.cfg.sticker.font_path.clone(),
-                width_px: sticker.width_px,
-                height_px: sticker.height_px,
-                x_px: sticker.x_px,
-                y_px: sticker.y_px,
-                font_size_px: sticker.font_size_px,
-                line_spacing: state.cfg.sticker.line_spacing,
-                threshold: sticker.threshold,
-                invert: sticker.invert,
-                trim_blank_top_bottom: sticker.trim_blank_top_bottom,
-                outline_only,
-                outline_thickness_px: 1,
-                banner_mode,
-                density: sticker.density,
+                fonts: font_selection(cfg),
+                width_px: Some(sticker.width_px),
+                height_px: Some(sticker.height_px),
+                x_px: Some(sticker.x_px),
+                y_px: Some(sticker.y_px),
+                font_size_px: Some(sticker.font_size_px),
+                line_spacing: Some(cfg.line_spacing),
+                threshold: Some(sticker.threshold),
+                invert: Some(sticker.invert),
+                invert_content_only: Some(cfg.invert_content_only),
+                trim_blank_top_bottom: Some(sticker.trim_blank_top_bottom),
+                outline_only: Some(outline_only),
+                outline_thickness_px: Some(1),
+                banner_mode: Some(banner_mode),
+                rotate_90: sticker.rotate_90.map(|r| r.as_str().to_string()),
+                background: cfg.background.map(|b| b.as_str().to_string()),
+                border_px: Some(cfg.border_px),
+                border_margin_px: Some(cfg.border_margin_px),
+                density: Some(sticker.density),
                 address: state.cfg.printerd.address.clone(),
+                ephemeral: Some(false),
+                ..Default::default()
             };
             state.printerd.render_text(&req).await?
         }
         StickerKind::Image => {
-            let source = sticker
-                .source_image_bytes
-                .clone()
-                .ok_or_else(|| anyhow!("missing source image in history"))?;
-            let req = RenderImageRequest {
-                image_base64: base64::engine::general_purpose::STANDARD.encode(source),
-                width_px: sticker.width_px.max(1),
-                max_height_px: Some(sticker.height_px.max(1)),
-                threshold: sticker.threshold,
-                dither_method: sticker
-                    .dither_method
-                    .unwrap_or(DitherMethod::FloydSteinberg),
-                invert: sticker.invert,
-                trim_blank_top_bottom: sticker.trim_blank_top_bottom,
+            let req = image_render_request(state, &sticker, false)?;
+            state.printerd.render_image(&req).await?
+        }
+        StickerKind::Qr | StickerKind::WifiQr => {
+            let data = if sticker.kind == StickerKind::WifiQr {
+                let payloads = state.wifi_payloads.read().await;
+                let Some(payload) = payloads.get(&sticker.id) else {
+                    bail!("пароль Wi-Fi не хранится в истории, отправьте /wifi ещё раз");
+                };
+                payload.clone()
+            } else {
+                sticker.text.clone()
+            };
+            let req = RenderQrRequest {
+                data,
                 density: sticker.density,
                 address: state.cfg.printerd.address.clone(),
+                ephemeral: false,
             };
-            state.printerd.render_image(&req).await?
+            state.printerd.render_qr(&req).await?
+        }
+    };
+    let render_id = render
+        .render_id
+        .context("printerd did not keep the render to print")?;
+    let (job_ids, shown) = match target {
+        PrintTarget::Default => {
+            let address = match state.db.printer_for_user(user_id).await? {
+                Some(name) => Some(name),
+                None => state.cfg.printerd.address.clone(),
+            };
+            let print_resp = state
+                .printerd
+                .print_render(&render_id, sticker.density, copies, address)
+                .await?;
+            wait_for_print(state, &print_resp.job_id, copies).await?;
+            (vec![print_resp.job_id.clone()], print_resp.job_id)
+        }
+        PrintTarget::AllPrinters => {
+            print_on_all_printers(state, &render_id, sticker.density, copies).await?
         }
     };
-    let print_resp = state
-        .printerd
-        .print_render(
-            &render.render_id,
-            sticker.density,
-            state.cfg.printerd.address.clone(),
-        )
-        .await?;
-
-    let wait_timeout = state.cfg.printerd.wait_job_timeout_seconds.unwrap_or(20);
-    let job = state
-        .printerd
-        .wait_job(&print_resp.job_id, wait_timeout)
-        .await?;
-    if job.status == "failed" {
-        bail!(
-            "принтер вернул ошибку: {}",
-            job.error.unwrap_or_else(|| "unknown".to_string())
-        );
-    }
-    if job.status != "done" {
-        bail!("печать не завершилась вовремя, статус: {}", job.status);
-    }
 
-    state
-        .db
-        .set_last_print_job(sticker_id, &print_resp.job_id)
-        .await?;
+    state.db.set_last_print_job(sticker_id, &job_ids).await?;
 
     info!(
         user_id = user_id,
         sticker_id = sticker_id,
-        job_id = %print_resp.job_id,
+        job_ids = ?job_ids,
         "sticker printed"
     );
 
-    Ok(print_resp.job_id)
+    Ok((shown, sticker.kind))
 }
 
-fn fit_font_size(
-    font: &FontArc,
-    text: &str,
-    max_width: f32,
-    min_size: f32,
-    max_size: f32,
-    line_spacing: f32,
-) -> Result<(f32, f32)> {
-    if min_size <= 0.0 || max_size <= 0.0 || min_size > max_size {
-        bail!("invalid font size bounds");
-    }
-
-    let mut lo = min_size;
-    let mut hi = max_size;
+/// Re-render request for an image sticker from history, with the settings it was made with.
+fn image_render_request(
+    state: &AppState,
+    sticker: &StickerRecord,
+    ephemeral: bool,
+) -> Result<RenderImageRequest> {
+    let source = sticker
+        .source_image_bytes
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing source image in history"))?;
+    Ok(RenderImageRequest {
+        image_base64: base64::engine::general_purpose::STANDARD.encode(source),
+        keep_source: None,
+        params: ImageRenderParams {
+            auto_orient: state.cfg.image_sticker.auto_orient,
+            width_px: Some(sticker.width_px.max(1)),
+            max_height_px: Some(sticker.height_px.max(1)),
+            resize_filter: state.cfg.image_sticker.resize_filter,
+            sharpen: state.cfg.image_sticker.sharpen,
+            threshold: Some(sticker.threshold),
+            dither_method: Some(
+                sticker
+                    .dither_method
+                    .unwrap_or(DitherMethod::FloydSteinberg),
+            ),
+            invert: Some(sticker.invert),
+            trim_blank_top_bottom: Some(sticker.trim_blank_top_bottom),
+            border_px: Some(state.cfg.image_sticker.border_px),
+            border_margin_px: Some(state.cfg.image_sticker.border_margin_px),
+            density: Some(sticker.density),
+            address: state.cfg.printerd.address.clone(),
+            ephemeral: Some(ephemeral),
+            ..Default::default()
+        },
+    })
+}
 
-    let (min_w, min_h) = measure_text_block(font, text, min_size, line_spacing);
-    if min_w > max_width {
-        bail!("text is too wide even at minimum font size {:.1}", min_size);
+/// Waits for a queued job and turns anything but `done` into an error for the chat.
+///
+/// A `/wait` call that ends with the job still queued or printing is repeated until
+/// `wait_job_deadline_seconds` runs out, so a slow print is not reported as stuck.
+async fn wait_for_print(state: &AppState, job_id: &str, copies: u8) -> Result<()> {
+    let cfg = &state.cfg.printerd;
+    let wait_timeout = cfg.wait_job_timeout_seconds.unwrap_or(20) * copies as u64;
+    let budget = cfg
+        .wait_job_deadline_seconds
+        .unwrap_or(DEFAULT_WAIT_JOB_DEADLINE_SECONDS)
+        * copies as u64;
+    let deadline = Instant::now() + Duration::from_secs(budget.max(wait_timeout));
+    let job = poll_job(job_id, wait_timeout, deadline, |timeout| {
+        state.printerd.wait_job(job_id, timeout)
+    })
+    .await?;
+    if job.status == "failed" {
+        bail!(
+            "принтер вернул ошибку: {}",
+            job.error.unwrap_or_else(|| "unknown".to_string())
+        );
+    }
+    if job.status != "done" && job.note.as_deref() == Some("printer paused") {
+        bail!("принтер на паузе: проверьте крышку и бумагу, печать продолжится сама");
     }
+    if job.status != "done" {
+        bail!("печать не завершилась вовремя, статус: {}", job.status);
+    }
+    Ok(())
+}
 
-    for _ in 0..24 {
-        let mid = (lo + hi) / 2.0;
-        let (w, _) = measure_text_block(font, text, mid, line_spacing);
-        if w <= max_width {
-            lo = mid;
-        } else {
-            hi = mid;
+/// The `/wait` loop of [`wait_for_print`]: calls `wait` with up to `wait_timeout` seconds, never past
+/// `deadline`, until the job is no longer queued or printing, the printer pauses or time runs out.
+async fn poll_job<F, Fut>(
+    job_id: &str,
+    wait_timeout: u64,
+    deadline: Instant,
+    mut wait: F,
+) -> Result<JobResponse>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = Result<JobResponse>>,
+{
+    loop {
+        // A zero-second last stretch is fine: `wait_job` raises it to printerd's minimum.
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let job = wait(wait_timeout.min(remaining.as_secs())).await?;
+        if !matches!(job.status.as_str(), "queued" | "printing") || Instant::now() >= deadline {
+            return Ok(job);
         }
+        // A pause needs someone at the printer; say so now rather than after the whole budget.
+        if job.note.as_deref() == Some("printer paused") {
+            return Ok(job);
+        }
+        info!(job_id = %job_id, status = %job.status, "print job still running, waiting again");
     }
-
-    let (_, h) = measure_text_block(font, text, lo, line_spacing);
-    Ok((lo, h.max(min_h)))
 }
 
-fn fit_font_size_by_height(
-    font: &FontArc,
-    text: &str,
-    max_height: f32,
-    min_size: f32,
-    max_size: f32,
-    line_spacing: f32,
-) -> Result<(f32, f32)> {
-    if min_size <= 0.0 || max_size <= 0.0 || min_size > max_size {
-        bail!("invalid font size bounds");
-    }
+/// Prints on every `fanout_addresses` printer. Fails only when none of them printed; otherwise
+/// returns the printed job ids and, for the user, those ids followed by the printers that failed.
+async fn print_on_all_printers(
+    state: &AppState,
+    render_id: &str,
+    density: u8,
+    copies: u8,
+) -> Result<(Vec<String>, String)> {
+    let addresses = &state.cfg.printerd.fanout_addresses;
+    let resp = state
+        .printerd
+        .print_fanout(render_id, addresses, density, copies)
+        .await?;
 
-    let (_, min_h) = measure_text_block(font, text, min_size, line_spacing);
-    if min_h > max_height {
-        bail!("text is too tall even at minimum font size {:.1}", min_size);
+    // The printers work in parallel, so waiting in turn costs no more than the slowest one.
+    let mut printed = Vec::new();
+    let mut failed = Vec::new();
+    for job in resp.jobs {
+        let Some(job_id) = job.job_id else {
+            let reason = job.error.as_deref().unwrap_or("задание не создано");
+            failed.push(format!("{}: {reason}", job.address));
+            continue;
+        };
+        match wait_for_print(state, &job_id, copies).await {
+            Ok(()) => printed.push(job_id),
+            Err(err) => failed.push(format!("{}: {err:#}", job.address)),
+        }
     }
 
-    let mut lo = min_size;
-    let mut hi = max_size;
-    for _ in 0..24 {
-        let mid = (lo + hi) / 2.0;
-        let (_, h) = measure_text_block(font, text, mid, line_spacing);
-        if h <= max_height {
-            lo = mid;
-        } else {
-            hi = mid;
-        }
+    if printed.is_empty() {
+        bail!("ни один принтер не напечатал: {}", failed.join("; "));
+    }
+    let mut summary = printed.join(", ");
+    if !failed.is_empty() {
+        summary.push_str(&format!(" (не напечатано: {})", failed.join("; ")));
     }
-    let (_, h) = measure_text_block(font, text, lo, line_spacing);
-    Ok((lo, h))
+    Ok((printed, summary))
 }
 
 fn build_ai_lineart_prompt(user_prompt: &str) -> String {
@@ -1384,53 +3136,100 @@ Centered composition with clear silhouette. Subject: {}",
     )
 }
 
-fn measure_text_block(font: &FontArc, text: &str, font_size: f32, line_spacing: f32) -> (f32, f32) {
-    let scale = PxScale::from(font_size);
-    let scaled = font.as_scaled(scale);
+fn print_keyboard(sticker_id: i64, printerd: &PrinterdConfig) -> InlineKeyboardMarkup {
+    let mut second_row = vec![InlineKeyboardButton::callback(
+        "💾 Сохранить",
+        format!("save:{sticker_id}"),
+    )];
+    if printerd.fanout_enabled() {
+        second_row.push(all_printers_button(sticker_id));
+    }
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            InlineKeyboardButton::callback("Печатать", format!("print:{sticker_id}")),
+            InlineKeyboardButton::callback("×5", format!("print5:{sticker_id}")),
+        ],
+        second_row,
+    ])
+}
 
-    let lines: Vec<&str> = text.split('\n').collect();
-    let mut max_width = 0.0f32;
+/// [`print_keyboard`] plus the threshold grid, for image stickers.
+fn image_preview_keyboard(sticker_id: i64, printerd: &PrinterdConfig) -> InlineKeyboardMarkup {
+    print_keyboard(sticker_id, printerd).append_row(vec![InlineKeyboardButton::callback(
+        "🎚 Сетка порогов",
+        format!("sweep:{sticker_id}"),
+    )])
+}
 
-    for line in &lines {
-        let mut width = 0.0f32;
-        let mut prev = None;
-        for ch in line.chars() {
-            let gid = scaled.glyph_id(ch);
-            if let Some(pg) = prev {
-                width += scaled.kern(pg, gid);
-            }
-            width += scaled.h_advance(gid);
-            prev = Some(gid);
-        }
-        if width > max_width {
-            max_width = width;
-        }
+fn history_item_keyboard(
+    sticker_id: i64,
+    kind: StickerKind,
+    printerd: &PrinterdConfig,
+) -> InlineKeyboardMarkup {
+    let mut rows = vec![vec![
+        InlineKeyboardButton::callback("Напечатать ещё раз", format!("reprint:{sticker_id}")),
+        InlineKeyboardButton::callback("×5", format!("print5:{sticker_id}")),
+    ]];
+    if printerd.fanout_enabled() {
+        rows.push(vec![all_printers_button(sticker_id)]);
     }
+    if kind.is_text() {
+        rows.push(vec![InlineKeyboardButton::callback(
+            "✏️ Изменить текст",
+            format!("edit:{sticker_id}"),
+        )]);
+    }
+    rows.push(vec![InlineKeyboardButton::callback(
+        "Удалить из истории",
+        format!("delete:{sticker_id}"),
+    )]);
+    InlineKeyboardMarkup::new(rows)
+}
 
-    let line_h = (scaled.ascent() - scaled.descent() + scaled.line_gap()).max(1.0) * line_spacing;
-    let total_h = line_h * lines.len().max(1) as f32;
-
-    (max_width, total_h)
+/// Caption line with printerd's rough print time, empty when it gave none.
+fn print_time_hint(record: &StickerRecord) -> String {
+    match record.estimated_seconds {
+        Some(secs) => format!("\n≈ {} с печати", secs.round().max(1.0)),
+        None => String::new(),
+    }
 }
 
-fn print_keyboard(sticker_id: i64) -> InlineKeyboardMarkup {
+/// Shown after a failed print: repeats the same print `action` on the sticker.
+fn retry_keyboard(action: &str, sticker_id: i64) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
-        "Печатать",
-        format!("print:{sticker_id}"),
+        "🔁 Повторить",
+        format!("{action}:{sticker_id}"),
     )]])
 }
 
-fn history_item_keyboard(sticker_id: i64) -> InlineKeyboardMarkup {
-    InlineKeyboardMarkup::new(vec![
-        vec![InlineKeyboardButton::callback(
-            "Напечатать ещё раз",
-            format!("reprint:{sticker_id}"),
-        )],
-        vec![InlineKeyboardButton::callback(
-            "Удалить из истории",
-            format!("delete:{sticker_id}"),
-        )],
-    ])
+/// One button per named printer, the user's pick marked, plus a way back to the configured default.
+fn printer_keyboard(printers: &[NamedPrinter], current: Option<&str>) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = printers
+        .iter()
+        // Telegram caps callback data at 64 bytes.
+        .filter(|p| p.name.len() <= 56)
+        .map(|p| {
+            let mark = if current == Some(p.name.as_str()) {
+                "✅ "
+            } else {
+                ""
+            };
+            vec![InlineKeyboardButton::callback(
+                format!("{mark}{}", p.name),
+                format!("printer:{}", p.name),
+            )]
+        })
+        .collect();
+    let mark = if current.is_none() { "✅ " } else { "" };
+    rows.push(vec![InlineKeyboardButton::callback(
+        format!("{mark}По умолчанию"),
+        "printer:",
+    )]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+fn all_printers_button(sticker_id: i64) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback("🖨 На все принтеры", format!("printall:{sticker_id}"))
 }
 
 fn clear_history_keyboard() -> InlineKeyboardMarkup {
@@ -1445,6 +3244,7 @@ fn main_menu_keyboard() -> KeyboardMarkup {
         vec![
             KeyboardButton::new("🆘 Помощь"),
             KeyboardButton::new("🗂 История"),
+            KeyboardButton::new("🔁 Повторить"),
             KeyboardButton::new("📊 Статистика"),
         ],
         vec![
@@ -1466,6 +3266,7 @@ fn map_menu_button_to_command(text: &str) -> Option<Command> {
     match text.trim() {
         "🆘 Помощь" => Some(Command::Help),
         "🗂 История" => Some(Command::History),
+        "🔁 Повторить" => Some(Command::Last),
         "📊 Статистика" => Some(Command::Stats),
         "🏷 Простой стикер" => Some(Command::Simple),
         "✏️ Контур текста" => Some(Command::Outline),
@@ -1476,12 +3277,157 @@ fn map_menu_button_to_command(text: &str) -> Option<Command> {
     }
 }
 
+/// printerd's RFC 3339 timestamp as a short local date and time.
+fn format_printed_at(at: &str, time_zone: Option<Tz>) -> String {
+    let Ok(at) = DateTime::parse_from_rfc3339(at) else {
+        return at.to_string();
+    };
+    match time_zone {
+        Some(tz) => at.with_timezone(&tz).format("%d.%m %H:%M").to_string(),
+        None => at.with_timezone(&Local).format("%d.%m %H:%M").to_string(),
+    }
+}
+
+/// `/wifi` arguments: either one line `SSID PASSWORD [WPA|WEP|nopass]`, where the SSID may contain
+/// spaces, or three lines (SSID, password, security) for values with spaces anywhere.
+fn parse_wifi_args(args: &str) -> Option<(String, String, String)> {
+    let args = args.trim();
+    if args.contains('\n') {
+        let mut lines = args.lines().map(str::trim);
+        let ssid = lines.next().filter(|v| !v.is_empty())?;
+        let password = lines.next().unwrap_or("");
+        let security = lines.next().filter(|v| !v.is_empty());
+        let security = security.unwrap_or(if password.is_empty() { "nopass" } else { "WPA" });
+        return Some((ssid.to_string(), password.to_string(), security.to_string()));
+    }
+
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let security = match words.last() {
+        Some(last) if ["wpa", "wep", "nopass"].contains(&last.to_ascii_lowercase().as_str()) => {
+            words.pop().map(str::to_string)
+        }
+        _ => None,
+    };
+    let password = match security.as_deref() {
+        Some(v) if v.eq_ignore_ascii_case("nopass") => String::new(),
+        _ => words.pop()?.to_string(),
+    };
+    if words.is_empty() {
+        return None;
+    }
+    let security = security.unwrap_or_else(|| "WPA".to_string());
+    Some((words.join(" "), password, security))
+}
+
+/// Wi-Fi network QR payload, `WIFI:T:<type>;S:<ssid>;P:<password>;;`, with `\`, `;`, `,`, `:` and
+/// `"` escaped by a backslash as the format requires.
+fn wifi_payload(ssid: &str, password: &str, security: &str) -> String {
+    fn escape(v: &str) -> String {
+        let mut out = String::with_capacity(v.len());
+        for ch in v.chars() {
+            if matches!(ch, '\\' | ';' | ',' | ':' | '"') {
+                out.push('\\');
+            }
+            out.push(ch);
+        }
+        out
+    }
+
+    let security = match security.to_ascii_lowercase().as_str() {
+        "wep" => "WEP",
+        "nopass" => "nopass",
+        _ => "WPA",
+    };
+    if security == "nopass" {
+        format!("WIFI:T:nopass;S:{};;", escape(ssid))
+    } else {
+        format!(
+            "WIFI:T:{security};S:{};P:{};;",
+            escape(ssid),
+            escape(password)
+        )
+    }
+}
+
+/// `55.75580, 37.61730`: five decimals are about a metre.
+fn coordinates_text(location: &Location) -> String {
+    format!("{:.5}, {:.5}", location.latitude, location.longitude)
+}
+
+/// OpenStreetMap link with a marker at `location`; phones open it in a browser or a maps app.
+fn map_url(location: &Location) -> String {
+    let (lat, lon) = (location.latitude, location.longitude);
+    format!("https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lon:.5}#map=17/{lat:.5}/{lon:.5}")
+}
+
+fn contact_name(contact: &Contact) -> String {
+    match contact.last_name.as_deref() {
+        Some(last) if !last.is_empty() => format!("{} {last}", contact.first_name),
+        _ => contact.first_name.clone(),
+    }
+}
+
+/// Minimal vCard 3.0 with the name and phone, so scanning the QR offers to save the contact.
+/// Telegram's own `vcard` is skipped: it can carry photos and far more than a small QR holds.
+fn contact_vcard(contact: &Contact) -> String {
+    fn escape(v: &str) -> String {
+        let mut out = String::with_capacity(v.len());
+        for ch in v.chars() {
+            if matches!(ch, '\\' | ';' | ',') {
+                out.push('\\');
+            }
+            out.push(ch);
+        }
+        out
+    }
+
+    format!(
+        "BEGIN:VCARD\r\nVERSION:3.0\r\nN:{};{};;;\r\nFN:{}\r\nTEL:{}\r\nEND:VCARD",
+        escape(contact.last_name.as_deref().unwrap_or("")),
+        escape(&contact.first_name),
+        escape(&contact_name(contact)),
+        escape(&contact.phone_number)
+    )
+}
+
+/// A `stickers` row selected as `id, kind, text, width_px, height_px, x_px, y_px, font_size_px,
+/// threshold, invert, trim_blank_top_bottom, density, dither_method, source_image_bytes,
+/// preview_png, created_at, saved, rotate_90, text_settings`.
+fn sticker_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<StickerRecord> {
+    Ok(StickerRecord {
+        id: row.get(0)?,
+        kind: parse_kind(row.get::<_, String>(1)?),
+        text: row.get(2)?,
+        width_px: row.get::<_, i64>(3)? as u32,
+        height_px: row.get::<_, i64>(4)? as u32,
+        x_px: row.get(5)?,
+        y_px: row.get(6)?,
+        font_size_px: row.get(7)?,
+        threshold: row.get::<_, i64>(8)? as u8,
+        invert: row.get::<_, i64>(9)? != 0,
+        trim_blank_top_bottom: row.get::<_, i64>(10)? != 0,
+        density: row.get::<_, i64>(11)? as u8,
+        dither_method: parse_dither_opt(row.get::<_, Option<String>>(12)?),
+        source_image_bytes: row.get(13)?,
+        preview_png: row.get(14)?,
+        created_at: row.get(15)?,
+        saved: row.get::<_, i64>(16)? != 0,
+        rotate_90: parse_rotation_opt(row.get::<_, Option<String>>(17)?),
+        text_settings: row
+            .get::<_, Option<String>>(18)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        estimated_seconds: None,
+    })
+}
+
 fn parse_kind(kind: String) -> StickerKind {
     match kind.as_str() {
         "image" => StickerKind::Image,
         "text_outline" => StickerKind::TextOutline,
         "text_banner" => StickerKind::TextBanner,
         "text_banner_outline" => StickerKind::TextBannerOutline,
+        "qr" => StickerKind::Qr,
+        "wifi" => StickerKind::WifiQr,
         _ => StickerKind::Text,
     }
 }
@@ -1494,6 +3440,14 @@ fn parse_dither_opt(v: Option<String>) -> Option<DitherMethod> {
     }
 }
 
+fn parse_rotation_opt(v: Option<String>) -> Option<TextRotation> {
+    match v.as_deref() {
+        Some("cw") => Some(TextRotation::Cw),
+        Some("ccw") => Some(TextRotation::Ccw),
+        _ => None,
+    }
+}
+
 impl PrinterdClient {
     fn new(cfg: PrinterdConfig) -> Self {
         Self {
@@ -1501,32 +3455,89 @@ impl PrinterdClient {
             base_url: cfg.base_url.trim_end_matches('/').to_string(),
             token: cfg.api_token,
             default_address: cfg.address,
+            retry_attempts: cfg
+                .retry_attempts
+                .unwrap_or(DEFAULT_PRINTERD_RETRY_ATTEMPTS)
+                .max(1),
+            retry_backoff: Duration::from_millis(
+                cfg.retry_backoff_ms
+                    .unwrap_or(DEFAULT_PRINTERD_RETRY_BACKOFF_MS),
+            ),
+        }
+    }
+
+    fn with_token(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.header("x-api-token", token),
+            None => request,
+        }
+    }
+
+    /// Sends the request made by `build`, retrying connection failures and 5xx answers with
+    /// doubling backoff. Other answers, and whatever the last attempt got, are returned as is.
+    async fn send_with_retry(
+        &self,
+        what: &str,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let mut delay = self.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            let retry = attempt < self.retry_attempts;
+            match self.with_token(build()).send().await {
+                Ok(resp) if retry && resp.status().is_server_error() => {
+                    warn!(attempt = attempt, status = %resp.status(), "{what} failed, retrying");
+                }
+                Ok(resp) => return Ok(resp),
+                Err(err) if retry && (err.is_connect() || err.is_timeout() || err.is_request()) => {
+                    warn!(attempt = attempt, error = %err, "{what} failed, retrying");
+                }
+                Err(err) => return Err(anyhow!(err).context(format!("{what} failed"))),
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
         }
     }
 
     async fn render_text(&self, req: &RenderTextRequest) -> Result<RenderTextResponse> {
         let url = format!("{}/api/v1/renders/text", self.base_url);
-        let mut request = self.http.post(url).json(req);
-        if let Some(token) = &self.token {
-            request = request.header("x-api-token", token);
-        }
-        let resp = request.send().await.context("printerd request failed")?;
+        let resp = self
+            .send_with_retry("printerd request", || self.http.post(&url).json(req))
+            .await?;
         parse_json_response(resp).await
     }
 
     async fn render_image(&self, req: &RenderImageRequest) -> Result<RenderTextResponse> {
         let url = format!("{}/api/v1/renders/image", self.base_url);
-        let mut request = self.http.post(url).json(req);
-        if let Some(token) = &self.token {
-            request = request.header("x-api-token", token);
-        }
-        let resp = request
-            .send()
-            .await
-            .context("printerd image request failed")?;
+        let resp = self
+            .send_with_retry("printerd image request", || self.http.post(&url).json(req))
+            .await?;
+        parse_json_response(resp).await
+    }
+
+    async fn render_qr(&self, req: &RenderQrRequest) -> Result<RenderTextResponse> {
+        let url = format!("{}/api/v1/renders/qr", self.base_url);
+        let resp = self
+            .send_with_retry("printerd qr request", || self.http.post(&url).json(req))
+            .await?;
         parse_json_response(resp).await
     }
 
+    /// The preview of `render`, inline for ephemeral renders and fetched otherwise.
+    async fn preview_png(&self, render: &RenderTextResponse) -> Result<Vec<u8>> {
+        if let Some(inline) = &render.preview_png_base64 {
+            return base64::engine::general_purpose::STANDARD
+                .decode(inline)
+                .context("printerd returned an invalid inline preview");
+        }
+        let preview_url = render
+            .preview_url
+            .as_deref()
+            .context("printerd returned no preview")?;
+        self.get_preview(preview_url).await
+    }
+
     async fn get_preview(&self, preview_url: &str) -> Result<Vec<u8>> {
         let url = if preview_url.starts_with("http://") || preview_url.starts_with("https://") {
             preview_url.to_string()
@@ -1534,11 +3545,9 @@ impl PrinterdClient {
             format!("{}{}", self.base_url, preview_url)
         };
 
-        let mut request = self.http.get(url);
-        if let Some(token) = &self.token {
-            request = request.header("x-api-token", token);
-        }
-        let resp = request.send().await.context("preview request failed")?;
+        let resp = self
+            .send_with_retry("preview request", || self.http.get(&url))
+            .await?;
         if !resp.status().is_success() {
             let status = resp.status();
             let body = resp.text().await.unwrap_or_default();
@@ -1552,6 +3561,7 @@ impl PrinterdClient {
         &self,
         render_id: &str,
         density: u8,
+        copies: u8,
         address: Option<String>,
     ) -> Result<PrintResponse> {
         let url = format!("{}/api/v1/print", self.base_url);
@@ -1559,13 +3569,72 @@ impl PrinterdClient {
             render_id: render_id.to_string(),
             address: address.or_else(|| self.default_address.clone()),
             density,
+            copies,
+            // Someone is waiting in the chat, so skip ahead of batch jobs.
+            priority: "high",
         };
 
-        let mut request = self.http.post(url).json(&req);
-        if let Some(token) = &self.token {
-            request = request.header("x-api-token", token);
-        }
-        let resp = request.send().await.context("print request failed")?;
+        // One key per print attempt: if the response is lost and we resend, printerd returns the
+        // job it already queued instead of printing the sticker twice. Only that makes retrying a
+        // print safe.
+        let idempotency_key = format!(
+            "tg-{render_id}-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+
+        let resp = self
+            .send_with_retry("print request", || {
+                self.http
+                    .post(&url)
+                    .header("idempotency-key", &idempotency_key)
+                    .json(&req)
+            })
+            .await?;
+        parse_json_response(resp).await
+    }
+
+    /// Queues one job per printer. Not retried: printerd has no idempotency key for fan-out, and
+    /// a resend would print everything twice.
+    async fn print_fanout(
+        &self,
+        render_id: &str,
+        addresses: &[String],
+        density: u8,
+        copies: u8,
+    ) -> Result<FanoutPrintResponse> {
+        let url = format!("{}/api/v1/print/fanout", self.base_url);
+        let req = FanoutPrintRequest {
+            render_id,
+            addresses,
+            density,
+            copies,
+            priority: "high",
+        };
+        let resp = self
+            .with_token(self.http.post(url))
+            .json(&req)
+            .send()
+            .await
+            .context("fan-out print request failed")?;
+        parse_json_response(resp).await
+    }
+
+    async fn list_printers(&self) -> Result<Vec<NamedPrinter>> {
+        let url = format!("{}/api/v1/printers", self.base_url);
+        let resp = self
+            .send_with_retry("printers request", || self.http.get(&url))
+            .await?;
+        parse_json_response(resp).await
+    }
+
+    async fn printer_status(&self, address: &str) -> Result<PrinterStatus> {
+        let url = format!("{}/api/v1/printers/{address}/status", self.base_url);
+        let resp = self
+            .send_with_retry("printer status request", || self.http.get(&url))
+            .await?;
         parse_json_response(resp).await
     }
 
@@ -1626,7 +3695,19 @@ async fn parse_json_response<T: for<'de> Deserialize<'de>>(resp: reqwest::Respon
 
     let text = resp.text().await.unwrap_or_default();
     if let Ok(err_body) = serde_json::from_str::<ApiErrorBody>(&text) {
-        bail!("printerd error {}: {}", status, err_body.error);
+        if let (Some(length), Some(max)) = (err_body.length_mm, err_body.max_length_mm) {
+            bail!("слишком длинный стикер: {length:.0} мм, максимум {max:.0} мм");
+        }
+        match err_body.code.as_deref() {
+            Some("blank_result") => bail!("стикер получился пустым"),
+            Some("too_dark") => bail!("стикер получился почти полностью чёрным"),
+            Some("text_does_not_fit") => bail!("текст не помещается на стикер"),
+            Some("queue_full") => bail!("очередь печати заполнена, попробуйте позже"),
+            Some("status_unknown") => {
+                bail!("принтер ещё ничего не печатал после запуска printerd, состояние неизвестно")
+            }
+            _ => bail!("printerd error {}: {}", status, err_body.error),
+        }
     }
     bail!("printerd error {}: {}", status, text)
 }
@@ -1646,8 +3727,10 @@ struct NewSticker {
     trim_blank_top_bottom: bool,
     density: u8,
     dither_method: Option<DitherMethod>,
+    rotate_90: Option<TextRotation>,
     source_image_bytes: Option<Vec<u8>>,
     preview_png: Vec<u8>,
+    text_settings: Option<StickerConfig>,
 }
 
 struct NewAiGeneration {
@@ -1724,9 +3807,12 @@ impl Db {
                         trim_blank_top_bottom INTEGER NOT NULL,
                         density INTEGER NOT NULL,
                         dither_method TEXT,
+                        rotate_90 TEXT,
                         source_image_bytes BLOB,
                         preview_png BLOB NOT NULL,
+                        text_settings TEXT,
                         last_printer_job_id TEXT,
+                        saved INTEGER NOT NULL DEFAULT 0,
                         created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
                     );
                     CREATE INDEX IF NOT EXISTS idx_stickers_user_created ON stickers(user_id, id DESC);
@@ -1747,6 +3833,10 @@ impl Db {
                         created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now'))
                     );
                     CREATE INDEX IF NOT EXISTS idx_ai_generations_user_created ON ai_generations(user_id, id DESC);
+                    CREATE TABLE IF NOT EXISTS user_printers (
+                        user_id INTEGER PRIMARY KEY,
+                        printer TEXT NOT NULL
+                    );
                     ",
                 )?;
                 // Migrations for existing DBs.
@@ -1756,7 +3846,23 @@ impl Db {
                 );
                 let _ = conn.execute("ALTER TABLE stickers ADD COLUMN kind TEXT NOT NULL DEFAULT 'text'", []);
                 let _ = conn.execute("ALTER TABLE stickers ADD COLUMN dither_method TEXT", []);
+                let _ = conn.execute("ALTER TABLE stickers ADD COLUMN rotate_90 TEXT", []);
                 let _ = conn.execute("ALTER TABLE stickers ADD COLUMN source_image_bytes BLOB", []);
+                let _ = conn.execute(
+                    "ALTER TABLE stickers ADD COLUMN saved INTEGER NOT NULL DEFAULT 0",
+                    [],
+                );
+                let _ = conn.execute("ALTER TABLE stickers ADD COLUMN text_settings TEXT", []);
+                // `/wifi` stickers used to keep their payload, password included, as the text.
+                let _ = conn.execute(
+                    "UPDATE stickers SET kind = 'wifi', text = 'Wi-Fi' WHERE kind = 'qr' AND text LIKE 'WIFI:%'",
+                    [],
+                );
+                // Their QR preview shows the password too.
+                let _ = conn.execute(
+                    "UPDATE stickers SET preview_png = X'' WHERE kind = 'wifi'",
+                    [],
+                );
                 Ok(())
             })
             .await
@@ -1884,9 +3990,9 @@ impl Db {
                     "INSERT INTO stickers (
                         user_id, chat_id, kind, text, width_px, height_px, x_px, y_px,
                         font_size_px, threshold, invert, trim_blank_top_bottom,
-                        density, dither_method, source_image_bytes, preview_png
-                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
-                    (
+                        density, dither_method, source_image_bytes, preview_png, rotate_90, text_settings
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                    rusqlite::params![
                         s.user_id,
                         s.chat_id,
                         match s.kind {
@@ -1895,6 +4001,8 @@ impl Db {
                             StickerKind::TextBanner => "text_banner",
                             StickerKind::TextBannerOutline => "text_banner_outline",
                             StickerKind::Image => "image",
+                            StickerKind::Qr => "qr",
+                            StickerKind::WifiQr => "wifi",
                         },
                         s.text,
                         s.width_px as i64,
@@ -1912,7 +4020,9 @@ impl Db {
                         }),
                         s.source_image_bytes,
                         s.preview_png,
-                    ),
+                        s.rotate_90.map(TextRotation::as_str),
+                        s.text_settings.and_then(|cfg| serde_json::to_string(&cfg).ok()),
+                    ],
                 )?;
                 Ok(conn.last_insert_rowid())
             })
@@ -2009,7 +4119,8 @@ impl Db {
             .call(move |conn| -> rusqlite::Result<Option<StickerRecord>> {
                 let mut stmt = conn.prepare(
                     "SELECT id, kind, text, width_px, height_px, x_px, y_px, font_size_px,
-                            threshold, invert, trim_blank_top_bottom, density, dither_method, source_image_bytes, preview_png, created_at
+                            threshold, invert, trim_blank_top_bottom, density, dither_method, source_image_bytes, preview_png, created_at,
+                            saved, rotate_90, text_settings
                      FROM stickers
                      WHERE id = ?1 AND user_id = ?2",
                 )?;
@@ -2019,24 +4130,7 @@ impl Db {
                     return Ok(None);
                 };
 
-                Ok(Some(StickerRecord {
-                    id: row.get(0)?,
-                    kind: parse_kind(row.get::<_, String>(1)?),
-                    text: row.get(2)?,
-                    width_px: row.get::<_, i64>(3)? as u32,
-                    height_px: row.get::<_, i64>(4)? as u32,
-                    x_px: row.get(5)?,
-                    y_px: row.get(6)?,
-                    font_size_px: row.get(7)?,
-                    threshold: row.get::<_, i64>(8)? as u8,
-                    invert: row.get::<_, i64>(9)? != 0,
-                    trim_blank_top_bottom: row.get::<_, i64>(10)? != 0,
-                    density: row.get::<_, i64>(11)? as u8,
-                    dither_method: parse_dither_opt(row.get::<_, Option<String>>(12)?),
-                    source_image_bytes: row.get(13)?,
-                    preview_png: row.get(14)?,
-                    created_at: row.get(15)?,
-                }))
+                Ok(Some(sticker_from_row(row)?))
             })
             .await
             .map_err(|e| anyhow!("failed to load sticker: {e}"))
@@ -2047,33 +4141,15 @@ impl Db {
             .call(move |conn| -> rusqlite::Result<Vec<StickerRecord>> {
                 let mut stmt = conn.prepare(
                     "SELECT id, kind, text, width_px, height_px, x_px, y_px, font_size_px,
-                            threshold, invert, trim_blank_top_bottom, density, dither_method, source_image_bytes, preview_png, created_at
+                            threshold, invert, trim_blank_top_bottom, density, dither_method, source_image_bytes, preview_png, created_at,
+                            saved, rotate_90, text_settings
                      FROM stickers
                      WHERE user_id = ?1
                      ORDER BY id DESC
                      LIMIT ?2",
                 )?;
 
-                let rows = stmt.query_map((user_id, limit), |row| {
-                    Ok(StickerRecord {
-                        id: row.get(0)?,
-                        kind: parse_kind(row.get::<_, String>(1)?),
-                        text: row.get(2)?,
-                        width_px: row.get::<_, i64>(3)? as u32,
-                        height_px: row.get::<_, i64>(4)? as u32,
-                        x_px: row.get(5)?,
-                        y_px: row.get(6)?,
-                        font_size_px: row.get(7)?,
-                        threshold: row.get::<_, i64>(8)? as u8,
-                        invert: row.get::<_, i64>(9)? != 0,
-                        trim_blank_top_bottom: row.get::<_, i64>(10)? != 0,
-                        density: row.get::<_, i64>(11)? as u8,
-                        dither_method: parse_dither_opt(row.get::<_, Option<String>>(12)?),
-                        source_image_bytes: row.get(13)?,
-                        preview_png: row.get(14)?,
-                        created_at: row.get(15)?,
-                    })
-                })?;
+                let rows = stmt.query_map((user_id, limit), sticker_from_row)?;
 
                 let mut out = Vec::new();
                 for row in rows {
@@ -2085,8 +4161,94 @@ impl Db {
             .map_err(|e| anyhow!("failed to load history: {e}"))
     }
 
-    async fn set_last_print_job(&self, id: i64, job_id: &str) -> Result<()> {
-        let jid = job_id.to_string();
+    /// The user's stickers whose text contains `query`, newest first. SQLite's `LIKE` folds case
+    /// for ASCII letters only.
+    async fn search_for_user(
+        &self,
+        user_id: i64,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<StickerRecord>> {
+        // SQLite's LIKE and lower() fold ASCII only, so "привет" would miss "Привет";
+        // texts are matched here and only the hits are loaded.
+        let needle = query.to_lowercase();
+        self.conn
+            .call(move |conn| -> rusqlite::Result<Vec<StickerRecord>> {
+                let mut ids = Vec::new();
+                let mut stmt =
+                    conn.prepare("SELECT id, text FROM stickers WHERE user_id = ?1 ORDER BY id DESC")?;
+                let mut rows = stmt.query([user_id])?;
+                while (ids.len() as i64) < limit
+                    && let Some(row) = rows.next()?
+                {
+                    let text: String = row.get(1)?;
+                    if text.to_lowercase().contains(&needle) {
+                        ids.push(row.get::<_, i64>(0)?);
+                    }
+                }
+
+                let mut stmt = conn.prepare(
+                    "SELECT id, kind, text, width_px, height_px, x_px, y_px, font_size_px,
+                            threshold, invert, trim_blank_top_bottom, density, dither_method, source_image_bytes, preview_png, created_at,
+                            saved, rotate_90, text_settings
+                     FROM stickers
+                     WHERE id = ?1",
+                )?;
+                ids.into_iter()
+                    .map(|id| stmt.query_row([id], sticker_from_row))
+                    .collect()
+            })
+            .await
+            .map_err(|e| anyhow!("failed to search history: {e}"))
+    }
+
+    async fn last_for_user(&self, user_id: i64) -> Result<Option<StickerRecord>> {
+        Ok(self
+            .list_recent_for_user(user_id, 1)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// The printer name or address the user picked with `/printers`.
+    async fn printer_for_user(&self, user_id: i64) -> Result<Option<String>> {
+        self.conn
+            .call(move |conn| -> rusqlite::Result<Option<String>> {
+                let mut stmt =
+                    conn.prepare("SELECT printer FROM user_printers WHERE user_id = ?1")?;
+                let mut rows = stmt.query([user_id])?;
+                match rows.next()? {
+                    Some(row) => Ok(Some(row.get(0)?)),
+                    None => Ok(None),
+                }
+            })
+            .await
+            .map_err(|e| anyhow!("failed to load printer choice: {e}"))
+    }
+
+    /// `None` goes back to `[printerd] address`.
+    async fn set_printer_for_user(&self, user_id: i64, printer: Option<String>) -> Result<()> {
+        self.conn
+            .call(move |conn| -> rusqlite::Result<()> {
+                match printer {
+                    Some(printer) => conn.execute(
+                        "INSERT INTO user_printers (user_id, printer) VALUES (?1, ?2)
+                         ON CONFLICT(user_id) DO UPDATE SET printer = excluded.printer",
+                        (user_id, printer),
+                    )?,
+                    None => {
+                        conn.execute("DELETE FROM user_printers WHERE user_id = ?1", [user_id])?
+                    }
+                };
+                Ok(())
+            })
+            .await
+            .map_err(|e| anyhow!("failed to save printer choice: {e}"))
+    }
+
+    /// Several jobs, from printing on all printers, are stored comma-separated.
+    async fn set_last_print_job(&self, id: i64, job_ids: &[String]) -> Result<()> {
+        let jid = job_ids.join(",");
         self.conn
             .call(move |conn| -> rusqlite::Result<()> {
                 conn.execute(
@@ -2099,6 +4261,38 @@ impl Db {
             .map_err(|e| anyhow!("failed to update print job id: {e}"))
     }
 
+    async fn mark_saved_for_user(&self, id: i64, user_id: i64) -> Result<bool> {
+        self.conn
+            .call(move |conn| -> rusqlite::Result<bool> {
+                let changed = conn.execute(
+                    "UPDATE stickers SET saved = 1 WHERE id = ?1 AND user_id = ?2",
+                    (id, user_id),
+                )?;
+                Ok(changed > 0)
+            })
+            .await
+            .map_err(|e| anyhow!("failed to save history item: {e}"))
+    }
+
+    async fn set_threshold_for_user(
+        &self,
+        id: i64,
+        user_id: i64,
+        threshold: u8,
+        preview_png: Vec<u8>,
+    ) -> Result<bool> {
+        self.conn
+            .call(move |conn| -> rusqlite::Result<bool> {
+                let changed = conn.execute(
+                    "UPDATE stickers SET threshold = ?1, preview_png = ?2 WHERE id = ?3 AND user_id = ?4",
+                    (threshold as i64, preview_png, id, user_id),
+                )?;
+                Ok(changed > 0)
+            })
+            .await
+            .map_err(|e| anyhow!("failed to update sticker threshold: {e}"))
+    }
+
     async fn delete_sticker_for_user(&self, id: i64, user_id: i64) -> Result<bool> {
         self.conn
             .call(move |conn| -> rusqlite::Result<bool> {
@@ -2122,3 +4316,207 @@ impl Db {
             .map_err(|e| anyhow!("failed to clear history: {e}"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sticker_config(toml_text: &str) -> StickerConfig {
+        toml::from_str(toml_text).expect("sticker config")
+    }
+
+    const STICKER_CONFIG: &str = r#"
+        font_path = "/fonts/a.ttf"
+        printer_width_px = 384
+        margin_left_px = 10
+        margin_right_px = 10
+        margin_top_px = 12
+        margin_bottom_px = 12
+        min_font_size_px = 14.0
+        max_font_size_px = 92.0
+        line_spacing = 1.1
+        threshold = 180
+        density = 3
+        invert = false
+        trim_blank_top_bottom = true
+    "#;
+
+    fn text_sticker(text_settings: Option<StickerConfig>) -> NewSticker {
+        NewSticker {
+            user_id: 1,
+            chat_id: 1,
+            kind: StickerKind::Text,
+            text: "hello".to_string(),
+            width_px: 384,
+            height_px: 96,
+            x_px: 10,
+            y_px: 12,
+            font_size_px: 40.0,
+            threshold: 180,
+            invert: false,
+            trim_blank_top_bottom: true,
+            density: 3,
+            dither_method: None,
+            rotate_90: None,
+            source_image_bytes: None,
+            preview_png: vec![1, 2, 3],
+            text_settings,
+        }
+    }
+
+    #[tokio::test]
+    async fn edits_keep_the_settings_a_sticker_was_made_with() {
+        let db = Db::open(":memory:").await.unwrap();
+        db.init().await.unwrap();
+        let made_with = sticker_config(&format!(
+            "{STICKER_CONFIG}\nborder_px = 3\nbackground = \"dots\"\nfixed_height_px = 96"
+        ));
+        let id = db
+            .insert_sticker(text_sticker(Some(made_with)))
+            .await
+            .unwrap();
+        let old_id = db.insert_sticker(text_sticker(None)).await.unwrap();
+
+        // The config changed since: no border, another font and line spacing.
+        let current = sticker_config(&STICKER_CONFIG.replace("a.ttf", "b.ttf"));
+        let stored = db.get_sticker_for_user(id, 1).await.unwrap().unwrap();
+        let cfg = text_settings(&stored, &current);
+        assert_eq!(cfg.font_path, "/fonts/a.ttf");
+        assert_eq!(cfg.border_px, 3);
+        assert_eq!(cfg.fixed_height_px, Some(96));
+        assert!(matches!(cfg.background, Some(TextBackground::Dots)));
+
+        let old = db.get_sticker_for_user(old_id, 1).await.unwrap().unwrap();
+        assert_eq!(text_settings(&old, &current).font_path, "/fonts/b.ttf");
+    }
+
+    #[tokio::test]
+    async fn search_ignores_case_of_cyrillic_text() {
+        let db = Db::open(":memory:").await.unwrap();
+        db.init().await.unwrap();
+        for text in ["Привет, мир", "ПРИВЕТ", "hello 100%", "пока"] {
+            db.insert_sticker(NewSticker {
+                text: text.to_string(),
+                ..text_sticker(None)
+            })
+            .await
+            .unwrap();
+        }
+
+        let texts = |found: Vec<StickerRecord>| -> Vec<String> {
+            found.into_iter().map(|s| s.text).collect()
+        };
+        assert_eq!(
+            texts(db.search_for_user(1, "привет", 10).await.unwrap()),
+            ["ПРИВЕТ", "Привет, мир"]
+        );
+        assert_eq!(
+            texts(db.search_for_user(1, "привет", 1).await.unwrap()),
+            ["ПРИВЕТ"]
+        );
+        assert_eq!(
+            texts(db.search_for_user(1, "HELLO 100%", 10).await.unwrap()),
+            ["hello 100%"]
+        );
+        let other_user = db.search_for_user(2, "привет", 10).await.unwrap();
+        assert!(other_user.is_empty());
+    }
+
+    fn job(status: &str, note: Option<&str>) -> Result<JobResponse> {
+        Ok(JobResponse {
+            status: status.to_string(),
+            error: None,
+            note: note.map(str::to_string),
+        })
+    }
+
+    #[tokio::test]
+    async fn poll_job_waits_again_until_the_job_finishes() {
+        let mut script = vec![
+            job("queued", None),
+            job("printing", None),
+            job("done", None),
+        ]
+        .into_iter();
+        let mut timeouts = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(600);
+        let done = poll_job("j1", 20, deadline, |timeout| {
+            timeouts.push(timeout);
+            let next = script.next().expect("polled after the job finished");
+            async move { next }
+        })
+        .await
+        .unwrap();
+        assert_eq!(done.status, "done");
+        assert_eq!(timeouts, [20, 20, 20]);
+
+        // A pause is reported at once instead of waiting out the deadline.
+        let mut script = vec![job("printing", Some("printer paused"))].into_iter();
+        let paused = poll_job("j2", 20, deadline, |_| {
+            let next = script.next().expect("polled again after a pause");
+            async move { next }
+        })
+        .await
+        .unwrap();
+        assert_eq!(paused.note.as_deref(), Some("printer paused"));
+    }
+
+    #[tokio::test]
+    async fn poll_job_stops_at_the_deadline() {
+        let mut timeouts = Vec::new();
+        let still_printing = poll_job("j1", 20, Instant::now(), |timeout| {
+            timeouts.push(timeout);
+            async { job("printing", None) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(still_printing.status, "printing");
+        // Never longer than what is left; `wait_job` turns the 0 into printerd's 1 s minimum.
+        assert_eq!(timeouts, [0]);
+    }
+
+    #[test]
+    fn wifi_payload_escapes_special_characters() {
+        assert_eq!(
+            wifi_payload("home", "secret", "wpa"),
+            "WIFI:T:WPA;S:home;P:secret;;"
+        );
+        assert_eq!(
+            wifi_payload("a;b,c:d", "p\\w\"d;", "WEP"),
+            r#"WIFI:T:WEP;S:a\;b\,c\:d;P:p\\w\"d\;;;"#
+        );
+        assert_eq!(
+            wifi_payload("guest", "", "nopass"),
+            "WIFI:T:nopass;S:guest;;"
+        );
+    }
+
+    #[test]
+    fn parse_wifi_args_splits_one_line_and_three_lines() {
+        let args = |ssid: &str, pass: &str, sec: &str| {
+            Some((ssid.to_string(), pass.to_string(), sec.to_string()))
+        };
+        assert_eq!(
+            parse_wifi_args("home secret"),
+            args("home", "secret", "WPA")
+        );
+        assert_eq!(
+            parse_wifi_args("My Home a;b,c:d wep"),
+            args("My Home", "a;b,c:d", "wep")
+        );
+        assert_eq!(
+            parse_wifi_args("Cafe Guest nopass"),
+            args("Cafe Guest", "", "nopass")
+        );
+        assert_eq!(
+            parse_wifi_args("My Home\npass with spaces\nWPA"),
+            args("My Home", "pass with spaces", "WPA")
+        );
+        assert_eq!(
+            parse_wifi_args("Open\n\nnopass"),
+            args("Open", "", "nopass")
+        );
+        assert_eq!(parse_wifi_args("onlyssid"), None);
+        assert_eq!(parse_wifi_args("  "), None);
+    }
+}